@@ -3,18 +3,36 @@ use proc_macro2::Span;
 use quote::quote;
 use syn;
 
-#[proc_macro_derive(PaginatedResource, attributes(resource_id, collection_name))]
+#[proc_macro_derive(
+    PaginatedResource,
+    attributes(resource_id, collection_name, root_array)
+)]
 pub fn paginated_resource_macro_derive(input: TokenStream) -> TokenStream {
     let input = syn::parse_macro_input!(input as syn::DeriveInput);
 
     let class_name = &input.ident;
     let vis = &input.vis;
+    let (id_name, id_type) = get_id_field(&input.data);
+
+    if is_root_array(&input) {
+        return quote! {
+            #[allow(missing_docs, unused)]
+            impl ::osauth::PaginatedResource for #class_name {
+                type Id = #id_type;
+                type Root = Vec<#class_name>;
+                fn resource_id(&self) -> Self::Id {
+                    self.#id_name.clone()
+                }
+            }
+        }
+        .into();
+    }
+
     let collection_name = syn::Ident::new(&get_collection_name(&input), Span::call_site());
     let collection_class_name = syn::Ident::new(
         &format!("{}DerivedOSResourceCollection", class_name),
         Span::call_site(),
     );
-    let (id_name, id_type) = get_id_field(&input.data);
 
     quote! {
         #[derive(Debug, ::serde::Deserialize)]
@@ -42,31 +60,152 @@ pub fn paginated_resource_macro_derive(input: TokenStream) -> TokenStream {
     .into()
 }
 
-fn has_attr(attrs: &Vec<syn::Attribute>, attr: &str) -> bool {
-    attrs.iter().find(|x| x.path.is_ident(attr)).is_some()
+#[proc_macro_derive(QueryParams, attributes(query))]
+pub fn query_params_macro_derive(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as syn::DeriveInput);
+
+    let class_name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut statements = Vec::new();
+    for field in get_named_fields(&input.data, "QueryParams") {
+        let options = get_query_options(&field.attrs);
+        if options.skip {
+            continue;
+        }
+
+        let ident = field.ident.as_ref().expect("no ident for a named field");
+        let name = options
+            .rename
+            .unwrap_or_else(|| ident.to_string().trim_start_matches("r#").to_string());
+        let title_case = if options.title_case {
+            quote! {
+                for value in &mut values {
+                    if *value == "true" {
+                        *value = "True".to_string();
+                    } else if *value == "false" {
+                        *value = "False".to_string();
+                    }
+                }
+            }
+        } else {
+            quote! {}
+        };
+        let push = if options.comma {
+            quote! {
+                if !values.is_empty() {
+                    pairs.push((#name.to_string(), values.join(",")));
+                }
+            }
+        } else {
+            quote! {
+                pairs.extend(values.into_iter().map(|value| (#name.to_string(), value)));
+            }
+        };
+        statements.push(quote! {
+            {
+                let mut values: Vec<String> = Vec::new();
+                ::osauth::QueryValue::append_to(&self.#ident, &mut values);
+                #title_case
+                #push
+            }
+        });
+    }
+
+    quote! {
+        #[allow(missing_docs, unused)]
+        impl #impl_generics ::osauth::QueryParams for #class_name #ty_generics #where_clause {
+            fn query_pairs(&self) -> Vec<(String, String)> {
+                let mut pairs = Vec::new();
+                #(#statements)*
+                pairs
+            }
+        }
+    }
+    .into()
 }
 
-fn get_id_field(data: &syn::Data) -> (&syn::Ident, &syn::Type) {
-    if let syn::Data::Struct(ref st) = data {
-        if let syn::Fields::Named(ref fs) = st.fields {
-            for field in &fs.named {
-                if has_attr(&field.attrs, "resource_id") {
-                    return (
-                        field.ident.as_ref().expect("no ident for resource_id"),
-                        &field.ty,
-                    );
+#[derive(Default)]
+struct QueryOptions {
+    comma: bool,
+    rename: Option<String>,
+    skip: bool,
+    title_case: bool,
+}
+
+fn get_query_options(attrs: &Vec<syn::Attribute>) -> QueryOptions {
+    let mut options = QueryOptions::default();
+    for attr in attrs.iter().filter(|x| x.path.is_ident("query")) {
+        let list = match attr.parse_meta() {
+            Ok(syn::Meta::List(list)) => list,
+            _ => panic!("expected #[query(...)]"),
+        };
+        for item in list.nested {
+            match item {
+                syn::NestedMeta::Meta(syn::Meta::Path(ref path)) if path.is_ident("comma") => {
+                    options.comma = true
+                }
+                syn::NestedMeta::Meta(syn::Meta::Path(ref path)) if path.is_ident("skip") => {
+                    options.skip = true
+                }
+                syn::NestedMeta::Meta(syn::Meta::Path(ref path)) if path.is_ident("title_case") => {
+                    options.title_case = true
+                }
+                syn::NestedMeta::Meta(syn::Meta::NameValue(ref nv))
+                    if nv.path.is_ident("rename") =>
+                {
+                    match nv.lit {
+                        syn::Lit::Str(ref s) => options.rename = Some(s.value()),
+                        _ => panic!("rename must be a string"),
+                    }
                 }
+                _ => panic!(
+                    "unsupported query attribute, expected comma, rename, skip or title_case"
+                ),
             }
+        }
+    }
+    options
+}
+
+fn get_named_fields<'d>(
+    data: &'d syn::Data,
+    derive: &str,
+) -> &'d syn::punctuated::Punctuated<syn::Field, syn::Token![,]> {
+    if let syn::Data::Struct(ref st) = data {
+        if let syn::Fields::Named(ref fs) = st.fields {
+            &fs.named
         } else {
-            panic!("only named fields are supported for derive(PaginatedResource)");
+            panic!("only named fields are supported for derive({})", derive);
         }
     } else {
-        panic!("only structs are supported for derive(PaginatedResource)");
+        panic!("only structs are supported for derive({})", derive);
+    }
+}
+
+fn has_attr(attrs: &Vec<syn::Attribute>, attr: &str) -> bool {
+    attrs.iter().find(|x| x.path.is_ident(attr)).is_some()
+}
+
+fn get_id_field(data: &syn::Data) -> (&syn::Ident, &syn::Type) {
+    for field in get_named_fields(data, "PaginatedResource") {
+        if has_attr(&field.attrs, "resource_id") {
+            return (
+                field.ident.as_ref().expect("no ident for resource_id"),
+                &field.ty,
+            );
+        }
     }
 
     panic!("#[resource_id] missing");
 }
 
+fn is_root_array(input: &syn::DeriveInput) -> bool {
+    input
+        .attrs
+        .iter()
+        .any(|attr| attr.path.is_ident("root_array"))
+}
+
 fn get_collection_name(input: &syn::DeriveInput) -> String {
     for attr in &input.attrs {
         if let Ok(syn::Meta::NameValue(nv)) = attr.parse_meta() {
@@ -20,7 +20,9 @@ use serde::de::DeserializeOwned;
 
 use super::services::{ServiceType, VersionedService};
 use super::session::ServiceRequestBuilder;
-use super::{ApiVersion, AuthType, EndpointFilters, Error, InterfaceType, Session};
+use super::{
+    ApiVersion, AuthType, EndpointFilters, Error, InterfaceType, JsonPatchOp, ServiceProbe, Session,
+};
 
 /// Adapter for a specific service.
 ///
@@ -274,6 +276,14 @@ impl<Srv: ServiceType + Send + Clone> Adapter<Srv> {
             .await
     }
 
+    /// Check the availability of the service.
+    ///
+    /// See [Session::probe](struct.Session.html#method.probe) for details.
+    #[inline]
+    pub async fn probe(&self) -> Result<ServiceProbe, Error> {
+        self.inner.probe(self.service.clone()).await
+    }
+
     /// Check if the service supports the API version.
     #[inline]
     pub async fn supports_api_version(&self, version: ApiVersion) -> Result<bool, Error> {
@@ -386,6 +396,30 @@ impl<Srv: ServiceType + Send + Clone> Adapter<Srv> {
         self.request(Method::POST, path)
     }
 
+    /// Start a PATCH request.
+    ///
+    /// See [request](#method.request) for an explanation of the parameters.
+    #[inline]
+    pub fn patch<I>(&self, path: I) -> ServiceRequestBuilder<Srv>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.request(Method::PATCH, path)
+    }
+
+    /// Start a PATCH request with a JSON Patch document.
+    ///
+    /// See [Session::patch_json](struct.Session.html#method.patch_json) for details.
+    #[inline]
+    pub fn patch_json<I>(&self, path: I, patch: &[JsonPatchOp]) -> ServiceRequestBuilder<Srv>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.inner.patch_json(self.service.clone(), path, patch)
+    }
+
     /// Start a PUT request.
     ///
     /// See [request](#method.request) for an explanation of the parameters.
@@ -0,0 +1,138 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Audit log of outgoing requests.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::{Method, Response, StatusCode, Url};
+
+use super::client::RequestInfo;
+use super::url as url_utils;
+use super::{Error, ErrorKind};
+
+/// A record of an outgoing request.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct AuditRecord {
+    /// HTTP method.
+    pub method: Method,
+    /// Request URL with credentials and sensitive query parameters redacted.
+    pub url: Url,
+    /// Service type (only for requests made through a [Session](struct.Session.html)).
+    pub service_type: Option<String>,
+    /// Operation tag provided by the caller, see
+    /// [ServiceRequestBuilder::operation](struct.ServiceRequestBuilder.html#method.operation).
+    pub operation: Option<String>,
+    /// Response status (if a response has been received).
+    pub status: Option<StatusCode>,
+    /// Kind of the error if the request has failed without a response.
+    pub error: Option<ErrorKind>,
+    /// Time from sending the request until receiving the response headers.
+    pub duration: Duration,
+}
+
+/// A destination for audit records.
+///
+/// Implemented for closures:
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use osauth::AuditRecord;
+///
+/// let session = osauth::Session::from_env()
+///     .await?
+///     .with_audit_sink(|record: &AuditRecord| {
+///         eprintln!(
+///             "{} {} {:?} -> {:?} in {:?}",
+///             record.method, record.url, record.operation, record.status, record.duration
+///         );
+///     });
+/// session
+///     .delete(osauth::services::COMPUTE, &["servers", "<UUID>"])
+///     .operation("delete-server")
+///     .send()
+///     .await?;
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+///
+/// Records never contain headers or bodies. Sinks are called synchronously in the request path
+/// and must not block. Requests made by the authentication type itself are not recorded.
+pub trait AuditSink: Send + Sync {
+    /// Store a record.
+    fn record(&self, record: &AuditRecord);
+}
+
+impl<F> AuditSink for F
+where
+    F: Fn(&AuditRecord) + Send + Sync,
+{
+    fn record(&self, record: &AuditRecord) {
+        self(record)
+    }
+}
+
+/// An optional audit sink.
+#[derive(Clone, Default)]
+pub(crate) struct AuditLog {
+    sink: Option<Arc<dyn AuditSink>>,
+}
+
+impl AuditLog {
+    /// Create a log with a sink.
+    pub fn new<S: AuditSink + 'static>(sink: S) -> AuditLog {
+        AuditLog {
+            sink: Some(Arc::new(sink)),
+        }
+    }
+
+    /// Record the result of a request.
+    pub fn record(
+        &self,
+        method: &Method,
+        url: &Url,
+        info: &RequestInfo,
+        result: &Result<Response, Error>,
+        duration: Duration,
+    ) {
+        if let Some(ref sink) = self.sink {
+            let (status, error) = match result {
+                Ok(response) => (Some(response.status()), None),
+                Err(e) => (e.status(), Some(e.kind())),
+            };
+            sink.record(&AuditRecord {
+                method: method.clone(),
+                url: url_utils::redact(url),
+                service_type: info.service_type.map(From::from),
+                operation: info.operation.clone(),
+                status,
+                error,
+                duration,
+            });
+        }
+    }
+}
+
+impl fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.sink {
+            Some(..) => f.write_str("AuditLog(<sink>)"),
+            None => f.write_str("AuditLog(None)"),
+        }
+    }
+}
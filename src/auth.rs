@@ -15,12 +15,14 @@
 //! Base code for authentication.
 
 use std::fmt::Debug;
+use std::sync::Arc;
 
 use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
 use reqwest::{Client, RequestBuilder, Url};
 use static_assertions::{assert_impl_all, assert_obj_safe};
 
-use super::{EndpointFilters, Error, ErrorKind};
+use super::{CatalogEntry, EndpointFilters, Error, ErrorKind};
 
 /// Trait for an authentication type.
 ///
@@ -47,8 +49,56 @@ pub trait AuthType: Debug + Sync + Send {
         filters: &EndpointFilters,
     ) -> Result<Url, Error>;
 
+    /// Get all records from the service catalog (if any).
+    ///
+    /// The default implementation returns an empty list, which is suitable for
+    /// authentication types without a service catalog.
+    async fn get_catalog(&self, _client: &Client) -> Result<Vec<CatalogEntry>, Error> {
+        Ok(Vec::new())
+    }
+
+    /// Get the ID of the authenticated user (if known).
+    ///
+    /// The default implementation returns `None`, which is suitable for
+    /// authentication types without a notion of a user.
+    async fn get_user_id(&self, _client: &Client) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+
+    /// Get names of the roles assigned to the authenticated user (if known).
+    ///
+    /// The default implementation returns an empty list, which is suitable for
+    /// authentication types without a notion of roles.
+    async fn get_roles(&self, _client: &Client) -> Result<Vec<String>, Error> {
+        Ok(Vec::new())
+    }
+
+    /// Get the current authentication token (if any), renewing it if needed.
+    ///
+    /// The default implementation returns `None`, which is suitable for
+    /// authentication types without a token.
+    async fn get_token(&self, _client: &Client) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+
     /// Refresh the authentication (renew the token, etc).
     async fn refresh(&self, client: &Client) -> Result<(), Error>;
+
+    /// Time when the current token was issued (if known).
+    ///
+    /// Must not block or authenticate. The default implementation returns `None`, which is
+    /// suitable for authentication types without a token.
+    fn token_issued_at(&self) -> Option<DateTime<FixedOffset>> {
+        None
+    }
+
+    /// Create a copy of this authentication that does not share its state (e.g. the token).
+    ///
+    /// The copy can be refreshed independently of the original. The default implementation
+    /// returns `None`, meaning that detaching is not supported.
+    fn detach(&self) -> Option<Arc<dyn AuthType>> {
+        None
+    }
 }
 
 assert_obj_safe!(AuthType);
@@ -127,6 +177,11 @@ impl AuthType for NoAuth {
     async fn refresh(&self, _client: &Client) -> Result<(), Error> {
         Ok(())
     }
+
+    /// Create a copy of this authentication.
+    fn detach(&self) -> Option<Arc<dyn AuthType>> {
+        Some(Arc::new(self.clone()))
+    }
 }
 
 #[cfg(test)]
@@ -14,6 +14,9 @@
 
 //! HTTP basic authentication.
 
+use std::fmt;
+use std::sync::Arc;
+
 use async_trait::async_trait;
 use reqwest::{Client, RequestBuilder, Url};
 use static_assertions::assert_impl_all;
@@ -29,7 +32,7 @@ use super::{AuthType, EndpointFilters, Error, ErrorKind};
 ///     .expect("Invalid endpoint URL");
 /// let session = osauth::Session::new(auth);
 /// ```
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct BasicAuth {
     endpoint: Url,
     username: String,
@@ -38,6 +41,16 @@ pub struct BasicAuth {
 
 assert_impl_all!(BasicAuth: Send, Sync);
 
+impl fmt::Debug for BasicAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BasicAuth")
+            .field("endpoint", &self.endpoint)
+            .field("username", &self.username)
+            .field("password", &"<hidden>")
+            .finish()
+    }
+}
+
 impl BasicAuth {
     /// Create a new HTTP basic authentication method using a fixed endpoint.
     ///
@@ -84,4 +97,9 @@ impl AuthType for BasicAuth {
     async fn refresh(&self, _client: &Client) -> Result<(), Error> {
         Ok(())
     }
+
+    /// Create a copy of this authentication.
+    fn detach(&self) -> Option<Arc<dyn AuthType>> {
+        Some(Arc::new(self.clone()))
+    }
 }
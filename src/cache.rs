@@ -14,23 +14,29 @@
 
 //! Internal service information cache.
 
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::error::Error as StdError;
 
-use log::debug;
-use reqwest::Url;
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use reqwest::{Method, Url};
 use tokio::sync::RwLock;
 
 use crate::protocol::ServiceInfo;
 use crate::services::ServiceType;
+use crate::url as url_utils;
 use crate::{client::AuthenticatedClient, ErrorKind};
-use crate::{EndpointFilters, Error};
+use crate::{AuthType, EndpointFilters, Error, Event};
 
 /// Service information cache.
 #[derive(Debug)]
 pub struct EndpointCache {
-    info: RwLock<HashMap<&'static str, ServiceInfo>>,
+    info: RwLock<HashMap<String, ServiceInfo>>,
     pub filters: EndpointFilters,
     pub overrides: HashMap<String, Url>,
+    pub hosts: HashMap<String, String>,
+    pub catalog_disabled: bool,
 }
 
 impl Clone for EndpointCache {
@@ -40,6 +46,8 @@ impl Clone for EndpointCache {
             info: RwLock::new(HashMap::new()),
             filters: self.filters.clone(),
             overrides: self.overrides.clone(),
+            hosts: self.hosts.clone(),
+            catalog_disabled: self.catalog_disabled,
         }
     }
 }
@@ -52,20 +60,45 @@ impl EndpointCache {
             info: RwLock::new(HashMap::new()),
             filters: EndpointFilters::default(),
             overrides: HashMap::new(),
+            hosts: HashMap::new(),
+            catalog_disabled: false,
         }
     }
 
     #[cfg(test)]
     pub fn new_with(service_type: &'static str, service_info: ServiceInfo) -> Self {
         let mut hm = HashMap::new();
-        let _ = hm.insert(service_type, service_info);
+        let _ = hm.insert(service_type.to_string(), service_info);
         EndpointCache {
             info: RwLock::new(hm),
             filters: EndpointFilters::default(),
             overrides: HashMap::new(),
+            hosts: HashMap::new(),
+            catalog_disabled: false,
         }
     }
 
+    /// Create a cache with the given information.
+    pub fn new_restored(
+        filters: EndpointFilters,
+        overrides: HashMap<String, Url>,
+        hosts: HashMap<String, String>,
+        info: HashMap<String, ServiceInfo>,
+    ) -> Self {
+        EndpointCache {
+            info: RwLock::new(info),
+            filters,
+            overrides,
+            hosts,
+            catalog_disabled: false,
+        }
+    }
+
+    /// A copy of the cached service information.
+    pub async fn service_info(&self) -> HashMap<String, ServiceInfo> {
+        self.info.read().await.clone()
+    }
+
     /// Clear the cache.
     #[inline]
     pub fn clear(&mut self) -> &mut Self {
@@ -73,37 +106,111 @@ impl EndpointCache {
         self
     }
 
+    /// Invalidate cached information for the service if the error suggests it is outdated.
+    ///
+    /// This happens on connection failures, HTTP 401 and HTTP 404 without an OpenStack error in
+    /// the body in response to a GET or PUT request to the service root or a version document
+    /// (which usually means that the endpoint has moved). On HTTP 401 the authentication is also
+    /// refreshed to receive an up-to-date catalog, unless it was renewed after the request started.
+    pub async fn invalidate_on_error<A: AuthType + ?Sized>(
+        &self,
+        client: &AuthenticatedClient<A>,
+        catalog_type: &str,
+        region: Option<&str>,
+        started: DateTime<Utc>,
+        error: &Error,
+    ) {
+        let key = cache_key(catalog_type, region);
+        let refresh_auth = match error.kind() {
+            ErrorKind::Unauthorized => true,
+            ErrorKind::ResourceNotFound if error.details().is_none() => {
+                let moved = self
+                    .info
+                    .read()
+                    .await
+                    .get(key.as_ref())
+                    .map(|info| is_root_request(info, error))
+                    .unwrap_or(false);
+                if !moved {
+                    return;
+                }
+                false
+            }
+            ErrorKind::ProtocolError if is_connection_failure(error) => false,
+            _ => return,
+        };
+
+        if self.info.write().await.remove(key.as_ref()).is_some() {
+            debug!(
+                "Invalidated cached information for service {} after {}",
+                catalog_type, error
+            );
+            client.emit(|| Event::EndpointInvalidated {
+                service_type: catalog_type.to_string(),
+                error: error.clone(),
+            });
+        }
+
+        if refresh_auth {
+            if let Err(e) = client.refresh_auth(started).await {
+                warn!(
+                    "Cannot refresh authentication after HTTP 401 from service {}: {}",
+                    catalog_type, e
+                );
+            }
+        }
+    }
+
     /// Ensure service info and return the cache.
-    pub async fn extract_service_info<Srv, F, T>(
+    ///
+    /// The region (if any) overrides the region from the filters.
+    pub async fn extract_service_info<Srv, F, T, A>(
         &self,
-        client: &AuthenticatedClient,
+        client: &AuthenticatedClient<A>,
         service: Srv,
+        region: Option<&str>,
         filter: F,
     ) -> Result<T, Error>
     where
         Srv: ServiceType + Send,
         F: FnOnce(&ServiceInfo) -> T + Send,
         T: Send,
+        A: AuthType + ?Sized,
     {
         let catalog_type = service.catalog_type();
-        if let Some(info) = self.info.read().await.get(catalog_type) {
+        let key = cache_key(catalog_type, region);
+        if let Some(info) = self.info.read().await.get(key.as_ref()) {
             return Ok(filter(info));
         }
 
-        debug!(
-            "No cached information for service {}, fetching",
-            catalog_type
-        );
+        debug!("No cached information for service {}, fetching", key);
 
         let mut lock = self.info.write().await;
         // Additonal check in case another thread has updated the token while we were waiting for
         // the write lock.
-        Ok(if let Some(info) = lock.get(catalog_type) {
+        Ok(if let Some(info) = lock.get(key.as_ref()) {
             filter(info)
         } else {
-            let ep = match self.overrides.get(catalog_type) {
-                Some(found) => found.clone(),
-                None => client.get_endpoint(catalog_type, &self.filters).await?,
+            let ep = match (self.overrides.get(catalog_type), region) {
+                (Some(found), _) => found.clone(),
+                (None, _) if self.catalog_disabled => {
+                    return Err(Error::new(
+                        ErrorKind::EndpointNotFound,
+                        format!(
+                            "Service {} has no endpoint override and the catalog is disabled",
+                            catalog_type
+                        ),
+                    ));
+                }
+                (None, Some(region)) => {
+                    let filters = EndpointFilters {
+                        region: Some(region.to_string()),
+                        fallback_regions: Vec::new(),
+                        ..self.filters.clone()
+                    };
+                    client.get_endpoint(catalog_type, &filters).await?
+                }
+                (None, None) => client.get_endpoint(catalog_type, &self.filters).await?,
             };
             if ep.cannot_be_a_base() || !ep.has_host() {
                 return Err(Error::new(
@@ -111,22 +218,67 @@ impl EndpointCache {
                     format!("Invalid URL {} received for service {}", ep, catalog_type),
                 ));
             }
-            let info = ServiceInfo::fetch(service, ep, client).await?;
+            let host = self.hosts.get(catalog_type).map(String::as_str);
+            let info = ServiceInfo::fetch(service, ep, host, client).await?;
             let value = filter(&info);
-            let _ = lock.insert(catalog_type, info);
+            let _ = lock.insert(key.into_owned(), info);
             value
         })
     }
 }
 
+/// Key of the cached information for a service in a region.
+fn cache_key<'s>(catalog_type: &'s str, region: Option<&str>) -> Cow<'s, str> {
+    match region {
+        Some(region) => Cow::Owned(format!("{}@{}", catalog_type, region)),
+        None => Cow::Borrowed(catalog_type),
+    }
+}
+
+fn is_connection_failure(error: &Error) -> bool {
+    error
+        .source()
+        .and_then(|e| e.downcast_ref::<reqwest::Error>())
+        .map(|e| e.is_connect())
+        .unwrap_or(false)
+}
+
+/// Whether the failed request was a GET or PUT to the service root or a version document.
+///
+/// Other requests (including HEAD) return HTTP 404 for missing resources all the time.
+fn is_root_request(info: &ServiceInfo, error: &Error) -> bool {
+    match error.method() {
+        Some(&Method::GET) | Some(&Method::PUT) => {}
+        _ => return false,
+    }
+    let url = match error.url() {
+        Some(url) => url,
+        None => return false,
+    };
+    let root = url_utils::strip_version(info.root_url.clone());
+    match url.path().strip_prefix(root.path().trim_end_matches('/')) {
+        Some(rest) => {
+            let rest = rest.trim_matches('/');
+            rest.is_empty() || (!rest.contains('/') && url_utils::is_version(rest))
+        }
+        None => false,
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use reqwest::Url;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use chrono::{DateTime, FixedOffset, Utc};
+    use reqwest::{Client, Method, RequestBuilder, Url};
 
     use crate::client::AuthenticatedClient;
+    use crate::errordetails;
     use crate::protocol::ServiceInfo;
     use crate::services::COMPUTE;
-    use crate::ErrorKind;
+    use crate::{AuthType, EndpointFilters, Error, ErrorKind};
 
     use super::EndpointCache;
 
@@ -144,7 +296,7 @@ mod test {
         };
         let cache = EndpointCache::new_with("compute", sinfo.clone());
         let sinfo2 = cache
-            .extract_service_info(&client, COMPUTE, |s| s.clone())
+            .extract_service_info(&client, COMPUTE, None, |s| s.clone())
             .await
             .unwrap();
         assert_eq!(sinfo, sinfo2);
@@ -155,10 +307,142 @@ mod test {
         let client = AuthenticatedClient::new_noauth("unix:/run/foo.socket").await;
         let cache = EndpointCache::new();
         let err = cache
-            .extract_service_info(&client, COMPUTE, |s| s.clone())
+            .extract_service_info(&client, COMPUTE, None, |s| s.clone())
             .await
             .err()
             .unwrap();
         assert_eq!(err.kind(), ErrorKind::InvalidResponse);
     }
+
+    #[tokio::test]
+    async fn test_catalog_disabled() {
+        let client = AuthenticatedClient::new_noauth("http://localhost").await;
+        let mut cache = EndpointCache::new();
+        cache.catalog_disabled = true;
+        let err = cache
+            .extract_service_info(&client, COMPUTE, None, |s| s.clone())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::EndpointNotFound);
+        assert!(err.to_string().contains("compute"));
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_on_error() {
+        let client = AuthenticatedClient::new_noauth("http://localhost").await;
+        let sinfo = ServiceInfo {
+            root_url: Url::parse("http://localhost/compute/v2.1").unwrap(),
+            major_version: None,
+            current_version: None,
+            minimum_version: None,
+        };
+        let cache = EndpointCache::new_with("compute", sinfo.clone());
+        let started = Utc::now();
+        let url = |path| {
+            Url::parse("http://localhost/compute/")
+                .unwrap()
+                .join(path)
+                .unwrap()
+        };
+
+        let not_found = Error::new(ErrorKind::ResourceNotFound, "Server not found")
+            .with_details(
+                errordetails::parse(r#"{"itemNotFound": {"message": "Not found"}}"#).unwrap(),
+            )
+            .with_request(Method::GET, &url("v2.1"));
+        cache
+            .invalidate_on_error(&client, "compute", None, started, &not_found)
+            .await;
+        let conflict = Error::new(ErrorKind::Conflict, "Conflict");
+        cache
+            .invalidate_on_error(&client, "compute", None, started, &conflict)
+            .await;
+        let missing = Error::new(ErrorKind::ResourceNotFound, "Not Found")
+            .with_request(Method::GET, &url("v2.1/servers/1234"));
+        cache
+            .invalidate_on_error(&client, "compute", None, started, &missing)
+            .await;
+        let head = Error::new(ErrorKind::ResourceNotFound, "Not Found")
+            .with_request(Method::HEAD, &url("v2.1"));
+        cache
+            .invalidate_on_error(&client, "compute", None, started, &head)
+            .await;
+        assert!(cache.info.read().await.contains_key("compute"));
+
+        for path in &["", "v2.1", "v2.1/"] {
+            let cache = EndpointCache::new_with("compute", sinfo.clone());
+            let moved = Error::new(ErrorKind::ResourceNotFound, "Not Found")
+                .with_request(Method::GET, &url(path));
+            cache
+                .invalidate_on_error(&client, "compute", None, started, &moved)
+                .await;
+            assert!(!cache.info.read().await.contains_key("compute"));
+        }
+
+        let cache = EndpointCache::new_with("compute", sinfo);
+        let unauthorized = Error::new(ErrorKind::Unauthorized, "Unauthorized");
+        cache
+            .invalidate_on_error(&client, "compute", None, started, &unauthorized)
+            .await;
+        assert!(!cache.info.read().await.contains_key("compute"));
+    }
+
+    #[derive(Debug, Default)]
+    struct CountingAuth {
+        issued_at: Mutex<Option<DateTime<Utc>>>,
+        refreshed: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl AuthType for CountingAuth {
+        async fn authenticate(
+            &self,
+            _client: &Client,
+            request: RequestBuilder,
+        ) -> Result<RequestBuilder, Error> {
+            Ok(request)
+        }
+
+        async fn get_endpoint(
+            &self,
+            _client: &Client,
+            _service_type: &str,
+            _filters: &EndpointFilters,
+        ) -> Result<Url, Error> {
+            Ok(Url::parse("http://localhost").unwrap())
+        }
+
+        async fn refresh(&self, _client: &Client) -> Result<(), Error> {
+            let _ = self.refreshed.fetch_add(1, Ordering::SeqCst);
+            *self.issued_at.lock().unwrap() = Some(Utc::now());
+            Ok(())
+        }
+
+        fn token_issued_at(&self) -> Option<DateTime<FixedOffset>> {
+            self.issued_at.lock().unwrap().map(From::from)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized_refresh() {
+        let before = Utc::now();
+        let auth = CountingAuth::default();
+        let refreshed = Arc::clone(&auth.refreshed);
+        let client = AuthenticatedClient::new(Client::new(), auth).await.unwrap();
+        assert_eq!(refreshed.load(Ordering::SeqCst), 1);
+        let cache = EndpointCache::new();
+        let unauthorized = Error::new(ErrorKind::Unauthorized, "Unauthorized");
+
+        // The token was renewed after the request started.
+        cache
+            .invalidate_on_error(&client, "compute", None, before, &unauthorized)
+            .await;
+        assert_eq!(refreshed.load(Ordering::SeqCst), 1);
+
+        cache
+            .invalidate_on_error(&client, "compute", None, Utc::now(), &unauthorized)
+            .await;
+        assert_eq!(refreshed.load(Ordering::SeqCst), 2);
+    }
 }
@@ -0,0 +1,542 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recording and replaying of HTTP interactions.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use log::{debug, trace};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Method, Request, Response, ResponseBuilderExt, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::client::Transport;
+use super::{Error, ErrorKind};
+
+/// Placeholder for redacted values.
+const REDACTED: &str = "<redacted>";
+
+/// Headers that carry credentials.
+const SENSITIVE_HEADERS: &[&str] = &[
+    "authorization",
+    "cookie",
+    "proxy-authorization",
+    "set-cookie",
+    "x-auth-token",
+    "x-service-token",
+    "x-subject-token",
+];
+
+/// JSON fields that carry credentials.
+const SENSITIVE_FIELDS: &[&str] = &["passcode", "password", "secret"];
+
+/// JSON fields that are credentials themselves or carry them in `id` (e.g. `token` in token
+/// requests and in Identity v2 responses).
+const SENSITIVE_OBJECTS: &[&str] = &["token"];
+
+/// Body of a request or a response.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(untagged)]
+enum RecordedBody {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl RecordedBody {
+    fn new(data: Vec<u8>) -> RecordedBody {
+        match String::from_utf8(data) {
+            Ok(text) => RecordedBody::Text(text),
+            Err(e) => RecordedBody::Binary(e.into_bytes()),
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            RecordedBody::Text(text) => text.into_bytes(),
+            RecordedBody::Binary(data) => data,
+        }
+    }
+}
+
+/// A recorded request and its response.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+struct Interaction {
+    #[serde(with = "method_serde")]
+    method: Method,
+    url: Url,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    request_headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request_body: Option<RecordedBody>,
+    status: u16,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<String, String>,
+    body: RecordedBody,
+}
+
+mod method_serde {
+    use std::str::FromStr;
+
+    use reqwest::Method;
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &Method, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(value.as_str())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Method, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Method::from_str(&value).map_err(D::Error::custom)
+    }
+}
+
+/// Convert headers into a sorted map, redacting credentials.
+fn record_headers(headers: &HeaderMap) -> BTreeMap<String, String> {
+    let mut result = BTreeMap::new();
+    for (name, value) in headers {
+        let value = if value.is_sensitive() || SENSITIVE_HEADERS.contains(&name.as_str()) {
+            REDACTED.to_string()
+        } else {
+            String::from_utf8_lossy(value.as_bytes()).into_owned()
+        };
+        let _ = result
+            .entry(name.as_str().to_string())
+            .and_modify(|existing: &mut String| {
+                existing.push_str(", ");
+                existing.push_str(&value);
+            })
+            .or_insert(value);
+    }
+    result
+}
+
+/// Redact credentials in a JSON value.
+fn redact_json(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, item) in map.iter_mut() {
+                let key = key.to_lowercase();
+                let sensitive_object = SENSITIVE_OBJECTS.contains(&key.as_str());
+                if (SENSITIVE_FIELDS.contains(&key.as_str()) && !item.is_null())
+                    || (sensitive_object && item.is_string())
+                {
+                    *item = Value::String(REDACTED.into());
+                } else {
+                    if sensitive_object {
+                        if let Some(id) = item.get_mut("id").filter(|id| !id.is_null()) {
+                            *id = Value::String(REDACTED.into());
+                        }
+                    }
+                    redact_json(item);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_json),
+        _ => {}
+    }
+}
+
+/// Record a body, redacting credentials in JSON.
+fn record_body(data: &[u8]) -> RecordedBody {
+    match serde_json::from_slice::<Value>(data) {
+        Ok(mut value) if value.is_object() || value.is_array() => {
+            redact_json(&mut value);
+            RecordedBody::Text(value.to_string())
+        }
+        _ => RecordedBody::new(data.to_vec()),
+    }
+}
+
+fn build_response(
+    url: Url,
+    status: u16,
+    headers: &BTreeMap<String, String>,
+    body: Vec<u8>,
+) -> Result<Response, Error> {
+    let status = StatusCode::from_u16(status).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid recorded status: {}", e),
+        )
+    })?;
+    let mut builder = http::Response::builder().status(status).url(url);
+    if let Some(result) = builder.headers_mut() {
+        for (name, value) in headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                let _ = result.insert(name, value);
+            }
+        }
+    }
+    Ok(builder
+        .body(body)
+        .expect("Response parts are always valid")
+        .into())
+}
+
+/// Transport that records all requests and responses to a file.
+///
+/// Each interaction is appended to the file as a line of JSON once its response is received
+/// (the file is truncated on the first one), so that it can be used by
+/// [ReplayTransport](struct.ReplayTransport.html) to run the same code without a cloud:
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use osauth::testsupport::RecordingTransport;
+///
+/// let session = osauth::Session::from_env()
+///     .await?
+///     .with_transport(RecordingTransport::new("servers.json"));
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+///
+/// Credentials in headers (e.g. `X-Auth-Token`) and in JSON bodies (e.g. `password` fields and
+/// token IDs) are redacted before recording. Response bodies are read in full, streaming requests
+/// are recorded without bodies. Requests made by the authentication type itself do not go
+/// through the transport and are not recorded.
+#[derive(Debug)]
+pub struct RecordingTransport {
+    path: PathBuf,
+    inner: Option<Arc<dyn Transport>>,
+    file: Arc<Mutex<Option<File>>>,
+}
+
+impl RecordingTransport {
+    /// Record interactions to the given file using the inner `reqwest` client.
+    pub fn new<P: Into<PathBuf>>(path: P) -> RecordingTransport {
+        RecordingTransport {
+            path: path.into(),
+            inner: None,
+            file: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Record interactions of another transport.
+    pub fn wrap<P: Into<PathBuf>, T: Transport + 'static>(path: P, inner: T) -> RecordingTransport {
+        RecordingTransport {
+            inner: Some(Arc::new(inner)),
+            ..RecordingTransport::new(path)
+        }
+    }
+
+    /// Path to the file with the recording.
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    #[allow(clippy::io_other_error)] // io::Error::other requires a newer Rust
+    async fn store(&self, interaction: Interaction) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(&interaction)?;
+        line.push(b'\n');
+        let path = self.path.clone();
+        let file = Arc::clone(&self.file);
+        tokio::task::spawn_blocking(move || {
+            let mut file = file.lock().expect("Recording lock is poisoned");
+            if file.is_none() {
+                *file = Some(File::create(&path)?);
+            }
+            file.as_mut()
+                .expect("Recording file is open")
+                .write_all(&line)
+        })
+        .await
+        .unwrap_or_else(|e| Err(io::Error::new(io::ErrorKind::Other, e)))
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::OperationFailed,
+                format!("Cannot write recording to {}: {}", self.path.display(), e),
+            )
+            .with_source(e)
+        })
+    }
+}
+
+#[async_trait]
+impl Transport for RecordingTransport {
+    async fn execute(&self, client: &Client, request: Request) -> Result<Response, Error> {
+        let method = request.method().clone();
+        let url = request.url().clone();
+        let request_headers = record_headers(request.headers());
+        let request_body = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .map(record_body);
+
+        let response = match self.inner {
+            Some(ref inner) => inner.execute(client, request).await?,
+            None => client.execute(request).await?,
+        };
+        let status = response.status().as_u16();
+        let headers = record_headers(response.headers());
+        let original_headers = response
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.as_str().to_string(),
+                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                )
+            })
+            .collect();
+        let body = response.bytes().await?.to_vec();
+
+        trace!("Recording HTTP {} request to {}", method, url);
+        self.store(Interaction {
+            method,
+            url: url.clone(),
+            request_headers,
+            request_body,
+            status,
+            headers,
+            body: record_body(&body),
+        })
+        .await?;
+        build_response(url, status, &original_headers, body)
+    }
+}
+
+/// Transport that replays interactions recorded by
+/// [RecordingTransport](struct.RecordingTransport.html).
+///
+/// Each request is answered with the first not yet replayed interaction with the same method and
+/// URL, so requests with the same URL are answered in the order of recording. Requests without
+/// a recorded interaction fail with `InvalidInput`.
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use osauth::testsupport::ReplayTransport;
+///
+/// let transport = ReplayTransport::load("servers.json")?;
+/// let session = osauth::Session::new(osauth::NoAuth::new("http://cloud.local/compute")?)
+///     .await?
+///     .with_transport(transport);
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[derive(Debug)]
+pub struct ReplayTransport {
+    interactions: Mutex<Vec<Interaction>>,
+}
+
+impl ReplayTransport {
+    /// Load interactions from a file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ReplayTransport, Error> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidConfig,
+                format!("Cannot read recording from {}: {}", path.display(), e),
+            )
+            .with_source(e)
+        })?;
+        let interactions = data
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                serde_json::from_str::<Interaction>(line).map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidConfig,
+                        format!(
+                            "Invalid recording in {} on line {}: {}",
+                            path.display(),
+                            index + 1,
+                            e
+                        ),
+                    )
+                    .with_source(e)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        debug!(
+            "Loaded {} recorded interactions from {}",
+            interactions.len(),
+            path.display()
+        );
+        Ok(ReplayTransport {
+            interactions: Mutex::new(interactions),
+        })
+    }
+
+    /// Number of interactions that have not been replayed yet.
+    pub fn remaining(&self) -> usize {
+        self.interactions
+            .lock()
+            .expect("Replay lock is poisoned")
+            .len()
+    }
+}
+
+#[async_trait]
+impl Transport for ReplayTransport {
+    async fn execute(&self, _client: &Client, request: Request) -> Result<Response, Error> {
+        let interaction = {
+            let mut interactions = self.interactions.lock().expect("Replay lock is poisoned");
+            let index = interactions
+                .iter()
+                .position(|item| item.method == request.method() && &item.url == request.url())
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "No recorded interaction for HTTP {} request to {}",
+                            request.method(),
+                            request.url()
+                        ),
+                    )
+                })?;
+            interactions.remove(index)
+        };
+        trace!(
+            "Replaying HTTP {} request to {}",
+            interaction.method,
+            interaction.url
+        );
+        build_response(
+            interaction.url,
+            interaction.status,
+            &interaction.headers,
+            interaction.body.into_bytes(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use async_trait::async_trait;
+    use reqwest::{Client, Method, Request, Response, Url};
+    use serde_json::{json, Value};
+
+    use super::{record_body, RecordedBody, RecordingTransport, ReplayTransport};
+    use crate::client::{AuthenticatedClient, Transport};
+    use crate::{Error, ErrorKind};
+
+    #[derive(Debug)]
+    struct FakeTransport;
+
+    #[async_trait]
+    impl Transport for FakeTransport {
+        async fn execute(&self, _client: &Client, _request: Request) -> Result<Response, Error> {
+            Ok(http::Response::builder()
+                .header("x-subject-token", "secret")
+                .body(r#"{"answer": 42}"#)
+                .unwrap()
+                .into())
+        }
+    }
+
+    #[test]
+    fn test_record_body() {
+        let body = record_body(br#"{"user": {"name": "admin", "password": "pa$$w0rd"}}"#);
+        match body {
+            RecordedBody::Text(text) => {
+                let value: Value = serde_json::from_str(&text).unwrap();
+                assert_eq!(
+                    value,
+                    json!({"user": {"name": "admin", "password": "<redacted>"}})
+                );
+            }
+            RecordedBody::Binary(..) => panic!("Unexpected binary body"),
+        }
+
+        let body = record_body(
+            br#"{"auth": {"identity": {"methods": ["token"], "token": {"id": "abcd"}}}}"#,
+        );
+        assert_eq!(
+            body,
+            RecordedBody::Text(
+                json!({"auth": {"identity": {"methods": ["token"], "token": {"id": "<redacted>"}}}})
+                    .to_string()
+            )
+        );
+
+        let body = record_body(
+            br#"{"access": {"token": {"id": "abcd", "expires": "2099-01-01T00:00:00Z"}}}"#,
+        );
+        assert_eq!(
+            body,
+            RecordedBody::Text(
+                json!({"access": {"token": {"id": "<redacted>", "expires": "2099-01-01T00:00:00Z"}}})
+                    .to_string()
+            )
+        );
+
+        assert_eq!(
+            record_body(br#"{"identity": {"token": "abcd"}}"#),
+            RecordedBody::Text(json!({"identity": {"token": "<redacted>"}}).to_string())
+        );
+
+        assert_eq!(
+            record_body(b"plain text"),
+            RecordedBody::Text("plain text".into())
+        );
+        assert_eq!(
+            record_body(&[0xff, 0x00]),
+            RecordedBody::Binary(vec![0xff, 0x00])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_record_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording.json");
+        let url = Url::parse("http://127.0.0.1:1/answer").unwrap();
+
+        let mut client = AuthenticatedClient::new_noauth("http://127.0.0.1:1").await;
+        client.set_transport(RecordingTransport::wrap(&path, FakeTransport));
+        for _ in 0..2 {
+            let value: Value = client
+                .request(Method::GET, url.clone())
+                .header("x-auth-token", "secret")
+                .fetch()
+                .await
+                .unwrap();
+            assert_eq!(value["answer"], 42);
+        }
+
+        let recording = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(recording.lines().count(), 2, "{}", recording);
+        assert!(!recording.contains("secret"), "{}", recording);
+        assert!(recording.contains("<redacted>"), "{}", recording);
+
+        let transport = ReplayTransport::load(&path).unwrap();
+        assert_eq!(transport.remaining(), 2);
+        let mut client = AuthenticatedClient::new_noauth("http://127.0.0.1:1").await;
+        client.set_transport(transport);
+        for _ in 0..2 {
+            let value: Value = client
+                .request(Method::GET, url.clone())
+                .fetch()
+                .await
+                .unwrap();
+            assert_eq!(value["answer"], 42);
+        }
+
+        let err = client.request(Method::GET, url).send().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
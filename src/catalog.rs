@@ -14,23 +14,152 @@
 
 //! Abstraction over a service catalog.
 
-use log::{debug, error};
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::fmt;
+
+use log::{debug, error, warn};
 use reqwest::Url;
 
 use super::identity::protocol;
 use super::{EndpointFilters, Error, ErrorKind};
 
+/// An endpoint of a service in the catalog.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CatalogEndpoint {
+    /// Endpoint interface (e.g. `public`).
+    pub interface: String,
+    /// Region of the endpoint.
+    pub region: String,
+    /// Endpoint URL as returned by the Identity service.
+    pub url: String,
+}
+
+/// A service record in the catalog.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CatalogEntry {
+    /// Service type (e.g. `compute`).
+    pub service_type: String,
+    /// Service name (if provided by the catalog).
+    pub service_name: Option<String>,
+    /// Endpoints of this service.
+    pub endpoints: Vec<CatalogEndpoint>,
+}
+
+impl CatalogEntry {
+    /// Regions in which this service has endpoints (sorted, without duplicates).
+    #[inline]
+    pub fn regions(&self) -> Vec<String> {
+        regions_of(&self.endpoints)
+    }
+}
+
+impl fmt::Display for CatalogEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} in {}", self.interface, self.region)
+    }
+}
+
+/// Regions present in the catalog entries (sorted, without duplicates).
+pub(crate) fn regions<'e, I>(entries: I) -> Vec<String>
+where
+    I: IntoIterator<Item = &'e CatalogEntry>,
+{
+    regions_of(entries.into_iter().flat_map(|entry| entry.endpoints.iter()))
+}
+
+/// Regions of the endpoints (sorted, without duplicates).
+pub(crate) fn regions_of<'e, I>(endpoints: I) -> Vec<String>
+where
+    I: IntoIterator<Item = &'e CatalogEndpoint>,
+{
+    endpoints
+        .into_iter()
+        .map(|endp| endp.region.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
 /// Abstraction over a service catalog.
 ///
 /// In standalone case only one URL is returned for any service.
 #[derive(Debug, Clone)]
 pub struct ServiceCatalog {
     inner: Vec<protocol::CatalogRecord>,
+    project_id: Option<String>,
+}
+
+/// Legacy placeholders for the project ID that may be present in endpoint URLs.
+const PROJECT_ID_PLACEHOLDERS: &[&str] = &[
+    "%(tenant_id)s",
+    "%(project_id)s",
+    "$(tenant_id)s",
+    "$(project_id)s",
+];
+
+/// Replace legacy project ID placeholders in the URL.
+fn substitute_project_id<'u>(url: &'u str, project_id: Option<&str>) -> Cow<'u, str> {
+    if !PROJECT_ID_PLACEHOLDERS.iter().any(|x| url.contains(x)) {
+        return Cow::Borrowed(url);
+    }
+
+    if let Some(project_id) = project_id {
+        let mut result = url.to_string();
+        for placeholder in PROJECT_ID_PLACEHOLDERS {
+            result = result.replace(placeholder, project_id);
+        }
+        Cow::Owned(result)
+    } else {
+        warn!(
+            "Endpoint {} contains a project ID placeholder, but the token is not project scoped",
+            url
+        );
+        Cow::Borrowed(url)
+    }
 }
 
 impl ServiceCatalog {
     pub(crate) fn new(catalog: Vec<protocol::CatalogRecord>) -> ServiceCatalog {
-        ServiceCatalog { inner: catalog }
+        ServiceCatalog {
+            inner: catalog,
+            project_id: None,
+        }
+    }
+
+    /// Use the project ID to substitute placeholders in endpoint URLs.
+    #[inline]
+    pub(crate) fn with_project_id(mut self, project_id: Option<String>) -> ServiceCatalog {
+        self.project_id = project_id;
+        self
+    }
+
+    /// All records in the catalog.
+    pub fn entries(&self) -> Vec<CatalogEntry> {
+        self.inner
+            .iter()
+            .map(|record| CatalogEntry {
+                service_type: record.service_type.clone(),
+                service_name: record.name.clone(),
+                endpoints: self.endpoints(&record.endpoints),
+            })
+            .collect()
+    }
+
+    fn endpoints<'e, I>(&self, endpoints: I) -> Vec<CatalogEndpoint>
+    where
+        I: IntoIterator<Item = &'e protocol::Endpoint>,
+    {
+        endpoints
+            .into_iter()
+            .map(|endp| CatalogEndpoint {
+                interface: endp.interface.clone(),
+                region: endp.region.clone(),
+                url: substitute_project_id(&endp.url, self.project_id.as_deref()).into_owned(),
+            })
+            .collect()
     }
 
     /// Find an endpoint in the catalog.
@@ -39,30 +168,111 @@ impl ServiceCatalog {
         service_type: &str,
         filters: &EndpointFilters,
     ) -> Result<Url, Error> {
-        let svc = match self.inner.iter().find(|x| x.service_type == *service_type) {
+        if self.inner.is_empty() {
+            return Err(Error::new(
+                ErrorKind::CatalogMissing,
+                "The service catalog is empty, is the token scoped?",
+            ));
+        }
+
+        let svc = match self.inner.iter().find(|x| {
+            x.service_type == *service_type
+                && match filters.service_name {
+                    Some(ref name) => x.name.as_ref() == Some(name),
+                    None => true,
+                }
+        }) {
             Some(s) => s,
             None => return Err(Error::new_endpoint_not_found(service_type)),
         };
 
         let mut endpoints: Vec<_> = svc.endpoints.iter().filter(|x| filters.check(x)).collect();
-        endpoints
-            // NOTE(dtantsur): because of the filter above unwrap never fails
-            .sort_unstable_by_key(|x| filters.interfaces.find(&x.interface).unwrap());
+        if endpoints.is_empty() {
+            let candidates = self.endpoints(&svc.endpoints);
+            let available = if candidates.is_empty() {
+                "none".to_string()
+            } else {
+                candidates
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            return Err(Error::new(
+                ErrorKind::EndpointNotFound,
+                format!(
+                    "Endpoint for service {} was not found for the requested region and \
+                     interfaces, available endpoints: {}",
+                    service_type, available
+                ),
+            )
+            .with_candidates(candidates));
+        }
+
+        // NOTE(dtantsur): because of the filter above unwraps never fail
+        endpoints.sort_by_key(|x| {
+            (
+                filters.find_region(&x.region).unwrap(),
+                filters.interfaces.find(&x.interface).unwrap(),
+            )
+        });
+
+        if filters.region.is_none() {
+            let interface = filters.interfaces.find(&endpoints[0].interface);
+            let regions = regions_of(
+                &self.endpoints(
+                    endpoints
+                        .iter()
+                        .filter(|x| filters.interfaces.find(&x.interface) == interface)
+                        .copied(),
+                ),
+            );
+            if regions.len() > 1 {
+                debug!(
+                    "Service {} is available in several regions ({}), using {}; \
+                     set a region to choose another one",
+                    service_type,
+                    regions.join(", "),
+                    endpoints[0].region
+                );
+            } else if regions_of(&self.endpoints(&svc.endpoints)).len() == 1 {
+                debug!(
+                    "No region requested, using the only region {} of service {}",
+                    endpoints[0].region, service_type
+                );
+            }
+        }
+
         endpoints
             .into_iter()
             .next()
             .ok_or_else(|| Error::new_endpoint_not_found(service_type))
             .and_then(|endp| {
                 debug!("Received {:?} for {}", endp, service_type);
-                Url::parse(&endp.url).map_err(|e| {
+                if filters.interfaces.find(&endp.interface) != Some(0) {
+                    debug!(
+                        "Preferred interface {:?} is not available for {}, using {}",
+                        filters.interfaces.first(),
+                        service_type,
+                        endp.interface
+                    );
+                }
+                if filters.find_region(&endp.region) != Some(0) {
+                    debug!(
+                        "Region {:?} is not available for {}, falling back to {}",
+                        filters.region, service_type, endp.region
+                    );
+                }
+                let url = substitute_project_id(&endp.url, self.project_id.as_deref());
+                Url::parse(&url).map_err(|e| {
                     error!(
                         "Invalid URL {} received from service catalog for service \
                      '{}', filters {:?}: {}",
-                        endp.url, service_type, filters, e
+                        url, service_type, filters, e
                     );
                     Error::new(
                         ErrorKind::InvalidResponse,
-                        format!("Invalid URL {} for {} - {}", endp.url, service_type, e),
+                        format!("Invalid URL {} for {} - {}", url, service_type, e),
                     )
                 })
             })
@@ -77,11 +287,12 @@ pub mod test {
     use crate::{EndpointFilters, Error, ErrorKind, InterfaceType, ValidInterfaces};
     use InterfaceType::*;
 
-    use super::ServiceCatalog;
+    use super::{regions, substitute_project_id, CatalogEndpoint, ServiceCatalog};
 
     fn demo_service1() -> CatalogRecord {
         CatalogRecord {
             service_type: String::from("identity"),
+            name: Some(String::from("keystone")),
             endpoints: vec![
                 Endpoint {
                     interface: String::from("public"),
@@ -105,6 +316,7 @@ pub mod test {
     fn demo_service2() -> CatalogRecord {
         CatalogRecord {
             service_type: String::from("baremetal"),
+            name: None,
             endpoints: vec![
                 Endpoint {
                     interface: String::from("public"),
@@ -133,6 +345,8 @@ pub mod test {
         let filters = EndpointFilters {
             interfaces: ValidInterfaces::one(interface_type),
             region: region.map(|x| x.to_string()),
+            fallback_regions: Vec::new(),
+            service_name: None,
         };
         cat.find_endpoint(service_type, &filters)
     }
@@ -151,6 +365,16 @@ pub mod test {
         assert_eq!(e3.as_str(), "https://host.one/baremetal");
     }
 
+    #[test]
+    fn test_find_endpoint_empty_catalog() {
+        let cat = ServiceCatalog::new(Vec::new());
+        let err = cat
+            .find_endpoint("identity", &EndpointFilters::default())
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::CatalogMissing);
+        assert!(err.is_auth_layer_failure());
+    }
+
     #[test]
     fn test_find_endpoint_from_many() {
         let cat = demo_catalog();
@@ -183,6 +407,162 @@ pub mod test {
         assert_eq!(e3.as_str(), "https://host.two:6385/");
     }
 
+    fn demo_service3() -> CatalogRecord {
+        CatalogRecord {
+            service_type: String::from("object-store"),
+            name: Some(String::from("swift")),
+            endpoints: vec![Endpoint {
+                interface: String::from("public"),
+                region: String::from("RegionOne"),
+                url: String::from("https://host.one/swift"),
+            }],
+        }
+    }
+
+    fn demo_service4() -> CatalogRecord {
+        CatalogRecord {
+            service_type: String::from("object-store"),
+            name: Some(String::from("ceph")),
+            endpoints: vec![Endpoint {
+                interface: String::from("public"),
+                region: String::from("RegionOne"),
+                url: String::from("https://host.one/ceph"),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_find_endpoint_single_region() {
+        let cat = ServiceCatalog::new(vec![demo_service3()]);
+        let filters = EndpointFilters::default().with_interfaces(vec![Internal, Public]);
+        let e1 = cat.find_endpoint("object-store", &filters).unwrap();
+        assert_eq!(e1.as_str(), "https://host.one/swift");
+
+        let e2 = find_endpoint(&cat, "object-store", Public, Some("RegionOne")).unwrap();
+        assert_eq!(e2, e1);
+        assert_not_found(find_endpoint(
+            &cat,
+            "object-store",
+            Public,
+            Some("RegionTwo"),
+        ));
+    }
+
+    #[test]
+    fn test_find_endpoint_with_service_name() {
+        let cat = ServiceCatalog::new(vec![demo_service3(), demo_service4()]);
+
+        let e1 = cat
+            .find_endpoint("object-store", &EndpointFilters::default())
+            .unwrap();
+        assert_eq!(e1.as_str(), "https://host.one/swift");
+
+        let f2 = EndpointFilters::default().with_service_name("ceph");
+        let e2 = cat.find_endpoint("object-store", &f2).unwrap();
+        assert_eq!(e2.as_str(), "https://host.one/ceph");
+
+        let f3 = EndpointFilters::default().with_service_name("banana");
+        assert_not_found(cat.find_endpoint("object-store", &f3));
+    }
+
+    #[test]
+    fn test_entries() {
+        let cat = demo_catalog();
+        let entries = cat.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].service_type, "identity");
+        assert_eq!(entries[0].service_name.as_deref(), Some("keystone"));
+        assert_eq!(entries[0].endpoints.len(), 3);
+        assert_eq!(
+            entries[1].endpoints[1],
+            CatalogEndpoint {
+                interface: String::from("public"),
+                region: String::from("RegionTwo"),
+                url: String::from("https://host.two:6385"),
+            }
+        );
+    }
+
+    #[test]
+    fn test_find_endpoint_with_fallback_regions() {
+        let cat = demo_catalog();
+
+        let f1 = EndpointFilters::default()
+            .with_region("RegionThree")
+            .with_fallback_regions(vec!["RegionTwo", "RegionOne"]);
+        let e1 = cat.find_endpoint("identity", &f1).unwrap();
+        assert_eq!(e1.as_str(), "https://host.two:5000/");
+
+        let f2 = EndpointFilters::default()
+            .with_interfaces(Internal)
+            .with_region("RegionTwo")
+            .with_fallback_regions(vec!["RegionOne"]);
+        let e2 = cat.find_endpoint("identity", &f2).unwrap();
+        assert_eq!(e2.as_str(), "http://192.168.22.1/identity");
+
+        let f3 = EndpointFilters::default()
+            .with_interfaces(vec![Internal, Public])
+            .with_region("RegionTwo")
+            .with_fallback_regions(vec!["RegionOne"]);
+        let e3 = cat.find_endpoint("identity", &f3).unwrap();
+        assert_eq!(e3.as_str(), "https://host.two:5000/");
+
+        let f4 = EndpointFilters::default()
+            .with_region("RegionThree")
+            .with_fallback_regions(vec!["RegionFour"]);
+        assert_not_found(cat.find_endpoint("identity", &f4));
+    }
+
+    #[test]
+    fn test_find_endpoint_with_project_id() {
+        let record = CatalogRecord {
+            service_type: String::from("object-store"),
+            name: None,
+            endpoints: vec![Endpoint {
+                interface: String::from("public"),
+                region: String::from("RegionOne"),
+                url: String::from("https://host.one/swift/v1/AUTH_%(tenant_id)s"),
+            }],
+        };
+        let cat = ServiceCatalog::new(vec![record]).with_project_id(Some("abcd".into()));
+
+        let e1 = cat
+            .find_endpoint("object-store", &EndpointFilters::default())
+            .unwrap();
+        assert_eq!(e1.as_str(), "https://host.one/swift/v1/AUTH_abcd");
+
+        let entries = cat.entries();
+        assert_eq!(
+            entries[0].endpoints[0].url,
+            "https://host.one/swift/v1/AUTH_abcd"
+        );
+    }
+
+    #[test]
+    fn test_substitute_project_id() {
+        assert_eq!(
+            substitute_project_id("https://host/v2/$(project_id)s", Some("abcd")),
+            "https://host/v2/abcd"
+        );
+        assert_eq!(
+            substitute_project_id("https://host/v2/%(project_id)s", None),
+            "https://host/v2/%(project_id)s"
+        );
+        assert_eq!(
+            substitute_project_id("https://host/v2", Some("abcd")),
+            "https://host/v2"
+        );
+    }
+
+    #[test]
+    fn test_regions() {
+        let cat = demo_catalog();
+        let entries = cat.entries();
+        assert_eq!(regions(&entries), vec!["RegionOne", "RegionTwo"]);
+        assert_eq!(entries[0].regions(), vec!["RegionOne", "RegionTwo"]);
+        assert!(regions(&Vec::new()).is_empty());
+    }
+
     fn assert_not_found(result: Result<Url, Error>) {
         let err = result.err().unwrap();
         if err.kind() != ErrorKind::EndpointNotFound {
@@ -203,4 +583,28 @@ pub mod test {
         let e1 = cat.find_endpoint("baremetal", &f1);
         assert_not_found(e1);
     }
+
+    #[test]
+    fn test_find_endpoint_not_found_candidates() {
+        let cat = demo_catalog();
+
+        let err = find_endpoint(&cat, "foobar", Public, None).unwrap_err();
+        assert!(err.candidate_endpoints().is_empty());
+
+        let err = find_endpoint(&cat, "identity", Internal, Some("RegionTwo")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::EndpointNotFound);
+        assert_eq!(err.candidate_endpoints().len(), 3);
+        assert_eq!(err.candidate_regions(), vec!["RegionOne", "RegionTwo"]);
+        assert_eq!(
+            err.candidate_endpoints()[1],
+            CatalogEndpoint {
+                interface: "internal".into(),
+                region: "RegionOne".into(),
+                url: "http://192.168.22.1/identity".into(),
+            }
+        );
+        assert!(err
+            .to_string()
+            .contains("public in RegionOne, internal in RegionOne, public in RegionTwo"));
+    }
 }
@@ -0,0 +1,142 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checksums of uploaded and downloaded data.
+
+use log::debug;
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+
+use super::{Error, ErrorKind};
+
+/// Hash algorithm.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Algorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+/// Incremental hash calculation.
+#[derive(Debug, Clone)]
+pub enum Hasher {
+    Md5(Md5),
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl Algorithm {
+    /// Find an algorithm by its conventional name (e.g. `sha256`).
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        match name.to_lowercase().as_str() {
+            "md5" => Some(Algorithm::Md5),
+            "sha1" => Some(Algorithm::Sha1),
+            "sha256" => Some(Algorithm::Sha256),
+            "sha512" => Some(Algorithm::Sha512),
+            _ => None,
+        }
+    }
+}
+
+impl Hasher {
+    /// Start a new hash calculation.
+    pub fn new(algorithm: Algorithm) -> Hasher {
+        match algorithm {
+            Algorithm::Md5 => Hasher::Md5(Md5::new()),
+            Algorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+        }
+    }
+
+    /// Feed more data.
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Md5(inner) => inner.update(data),
+            Hasher::Sha1(inner) => inner.update(data),
+            Hasher::Sha256(inner) => inner.update(data),
+            Hasher::Sha512(inner) => inner.update(data),
+        }
+    }
+
+    /// Finish the calculation and return the digest as a lower-case hex string.
+    pub fn finish(self) -> String {
+        match self {
+            Hasher::Md5(inner) => format!("{:x}", inner.finalize()),
+            Hasher::Sha1(inner) => format!("{:x}", inner.finalize()),
+            Hasher::Sha256(inner) => format!("{:x}", inner.finalize()),
+            Hasher::Sha512(inner) => format!("{:x}", inner.finalize()),
+        }
+    }
+}
+
+/// Verify that the digest matches the expected hex value (case-insensitive).
+///
+/// The `what` is used in messages, e.g. `image <ID>`.
+pub fn verify(
+    what: &str,
+    algorithm: Algorithm,
+    expected: &str,
+    hasher: Hasher,
+) -> Result<(), Error> {
+    let actual = hasher.finish();
+    if actual.eq_ignore_ascii_case(expected) {
+        debug!("Verified {:?} checksum of {}", algorithm, what);
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::ChecksumMismatch,
+            format!(
+                "{:?} checksum mismatch for {}: expected {}, got {}",
+                algorithm, what, expected, actual
+            ),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Algorithm, Hasher};
+
+    #[test]
+    fn test_algorithm_from_name() {
+        assert_eq!(Algorithm::from_name("SHA512"), Some(Algorithm::Sha512));
+        assert_eq!(Algorithm::from_name("md5"), Some(Algorithm::Md5));
+        assert_eq!(Algorithm::from_name("blake2"), None);
+    }
+
+    #[test]
+    fn test_incremental() {
+        let data: Vec<u8> = (0..=255u8).cycle().take(1280).collect();
+        for (algorithm, expected) in [
+            (Algorithm::Md5, "82829f1f3f2bb0f18b25f278e5bba8bd"),
+            (
+                Algorithm::Sha512,
+                "c93f55ccf2fa8c82699ff9b58afe3591242b135d908a6d865e17e38adb41c21d\
+                 1d5359e51273036373d54d20b5659cc87e6e7b381ff027d33f971416cc590f90",
+            ),
+        ] {
+            for chunk_size in [7, 64, 1280] {
+                let mut hasher = Hasher::new(algorithm);
+                for chunk in data.chunks(chunk_size) {
+                    hasher.update(chunk);
+                }
+                assert_eq!(hasher.finish(), expected);
+            }
+        }
+    }
+}
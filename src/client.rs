@@ -16,25 +16,42 @@
 
 use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::fmt::Debug;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-#[cfg(feature = "stream")]
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 #[cfg(feature = "stream")]
 use futures::Stream;
-use http::header::{HeaderMap, HeaderName, HeaderValue};
+use http::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, USER_AGENT};
 use http::Error as HttpError;
-use log::trace;
+use log::{debug, trace, warn};
 use reqwest::{Body, Client, Method, Request, RequestBuilder as HttpRequestBuilder, Response, Url};
 use serde::de::DeserializeOwned;
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use static_assertions::assert_eq_size;
+use tokio::time::sleep;
 
+use super::audit::{AuditLog, AuditSink};
+use super::errordetails;
+use super::events::{Event, EventHandler, EventSink};
+#[cfg(feature = "tracing")]
+use super::instrument;
+use super::metrics::MetricsSink;
+use super::redirect::{self, RedirectPolicy};
+use super::retry::RetryPolicy;
+use super::services::VersionedService;
+use super::shutdown::Lifecycle;
+use super::snapshot::{ApiVersionSnapshot, AuthSnapshot};
+use super::stats::{SessionStats, StatsCollector};
 #[cfg(feature = "stream")]
 use super::stream::{paginated, FetchNext, PaginatedResource};
 use super::url as url_utils;
-use super::{AuthType, EndpointFilters, Error};
+use super::useragent::UserAgent;
+#[cfg(feature = "websocket")]
+use super::websocket::{self, WebSocket};
+use super::{ApiVersion, AuthType, CatalogEntry, EndpointFilters, Error, ErrorKind};
 
 /// A properly typed constant for use with root paths.
 ///
@@ -55,13 +72,107 @@ use super::{AuthType, EndpointFilters, Error};
 /// ```
 pub const NO_PATH: Option<&'static str> = None;
 
+/// Transport used to send authenticated requests.
+///
+/// The default transport sends requests using the inner `reqwest` client. A custom transport
+/// can wrap another HTTP stack, for example a `reqwest_middleware::ClientWithMiddleware` with
+/// retry or tracing middleware, while the authentication is still handled by
+/// [AuthenticatedClient](struct.AuthenticatedClient.html):
+///
+/// ```rust,no_run
+/// use async_trait::async_trait;
+/// use osauth::client::Transport;
+///
+/// #[derive(Debug)]
+/// struct LoggingTransport;
+///
+/// #[async_trait]
+/// impl Transport for LoggingTransport {
+///     async fn execute(
+///         &self,
+///         client: &reqwest::Client,
+///         request: reqwest::Request,
+///     ) -> Result<reqwest::Response, osauth::Error> {
+///         println!("{} {}", request.method(), request.url());
+///         client.execute(request).await.map_err(From::from)
+///     }
+/// }
+/// ```
+///
+/// Requests to the identity service made by the authentication type itself do not go through
+/// the transport.
+#[async_trait]
+pub trait Transport: Debug + Send + Sync {
+    /// Send an authenticated request.
+    ///
+    /// The `client` is the inner client of the `AuthenticatedClient` and may be ignored.
+    async fn execute(&self, client: &Client, request: Request) -> Result<Response, Error>;
+}
+
 /// Authenticated HTTP client.
 ///
 /// Uses `Arc` internally and should be reused when possible by cloning it.
-#[derive(Debug, Clone)]
-pub struct AuthenticatedClient {
+///
+/// The authentication type is dynamic by default. A client with a concrete authentication type
+/// (created with [new_typed](#method.new_typed)) avoids dynamic dispatch on every request.
+#[derive(Debug)]
+pub struct AuthenticatedClient<A: ?Sized = dyn AuthType> {
     client: Client,
-    auth: Arc<dyn AuthType>,
+    auth: Arc<A>,
+    state: ClientState,
+}
+
+/// Configuration and shared state of a client that does not depend on the authentication type.
+#[derive(Debug, Clone, Default)]
+struct ClientState {
+    response_options: ResponseOptions,
+    transport: Option<Arc<dyn Transport>>,
+    events: EventSink,
+    audit: AuditLog,
+    metrics: Option<Arc<dyn MetricsSink>>,
+    slow_request_threshold: Option<Duration>,
+    redirect_policy: RedirectPolicy,
+    retry_policy: RetryPolicy,
+    user_agent: Arc<UserAgent>,
+    accept: Arc<HashMap<String, HeaderValue>>,
+    api_versions: Arc<HashMap<String, (ApiVersion, HeaderName, HeaderValue)>>,
+    stats: Arc<StatsCollector>,
+    lifecycle: Arc<Lifecycle>,
+    auth_snapshot: Option<Arc<AuthSnapshot>>,
+}
+
+impl<A: ?Sized> Clone for AuthenticatedClient<A> {
+    fn clone(&self) -> AuthenticatedClient<A> {
+        AuthenticatedClient {
+            client: self.client.clone(),
+            auth: Arc::clone(&self.auth),
+            state: self.state.clone(),
+        }
+    }
+}
+
+/// Default maximum number of bytes read from an error response body.
+const DEFAULT_MAX_ERROR_BODY_SIZE: usize = 64 * 1024;
+
+/// Media type of JSON.
+pub(crate) const JSON: &str = "application/json";
+
+/// Options for reading HTTP responses and building errors from them.
+#[derive(Debug, Clone, Copy)]
+struct ResponseOptions {
+    max_body_size: usize,
+    max_message_length: usize,
+    max_response_size: Option<usize>,
+}
+
+impl Default for ResponseOptions {
+    fn default() -> ResponseOptions {
+        ResponseOptions {
+            max_body_size: DEFAULT_MAX_ERROR_BODY_SIZE,
+            max_message_length: errordetails::DEFAULT_MAX_MESSAGE_LENGTH,
+            max_response_size: None,
+        }
+    }
 }
 
 assert_eq_size!(AuthenticatedClient, Option<AuthenticatedClient>);
@@ -79,25 +190,493 @@ impl AuthenticatedClient {
         ))
     }
 
+    /// Create a new authenticated client without authenticating.
+    ///
+    /// The authentication is deferred until the first request or an explicit call to
+    /// [refresh](#method.refresh). Use this when the client is created in code paths that
+    /// must not block or fail when the cloud is not reachable.
+    #[inline]
+    pub fn new_lazy<Auth: AuthType + 'static>(
+        client: Client,
+        auth_type: Auth,
+    ) -> AuthenticatedClient {
+        AuthenticatedClient::new_internal(client, Arc::new(auth_type))
+    }
+
     #[inline]
     pub(crate) fn new_internal(client: Client, auth: Arc<dyn AuthType>) -> AuthenticatedClient {
-        AuthenticatedClient { client, auth }
+        AuthenticatedClient {
+            client,
+            auth,
+            state: ClientState::default(),
+        }
+    }
+
+    /// Set a new authentication for this client.
+    #[inline]
+    pub fn set_auth_type<Auth: AuthType + 'static>(&mut self, auth_type: Auth) {
+        self.auth = Arc::new(auth_type);
+        self.state.auth_snapshot = None;
     }
 
+    /// Create a copy of this client with its own authentication state.
+    ///
+    /// Unlike clones, the resulting client can be refreshed without affecting this one (and
+    /// vice versa). Fails with [InvalidInput](../enum.ErrorKind.html#variant.InvalidInput) if
+    /// the authentication type does not support [detaching](../trait.AuthType.html#method.detach).
+    pub fn detach(&self) -> Result<AuthenticatedClient, Error> {
+        let auth = self.auth.detach().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "Authentication type does not support detaching",
+            )
+        })?;
+        Ok(AuthenticatedClient {
+            client: self.client.clone(),
+            auth,
+            state: self.state.clone(),
+        })
+    }
+
+    #[cfg(test)]
+    pub(crate) async fn new_noauth(endpoint: &str) -> AuthenticatedClient {
+        use crate::NoAuth;
+        AuthenticatedClient::new(Client::new(), NoAuth::new(endpoint).unwrap())
+            .await
+            .unwrap()
+    }
+}
+
+impl<A: AuthType> AuthenticatedClient<A> {
+    /// Create a new authenticated client with a concrete authentication type.
+    pub async fn new_typed(client: Client, auth_type: A) -> Result<AuthenticatedClient<A>, Error> {
+        auth_type.refresh(&client).await?;
+        Ok(AuthenticatedClient::new_typed_lazy(client, auth_type))
+    }
+
+    /// Create a new authenticated client with a concrete authentication type without
+    /// authenticating.
+    ///
+    /// See [new_lazy](#method.new_lazy) for details.
+    #[inline]
+    pub fn new_typed_lazy(client: Client, auth_type: A) -> AuthenticatedClient<A> {
+        AuthenticatedClient {
+            client,
+            auth: Arc::new(auth_type),
+            state: ClientState::default(),
+        }
+    }
+
+    /// Convert into a client with a dynamic authentication type.
+    #[inline]
+    pub fn into_dyn(self) -> AuthenticatedClient
+    where
+        A: 'static,
+    {
+        AuthenticatedClient {
+            client: self.client,
+            auth: self.auth,
+            state: self.state,
+        }
+    }
+}
+
+impl<A: AuthType + ?Sized> AuthenticatedClient<A> {
     /// Get a reference to the authentication type in use.
     #[inline]
-    pub fn auth_type(&self) -> &dyn AuthType {
+    pub fn auth_type(&self) -> &A {
         self.auth.as_ref()
     }
 
+    /// Maximum number of bytes read from an error response body.
+    #[inline]
+    pub fn max_error_body_size(&self) -> usize {
+        self.state.response_options.max_body_size
+    }
+
+    /// Set the maximum number of bytes read from an error response body.
+    ///
+    /// The rest of the body is discarded without reading (64 KiB by default). This protects
+    /// against misbehaving endpoints returning huge error responses.
+    #[inline]
+    pub fn set_max_error_body_size(&mut self, value: usize) {
+        self.state.response_options.max_body_size = value;
+    }
+
+    /// Maximum length of error messages built from non-JSON responses.
+    #[inline]
+    pub fn max_error_message_length(&self) -> usize {
+        self.state.response_options.max_message_length
+    }
+
+    /// Set the maximum length of error messages built from non-JSON responses.
+    ///
+    /// HTML tags are removed from such messages and the result is truncated to the given number
+    /// of characters (1024 by default). The original body is still available via
+    /// [Error::body](../struct.Error.html#method.body).
+    #[inline]
+    pub fn set_max_error_message_length(&mut self, value: usize) {
+        self.state.response_options.max_message_length = value;
+    }
+
+    /// Maximum size of a response body read by [fetch](struct.RequestBuilder.html#method.fetch).
+    #[inline]
+    pub fn max_response_size(&self) -> Option<usize> {
+        self.state.response_options.max_response_size
+    }
+
+    /// Set the maximum size of a response body read by
+    /// [fetch](struct.RequestBuilder.html#method.fetch) (unlimited by default).
+    ///
+    /// Larger responses result in an error with kind
+    /// [ResponseTooLarge](../enum.ErrorKind.html#variant.ResponseTooLarge) instead of being
+    /// buffered in memory. Responses returned by `send` are not affected.
+    #[inline]
+    pub fn set_max_response_size(&mut self, value: impl Into<Option<usize>>) {
+        self.state.response_options.max_response_size = value.into();
+    }
+
+    /// Threshold for logging slow requests.
+    #[inline]
+    pub fn slow_request_threshold(&self) -> Option<Duration> {
+        self.state.slow_request_threshold
+    }
+
+    /// Set a threshold for logging slow requests (disabled by default).
+    ///
+    /// Requests that take longer than the threshold to receive response headers are logged
+    /// with a warning.
+    #[inline]
+    pub fn set_slow_request_threshold(&mut self, value: impl Into<Option<Duration>>) {
+        self.state.slow_request_threshold = value.into();
+    }
+
+    /// Redirect policy.
+    #[inline]
+    pub fn redirect_policy(&self) -> RedirectPolicy {
+        self.state.redirect_policy
+    }
+
+    /// Set the redirect policy.
+    ///
+    /// See [RedirectPolicy](../enum.RedirectPolicy.html) for details.
+    #[inline]
+    pub fn set_redirect_policy(&mut self, value: RedirectPolicy) {
+        self.state.redirect_policy = value;
+    }
+
+    /// Retry policy.
+    #[inline]
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.state.retry_policy
+    }
+
+    /// Set the retry policy.
+    ///
+    /// See [RetryPolicy](../struct.RetryPolicy.html) for details.
+    #[inline]
+    pub fn set_retry_policy(&mut self, value: RetryPolicy) {
+        self.state.retry_policy = value;
+    }
+
+    /// User-Agent sent with requests (if configured).
+    #[inline]
+    pub fn user_agent(&self) -> Option<&str> {
+        self.state
+            .user_agent
+            .header()
+            .map(|value| value.to_str().expect("User-Agent is always ASCII"))
+    }
+
+    /// Set the application name for the User-Agent header.
+    ///
+    /// The User-Agent is only sent when an application name or an additional component is set.
+    /// It has the form `<app_name>/<app_version> <components> osauth/<version>`. Characters
+    /// that are not allowed in the header are replaced with underscores.
+    #[inline]
+    pub fn set_app_name<S: AsRef<str>>(&mut self, value: S) {
+        Arc::make_mut(&mut self.state.user_agent).set_app_name(value.as_ref());
+    }
+
+    /// Set the application version for the User-Agent header.
+    #[inline]
+    pub fn set_app_version<S: AsRef<str>>(&mut self, value: S) {
+        Arc::make_mut(&mut self.state.user_agent).set_app_version(value.as_ref());
+    }
+
+    /// Add a component (e.g. a library or a plugin) to the User-Agent header.
+    #[inline]
+    pub fn add_user_agent_component<N: AsRef<str>, V: AsRef<str>>(&mut self, name: N, version: V) {
+        Arc::make_mut(&mut self.state.user_agent).add_component(name.as_ref(), version.as_ref());
+    }
+
+    /// Default `Accept` header for requests to the given service type.
+    #[inline]
+    pub fn default_accept(&self, service_type: &str) -> Option<&HeaderValue> {
+        self.state.accept.get(service_type)
+    }
+
+    /// Set the default `Accept` header for requests to the given service type.
+    ///
+    /// Only used when the request has no `Accept` header and does not expect JSON (requests
+    /// using `fetch` accept `application/json` by default).
+    #[inline]
+    pub fn set_default_accept<S: Into<String>>(&mut self, service_type: S, value: HeaderValue) {
+        let _ = Arc::make_mut(&mut self.state.accept).insert(service_type.into(), value);
+    }
+
+    /// Default API version for requests to the given service type.
+    #[inline]
+    pub fn default_api_version(&self, service_type: &str) -> Option<ApiVersion> {
+        self.state
+            .api_versions
+            .get(service_type)
+            .map(|(version, _, _)| *version)
+    }
+
+    /// Set the default API version for requests to the given service.
+    ///
+    /// Only used by requests to the service that do not specify an API version explicitly.
+    pub fn set_default_api_version<Srv: VersionedService>(
+        &mut self,
+        service: Srv,
+        version: ApiVersion,
+    ) {
+        let (name, value) = service.get_version_header(version);
+        let _ = Arc::make_mut(&mut self.state.api_versions)
+            .insert(service.catalog_type().to_string(), (version, name, value));
+    }
+
     /// Authenticate a request.
     #[inline]
-    async fn authenticate(&self, request: HttpRequestBuilder) -> Result<Request, Error> {
-        self.auth
+    async fn authenticate(
+        &self,
+        request: HttpRequestBuilder,
+        accept: Option<&HeaderValue>,
+    ) -> Result<Request, Error> {
+        let mut request = self
+            .auth
             .authenticate(&self.client, request)
             .await?
-            .build()
-            .map_err(Error::from)
+            .build()?;
+        let headers = request.headers_mut();
+        if let Some(value) = self.state.user_agent.header() {
+            let _ = headers.entry(USER_AGENT).or_insert_with(|| value.clone());
+        }
+        if let Some(value) = accept {
+            let _ = headers.entry(ACCEPT).or_insert_with(|| value.clone());
+        }
+        Ok(request)
+    }
+
+    /// Shutdown state of this client and its clones.
+    #[inline]
+    pub(crate) fn lifecycle(&self) -> Arc<Lifecycle> {
+        Arc::clone(&self.state.lifecycle)
+    }
+
+    /// Default API versions in a serializable form.
+    pub(crate) fn api_versions_snapshot(&self) -> HashMap<String, ApiVersionSnapshot> {
+        self.state
+            .api_versions
+            .iter()
+            .map(|(key, (version, name, _))| {
+                let value = ApiVersionSnapshot {
+                    version: *version,
+                    header: name.to_string(),
+                };
+                (key.clone(), value)
+            })
+            .collect()
+    }
+
+    /// Replace the default API versions with the ones from a snapshot.
+    pub(crate) fn restore_api_versions(
+        &mut self,
+        value: HashMap<String, ApiVersionSnapshot>,
+    ) -> Result<(), Error> {
+        let api_versions = value
+            .into_iter()
+            .map(|(key, item)| {
+                let name = HeaderName::try_from(item.header.as_str()).map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Invalid API version header `{}`: {}", item.header, e),
+                    )
+                })?;
+                Ok((key, (item.version, name, item.version.into())))
+            })
+            .collect::<Result<HashMap<_, _>, Error>>()?;
+        self.state.api_versions = Arc::new(api_versions);
+        Ok(())
+    }
+
+    /// Parameters of the authentication (if known).
+    #[inline]
+    pub(crate) fn auth_snapshot(&self) -> Option<&AuthSnapshot> {
+        self.state.auth_snapshot.as_deref()
+    }
+
+    /// Record the parameters of the authentication.
+    #[inline]
+    pub(crate) fn set_auth_snapshot(&mut self, value: AuthSnapshot) {
+        self.state.auth_snapshot = Some(Arc::new(value));
+    }
+
+    /// Execute a request, retrying it according to the retry policy.
+    async fn execute(&self, mut request: Request, info: &RequestInfo) -> Result<Response, Error> {
+        let policy = &self.state.retry_policy;
+        if !policy.is_enabled() {
+            return self.execute_once(request, info).await;
+        }
+
+        policy.start();
+        let mut attempt = 0;
+        loop {
+            let copy = request.try_clone();
+            let method = request.method().clone();
+            let result = self.execute_once(request, info).await;
+            attempt += 1;
+            let next = match copy {
+                Some(next) => next,
+                None => return result,
+            };
+            let delay = match policy.delay(attempt, &method, &result) {
+                Some(delay) => delay,
+                None => return result,
+            };
+            if !policy.allow() {
+                debug!(
+                    "Retry budget exhausted, not retrying HTTP {} request",
+                    method
+                );
+                return result;
+            }
+
+            let error = match result {
+                Ok(response) => match check(response).await {
+                    Err(error) => error,
+                    // The status is an error status, so this cannot happen.
+                    Ok(response) => return Ok(response),
+                },
+                Err(error) => error,
+            };
+            warn!(
+                "HTTP {} request to {} failed, retrying in {:?} ({}/{}): {}",
+                method,
+                url_utils::redact(next.url()),
+                delay,
+                attempt,
+                policy.max_retries(),
+                error
+            );
+            self.emit(|| Event::RequestRetried { attempt, error });
+            sleep(delay).await;
+            request = next;
+        }
+    }
+
+    /// Execute a request, remembering its method for error reporting.
+    async fn execute_once(&self, request: Request, info: &RequestInfo) -> Result<Response, Error> {
+        let method = request.method().clone();
+        let url = request.url().clone();
+        trace!("Sending HTTP {} request to {}", method, url);
+        if let Some(ref metrics) = self.state.metrics {
+            metrics.request_started(&method, info.service_type);
+        }
+        let _in_flight = self.state.stats.start();
+        let started = Instant::now();
+        let future = self.send_following_redirects(request);
+        #[cfg(feature = "tracing")]
+        let result = instrument::request(&method, &url, future).await;
+        #[cfg(not(feature = "tracing"))]
+        let result = future.await;
+        let duration = started.elapsed();
+        if let Some(threshold) = self.state.slow_request_threshold {
+            if duration > threshold {
+                warn!(
+                    "HTTP {} request to {}{} took {:?}",
+                    method,
+                    url_utils::redact(&url),
+                    info.service_type
+                        .map(|service| format!(" (service {})", service))
+                        .unwrap_or_default(),
+                    duration
+                );
+            }
+        }
+        if let Some(ref metrics) = self.state.metrics {
+            let status = match result {
+                Ok(ref response) => Some(response.status()),
+                Err(ref e) => e.status(),
+            };
+            metrics.request_finished(&method, info.service_type, status, duration);
+        }
+        let failed = match result {
+            Ok(ref response) => response.status().is_server_error(),
+            Err(_) => true,
+        };
+        self.state.stats.finish(info.service_type, failed);
+        self.state
+            .audit
+            .record(&method, &url, info, &result, duration);
+        let mut response = result.map_err(|e| e.with_request(method.clone(), &url))?;
+        let extensions = response.extensions_mut();
+        let _ = extensions.insert(method);
+        let _ = extensions.insert(self.state.response_options);
+        Ok(response)
+    }
+
+    /// Send a request using the transport, following redirects according to the policy.
+    async fn send_following_redirects(&self, mut request: Request) -> Result<Response, Error> {
+        let max_redirects = self.state.redirect_policy.max_redirects();
+        let mut redirects = 0;
+        loop {
+            let copy = if max_redirects > 0 {
+                request.try_clone()
+            } else {
+                None
+            };
+            let response = match self.state.transport {
+                Some(ref transport) => transport.execute(&self.client, request).await?,
+                None => self.client.execute(request).await?,
+            };
+            if !response.status().is_redirection() {
+                return Ok(response);
+            }
+
+            request = match copy
+                .and_then(|req| redirect::follow(self.state.redirect_policy, req, &response))
+            {
+                Some(next) => next,
+                None => return Ok(response),
+            };
+            redirects += 1;
+            if redirects > max_redirects {
+                return Err(Error::new(
+                    ErrorKind::ProtocolError,
+                    format!("Too many redirects (more than {})", max_redirects),
+                ));
+            }
+            debug!(
+                "Following HTTP {} redirect to {}",
+                response.status(),
+                url_utils::redact(request.url())
+            );
+        }
+    }
+
+    /// Authenticate and execute a prepared request.
+    #[cfg(feature = "tower")]
+    pub(crate) async fn send_request(&self, request: Request) -> Result<Response, Error> {
+        self.state
+            .lifecycle
+            .run(async {
+                let request = HttpRequestBuilder::from_parts(self.client.clone(), request);
+                let request = self.authenticate(request, None).await?;
+                self.execute(request, &RequestInfo::default()).await
+            })
+            .await
     }
 
     /// Get a URL for the requested service.
@@ -112,6 +691,30 @@ impl AuthenticatedClient {
             .await
     }
 
+    /// Get all records from the service catalog.
+    #[inline]
+    pub async fn get_catalog(&self) -> Result<Vec<CatalogEntry>, Error> {
+        self.auth.get_catalog(&self.client).await
+    }
+
+    /// Get the ID of the authenticated user (if known).
+    #[inline]
+    pub async fn get_user_id(&self) -> Result<Option<String>, Error> {
+        self.auth.get_user_id(&self.client).await
+    }
+
+    /// Get names of the roles assigned to the authenticated user (if known).
+    #[inline]
+    pub async fn get_roles(&self) -> Result<Vec<String>, Error> {
+        self.auth.get_roles(&self.client).await
+    }
+
+    /// Get the current authentication token (if any), renewing it if needed.
+    #[inline]
+    pub async fn get_token(&self) -> Result<Option<String>, Error> {
+        self.auth.get_token(&self.client).await
+    }
+
     /// Get a reference to the inner (non-authenticated) client.
     #[inline]
     pub fn inner(&self) -> &Client {
@@ -123,16 +726,59 @@ impl AuthenticatedClient {
     /// # Warning
     ///
     /// Authentication will also be updated for clones of this client, since they share the same
-    /// authentication object.
+    /// authentication object. Use [detach](#method.detach) to avoid that.
     #[inline]
     pub async fn refresh(&mut self) -> Result<(), Error> {
         self.auth.refresh(&self.client).await
     }
 
-    /// Set a new authentication for this client.
+    /// Update the authentication without requiring a mutable reference.
+    ///
+    /// Nothing is done if the current token was issued after `started`, e.g. by a concurrent
+    /// request that has also received HTTP 401.
+    pub(crate) async fn refresh_auth(&self, started: DateTime<Utc>) -> Result<(), Error> {
+        if let Some(issued_at) = self.auth.token_issued_at() {
+            if issued_at > started {
+                debug!(
+                    "Token was issued at {} after the request started, not refreshing",
+                    issued_at
+                );
+                return Ok(());
+            }
+        }
+        self.auth.refresh(&self.client).await
+    }
+
+    /// Get a snapshot of runtime statistics.
+    ///
+    /// Statistics are shared between clones of this client.
+    pub fn stats(&self) -> SessionStats {
+        let token_age = self
+            .auth
+            .token_issued_at()
+            .and_then(|issued_at| Utc::now().signed_duration_since(issued_at).to_std().ok());
+        self.state.stats.snapshot(token_age)
+    }
+
+    /// Stop accepting new requests and wait for the running ones to finish.
+    ///
+    /// Requests that are still running after the timeout are cancelled and fail with
+    /// [OperationFailed](../enum.ErrorKind.html#variant.OperationFailed), in which case this call
+    /// fails with [OperationTimedOut](../enum.ErrorKind.html#variant.OperationTimedOut).
+    /// Requests are tracked from authentication (including token renewal and endpoint
+    /// resolution) until their response headers are received, reading the response bodies is
+    /// not waited for.
+    ///
+    /// The shutdown affects all clones of this client.
     #[inline]
-    pub fn set_auth_type<Auth: AuthType + 'static>(&mut self, auth_type: Auth) {
-        self.auth = Arc::new(auth_type);
+    pub async fn shutdown(&self, timeout: Duration) -> Result<(), Error> {
+        self.state.lifecycle.shutdown(timeout).await
+    }
+
+    /// Whether [shutdown](#method.shutdown) has been called on this client or its clones.
+    #[inline]
+    pub fn is_shut_down(&self) -> bool {
+        self.state.lifecycle.is_shut_down()
     }
 
     /// Set a new internal client implementation.
@@ -141,26 +787,67 @@ impl AuthenticatedClient {
         self.client = client;
     }
 
+    /// Set a custom transport for sending authenticated requests.
+    ///
+    /// See [Transport](trait.Transport.html) for details.
+    #[inline]
+    pub fn set_transport<T: Transport + 'static>(&mut self, transport: T) {
+        self.state.transport = Some(Arc::new(transport));
+    }
+
+    /// Set a handler for lifecycle events.
+    ///
+    /// See [Event](../enum.Event.html) for the list of events.
+    #[inline]
+    pub fn set_event_handler<H: EventHandler + 'static>(&mut self, handler: H) {
+        self.state.events = EventSink::new(handler);
+    }
+
+    /// Set a sink for the audit log of outgoing requests.
+    ///
+    /// See [AuditSink](../trait.AuditSink.html) for details.
+    #[inline]
+    pub fn set_audit_sink<S: AuditSink + 'static>(&mut self, sink: S) {
+        self.state.audit = AuditLog::new(sink);
+    }
+
+    /// Set a sink for request metrics.
+    ///
+    /// See [MetricsSink](../trait.MetricsSink.html) for details.
+    #[inline]
+    pub fn set_metrics_sink<S: MetricsSink + 'static>(&mut self, sink: S) {
+        self.state.metrics = Some(Arc::new(sink));
+    }
+
+    /// Emit events caused by an error.
+    pub(crate) fn emit_error(&self, error: &Error) {
+        if error.kind() == ErrorKind::TooManyRequests {
+            self.emit(|| Event::RateLimited {
+                error: error.clone(),
+            });
+        }
+    }
+
+    /// Emit a lifecycle event.
+    #[inline]
+    pub(crate) fn emit<F: FnOnce() -> Event>(&self, event: F) {
+        self.state.events.emit(event)
+    }
+
     /// Start an authenticated request.
     #[inline]
-    pub fn request(&self, method: Method, url: Url) -> RequestBuilder {
+    pub fn request(&self, method: Method, url: Url) -> RequestBuilder<A> {
         RequestBuilder {
             inner: self.client.request(method, url),
             client: self.clone(),
+            info: RequestInfo::default(),
+            accept: None,
         }
     }
-
-    #[cfg(test)]
-    pub(crate) async fn new_noauth(endpoint: &str) -> AuthenticatedClient {
-        use crate::NoAuth;
-        AuthenticatedClient::new(Client::new(), NoAuth::new(endpoint).unwrap())
-            .await
-            .unwrap()
-    }
 }
 
-impl From<AuthenticatedClient> for Client {
-    fn from(value: AuthenticatedClient) -> Client {
+impl<A: ?Sized> From<AuthenticatedClient<A>> for Client {
+    fn from(value: AuthenticatedClient<A>) -> Client {
         value.client
     }
 }
@@ -168,70 +855,144 @@ impl From<AuthenticatedClient> for Client {
 /// A request builder with error handling.
 #[derive(Debug)]
 #[must_use = "preparing a request is not enough to run it"]
-pub struct RequestBuilder {
+pub struct RequestBuilder<A: ?Sized = dyn AuthType> {
     inner: HttpRequestBuilder,
-    client: AuthenticatedClient,
+    client: AuthenticatedClient<A>,
+    info: RequestInfo,
+    accept: Option<HeaderValue>,
 }
 
-#[derive(Debug, Deserialize)]
-struct Message {
-    message: Option<String>,
-    faultstring: Option<String>,
-    title: Option<String>,
-    // Ironic legacy format: JSON inside JSON (sigh)
-    error_message: Option<String>,
+/// Information about a request used for reporting.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RequestInfo {
+    pub operation: Option<String>,
+    pub service_type: Option<&'static str>,
 }
 
-impl Message {
-    fn convert(self, recursive: bool) -> Option<String> {
-        if let Some(value) = self.message.or(self.faultstring).or(self.title) {
-            println!("Normal {}", value);
-            Some(value)
-        } else if recursive {
-            if let Some(json) = self.error_message {
-                return serde_json::from_str::<Message>(&json).ok().and_then(|msg| {
-                    println!("submessage {:?}", msg);
-                    msg.convert(false)
-                });
-            } else {
-                None
+/// Read at most `limit` bytes of the response body.
+async fn read_body(mut response: Response, limit: usize) -> Result<String, Error> {
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        let remaining = limit - body.len();
+        if chunk.len() > remaining {
+            body.extend_from_slice(&chunk[..remaining]);
+            debug!(
+                "Error response from {} truncated to {} bytes",
+                response.url(),
+                limit
+            );
+            break;
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    match String::from_utf8(body) {
+        Ok(body) => Ok(body),
+        Err(err) => {
+            let utf8_error = err.utf8_error();
+            let mut body = err.into_bytes();
+            // The body may have been truncated in the middle of a character.
+            if utf8_error.error_len().is_none() {
+                body.truncate(utf8_error.valid_up_to());
             }
-        } else {
-            None
+            Ok(String::from_utf8_lossy(&body).into_owned())
         }
     }
 }
 
-impl From<Message> for Option<String> {
-    fn from(value: Message) -> Option<String> {
-        value.convert(true)
+/// Read the whole response body, respecting the maximum response size.
+pub(crate) async fn read_response(mut response: Response) -> Result<Vec<u8>, Error> {
+    let limit = match response
+        .extensions()
+        .get::<ResponseOptions>()
+        .and_then(|options| options.max_response_size)
+    {
+        Some(limit) => limit,
+        None => return Ok(response.bytes().await?.to_vec()),
+    };
+
+    let too_large = |url: &Url| {
+        Error::new(
+            ErrorKind::ResponseTooLarge,
+            format!("Response from {} is larger than {} bytes", url, limit),
+        )
+    };
+    if response.content_length().map(|len| len as usize > limit) == Some(true) {
+        return Err(too_large(response.url()));
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if chunk.len() > limit - body.len() {
+            return Err(too_large(response.url()));
+        }
+        body.extend_from_slice(&chunk);
     }
+    Ok(body)
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
-enum ErrorResponse {
-    Map(HashMap<String, Message>),
-    Message(Message),
+/// Read the response body as JSON, respecting the maximum response size.
+pub(crate) async fn read_json<T: DeserializeOwned>(response: Response) -> Result<T, Error> {
+    let body = read_response(response).await?;
+    serde_json::from_slice(&body).map_err(Error::from)
 }
 
-fn extract_message(text: String) -> String {
-    serde_json::from_str::<ErrorResponse>(&text)
-        .ok()
-        .and_then(|body| match body {
-            ErrorResponse::Map(map) => map.into_iter().next().and_then(|(_k, v)| v.into()),
-            ErrorResponse::Message(msg) => msg.into(),
-        })
-        .unwrap_or(text)
+/// Check that a JSON pointer (RFC 6901) is syntactically valid.
+pub(crate) fn check_json_pointer(pointer: &str) -> Result<(), Error> {
+    if pointer.is_empty() || pointer.starts_with('/') {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("JSON pointer must start with a slash, got {}", pointer),
+        ))
+    }
+}
+
+/// Read a fragment of a JSON response pointed to by a JSON pointer (RFC 6901).
+pub(crate) async fn read_json_field<T: DeserializeOwned>(
+    response: Response,
+    pointer: &str,
+) -> Result<T, Error> {
+    let mut value: serde_json::Value = read_json(response).await?;
+    let field = value.pointer_mut(pointer).map(serde_json::Value::take);
+    match field {
+        Some(field) => serde_json::from_value(field).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidResponse,
+                format!("Invalid value of field {} in the response: {}", pointer, e),
+            )
+        }),
+        None => Err(Error::new(
+            ErrorKind::InvalidResponse,
+            format!("No field {} in the response", pointer),
+        )),
+    }
 }
 
 /// Check for OpenStack errors in the response.
 pub async fn check(response: Response) -> Result<Response, Error> {
     let status = response.status();
     if status.is_client_error() || status.is_server_error() {
-        let message = extract_message(response.text().await?);
+        let method = response.extensions().get::<Method>().cloned();
+        let url = response.url().clone();
+        let headers = response.headers().clone();
+        let options = response
+            .extensions()
+            .get::<ResponseOptions>()
+            .copied()
+            .unwrap_or_default();
+        let body = read_body(response, options.max_body_size).await?;
+        let (message, details) = errordetails::extract_message(&body, options.max_message_length);
         trace!("HTTP request returned {}; error: {}", status, message);
-        Err(Error::new(status.into(), message).with_status(status))
+        let mut error = Error::new(status.into(), message).with_status(status);
+        if let Some(method) = method {
+            error.set_request(method, &url);
+        }
+        if let Some(details) = details {
+            error.set_details(details);
+        }
+        Err(error.with_body(body).with_headers(headers))
     } else {
         trace!(
             "HTTP request to {} returned {}",
@@ -242,15 +1003,29 @@ pub async fn check(response: Response) -> Result<Response, Error> {
     }
 }
 
-impl RequestBuilder {
+impl<A: AuthType + ?Sized> RequestBuilder<A> {
     /// Get a reference to the client.
     #[inline]
-    pub fn client(&self) -> &AuthenticatedClient {
+    pub fn client(&self) -> &AuthenticatedClient<A> {
         &self.client
     }
 
+    /// Tag the request with an operation name for the audit log.
+    ///
+    /// See [AuditSink](../trait.AuditSink.html) for details.
+    pub fn operation<S: Into<String>>(mut self, operation: S) -> RequestBuilder<A> {
+        self.info.operation = Some(operation.into());
+        self
+    }
+
+    /// Set the service type for reporting.
+    pub(crate) fn service_type(mut self, service_type: &'static str) -> RequestBuilder<A> {
+        self.info.service_type = Some(service_type);
+        self
+    }
+
     /// Add a body to the request.
-    pub fn body<T: Into<Body>>(self, body: T) -> RequestBuilder {
+    pub fn body<T: Into<Body>>(self, body: T) -> RequestBuilder<A> {
         RequestBuilder {
             inner: self.inner.body(body),
             ..self
@@ -258,7 +1033,7 @@ impl RequestBuilder {
     }
 
     /// Add a header to the request.
-    pub fn header<K, V>(self, key: K, value: V) -> RequestBuilder
+    pub fn header<K, V>(self, key: K, value: V) -> RequestBuilder<A>
     where
         HeaderName: TryFrom<K>,
         <HeaderName as TryFrom<K>>::Error: Into<HttpError>,
@@ -271,8 +1046,35 @@ impl RequestBuilder {
         }
     }
 
+    /// Set the `Accept` header of the request.
+    ///
+    /// Overrides the default `application/json` for `fetch` and the
+    /// [per-service default](struct.AuthenticatedClient.html#method.set_default_accept).
+    pub fn accept<V>(self, value: V) -> RequestBuilder<A>
+    where
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: Into<HttpError>,
+    {
+        self.header(ACCEPT, value)
+    }
+
+    /// Use `application/json` as the `Accept` header unless it is set explicitly.
+    pub(crate) fn accept_json_by_default(mut self) -> RequestBuilder<A> {
+        self.accept = Some(HeaderValue::from_static(JSON));
+        self
+    }
+
+    /// Default `Accept` header for this request.
+    fn default_accept(&self) -> Option<&HeaderValue> {
+        self.accept.as_ref().or_else(|| {
+            self.info
+                .service_type
+                .and_then(|service_type| self.client.default_accept(service_type))
+        })
+    }
+
     /// Add headers to a request.
-    pub fn headers(self, headers: HeaderMap) -> RequestBuilder {
+    pub fn headers(self, headers: HeaderMap) -> RequestBuilder<A> {
         RequestBuilder {
             inner: self.inner.headers(headers),
             ..self
@@ -280,7 +1082,7 @@ impl RequestBuilder {
     }
 
     /// Add a JSON body to the request.
-    pub fn json<T: Serialize + ?Sized>(self, json: &T) -> RequestBuilder {
+    pub fn json<T: Serialize + ?Sized>(self, json: &T) -> RequestBuilder<A> {
         RequestBuilder {
             inner: self.inner.json(json),
             ..self
@@ -288,15 +1090,46 @@ impl RequestBuilder {
     }
 
     /// Send a query with the request.
-    pub fn query<T: Serialize + ?Sized>(self, query: &T) -> RequestBuilder {
+    pub fn query<T: Serialize + ?Sized>(self, query: &T) -> RequestBuilder<A> {
         RequestBuilder {
             inner: self.inner.query(query),
             ..self
         }
     }
 
+    /// Append a single query parameter to the request.
+    ///
+    /// Existing query parameters are preserved, repeated keys are sent several times.
+    pub fn query_pair<K, V>(self, key: K, value: V) -> RequestBuilder<A>
+    where
+        K: AsRef<str>,
+        V: ToString,
+    {
+        self.query(&[(key.as_ref(), value.to_string())])
+    }
+
+    /// Append query parameters to the request.
+    ///
+    /// Existing query parameters are preserved, repeated keys are sent several times.
+    pub fn append_query<I, K, V>(self, pairs: I) -> RequestBuilder<A>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: ToString,
+    {
+        let pairs: Vec<_> = pairs
+            .into_iter()
+            .map(|(key, value)| (key.as_ref().to_string(), value.to_string()))
+            .collect();
+        if pairs.is_empty() {
+            self
+        } else {
+            self.query(&pairs)
+        }
+    }
+
     /// Override the timeout for the request.
-    pub fn timeout(self, timeout: Duration) -> RequestBuilder {
+    pub fn timeout(self, timeout: Duration) -> RequestBuilder<A> {
         RequestBuilder {
             inner: self.inner.timeout(timeout),
             ..self
@@ -304,31 +1137,105 @@ impl RequestBuilder {
     }
 
     /// Send the request and receive JSON in response.
+    ///
+    /// Accepts `application/json` unless the `Accept` header is set explicitly.
     pub async fn fetch<T>(self) -> Result<T, Error>
     where
         T: DeserializeOwned + Send,
     {
-        self.send().await?.json::<T>().await.map_err(Error::from)
+        read_json(self.accept_json_by_default().send().await?).await
+    }
+
+    /// Send the request and receive a fragment of the JSON response.
+    ///
+    /// The fragment is identified by a JSON pointer (RFC 6901), e.g. `/server/id`.
+    /// Accepts `application/json` unless the `Accept` header is set explicitly.
+    pub async fn fetch_field<T>(self, pointer: &str) -> Result<T, Error>
+    where
+        T: DeserializeOwned + Send,
+    {
+        check_json_pointer(pointer)?;
+        read_json_field(self.accept_json_by_default().send().await?, pointer).await
     }
 
     /// Send the request and check for errors.
     pub async fn send(self) -> Result<Response, Error> {
-        check(self.send_unchecked().await?).await
+        let client = self.client.clone();
+        let result = check(self.send_unchecked().await?).await;
+        if let Err(ref e) = result {
+            client.emit_error(e);
+        }
+        result
     }
 
     /// Send the request without checking for HTTP and OpenStack errors.
     pub async fn send_unchecked(self) -> Result<Response, Error> {
-        let req = self.client.authenticate(self.inner).await?;
-        trace!("Sending HTTP {} request to {}", req.method(), req.url());
-        self.client.client.execute(req).await.map_err(Error::from)
+        let accept = self.default_accept().cloned();
+        self.client
+            .lifecycle()
+            .run(async move {
+                let req = self
+                    .client
+                    .authenticate(self.inner, accept.as_ref())
+                    .await?;
+                self.client.execute(req, &self.info).await
+            })
+            .await
+    }
+
+    /// Authenticate the request without sending it.
+    ///
+    /// The resulting request contains the authentication headers (and other modifications made
+    /// by the authentication type, e.g. signatures). Use it to drive a different HTTP stack or
+    /// to generate commands for other tools.
+    pub async fn prepare(self) -> Result<Request, Error> {
+        let accept = self.default_accept().cloned();
+        self.client.authenticate(self.inner, accept.as_ref()).await
+    }
+
+    /// Open a WebSocket connection.
+    ///
+    /// The request must be a GET request. Use headers to request a sub-protocol.
+    #[cfg(feature = "websocket")]
+    pub async fn connect_websocket(self) -> Result<WebSocket, Error> {
+        let (headers, accept) = websocket::handshake();
+        websocket::upgrade(self.headers(headers).send_unchecked().await?, &accept).await
     }
 
     /// Send the request to the given URL.
+    ///
+    /// Unlike [send_unchecked](#method.send_unchecked), the request is not registered with the
+    /// shutdown lifecycle, the caller is expected to do it.
     pub(crate) async fn send_unchecked_to(self, url: &Url) -> Result<Response, Error> {
-        let mut req = self.client.authenticate(self.inner).await?;
+        let client = self.client.clone();
+        let info = self.info.clone();
+        let req = self.prepare_for(url).await?;
+        client.execute(req, &info).await
+    }
+
+    /// Authenticate the request for the given URL without sending it.
+    pub(crate) async fn prepare_for(self, url: &Url) -> Result<Request, Error> {
+        let accept = self.default_accept().cloned();
+        // The real URL must be known before authentication since it may be used for signing.
+        let mut req = self.inner.build()?;
         url_utils::merge(req.url_mut(), url);
-        trace!("Sending HTTP {} request to {}", req.method(), req.url());
-        self.client.client.execute(req).await.map_err(Error::from)
+        if let Some((_, name, value)) = self
+            .info
+            .service_type
+            .and_then(|service_type| self.client.state.api_versions.get(service_type))
+        {
+            let _ = req
+                .headers_mut()
+                .entry(name)
+                .or_insert_with(|| value.clone());
+        }
+        let inner = HttpRequestBuilder::from_parts(self.client.client.clone(), req);
+        self.client.authenticate(inner, accept.as_ref()).await
+    }
+
+    /// Build a copy of the request (if possible).
+    pub(crate) fn build_request(&self) -> Option<Request> {
+        self.inner.try_clone()?.build().ok()
     }
 
     #[cfg(test)]
@@ -360,17 +1267,19 @@ impl RequestBuilder {
     }
 
     /// Attempt to clone this request builder.
-    pub fn try_clone(&self) -> Option<RequestBuilder> {
+    pub fn try_clone(&self) -> Option<RequestBuilder<A>> {
         self.inner.try_clone().map(|inner| RequestBuilder {
             inner,
             client: self.client.clone(),
+            info: self.info.clone(),
+            accept: self.accept.clone(),
         })
     }
 }
 
 #[cfg(feature = "stream")]
 #[async_trait]
-impl FetchNext for RequestBuilder {
+impl<A: AuthType + ?Sized> FetchNext for RequestBuilder<A> {
     async fn fetch_next<Q: Serialize + Send, T: DeserializeOwned + Send>(
         &self,
         query: Q,
@@ -384,41 +1293,532 @@ impl FetchNext for RequestBuilder {
 }
 
 #[cfg(test)]
-mod test_extract_message {
-    use super::extract_message;
+mod test_check {
+    use http::header::CONTENT_TYPE;
+    use reqwest::{Response, StatusCode};
+
+    use super::{
+        check, check_json_pointer, read_body, read_json, read_json_field, read_response,
+        ResponseOptions,
+    };
+    use crate::{ErrorDetails, ErrorKind};
 
-    #[test]
-    fn test_plain() {
-        let msg = "<html><body>I failed</body></html>";
-        let result = extract_message(msg.to_string());
-        assert_eq!(result, msg);
+    fn response(status: StatusCode, body: &str) -> Response {
+        http::Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "text/html")
+            .body(body.to_string())
+            .unwrap()
+            .into()
     }
 
-    #[test]
-    fn test_simple_message() {
-        let msg = r#"{"message": "I failed"}"#;
-        let result = extract_message(msg.to_string());
-        assert_eq!(result, "I failed");
+    fn limited_response(body: &str, limit: usize) -> Response {
+        let mut resp = response(StatusCode::OK, body);
+        let _ = resp.extensions_mut().insert(ResponseOptions {
+            max_response_size: Some(limit),
+            ..ResponseOptions::default()
+        });
+        resp
     }
 
-    #[test]
-    fn test_nested_message() {
-        let msg = r#"{"SomethingFailed": {"message": "I failed"}}"#;
-        let result = extract_message(msg.to_string());
-        assert_eq!(result, "I failed");
+    #[tokio::test]
+    async fn test_read_response_limit() {
+        let body = read_response(limited_response("{}", 2)).await.unwrap();
+        assert_eq!(body, b"{}");
+        let value: serde_json::Value = read_json(response(StatusCode::OK, "{}")).await.unwrap();
+        assert!(value.as_object().unwrap().is_empty());
+
+        let err = read_response(limited_response("[1, 2]", 2))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ResponseTooLarge);
+        let err = read_json::<Vec<u8>>(limited_response("[1, 2]", 5))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ResponseTooLarge);
+    }
+
+    #[tokio::test]
+    async fn test_read_json_field() {
+        let body = r#"{"server": {"id": "abcd", "addresses": [{"port": 22}]}}"#;
+        let id: String = read_json_field(response(StatusCode::OK, body), "/server/id")
+            .await
+            .unwrap();
+        assert_eq!(id, "abcd");
+        let port: u16 = read_json_field(response(StatusCode::OK, body), "/server/addresses/0/port")
+            .await
+            .unwrap();
+        assert_eq!(port, 22);
+        let whole: serde_json::Value = read_json_field(response(StatusCode::OK, body), "")
+            .await
+            .unwrap();
+        assert_eq!(whole["server"]["id"], "abcd");
+
+        let err = read_json_field::<String>(response(StatusCode::OK, body), "/server/name")
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidResponse);
+        let err = read_json_field::<u16>(response(StatusCode::OK, body), "/server/id")
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidResponse);
+        check_json_pointer("/server/id").unwrap();
+        check_json_pointer("").unwrap();
+        let err = check_json_pointer("server").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn test_check_ok() {
+        let resp = check(response(StatusCode::OK, "{}")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_check_json() {
+        let body = r#"{"itemNotFound": {"code": 404, "message": "Not here"}}"#;
+        let err = check(response(StatusCode::NOT_FOUND, body))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ResourceNotFound);
+        assert_eq!(err.status(), Some(StatusCode::NOT_FOUND));
+        assert_eq!(err.body(), Some(body));
+        assert!(matches!(
+            err.details(),
+            Some(ErrorDetails::Generic {
+                code: Some(404),
+                ..
+            })
+        ));
+        assert!(err.to_string().ends_with(": Not here"));
+    }
+
+    #[tokio::test]
+    async fn test_check_html() {
+        let body = "<html><body><h1>502 Bad Gateway</h1></body></html>";
+        let err = check(response(StatusCode::BAD_GATEWAY, body))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InternalServerError);
+        assert_eq!(err.body(), Some(body));
+        assert!(err.details().is_none());
+        assert!(err.to_string().ends_with(": 502 Bad Gateway"));
+    }
+
+    #[tokio::test]
+    async fn test_read_body_limit() {
+        let body = read_body(response(StatusCode::OK, "abcdef"), 4)
+            .await
+            .unwrap();
+        assert_eq!(body, "abcd");
+        let body = read_body(response(StatusCode::OK, "abcdef"), 6)
+            .await
+            .unwrap();
+        assert_eq!(body, "abcdef");
+        // Truncated in the middle of a character
+        let body = read_body(response(StatusCode::OK, "ab\u{e4}"), 3)
+            .await
+            .unwrap();
+        assert_eq!(body, "ab");
+    }
+}
+
+#[cfg(test)]
+mod test_transport {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use http::header::USER_AGENT;
+    use reqwest::{Client, Method, Request, RequestBuilder, Response, StatusCode, Url};
+
+    use super::{AuthenticatedClient, Transport};
+    use crate::services::OBJECT_STORAGE;
+    use crate::{
+        AuditRecord, AuthType, EndpointFilters, Error, ErrorKind, Event, MetricsSink, RetryBudget,
+        RetryPolicy, Session,
+    };
+
+    #[derive(Debug, Default)]
+    struct FakeTransport {
+        requests: Arc<Mutex<Vec<(Method, Url)>>>,
+    }
+
+    #[async_trait]
+    impl Transport for FakeTransport {
+        async fn execute(&self, _client: &Client, request: Request) -> Result<Response, Error> {
+            let status = match request.url().path() {
+                "/missing" => StatusCode::NOT_FOUND,
+                "/unavailable" => StatusCode::SERVICE_UNAVAILABLE,
+                _ => StatusCode::OK,
+            };
+            self.requests
+                .lock()
+                .unwrap()
+                .push((request.method().clone(), request.url().clone()));
+            Ok(http::Response::builder()
+                .status(status)
+                .body(r#"{"answer": 42}"#)
+                .unwrap()
+                .into())
+        }
+    }
+
+    /// Authentication that never receives a token or a catalog.
+    #[derive(Debug)]
+    struct HangingAuth;
+
+    #[async_trait]
+    impl AuthType for HangingAuth {
+        async fn authenticate(
+            &self,
+            _client: &Client,
+            _request: RequestBuilder,
+        ) -> Result<RequestBuilder, Error> {
+            std::future::pending().await
+        }
+
+        async fn get_endpoint(
+            &self,
+            _client: &Client,
+            _service_type: &str,
+            _filters: &EndpointFilters,
+        ) -> Result<Url, Error> {
+            std::future::pending().await
+        }
+
+        async fn refresh(&self, _client: &Client) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prepare() {
+        let client = AuthenticatedClient::new(
+            Client::new(),
+            crate::BasicAuth::new("http://127.0.0.1:1", "user", "pass").unwrap(),
+        )
+        .await
+        .unwrap();
+        let url = Url::parse("http://127.0.0.1:1/answer").unwrap();
+        let request = client
+            .request(Method::GET, url.clone())
+            .prepare()
+            .await
+            .unwrap();
+        assert_eq!(request.url(), &url);
+        assert_eq!(request.headers()["authorization"], "Basic dXNlcjpwYXNz");
+    }
+
+    #[tokio::test]
+    async fn test_query_pair() {
+        let client = AuthenticatedClient::new_noauth("http://127.0.0.1:1").await;
+        let url = Url::parse("http://127.0.0.1:1/answer?limit=10").unwrap();
+        let mut builder = client.request(Method::GET, url);
+        for tag in &["a", "b"] {
+            builder = builder.query_pair("tag", tag);
+        }
+        let request = builder
+            .append_query(vec![("marker", "abcd")])
+            .append_query(Vec::<(&str, u32)>::new())
+            .query_pair("detail", true)
+            .prepare()
+            .await
+            .unwrap();
+        assert_eq!(
+            request.url().query(),
+            Some("limit=10&tag=a&tag=b&marker=abcd&detail=true")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_user_agent() {
+        let mut client = AuthenticatedClient::new_noauth("http://127.0.0.1:1").await;
+        let url = Url::parse("http://127.0.0.1:1/answer").unwrap();
+        let request = client
+            .request(Method::GET, url.clone())
+            .prepare()
+            .await
+            .unwrap();
+        assert!(request.headers().get(USER_AGENT).is_none());
+
+        client.set_app_name("tool");
+        client.set_app_version("1.0");
+        let ua = client.user_agent().unwrap().to_string();
+        assert!(ua.starts_with("tool/1.0 osauth/"));
+        let request = client
+            .request(Method::GET, url.clone())
+            .prepare()
+            .await
+            .unwrap();
+        assert_eq!(request.headers()[USER_AGENT], ua.as_str());
+
+        let request = client
+            .request(Method::GET, url)
+            .header(USER_AGENT, "custom")
+            .prepare()
+            .await
+            .unwrap();
+        assert_eq!(request.headers()[USER_AGENT], "custom");
+    }
+
+    #[tokio::test]
+    async fn test_audit_sink() {
+        let mut client = AuthenticatedClient::new_noauth("http://127.0.0.1:1").await;
+        client.set_transport(FakeTransport::default());
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let records2 = Arc::clone(&records);
+        client.set_audit_sink(move |record: &AuditRecord| {
+            records2.lock().unwrap().push(record.clone());
+        });
+
+        let url = Url::parse("http://127.0.0.1:1/answer?temp_url_sig=abcd&limit=1").unwrap();
+        let _ = client
+            .request(Method::GET, url)
+            .operation("get-answer")
+            .send()
+            .await
+            .unwrap();
+        let url = Url::parse("http://127.0.0.1:1/missing").unwrap();
+        let _ = client
+            .request(Method::DELETE, url)
+            .send()
+            .await
+            .unwrap_err();
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].method, Method::GET);
+        assert_eq!(
+            records[0].url.as_str(),
+            "http://127.0.0.1:1/answer?temp_url_sig=***&limit=1"
+        );
+        assert_eq!(records[0].operation.as_deref(), Some("get-answer"));
+        assert_eq!(records[0].status, Some(StatusCode::OK));
+        assert_eq!(records[1].method, Method::DELETE);
+        assert!(records[1].operation.is_none());
+        assert_eq!(records[1].status, Some(StatusCode::NOT_FOUND));
+        assert!(records[1].error.is_none());
+    }
+
+    type MetricsRecord = (Method, Option<String>, Option<StatusCode>);
+
+    #[derive(Debug, Default)]
+    struct FakeMetrics {
+        requests: Mutex<Vec<MetricsRecord>>,
+    }
+
+    impl MetricsSink for FakeMetrics {
+        fn request_finished(
+            &self,
+            method: &Method,
+            service_type: Option<&str>,
+            status: Option<StatusCode>,
+            _duration: Duration,
+        ) {
+            self.requests.lock().unwrap().push((
+                method.clone(),
+                service_type.map(From::from),
+                status,
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_metrics_sink() {
+        let mut client = AuthenticatedClient::new_noauth("http://127.0.0.1:1").await;
+        client.set_transport(FakeTransport::default());
+        let metrics = Arc::new(FakeMetrics::default());
+        client.set_metrics_sink(Arc::clone(&metrics));
+
+        let url = Url::parse("http://127.0.0.1:1/missing").unwrap();
+        let _ = client.request(Method::GET, url).send().await.unwrap_err();
+        let session = Session::new_with_authenticated_client(client);
+        let _ = session
+            .get(OBJECT_STORAGE, &["answer"])
+            .send()
+            .await
+            .unwrap();
+
+        let requests = metrics.requests.lock().unwrap();
+        assert_eq!(
+            *requests,
+            vec![
+                (Method::GET, None, Some(StatusCode::NOT_FOUND)),
+                (
+                    Method::GET,
+                    Some("object-store".into()),
+                    Some(StatusCode::OK)
+                ),
+            ]
+        );
+    }
+
+    #[cfg(feature = "stream")]
+    #[tokio::test]
+    async fn test_send_all() {
+        use futures::stream::StreamExt;
+
+        let mut client = AuthenticatedClient::new_noauth("http://127.0.0.1:1").await;
+        let transport = FakeTransport::default();
+        let requests = Arc::clone(&transport.requests);
+        client.set_transport(transport);
+        let session = Session::new_with_authenticated_client(client);
+
+        let paths = ["answer", "missing", "answer", "missing", "answer"];
+        let results: Vec<_> = session
+            .send_all(
+                paths
+                    .iter()
+                    .map(|path| session.get(OBJECT_STORAGE, &[path])),
+                2,
+            )
+            .collect()
+            .await;
+        let statuses: Vec<_> = results
+            .into_iter()
+            .map(|result| match result {
+                Ok(response) => response.status(),
+                Err(e) => e.status().unwrap(),
+            })
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                StatusCode::OK,
+                StatusCode::NOT_FOUND,
+                StatusCode::OK,
+                StatusCode::NOT_FOUND,
+                StatusCode::OK
+            ]
+        );
+        let sent = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, url)| url.path() != "/")
+            .count();
+        assert_eq!(sent, paths.len());
+    }
+
+    #[tokio::test]
+    async fn test_transport() {
+        let mut client = AuthenticatedClient::new_noauth("http://127.0.0.1:1").await;
+        let transport = FakeTransport::default();
+        let requests = Arc::clone(&transport.requests);
+        client.set_transport(transport);
+
+        let url = Url::parse("http://127.0.0.1:1/answer").unwrap();
+        let value: serde_json::Value = client
+            .request(Method::GET, url.clone())
+            .fetch()
+            .await
+            .unwrap();
+        assert_eq!(value["answer"], 42);
+
+        let url = Url::parse("http://127.0.0.1:1/missing").unwrap();
+        let err = client
+            .request(Method::DELETE, url)
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ResourceNotFound);
+        assert_eq!(err.method(), Some(&Method::DELETE));
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].1.path(), "/answer");
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let mut client = AuthenticatedClient::new_noauth("http://127.0.0.1:1").await;
+        client.set_transport(FakeTransport::default());
+        let session = Session::new_with_authenticated_client(client);
+        for path in ["answer", "missing", "unavailable", "answer"] {
+            let _ = session.get(OBJECT_STORAGE, &[path]).send().await;
+        }
+
+        let stats = session.stats();
+        assert_eq!(stats.requests_in_flight, 0);
+        assert!(stats.token_age.is_none());
+        let service = &stats.services["object-store"];
+        assert_eq!(service.requests, 4);
+        // Client errors are not counted.
+        assert_eq!(service.errors, 1);
+        assert!((service.recent_error_rate - 0.25).abs() < f64::EPSILON);
+        assert_eq!(session.clone().stats(), stats);
     }
 
-    #[test]
-    fn test_ironic_message() {
-        let msg = r#"{"error_message": {"faultstring": "I failed"}}"#;
-        let result = extract_message(msg.to_string());
-        assert_eq!(result, "I failed");
+    #[tokio::test]
+    async fn test_retry() {
+        let mut client = AuthenticatedClient::new_noauth("http://127.0.0.1:1").await;
+        let transport = FakeTransport::default();
+        let requests = Arc::clone(&transport.requests);
+        client.set_transport(transport);
+        let retries = Arc::new(Mutex::new(Vec::new()));
+        let retries_clone = Arc::clone(&retries);
+        client.set_event_handler(move |event: &Event| {
+            if let Event::RequestRetried { attempt, error } = event {
+                retries_clone
+                    .lock()
+                    .unwrap()
+                    .push((*attempt, error.status()));
+            }
+        });
+        client.set_retry_policy(
+            RetryPolicy::new(2)
+                .with_initial_delay(Duration::from_millis(1))
+                .with_budget(RetryBudget::new(0.1, 3)),
+        );
+
+        let url = Url::parse("http://127.0.0.1:1/unavailable").unwrap();
+        let err = client
+            .request(Method::POST, url.clone())
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::SERVICE_UNAVAILABLE));
+        assert_eq!(requests.lock().unwrap().len(), 3);
+        assert_eq!(
+            *retries.lock().unwrap(),
+            vec![
+                (1, Some(StatusCode::SERVICE_UNAVAILABLE)),
+                (2, Some(StatusCode::SERVICE_UNAVAILABLE))
+            ]
+        );
+
+        // Only one retry is left in the budget.
+        let _ = client.request(Method::GET, url).send().await.unwrap_err();
+        assert_eq!(requests.lock().unwrap().len(), 5);
+
+        // Successful requests are not retried.
+        let url = Url::parse("http://127.0.0.1:1/answer").unwrap();
+        let _ = client.request(Method::GET, url).send().await.unwrap();
+        assert_eq!(requests.lock().unwrap().len(), 6);
+        assert_eq!(retries.lock().unwrap().len(), 3);
     }
 
-    #[test]
-    fn test_ironic_legacy() {
-        let msg = r#"{"error_message": "{\"faultstring\": \"I failed\"}"}"#;
-        let result = extract_message(msg.to_string());
-        assert_eq!(result, "I failed");
+    #[tokio::test]
+    async fn test_shutdown_cancels_authentication() {
+        let client = AuthenticatedClient::new(Client::new(), HangingAuth)
+            .await
+            .unwrap();
+        let session = Session::new_with_authenticated_client(client.clone());
+        let url = Url::parse("http://127.0.0.1:1/answer").unwrap();
+        let request = tokio::spawn(async move { client.request(Method::GET, url).send().await });
+        let service_request = tokio::spawn(session.clone().get(OBJECT_STORAGE, &["answer"]).send());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // Both requests are stuck before sending anything, but are waited for.
+        let err = session
+            .shutdown(Duration::from_millis(10))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::OperationTimedOut);
+        assert!(err.to_string().contains("2 request(s)"), "{}", err);
+        for task in [request, service_request] {
+            let err = task.await.unwrap().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::OperationFailed);
+        }
     }
 }
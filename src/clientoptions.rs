@@ -0,0 +1,726 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Options of the underlying HTTP client.
+
+use std::collections::{BTreeMap, HashMap};
+#[cfg(any(feature = "native-tls", feature = "rustls"))]
+use std::fs;
+#[cfg(feature = "hyper")]
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+#[cfg(any(feature = "native-tls", feature = "rustls"))]
+use std::path::Path;
+#[cfg(feature = "hyper")]
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "hyper")]
+use hyper::client::connect::dns::Name;
+#[cfg(feature = "hyper")]
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::redirect::Policy;
+#[cfg(any(feature = "native-tls", feature = "rustls"))]
+use reqwest::tls::Version as TlsVersion;
+#[cfg(any(feature = "native-tls", feature = "rustls"))]
+use reqwest::Certificate;
+use reqwest::{Client, ClientBuilder};
+
+use super::pool::{HostPoolTransport, PoolOptions};
+use super::Error;
+#[cfg(any(feature = "native-tls", feature = "rustls"))]
+use super::ErrorKind;
+
+/// IP address family to use for connections.
+///
+/// When a host name resolves to both IPv4 and IPv6 addresses, connections are attempted to the
+/// first address family, falling back to the other one after a short delay ("happy eyeballs").
+/// On dual-stack clouds with broken IPv6 connectivity, prefer or force IPv4 to avoid waiting for
+/// connection timeouts.
+///
+/// Only applies to host names resolved via DNS, not to IP addresses in URLs or to
+/// [DNS overrides](struct.ClientOptions.html#method.add_dns_override).
+#[cfg(feature = "hyper")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IpFamily {
+    /// Use the order returned by the system resolver (the default).
+    Any,
+    /// Try IPv4 addresses first.
+    PreferIpv4,
+    /// Try IPv6 addresses first.
+    PreferIpv6,
+    /// Only use IPv4 addresses.
+    Ipv4Only,
+    /// Only use IPv6 addresses.
+    Ipv6Only,
+}
+
+#[cfg(feature = "hyper")]
+#[allow(clippy::derivable_impls)]
+impl Default for IpFamily {
+    fn default() -> IpFamily {
+        IpFamily::Any
+    }
+}
+
+#[cfg(feature = "hyper")]
+impl IpFamily {
+    /// Filter and order the addresses according to the family preference.
+    fn apply(self, mut addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        match self {
+            IpFamily::Any => {}
+            // Sorting is stable, so the order within a family is preserved.
+            IpFamily::PreferIpv4 => addrs.sort_by_key(|addr| addr.is_ipv6()),
+            IpFamily::PreferIpv6 => addrs.sort_by_key(|addr| addr.is_ipv4()),
+            IpFamily::Ipv4Only => addrs.retain(|addr| addr.is_ipv4()),
+            IpFamily::Ipv6Only => addrs.retain(|addr| addr.is_ipv6()),
+        }
+        addrs
+    }
+}
+
+/// A system resolver applying the IP family preference.
+#[cfg(feature = "hyper")]
+#[derive(Debug)]
+struct FamilyResolver(IpFamily);
+
+#[cfg(feature = "hyper")]
+impl Resolve for FamilyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let family = self.0;
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0)).await?;
+            let addrs = family.apply(addrs.collect());
+            if addrs.is_empty() {
+                let msg = format!("No addresses of family {:?} for {}", family, name);
+                return Err(io::Error::new(io::ErrorKind::NotFound, msg).into());
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// Options of the underlying HTTP client.
+///
+/// Use [build](#method.build) to create an HTTP client for
+/// [Session::new_with_client](struct.Session.html#method.new_with_client) or pass the options to
+/// [CloudConfig::with_client_options](struct.CloudConfig.html#method.with_client_options).
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// let options = osauth::ClientOptions::new()
+///     .with_http2_adaptive_window(true)
+///     .with_http2_prior_knowledge(true);
+/// let session = osauth::CloudConfig::from_env()?
+///     .with_client_options(options)
+///     .create_session()
+///     .await?;
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+///
+/// The resulting client does not follow redirects, they are handled by the session according to
+/// its [RedirectPolicy](enum.RedirectPolicy.html).
+///
+/// The maximum number of concurrent HTTP/2 streams is announced by the server and cannot be
+/// changed on the client side.
+///
+/// The TLS backend is selected at build time with either the `native-tls` (the default) or the
+/// `rustls` feature. TLS options are only available when one of them is enabled. Cipher suites
+/// cannot be restricted here: `native-tls` uses the system configuration, `rustls` only supports
+/// modern cipher suites.
+// Not Copy on purpose: TLS options (e.g. certificates) are not Copy.
+#[allow(missing_copy_implementations)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientOptions {
+    dns_overrides: BTreeMap<String, Vec<IpAddr>>,
+    http2_adaptive_window: bool,
+    http2_initial_connection_window_size: Option<u32>,
+    http2_initial_stream_window_size: Option<u32>,
+    http2_keep_alive_interval: Option<Duration>,
+    http2_max_frame_size: Option<u32>,
+    http2_prior_knowledge: bool,
+    host_pools: BTreeMap<String, PoolOptions>,
+    #[cfg(feature = "hyper")]
+    ip_family: IpFamily,
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    max_tls_version: Option<TlsVersion>,
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    min_tls_version: Option<TlsVersion>,
+    pool: PoolOptions,
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    root_certificates: Vec<Vec<u8>>,
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    tls_built_in_root_certs: bool,
+}
+
+#[cfg_attr(
+    not(any(feature = "native-tls", feature = "rustls")),
+    allow(clippy::derivable_impls)
+)]
+impl Default for ClientOptions {
+    fn default() -> ClientOptions {
+        ClientOptions {
+            dns_overrides: BTreeMap::new(),
+            http2_adaptive_window: false,
+            http2_initial_connection_window_size: None,
+            http2_initial_stream_window_size: None,
+            http2_keep_alive_interval: None,
+            http2_max_frame_size: None,
+            http2_prior_knowledge: false,
+            host_pools: BTreeMap::new(),
+            #[cfg(feature = "hyper")]
+            ip_family: IpFamily::default(),
+            #[cfg(any(feature = "native-tls", feature = "rustls"))]
+            max_tls_version: None,
+            #[cfg(any(feature = "native-tls", feature = "rustls"))]
+            min_tls_version: None,
+            pool: PoolOptions::default(),
+            #[cfg(any(feature = "native-tls", feature = "rustls"))]
+            root_certificates: Vec::new(),
+            #[cfg(any(feature = "native-tls", feature = "rustls"))]
+            tls_built_in_root_certs: true,
+        }
+    }
+}
+
+impl ClientOptions {
+    /// Create default options.
+    #[inline]
+    pub fn new() -> ClientOptions {
+        ClientOptions::default()
+    }
+
+    /// Create an HTTP client with these options.
+    pub fn build(&self) -> Result<Client, Error> {
+        self.apply(Client::builder())?.build().map_err(Error::from)
+    }
+
+    /// Create a transport with separate connection pools for the hosts configured with
+    /// [add_host_pool_options](#method.add_host_pool_options).
+    ///
+    /// Returns `None` if no per-host options are set. Install the transport with
+    /// [Session::set_transport](struct.Session.html#method.set_transport) when the session is
+    /// not created from a [CloudConfig](struct.CloudConfig.html).
+    pub fn build_host_pools(&self) -> Result<Option<HostPoolTransport>, Error> {
+        if self.host_pools.is_empty() {
+            return Ok(None);
+        }
+
+        let mut clients = HashMap::with_capacity(self.host_pools.len());
+        for (host, pool) in &self.host_pools {
+            // Per-host values override the global ones.
+            let builder = pool.apply(self.apply(Client::builder())?);
+            let _ = clients.insert(host.clone(), builder.build()?);
+        }
+        Ok(Some(HostPoolTransport::new(clients)))
+    }
+
+    /// Apply the options to an HTTP client builder.
+    pub(crate) fn apply(&self, mut builder: ClientBuilder) -> Result<ClientBuilder, Error> {
+        #[cfg(any(feature = "native-tls", feature = "rustls"))]
+        {
+            for pem in &self.root_certificates {
+                let certs = Certificate::from_pem_bundle(pem).map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidConfig,
+                        format!("Cannot parse root certificates as PEM: {}", e),
+                    )
+                    .with_source(e)
+                })?;
+                if certs.is_empty() {
+                    return Err(Error::new(
+                        ErrorKind::InvalidConfig,
+                        "No certificates found in a PEM root certificate bundle",
+                    ));
+                }
+                for cert in certs {
+                    builder = builder.add_root_certificate(cert);
+                }
+            }
+            builder = builder.tls_built_in_root_certs(self.tls_built_in_root_certs);
+            if let (Some(min), Some(max)) = (self.min_tls_version, self.max_tls_version) {
+                if min > max {
+                    return Err(Error::new(
+                        ErrorKind::InvalidConfig,
+                        format!(
+                            "Minimum TLS version {:?} is higher than the maximum {:?}",
+                            min, max
+                        ),
+                    ));
+                }
+            }
+            if let Some(min) = self.min_tls_version {
+                builder = builder.min_tls_version(min);
+            }
+            if let Some(max) = self.max_tls_version {
+                builder = builder.max_tls_version(max);
+            }
+        }
+        #[cfg(feature = "hyper")]
+        if self.ip_family != IpFamily::Any {
+            builder = builder.dns_resolver(Arc::new(FamilyResolver(self.ip_family)));
+        }
+        for (host, addresses) in &self.dns_overrides {
+            // The port is taken from the URL, the one in the address is ignored.
+            let addresses: Vec<_> = addresses
+                .iter()
+                .map(|addr| SocketAddr::new(*addr, 0))
+                .collect();
+            builder = builder.resolve_to_addrs(host, &addresses);
+        }
+        if self.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+        if let Some(interval) = self.http2_keep_alive_interval {
+            builder = builder
+                .http2_keep_alive_interval(interval)
+                .http2_keep_alive_while_idle(true);
+        }
+        builder = self.pool.apply(builder);
+        // Redirects are followed by the session, see RedirectPolicy.
+        Ok(builder
+            .redirect(Policy::none())
+            .http2_adaptive_window(self.http2_adaptive_window)
+            .http2_initial_connection_window_size(self.http2_initial_connection_window_size)
+            .http2_initial_stream_window_size(self.http2_initial_stream_window_size)
+            .http2_max_frame_size(self.http2_max_frame_size))
+    }
+
+    /// Resolve the host name to the given IP address instead of using DNS.
+    ///
+    /// Useful when the host names in the service catalog cannot be resolved from the client
+    /// network (e.g. with access through a tunnel). Can be called several times for the same
+    /// host to provide several addresses. The port is always taken from the URL.
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let options = osauth::ClientOptions::new()
+    ///     .with_dns_override("controller.cloud.internal", "192.0.2.10".parse().unwrap());
+    /// let session = osauth::CloudConfig::from_env()?
+    ///     .with_client_options(options)
+    ///     .create_session()
+    ///     .await?;
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub fn add_dns_override<S: AsRef<str>>(&mut self, host: S, address: IpAddr) {
+        self.dns_overrides
+            .entry(host.as_ref().to_lowercase())
+            .or_default()
+            .push(address);
+    }
+
+    /// Use a separate connection pool with the given settings for the host.
+    ///
+    /// Settings that are not set in `options` are taken from the
+    /// [global pool options](#method.set_pool_options). Requests to the host are sent through
+    /// the transport created by [build_host_pools](#method.build_host_pools).
+    pub fn add_host_pool_options<S: AsRef<str>>(&mut self, host: S, options: PoolOptions) {
+        let _ = self
+            .host_pools
+            .insert(host.as_ref().to_lowercase(), options);
+    }
+
+    /// Add a trusted root certificate (or a bundle of them) in the PEM format.
+    ///
+    /// The certificates are validated when the HTTP client is built.
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    #[inline]
+    pub fn add_root_certificate_pem<T: Into<Vec<u8>>>(&mut self, pem: T) {
+        self.root_certificates.push(pem.into());
+    }
+
+    /// Add trusted root certificates from a PEM file (e.g. a CA bundle).
+    ///
+    /// The file is read immediately, the certificates are validated when the HTTP client is
+    /// built.
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    pub fn add_root_certificate_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let pem = fs::read(path).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidConfig,
+                format!("Cannot read certificates from {}: {}", path.display(), e),
+            )
+            .with_source(e)
+        })?;
+        self.add_root_certificate_pem(pem);
+        Ok(())
+    }
+
+    /// Whether to use the HTTP/2 adaptive flow control (disabled by default).
+    ///
+    /// Overrides the window sizes when enabled.
+    #[inline]
+    pub fn set_http2_adaptive_window(&mut self, value: bool) {
+        self.http2_adaptive_window = value;
+    }
+
+    /// Initial HTTP/2 connection-level flow control window size.
+    #[inline]
+    pub fn set_http2_initial_connection_window_size(&mut self, value: impl Into<Option<u32>>) {
+        self.http2_initial_connection_window_size = value.into();
+    }
+
+    /// Initial HTTP/2 stream-level flow control window size.
+    #[inline]
+    pub fn set_http2_initial_stream_window_size(&mut self, value: impl Into<Option<u32>>) {
+        self.http2_initial_stream_window_size = value.into();
+    }
+
+    /// Interval of HTTP/2 keep-alive pings (disabled by default).
+    ///
+    /// Pings are also sent on idle connections, so that load balancers do not drop them.
+    #[inline]
+    pub fn set_http2_keep_alive_interval(&mut self, value: impl Into<Option<Duration>>) {
+        self.http2_keep_alive_interval = value.into();
+    }
+
+    /// Maximum HTTP/2 frame size.
+    #[inline]
+    pub fn set_http2_max_frame_size(&mut self, value: impl Into<Option<u32>>) {
+        self.http2_max_frame_size = value.into();
+    }
+
+    /// Whether to use HTTP/2 without negotiating it (disabled by default).
+    ///
+    /// Only enable this if all endpoints (including the identity service) support HTTP/2.
+    #[inline]
+    pub fn set_http2_prior_knowledge(&mut self, value: bool) {
+        self.http2_prior_knowledge = value;
+    }
+
+    /// IP address family to use for connections (the system resolver order by default).
+    ///
+    /// See [IpFamily](enum.IpFamily.html) for details.
+    #[cfg(feature = "hyper")]
+    #[inline]
+    pub fn set_ip_family(&mut self, value: IpFamily) {
+        self.ip_family = value;
+    }
+
+    /// Maximum allowed TLS version (no maximum by default).
+    ///
+    /// `TLS_1_3` cannot be used as a maximum with the `native-tls` backend.
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    #[inline]
+    pub fn set_max_tls_version(&mut self, value: impl Into<Option<TlsVersion>>) {
+        self.max_tls_version = value.into();
+    }
+
+    /// Minimum required TLS version (the backend default by default).
+    ///
+    /// `TLS_1_3` cannot be used as a minimum with the `native-tls` backend, use the `rustls`
+    /// feature for TLS 1.3-only connections.
+    ///
+    /// ```rust,no_run
+    /// let options = osauth::ClientOptions::new()
+    ///     .with_min_tls_version(reqwest::tls::Version::TLS_1_2);
+    /// let client = options.build().expect("Cannot create an HTTP client");
+    /// ```
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    #[inline]
+    pub fn set_min_tls_version(&mut self, value: impl Into<Option<TlsVersion>>) {
+        self.min_tls_version = value.into();
+    }
+
+    /// Settings of the connection pool for all hosts (the `reqwest` defaults by default).
+    #[inline]
+    pub fn set_pool_options(&mut self, value: PoolOptions) {
+        self.pool = value;
+    }
+
+    /// Whether to trust the built-in root certificates (enabled by default).
+    ///
+    /// Disable to only trust certificates added with
+    /// [add_root_certificate_pem](#method.add_root_certificate_pem), e.g. in private clouds.
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    #[inline]
+    pub fn set_tls_built_in_root_certs(&mut self, value: bool) {
+        self.tls_built_in_root_certs = value;
+    }
+
+    /// Resolve the host name to the given IP address instead of using DNS.
+    #[inline]
+    pub fn with_dns_override<S: AsRef<str>>(mut self, host: S, address: IpAddr) -> Self {
+        self.add_dns_override(host, address);
+        self
+    }
+
+    /// Use a separate connection pool with the given settings for the host.
+    #[inline]
+    pub fn with_host_pool_options<S: AsRef<str>>(mut self, host: S, options: PoolOptions) -> Self {
+        self.add_host_pool_options(host, options);
+        self
+    }
+
+    /// Whether to use the HTTP/2 adaptive flow control (disabled by default).
+    #[inline]
+    pub fn with_http2_adaptive_window(mut self, value: bool) -> Self {
+        self.set_http2_adaptive_window(value);
+        self
+    }
+
+    /// Initial HTTP/2 connection-level flow control window size.
+    #[inline]
+    pub fn with_http2_initial_connection_window_size(
+        mut self,
+        value: impl Into<Option<u32>>,
+    ) -> Self {
+        self.set_http2_initial_connection_window_size(value);
+        self
+    }
+
+    /// Initial HTTP/2 stream-level flow control window size.
+    #[inline]
+    pub fn with_http2_initial_stream_window_size(mut self, value: impl Into<Option<u32>>) -> Self {
+        self.set_http2_initial_stream_window_size(value);
+        self
+    }
+
+    /// Interval of HTTP/2 keep-alive pings (disabled by default).
+    #[inline]
+    pub fn with_http2_keep_alive_interval(mut self, value: impl Into<Option<Duration>>) -> Self {
+        self.set_http2_keep_alive_interval(value);
+        self
+    }
+
+    /// Maximum HTTP/2 frame size.
+    #[inline]
+    pub fn with_http2_max_frame_size(mut self, value: impl Into<Option<u32>>) -> Self {
+        self.set_http2_max_frame_size(value);
+        self
+    }
+
+    /// Whether to use HTTP/2 without negotiating it (disabled by default).
+    #[inline]
+    pub fn with_http2_prior_knowledge(mut self, value: bool) -> Self {
+        self.set_http2_prior_knowledge(value);
+        self
+    }
+
+    /// IP address family to use for connections (the system resolver order by default).
+    #[cfg(feature = "hyper")]
+    #[inline]
+    pub fn with_ip_family(mut self, value: IpFamily) -> Self {
+        self.set_ip_family(value);
+        self
+    }
+
+    /// Maximum allowed TLS version (no maximum by default).
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    #[inline]
+    pub fn with_max_tls_version(mut self, value: impl Into<Option<TlsVersion>>) -> Self {
+        self.set_max_tls_version(value);
+        self
+    }
+
+    /// Minimum required TLS version (the backend default by default).
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    #[inline]
+    pub fn with_min_tls_version(mut self, value: impl Into<Option<TlsVersion>>) -> Self {
+        self.set_min_tls_version(value);
+        self
+    }
+
+    /// Settings of the connection pool for all hosts (the `reqwest` defaults by default).
+    #[inline]
+    pub fn with_pool_options(mut self, value: PoolOptions) -> Self {
+        self.set_pool_options(value);
+        self
+    }
+
+    /// Add trusted root certificates from a PEM file (e.g. a CA bundle).
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    #[inline]
+    pub fn with_root_certificate_file<P: AsRef<Path>>(mut self, path: P) -> Result<Self, Error> {
+        self.add_root_certificate_file(path)?;
+        Ok(self)
+    }
+
+    /// Add a trusted root certificate (or a bundle of them) in the PEM format.
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    #[inline]
+    pub fn with_root_certificate_pem<T: Into<Vec<u8>>>(mut self, pem: T) -> Self {
+        self.add_root_certificate_pem(pem);
+        self
+    }
+
+    /// Whether to trust the built-in root certificates (enabled by default).
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    #[inline]
+    pub fn with_tls_built_in_root_certs(mut self, value: bool) -> Self {
+        self.set_tls_built_in_root_certs(value);
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::ClientOptions;
+    use crate::utils::test::{serve_raw_http, NO_CONTENT};
+
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    const CERT: &str = r#"-----BEGIN CERTIFICATE-----
+MIIBYzCCAQqgAwIBAgIUJcTlPhsFyWG9S0pAAElKuSFEPBYwCgYIKoZIzj0EAwIw
+FDESMBAGA1UEAwwJbG9jYWxob3N0MB4XDTIwMTAwMjExNTU1NloXDTIwMTEwMTEx
+NTU1NlowFDESMBAGA1UEAwwJbG9jYWxob3N0MFkwEwYHKoZIzj0CAQYIKoZIzj0D
+AQcDQgAEsfpkV9dAThk54U1K+rXUnNbpwuNo5wCRrKpk+cNR/2HBO8VydNj7dkxs
+VBUvI7M9hY8dgg1jBVoPcCf0GSOvuqM6MDgwFAYDVR0RBA0wC4IJbG9jYWxob3N0
+MAsGA1UdDwQEAwIHgDATBgNVHSUEDDAKBggrBgEFBQcDATAKBggqhkjOPQQDAgNH
+ADBEAiAdjF7484kjb3XJoLbgqnZh4V1yHKs57eBVuil9/V0YugIgLwb/vSUAPowb
+hK9jLBzNvo8qzKqaGfnGieuLeXCqFDA=
+-----END CERTIFICATE-----"#;
+
+    #[test]
+    fn test_build() {
+        let options = ClientOptions::new()
+            .with_http2_adaptive_window(true)
+            .with_http2_initial_connection_window_size(1 << 20)
+            .with_http2_initial_stream_window_size(1 << 18)
+            .with_http2_keep_alive_interval(Duration::from_secs(30))
+            .with_http2_max_frame_size(1 << 16)
+            .with_http2_prior_knowledge(true);
+        let _ = options.build().unwrap();
+        let _ = ClientOptions::new().build().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dns_override() {
+        let (addr, server) = serve_raw_http(vec![NO_CONTENT.into()]).await;
+
+        let client = ClientOptions::new()
+            .with_dns_override("Controller.Cloud.Invalid", "127.0.0.1".parse().unwrap())
+            .build()
+            .unwrap();
+        let response = client
+            .get(format!("http://controller.cloud.invalid:{}/", addr.port()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
+        let _ = server.await.unwrap();
+    }
+
+    #[cfg(feature = "hyper")]
+    #[test]
+    fn test_ip_family() {
+        use std::net::SocketAddr;
+
+        use super::IpFamily;
+
+        let addrs: Vec<SocketAddr> = vec![
+            "[2001:db8::1]:0".parse().unwrap(),
+            "192.0.2.1:0".parse().unwrap(),
+            "[2001:db8::2]:0".parse().unwrap(),
+            "192.0.2.2:0".parse().unwrap(),
+        ];
+        assert_eq!(IpFamily::Any.apply(addrs.clone()), addrs);
+        assert_eq!(
+            IpFamily::PreferIpv4.apply(addrs.clone()),
+            vec![addrs[1], addrs[3], addrs[0], addrs[2]]
+        );
+        assert_eq!(
+            IpFamily::PreferIpv6.apply(addrs.clone()),
+            vec![addrs[0], addrs[2], addrs[1], addrs[3]]
+        );
+        assert_eq!(
+            IpFamily::Ipv4Only.apply(addrs.clone()),
+            vec![addrs[1], addrs[3]]
+        );
+        assert_eq!(
+            IpFamily::Ipv6Only.apply(addrs.clone()),
+            vec![addrs[0], addrs[2]]
+        );
+    }
+
+    #[cfg(feature = "hyper")]
+    #[tokio::test]
+    async fn test_ip_family_resolver() {
+        use reqwest::dns::Resolve;
+
+        use super::{FamilyResolver, IpFamily};
+
+        let addrs: Vec<_> = FamilyResolver(IpFamily::Ipv4Only)
+            .resolve("localhost".parse().unwrap())
+            .await
+            .unwrap()
+            .collect();
+        assert!(!addrs.is_empty());
+        assert!(addrs.iter().all(|addr| addr.is_ipv4()));
+        let _ = ClientOptions::new()
+            .with_ip_family(IpFamily::PreferIpv4)
+            .build()
+            .unwrap();
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    #[test]
+    fn test_build_root_certificates() {
+        let options = ClientOptions::new()
+            .with_root_certificate_pem(CERT)
+            .with_tls_built_in_root_certs(false);
+        let _ = options.build().unwrap();
+
+        let err = ClientOptions::new()
+            .with_root_certificate_pem("not a certificate")
+            .build()
+            .unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::InvalidConfig);
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    #[test]
+    fn test_build_root_certificate_file() {
+        use std::io::Write;
+
+        let mut bundle = tempfile::NamedTempFile::new().unwrap();
+        write!(bundle, "{}\n{}\n", CERT, CERT).unwrap();
+        bundle.flush().unwrap();
+        let options = ClientOptions::new()
+            .with_root_certificate_file(bundle.path())
+            .unwrap();
+        let _ = options.build().unwrap();
+
+        let err = ClientOptions::new()
+            .with_root_certificate_file("/I/do/not/exist")
+            .unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::InvalidConfig);
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    #[test]
+    fn test_build_tls_versions() {
+        use reqwest::tls::Version;
+
+        let options = ClientOptions::new()
+            .with_min_tls_version(Version::TLS_1_2)
+            .with_max_tls_version(Version::TLS_1_2);
+        let _ = options.build().unwrap();
+
+        let err = ClientOptions::new()
+            .with_min_tls_version(Version::TLS_1_2)
+            .with_max_tls_version(Version::TLS_1_1)
+            .build()
+            .unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::InvalidConfig);
+    }
+}
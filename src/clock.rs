@@ -0,0 +1,127 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Time sources.
+
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+
+/// A source of the current time.
+///
+/// Used to check token validity. The default is [SystemClock](struct.SystemClock.html),
+/// [ManualClock](struct.ManualClock.html) allows simulating token expiry in tests.
+///
+/// The local time is adjusted by the offset of the server clock (as reported in the `Date`
+/// header of the token response) before comparing it with the token expiration time.
+pub trait Clock: Debug + Send + Sync {
+    /// Current time.
+    fn now(&self) -> DateTime<Utc>;
+}
+
+impl<T: Clock + ?Sized> Clock for Arc<T> {
+    fn now(&self) -> DateTime<Utc> {
+        (**self).now()
+    }
+}
+
+/// The system clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that only moves when told to.
+///
+/// Clones share the time, so a clone can be used to move the clock after it is installed:
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use osauth::ManualClock;
+///
+/// let clock = ManualClock::default();
+/// let auth = osauth::identity::Password::new(
+///     "https://cloud.local/identity",
+///     "admin",
+///     "pa$$w0rd",
+///     "Default",
+/// )?
+/// .with_clock(clock.clone());
+/// let session = osauth::Session::new(auth).await?;
+/// clock.advance(chrono::Duration::days(1));
+/// // The token is considered expired and is refreshed on the next request.
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl Default for ManualClock {
+    /// Create a clock starting at the current system time.
+    fn default() -> ManualClock {
+        ManualClock::new(Utc::now())
+    }
+}
+
+impl ManualClock {
+    /// Create a clock starting at the given time.
+    pub fn new(now: DateTime<Utc>) -> ManualClock {
+        ManualClock {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    /// Set the current time.
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().expect("Clock lock is poisoned") = now;
+    }
+
+    /// Move the clock forward (or backward with a negative duration).
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().expect("Clock lock is poisoned") += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().expect("Clock lock is poisoned")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{Clock, ManualClock};
+
+    #[test]
+    fn test_manual_clock() {
+        let start = Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap();
+        let clock = ManualClock::new(start);
+        let clone = clock.clone();
+        clone.advance(Duration::hours(1));
+        assert_eq!(clock.now(), start + Duration::hours(1));
+        clock.set(start);
+        assert_eq!(clone.now(), start);
+    }
+}
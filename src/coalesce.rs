@@ -0,0 +1,173 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Coalescing of identical concurrent requests.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use log::trace;
+use reqwest::{Method, Request};
+use tokio::sync::OnceCell;
+
+use super::Error;
+
+type Slot = Arc<OnceCell<Result<Arc<[u8]>, Error>>>;
+
+/// Registry of in-flight requests.
+#[derive(Debug, Default)]
+pub(crate) struct Coalescer {
+    inflight: Mutex<HashMap<String, Slot>>,
+}
+
+impl Coalescer {
+    /// Run the fetch unless an identical one is already in flight, in which case wait for it.
+    pub async fn fetch<F, R>(&self, key: String, fetch: F) -> Result<Arc<[u8]>, Error>
+    where
+        F: FnOnce() -> R,
+        R: Future<Output = Result<Arc<[u8]>, Error>>,
+    {
+        let slot = Arc::clone(
+            self.inflight
+                .lock()
+                .expect("Request coalescing lock is poisoned")
+                .entry(key.clone())
+                .or_default(),
+        );
+        // Only the first line: the headers contain the token.
+        trace!(
+            "Fetching or waiting for {}",
+            key.lines().next().unwrap_or_default()
+        );
+        let result = slot.get_or_init(fetch).await.clone();
+
+        let mut inflight = self
+            .inflight
+            .lock()
+            .expect("Request coalescing lock is poisoned");
+        if inflight
+            .get(&key)
+            .map(|existing| Arc::ptr_eq(existing, &slot))
+            .unwrap_or(false)
+        {
+            let _ = inflight.remove(&key);
+        }
+        result
+    }
+}
+
+/// A key identifying the request for coalescing or `None` if it cannot be coalesced.
+///
+/// Only GET requests without a body are coalesced. The key includes the URL and all headers
+/// (including the API version).
+pub(crate) fn request_key(prefix: &str, request: &Request) -> Option<String> {
+    if request.method() != Method::GET || request.body().is_some() {
+        return None;
+    }
+
+    let mut headers: Vec<_> = request
+        .headers()
+        .iter()
+        .map(|(name, value)| (name.as_str(), String::from_utf8_lossy(value.as_bytes())))
+        .collect();
+    headers.sort();
+
+    let mut key = format!("{} {}", prefix, request.url());
+    for (name, value) in headers {
+        key.push('\n');
+        key.push_str(name);
+        key.push_str(": ");
+        key.push_str(&value);
+    }
+    Some(key)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use reqwest::{Client, Method, Url};
+
+    use super::{request_key, Coalescer};
+
+    #[tokio::test]
+    async fn test_fetch_coalesced() {
+        let coalescer = Arc::new(Coalescer::default());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let tasks: Vec<_> = (0..4)
+            .map(|_| {
+                let coalescer = Arc::clone(&coalescer);
+                let calls = Arc::clone(&calls);
+                tokio::spawn(async move {
+                    coalescer
+                        .fetch("key".into(), || async move {
+                            let _ = calls.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(50)).await;
+                            Ok(Arc::from(&b"data"[..]))
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(&*task.await.unwrap().unwrap(), b"data");
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(coalescer.inflight.lock().unwrap().is_empty());
+
+        // Finished requests are not reused.
+        let _ = coalescer
+            .fetch("key".into(), || async {
+                let _ = calls.fetch_add(1, Ordering::SeqCst);
+                Ok(Arc::from(&b"data"[..]))
+            })
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_request_key() {
+        let client = Client::new();
+        let url = Url::parse("http://openstack/servers?limit=1").unwrap();
+        let first = client
+            .get(url.clone())
+            .header("x-openstack-nova-api-version", "2.1")
+            .header("accept", "application/json")
+            .build()
+            .unwrap();
+        let second = client
+            .get(url.clone())
+            .header("accept", "application/json")
+            .header("x-openstack-nova-api-version", "2.1")
+            .build()
+            .unwrap();
+        let key = request_key("compute", &first).unwrap();
+        assert_eq!(Some(key.clone()), request_key("compute", &second));
+        assert_ne!(Some(key.clone()), request_key("network", &second));
+
+        let other_version = client
+            .get(url.clone())
+            .header("x-openstack-nova-api-version", "2.90")
+            .build()
+            .unwrap();
+        assert_ne!(Some(key), request_key("compute", &other_version));
+
+        let post = client.request(Method::POST, url).build().unwrap();
+        assert!(request_key("compute", &post).is_none());
+    }
+}
@@ -15,10 +15,12 @@
 //! Reusable JSON structures and protocol bits.
 
 use std::cmp::Ordering;
+use std::iter::FusedIterator;
+use std::vec::IntoIter;
 
 use reqwest::Url;
 use serde::de::{DeserializeOwned, Error as DeserError};
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 
 use crate::ApiVersion;
@@ -56,7 +58,7 @@ pub struct IdAndName {
 }
 
 /// A reference to a resource by either its ID or name.
-#[derive(Clone, Debug, Serialize, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub enum IdOrName {
     /// Resource ID.
     #[serde(rename = "id")]
@@ -79,6 +81,7 @@ pub enum VersionStatus {
     Unknown,
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for VersionStatus {
     fn default() -> VersionStatus {
         VersionStatus::Unknown
@@ -109,6 +112,27 @@ where
     }
 }
 
+impl VersionStatus {
+    /// The status as used in version documents.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VersionStatus::Current => "CURRENT",
+            VersionStatus::Supported => "SUPPORTED",
+            VersionStatus::Deprecated => "DEPRECATED",
+            VersionStatus::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+impl Serialize for VersionStatus {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
 impl<'de> Deserialize<'de> for VersionStatus {
     fn deserialize<D>(deserializer: D) -> Result<VersionStatus, D::Error>
     where
@@ -120,7 +144,7 @@ impl<'de> Deserialize<'de> for VersionStatus {
 }
 
 /// A single API version as returned by a version discovery endpoint.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Version {
     /// Major version ID.
     ///
@@ -146,6 +170,14 @@ impl Version {
     pub fn is_stable(&self) -> bool {
         self.status.is_stable()
     }
+
+    /// URL of the version root (the `self` link), if present.
+    pub fn self_link(&self) -> Option<&Url> {
+        self.links
+            .iter()
+            .find(|link| link.rel == "self")
+            .map(|link| &link.href)
+    }
 }
 
 impl PartialEq for Version {
@@ -158,7 +190,7 @@ impl Eq for Version {}
 
 impl PartialOrd for Version {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(&other))
+        Some(self.cmp(other))
     }
 }
 
@@ -168,6 +200,125 @@ impl Ord for Version {
     }
 }
 
+/// A version discovery document.
+///
+/// Services return either a list of major versions (usually from their root endpoint) or a
+/// single version (from a versioned endpoint). Use it for custom discovery of services that do
+/// not follow the conventions understood by [Session](../struct.Session.html):
+///
+/// ```rust,no_run
+/// use osauth::common::VersionRoot;
+///
+/// # async fn example() -> Result<(), osauth::Error> {
+/// let session = osauth::Session::from_env().await?;
+/// let root: VersionRoot = session
+///     .get(osauth::services::BAREMETAL, osauth::client::NO_PATH)
+///     .fetch()
+///     .await?;
+/// for version in root.into_stable_iter() {
+///     println!("{} at {:?}", version.id, version.self_link());
+/// }
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum VersionRoot {
+    /// Multiple major versions.
+    MultipleVersions {
+        /// Available major versions.
+        versions: Vec<Version>,
+    },
+    /// Single major version.
+    OneVersion {
+        /// The major version.
+        version: Version,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum IntoStableIterInner {
+    Many(IntoIter<Version>),
+    One(Option<Version>),
+}
+
+/// An iterator over stable versions.
+#[derive(Debug)]
+pub struct IntoStableIter(IntoStableIterInner);
+
+impl Iterator for IntoStableIter {
+    type Item = Version;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.0 {
+            IntoStableIterInner::Many(ref mut inner) => {
+                for next in inner {
+                    if next.is_stable() {
+                        return Some(next);
+                    }
+                }
+
+                None
+            }
+            IntoStableIterInner::One(ref mut opt) => opt.take(),
+        }
+    }
+}
+
+impl DoubleEndedIterator for IntoStableIter {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self.0 {
+            IntoStableIterInner::Many(ref mut inner) => {
+                while let Some(next) = inner.next_back() {
+                    if next.is_stable() {
+                        return Some(next);
+                    }
+                }
+
+                None
+            }
+            IntoStableIterInner::One(ref mut opt) => opt.take(),
+        }
+    }
+}
+
+impl FusedIterator for IntoStableIter {}
+
+impl VersionRoot {
+    /// Sort versions from lowest to highest (using unstable sorting).
+    #[inline]
+    pub fn sort(&mut self) {
+        if let VersionRoot::MultipleVersions {
+            versions: ref mut vers,
+        } = self
+        {
+            vers.sort_unstable();
+        }
+    }
+
+    /// Create an iterator over stable versions.
+    pub fn into_stable_iter(self) -> IntoStableIter {
+        match self {
+            VersionRoot::MultipleVersions { versions: vers } => {
+                IntoStableIter(IntoStableIterInner::Many(vers.into_iter()))
+            }
+            VersionRoot::OneVersion { version: ver } => {
+                let stable = if ver.is_stable() { Some(ver) } else { None };
+                IntoStableIter(IntoStableIterInner::One(stable))
+            }
+        }
+    }
+
+    /// Convert into a list of all versions (including unstable ones).
+    pub fn into_versions(self) -> Vec<Version> {
+        match self {
+            VersionRoot::MultipleVersions { versions } => versions,
+            VersionRoot::OneVersion { version } => vec![version],
+        }
+    }
+}
+
 /// Deserialize a value where empty string is replaced by `Default` value.
 pub fn empty_as_default<'de, D, T>(des: D) -> Result<T, D::Error>
 where
@@ -182,11 +333,11 @@ where
 }
 
 #[cfg(test)]
-pub mod test {
+pub(crate) mod test {
     use serde::{Deserialize, Serialize};
     use serde_json;
 
-    use super::{empty_as_default, Version, VersionStatus};
+    use super::{empty_as_default, Version, VersionRoot, VersionStatus};
     use crate::ApiVersion;
 
     pub fn compare<T: Serialize>(sample: &str, value: T) {
@@ -307,6 +458,28 @@ pub mod test {
     fn test_version_parse() {
         let version: Version = serde_json::from_str(COMPUTE_ONE).unwrap();
         assert_eq!(version.id, ApiVersion(2, 1));
+        assert_eq!(
+            version.self_link().unwrap().as_str(),
+            "https://example.org:13774/v2.1/"
+        );
+    }
+
+    #[test]
+    fn test_version_root_roundtrip() {
+        let sample = format!(r#"{{"versions": [{}]}}"#, COMPUTE_ONE);
+        let root: VersionRoot = serde_json::from_str(&sample).unwrap();
+        let value = serde_json::to_value(&root).unwrap();
+        assert_eq!(value["versions"][0]["id"], "2.1");
+        assert_eq!(value["versions"][0]["status"], "CURRENT");
+        assert_eq!(value["versions"][0]["min_version"], "2.1");
+        let versions = root.into_versions();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].version, Some(ApiVersion(2, 42)));
+
+        let sample = format!(r#"{{"version": {}}}"#, COMPUTE_ONE);
+        let root: VersionRoot = serde_json::from_str(&sample).unwrap();
+        assert!(matches!(root, VersionRoot::OneVersion { .. }));
+        assert_eq!(root.into_stable_iter().count(), 1);
     }
 
     #[test]
@@ -324,5 +497,9 @@ pub mod test {
             serde_json::from_str::<VersionStatus>("\"SUPPORTED\"").unwrap(),
             VersionStatus::Supported
         );
+        assert_eq!(
+            serde_json::to_string(&VersionStatus::Deprecated).unwrap(),
+            "\"DEPRECATED\""
+        );
     }
 }
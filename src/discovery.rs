@@ -0,0 +1,172 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Process-wide cache of version discovery documents.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use reqwest::header::{HeaderMap, CACHE_CONTROL};
+use reqwest::Url;
+
+use super::common::VersionRoot;
+
+/// Time to keep documents without `Cache-Control`.
+const DEFAULT_TTL: Duration = Duration::from_secs(300);
+
+/// Maximum time to keep documents regardless of `Cache-Control`.
+const MAX_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Endpoint and the `Host` header override (if any).
+type Key = (Url, Option<String>);
+
+lazy_static::lazy_static! {
+    /// Discovery documents shared between sessions in this process.
+    static ref DISCOVERY_CACHE: Mutex<HashMap<Key, (Instant, VersionRoot)>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Get a cached document for the endpoint if it has not expired.
+pub(crate) fn get(endpoint: &Url, host: Option<&str>) -> Option<VersionRoot> {
+    let key = (endpoint.clone(), host.map(From::from));
+    let cache = DISCOVERY_CACHE
+        .lock()
+        .expect("Discovery cache lock is poisoned");
+    cache
+        .get(&key)
+        .filter(|(expires, _)| *expires > Instant::now())
+        .map(|(_, root)| root.clone())
+}
+
+/// Cache a document received with the given headers.
+///
+/// Documents with `Cache-Control: no-store`, `no-cache` or `max-age=0` are not cached.
+pub(crate) fn put(endpoint: Url, host: Option<&str>, headers: &HeaderMap, root: &VersionRoot) {
+    let ttl = match time_to_live(headers) {
+        Some(ttl) => ttl,
+        None => return,
+    };
+    let now = Instant::now();
+    let mut cache = DISCOVERY_CACHE
+        .lock()
+        .expect("Discovery cache lock is poisoned");
+    cache.retain(|_, (expires, _)| *expires > now);
+    let _ = cache.insert((endpoint, host.map(From::from)), (now + ttl, root.clone()));
+}
+
+/// Remove all cached version discovery documents.
+///
+/// Discovery documents are shared by all sessions in the process and kept according to their
+/// `Cache-Control` header (for 5 minutes if it is missing, for one day at most). Call this function
+/// to force a new discovery, e.g. after a cloud has been upgraded.
+pub fn clear_discovery_cache() {
+    DISCOVERY_CACHE
+        .lock()
+        .expect("Discovery cache lock is poisoned")
+        .clear();
+}
+
+/// How long a response can be cached according to its `Cache-Control` header.
+fn time_to_live(headers: &HeaderMap) -> Option<Duration> {
+    let mut result = DEFAULT_TTL;
+    for value in headers.get_all(CACHE_CONTROL) {
+        let value = match value.to_str() {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        for directive in value.split(',') {
+            let directive = directive.trim().to_lowercase();
+            if directive == "no-store" || directive == "no-cache" {
+                return None;
+            }
+            if let Some(max_age) = directive.strip_prefix("max-age=") {
+                match max_age.trim_matches('"').parse() {
+                    Ok(0) => return None,
+                    Ok(seconds) => result = Duration::from_secs(seconds).min(MAX_TTL),
+                    // An invalid max-age means the response is stale.
+                    Err(_) => return None,
+                }
+            }
+        }
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use reqwest::header::{HeaderMap, HeaderValue, CACHE_CONTROL};
+    use reqwest::Url;
+
+    use super::{clear_discovery_cache, get, put, time_to_live, DEFAULT_TTL, MAX_TTL};
+    use crate::common::VersionRoot;
+
+    fn headers(value: &'static str) -> HeaderMap {
+        let mut result = HeaderMap::new();
+        let _ = result.insert(CACHE_CONTROL, HeaderValue::from_static(value));
+        result
+    }
+
+    #[test]
+    fn test_time_to_live() {
+        assert_eq!(time_to_live(&HeaderMap::new()), Some(DEFAULT_TTL));
+        assert_eq!(
+            time_to_live(&headers("public, max-age=60")),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            time_to_live(&headers("Max-Age=\"3600\"")),
+            Some(Duration::from_secs(3600))
+        );
+        assert_eq!(
+            time_to_live(&headers("max-age=18446744073709551615")),
+            Some(MAX_TTL)
+        );
+        assert_eq!(time_to_live(&headers("max-age=0")), None);
+        assert_eq!(time_to_live(&headers("max-age=banana")), None);
+        assert_eq!(time_to_live(&headers("private, no-cache")), None);
+        assert_eq!(time_to_live(&headers("no-store")), None);
+    }
+
+    #[test]
+    fn test_cache() {
+        let root: VersionRoot = serde_json::from_value(serde_json::json!({
+            "versions": [{"id": "v2.1", "status": "CURRENT", "links": []}]
+        }))
+        .unwrap();
+        let endpoint = Url::parse("http://discovery.test/compute").unwrap();
+        assert!(get(&endpoint, None).is_none());
+
+        put(endpoint.clone(), None, &headers("no-store"), &root);
+        assert!(get(&endpoint, None).is_none());
+
+        put(endpoint.clone(), None, &HeaderMap::new(), &root);
+        let cached = get(&endpoint, None).unwrap();
+        assert_eq!(cached.into_versions().len(), 1);
+        assert!(get(&endpoint, Some("compute.example.com")).is_none());
+
+        put(
+            endpoint.clone(),
+            None,
+            &headers("max-age=18446744073709551615"),
+            &root,
+        );
+        assert!(get(&endpoint, None).is_some());
+
+        clear_discovery_cache();
+        assert!(get(&endpoint, None).is_none());
+    }
+}
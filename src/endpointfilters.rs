@@ -45,12 +45,22 @@ pub struct ValidInterfaces {
 #[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
 #[non_exhaustive]
 pub struct EndpointFilters {
-    /// Acceptable endpoint interfaces in the reverse priority order.
+    /// Acceptable endpoint interfaces in the priority order.
+    ///
+    /// The first interface that is present in the catalog is used, the following ones serve
+    /// as fallbacks.
     pub interfaces: ValidInterfaces,
     /// Cloud region.
     pub region: Option<String>,
+    /// Regions to try in the given order if no endpoint is found in `region`.
+    ///
+    /// Ignored if `region` is not set.
+    pub fallback_regions: Vec<String>,
+    /// Service name (for catalogs with several services of the same type).
+    pub service_name: Option<String>,
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for InterfaceType {
     fn default() -> Self {
         InterfaceType::Public
@@ -181,8 +191,9 @@ impl ValidInterfaces {
     /// Any duplicates are ignored.
     #[inline]
     pub fn append(&mut self, other: &ValidInterfaces) {
-        self.items = other.items;
-        self.len = other.len;
+        for item in other.iter() {
+            let _ = self.push(*item);
+        }
     }
 
     /// One valid interface.
@@ -244,6 +255,27 @@ impl FromStr for InterfaceType {
     }
 }
 
+impl FromStr for ValidInterfaces {
+    type Err = Error;
+
+    /// Parse a comma-separated list of interfaces in the priority order.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut result = ValidInterfaces::empty();
+        for item in s.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let _ = result.push(InterfaceType::from_str(item)?);
+        }
+
+        if result.is_empty() {
+            Err(Error::new(
+                ErrorKind::InvalidInput,
+                "At least one interface type is required",
+            ))
+        } else {
+            Ok(result)
+        }
+    }
+}
+
 impl EndpointFilters {
     /// Create filters with interfaces and region.
     ///
@@ -256,19 +288,44 @@ impl EndpointFilters {
         EndpointFilters {
             interfaces: interfaces.into_iter().collect(),
             region: Some(region.into()),
+            fallback_regions: Vec::new(),
+            service_name: None,
         }
     }
 
+    /// Create filters from environment variables.
+    ///
+    /// The region is taken from `OS_REGION_NAME`, the interfaces from `OS_INTERFACE`
+    /// (a comma-separated list in the priority order) or `OS_ENDPOINT_TYPE`.
+    /// Missing variables result in default values.
+    ///
+    /// ```rust,no_run
+    /// let filters = osauth::EndpointFilters::from_env().expect("Invalid OS_INTERFACE");
+    /// ```
+    pub fn from_env() -> Result<EndpointFilters, Error> {
+        crate::loading::filters_from_env()
+    }
+
     /// Whether the filters match the provided endpoint.
     pub fn check(&self, endpoint: &Endpoint) -> bool {
-        if !self.interfaces.check(endpoint) {
-            return false;
-        }
+        self.interfaces.check(endpoint) && self.find_region(&endpoint.region).is_some()
+    }
 
-        if let Some(ref region) = self.region {
-            endpoint.region == *region
+    /// Priority of the region: 0 for the primary region, 1+ for fallbacks.
+    ///
+    /// Any region has priority 0 if no region is set.
+    pub(crate) fn find_region(&self, region: &str) -> Option<usize> {
+        if let Some(ref primary) = self.region {
+            if primary == region {
+                Some(0)
+            } else {
+                self.fallback_regions
+                    .iter()
+                    .position(|x| x == region)
+                    .map(|x| x + 1)
+            }
         } else {
-            true
+            Some(0)
         }
     }
 
@@ -286,6 +343,22 @@ impl EndpointFilters {
         self.region = Some(value.into());
     }
 
+    /// Set regions to try if no endpoint is found in the primary region.
+    #[inline]
+    pub fn set_fallback_regions<I, T>(&mut self, value: I)
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.fallback_regions = value.into_iter().map(Into::into).collect();
+    }
+
+    /// Set service name.
+    #[inline]
+    pub fn set_service_name<T: Into<String>>(&mut self, value: T) {
+        self.service_name = Some(value.into());
+    }
+
     /// Add one or more valid interfaces.
     ///
     /// Hint: because of the generic argument can be used with one `InterfaceType` as well.
@@ -301,6 +374,24 @@ impl EndpointFilters {
         self.set_region(value);
         self
     }
+
+    /// Add regions to try if no endpoint is found in the primary region.
+    #[inline]
+    pub fn with_fallback_regions<I, T>(mut self, value: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.set_fallback_regions(value);
+        self
+    }
+
+    /// Add a service name.
+    #[inline]
+    pub fn with_service_name<T: Into<String>>(mut self, value: T) -> Self {
+        self.set_service_name(value);
+        self
+    }
 }
 
 #[cfg(test)]
@@ -308,18 +399,20 @@ pub mod test {
     use std::collections::hash_map::DefaultHasher;
     use std::hash::{Hash, Hasher};
 
+    use std::str::FromStr;
+
     use super::{InterfaceType, ValidInterfaces};
     use InterfaceType::*;
 
     #[test]
     fn test_valid_interfaces_basics() {
-        assert_eq!(std::mem::size_of::<ValidInterfaces>(), 4);
+        assert_eq!(size_of::<ValidInterfaces>(), 4);
 
         let empty = ValidInterfaces::empty();
         assert_eq!(empty.len(), 0);
         assert!(empty
             .iter()
-            .map(|x| *x)
+            .copied()
             .collect::<Vec<InterfaceType>>()
             .is_empty());
         assert_eq!(
@@ -398,6 +491,25 @@ pub mod test {
         assert_eq!(get_hash(&vi1), get_hash(&vi2));
     }
 
+    #[test]
+    fn test_valid_interfaces_append() {
+        let mut vi = ValidInterfaces::one(Internal);
+        vi.append(&vec![Public, Internal].into());
+        assert_eq!(*vi, [Internal, Public]);
+        vi.append(&vec![Admin, Public].into());
+        assert_eq!(*vi, [Internal, Public, Admin]);
+    }
+
+    #[test]
+    fn test_valid_interfaces_from_str() {
+        let vi = ValidInterfaces::from_str("internal").unwrap();
+        assert_eq!(*vi, [Internal]);
+        let vi = ValidInterfaces::from_str("internal, publicURL,internal").unwrap();
+        assert_eq!(*vi, [Internal, Public]);
+        assert!(ValidInterfaces::from_str("").is_err());
+        assert!(ValidInterfaces::from_str("internal,banana").is_err());
+    }
+
     #[test]
     fn test_valid_interfaces_from() {
         let vi: ValidInterfaces = vec![Public, Internal].into();
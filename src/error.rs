@@ -14,10 +14,30 @@
 
 //! Error and Result implementations.
 
+use std::error::Error as StdError;
 use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
 
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
 use reqwest::Error as HttpClientError;
-use reqwest::StatusCode;
+use reqwest::{Method, StatusCode, Url};
+
+use super::catalog;
+use super::url as url_utils;
+use super::{CatalogEndpoint, ErrorDetails};
+
+/// Headers that may contain the request ID, in the order of preference.
+const REQUEST_ID_HEADERS: &[&str] = &["x-openstack-request-id", "x-compute-request-id"];
+
+/// Extract the request ID from response headers.
+pub(crate) fn request_id(headers: &HeaderMap) -> Option<&str> {
+    REQUEST_ID_HEADERS
+        .iter()
+        .find_map(|name| headers.get(*name))
+        .and_then(|value| value.to_str().ok())
+}
 
 /// Kind of an error.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -25,9 +45,20 @@ use reqwest::StatusCode;
 pub enum ErrorKind {
     /// Authentication failure
     ///
-    /// Maps to HTTP 401.
+    /// The authentication layer has rejected the credentials, e.g. the identity service
+    /// returned HTTP 401 when a token was requested or refreshed.
     AuthenticationFailed,
 
+    /// The service did not accept the authentication.
+    ///
+    /// Maps to HTTP 401 returned by a service (as opposed to the authentication layer).
+    Unauthorized,
+
+    /// The authentication does not provide a service catalog.
+    ///
+    /// For example, the token is not scoped to a project or a domain.
+    CatalogMissing,
+
     /// Access denied.
     ///
     /// Maps to HTTP 403.
@@ -55,8 +86,30 @@ pub enum ErrorKind {
     IncompatibleApiVersion,
 
     /// Conflict in the request.
+    ///
+    /// Maps to HTTP 409.
     Conflict,
 
+    /// A precondition of the request (e.g. `If-Match`) was not satisfied.
+    ///
+    /// Maps to HTTP 412.
+    PreconditionFailed,
+
+    /// Request body is larger than the server is willing to process.
+    ///
+    /// Maps to HTTP 413.
+    RequestEntityTooLarge,
+
+    /// Content type of the request is not supported.
+    ///
+    /// Maps to HTTP 415.
+    UnsupportedMediaType,
+
+    /// Rate limit or quota reached.
+    ///
+    /// Maps to HTTP 429.
+    TooManyRequests,
+
     /// Operation has reached the specified time out.
     OperationTimedOut,
 
@@ -71,11 +124,22 @@ pub enum ErrorKind {
 
     /// Internal server error.
     ///
-    /// Maps to HTTP 5xx codes.
+    /// Maps to HTTP 5xx codes except for 501.
     InternalServerError,
 
+    /// Requested feature is not implemented by the server.
+    ///
+    /// Maps to HTTP 501.
+    NotImplemented,
+
     /// Invalid clouds.yaml, clouds-public.yaml or secure.yaml file.
     InvalidConfig,
+
+    /// Response body is larger than the configured maximum.
+    ResponseTooLarge,
+
+    /// Transferred data does not match its checksum.
+    ChecksumMismatch,
 }
 
 /// Error from an OpenStack call.
@@ -84,6 +148,13 @@ pub struct Error {
     kind: ErrorKind,
     message: String,
     status: Option<StatusCode>,
+    body: Option<Box<str>>,
+    headers: Option<Box<HeaderMap>>,
+    details: Option<Box<ErrorDetails>>,
+    source: Option<Arc<dyn StdError + Send + Sync + 'static>>,
+    request: Option<Box<(Method, Url)>>,
+    service_type: Option<Box<str>>,
+    candidates: Option<Box<[CatalogEndpoint]>>,
 }
 
 impl Error {
@@ -94,9 +165,102 @@ impl Error {
             kind,
             message: message.into(),
             status: None,
+            body: None,
+            headers: None,
+            details: None,
+            source: None,
+            request: None,
+            service_type: None,
+            candidates: None,
         }
     }
 
+    /// Add the method and URL of the failed request.
+    ///
+    /// Credentials and sensitive query parameters are removed from the URL.
+    #[inline]
+    pub fn set_request(&mut self, method: Method, url: &Url) {
+        self.request = Some(Box::new((method, url_utils::redact(url))));
+    }
+
+    /// Add the method and URL of the failed request.
+    ///
+    /// Credentials and sensitive query parameters are removed from the URL.
+    #[inline]
+    pub fn with_request(mut self, method: Method, url: &Url) -> Self {
+        self.set_request(method, url);
+        self
+    }
+
+    /// Add the service type of the failed request.
+    #[inline]
+    pub fn set_service_type<S: Into<String>>(&mut self, service_type: S) {
+        self.service_type = Some(service_type.into().into_boxed_str());
+    }
+
+    /// Add the service type of the failed request.
+    #[inline]
+    pub fn with_service_type<S: Into<String>>(mut self, service_type: S) -> Self {
+        self.set_service_type(service_type);
+        self
+    }
+
+    /// Add the underlying error.
+    ///
+    /// It will be returned from [source](#method.source).
+    #[inline]
+    pub fn set_source<E: StdError + Send + Sync + 'static>(&mut self, source: E) {
+        self.source = Some(Arc::new(source));
+    }
+
+    /// Add the underlying error.
+    ///
+    /// It will be returned from [source](#method.source).
+    #[inline]
+    pub fn with_source<E: StdError + Send + Sync + 'static>(mut self, source: E) -> Self {
+        self.set_source(source);
+        self
+    }
+
+    /// Add structured details of the error response.
+    #[inline]
+    pub fn set_details(&mut self, details: ErrorDetails) {
+        self.details = Some(Box::new(details));
+    }
+
+    /// Add structured details of the error response.
+    #[inline]
+    pub fn with_details(mut self, details: ErrorDetails) -> Self {
+        self.set_details(details);
+        self
+    }
+
+    /// Add the raw response body to the error.
+    #[inline]
+    pub fn set_body<S: Into<String>>(&mut self, body: S) {
+        self.body = Some(body.into().into_boxed_str());
+    }
+
+    /// Add the raw response body to the error.
+    #[inline]
+    pub fn with_body<S: Into<String>>(mut self, body: S) -> Self {
+        self.set_body(body);
+        self
+    }
+
+    /// Add response headers to the error.
+    #[inline]
+    pub fn set_headers(&mut self, headers: HeaderMap) {
+        self.headers = Some(Box::new(headers));
+    }
+
+    /// Add response headers to the error.
+    #[inline]
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.set_headers(headers);
+        self
+    }
+
     /// Add an HTTP status code to the error.
     #[inline]
     pub fn set_status(&mut self, status: StatusCode) {
@@ -116,6 +280,134 @@ impl Error {
         self.kind
     }
 
+    /// Structured details of the error response (if recognized).
+    #[inline]
+    pub fn details(&self) -> Option<&ErrorDetails> {
+        self.details.as_deref()
+    }
+
+    /// HTTP status code (if the error comes from an HTTP response).
+    #[inline]
+    pub fn status(&self) -> Option<StatusCode> {
+        self.status
+    }
+
+    /// HTTP method of the failed request (if known).
+    #[inline]
+    pub fn method(&self) -> Option<&Method> {
+        self.request.as_ref().map(|request| &request.0)
+    }
+
+    /// URL of the failed request (if known) with secrets removed.
+    #[inline]
+    pub fn url(&self) -> Option<&Url> {
+        self.request.as_ref().map(|request| &request.1)
+    }
+
+    /// Service type of the failed request (if known).
+    #[inline]
+    pub fn service_type(&self) -> Option<&str> {
+        self.service_type.as_deref()
+    }
+
+    /// Catalog endpoints that could have been used for the request.
+    ///
+    /// Provided with [EndpointNotFound](enum.ErrorKind.html#variant.EndpointNotFound) errors,
+    /// e.g. when the service is available in several regions and no region was requested.
+    #[inline]
+    pub fn candidate_endpoints(&self) -> &[CatalogEndpoint] {
+        self.candidates.as_deref().unwrap_or(&[])
+    }
+
+    /// Regions of the [candidate endpoints](#method.candidate_endpoints) (sorted, without
+    /// duplicates).
+    pub fn candidate_regions(&self) -> Vec<String> {
+        catalog::regions_of(self.candidate_endpoints())
+    }
+
+    /// Request ID assigned by the server (if any).
+    ///
+    /// Quote it when reporting issues to cloud operators.
+    pub fn request_id(&self) -> Option<&str> {
+        request_id(self.headers.as_ref()?)
+    }
+
+    /// Whether the error means that authentication has failed.
+    ///
+    /// Covers both failures of the authentication layer and HTTP 401 from services.
+    #[inline]
+    pub fn is_auth_failure(&self) -> bool {
+        matches!(
+            self.kind,
+            ErrorKind::AuthenticationFailed | ErrorKind::Unauthorized
+        )
+    }
+
+    /// Whether the error comes from the authentication layer rather than the target service.
+    ///
+    /// This is true when the credentials are rejected or the service catalog is not available.
+    /// Use this call to decide whether the credentials need rotating.
+    #[inline]
+    pub fn is_auth_layer_failure(&self) -> bool {
+        self.kind.is_auth_layer()
+    }
+
+    /// Whether the error means that the request conflicts with the current state.
+    #[inline]
+    pub fn is_conflict(&self) -> bool {
+        self.kind == ErrorKind::Conflict
+    }
+
+    /// Whether the error means that the requested resource was not found.
+    #[inline]
+    pub fn is_not_found(&self) -> bool {
+        self.kind == ErrorKind::ResourceNotFound
+    }
+
+    /// Whether the error means that a rate limit has been reached.
+    ///
+    /// See [retry_after](#method.retry_after) for how long to wait before retrying.
+    #[inline]
+    pub fn is_rate_limited(&self) -> bool {
+        self.kind == ErrorKind::TooManyRequests
+    }
+
+    /// Raw response body (if the error comes from an HTTP response).
+    ///
+    /// Unlike the message, the body is stored as it was received from the server, but it is
+    /// truncated to the size limit configured on the client (64 KiB by default).
+    #[inline]
+    pub fn body(&self) -> Option<&str> {
+        self.body.as_deref()
+    }
+
+    /// Response headers (if the error comes from an HTTP response).
+    #[inline]
+    pub fn headers(&self) -> Option<&HeaderMap> {
+        self.headers.as_deref()
+    }
+
+    /// Time to wait before retrying as requested by the server.
+    ///
+    /// Parses the `Retry-After` header of HTTP 429 and 503 responses, which can contain either
+    /// a number of seconds or an HTTP date.
+    pub fn retry_after(&self) -> Option<Duration> {
+        retry_after(self.status?, self.headers.as_ref()?)
+    }
+
+    /// Change the kind of the error.
+    #[inline]
+    pub(crate) fn with_kind(mut self, kind: ErrorKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Add the catalog endpoints that could have been used for the request.
+    pub(crate) fn with_candidates(mut self, candidates: Vec<CatalogEndpoint>) -> Self {
+        self.candidates = Some(candidates.into_boxed_slice());
+        self
+    }
+
     /// Helper - error of kind EndpointNotFound.
     pub(crate) fn new_endpoint_not_found<D: fmt::Display>(service_type: D) -> Error {
         Error::new(
@@ -125,13 +417,50 @@ impl Error {
     }
 }
 
+/// Time to wait before retrying as requested in the response with the given status and headers.
+pub(crate) fn retry_after(status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+    match status {
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+            let value = headers.get(RETRY_AFTER)?.to_str().ok()?;
+            parse_retry_after(value, Utc::now())
+        }
+        _ => None,
+    }
+}
+
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        Some(Duration::from_secs(seconds))
+    } else {
+        let date = DateTime::parse_from_rfc2822(value).ok()?;
+        // A date in the past means that the request can be retried immediately.
+        Some(
+            (date.with_timezone(&Utc) - now)
+                .to_std()
+                .unwrap_or_default(),
+        )
+    }
+}
+
 impl ErrorKind {
+    /// Whether the error kind belongs to the authentication layer.
+    #[inline]
+    pub fn is_auth_layer(self) -> bool {
+        matches!(
+            self,
+            ErrorKind::AuthenticationFailed | ErrorKind::CatalogMissing
+        )
+    }
+
     /// Short description of the error kind.
     #[allow(clippy::trivially_copy_pass_by_ref)]
     #[inline]
     pub fn description(&self) -> &'static str {
         match self {
             ErrorKind::AuthenticationFailed => "Failed to authenticate",
+            ErrorKind::Unauthorized => "Authentication was not accepted by the service",
+            ErrorKind::CatalogMissing => "No service catalog available",
             ErrorKind::AccessDenied => "Access to the resource is denied",
             ErrorKind::ResourceNotFound => "Requested resource was not found",
             ErrorKind::TooManyItems => "Request returned too many items",
@@ -139,12 +468,19 @@ impl ErrorKind {
             ErrorKind::InvalidInput => "Input value(s) are invalid or missing",
             ErrorKind::IncompatibleApiVersion => "Incompatible or unsupported API version",
             ErrorKind::Conflict => "Requested cannot be fulfilled due to a conflict",
+            ErrorKind::PreconditionFailed => "Precondition of the request was not satisfied",
+            ErrorKind::RequestEntityTooLarge => "Request body is too large",
+            ErrorKind::UnsupportedMediaType => "Content type of the request is not supported",
+            ErrorKind::TooManyRequests => "Too many requests, rate limit or quota reached",
             ErrorKind::OperationTimedOut => "Time out reached while waiting for the operation",
             ErrorKind::OperationFailed => "Requested operation has failed",
             ErrorKind::ProtocolError => "Error when accessing the server",
             ErrorKind::InvalidResponse => "Received invalid response",
             ErrorKind::InternalServerError => "Internal server error or bad gateway",
+            ErrorKind::NotImplemented => "Requested feature is not implemented",
             ErrorKind::InvalidConfig => "configuration file cannot be found or is invalid",
+            ErrorKind::ResponseTooLarge => "Response body is too large",
+            ErrorKind::ChecksumMismatch => "Data does not match its checksum",
         }
     }
 }
@@ -157,28 +493,53 @@ impl fmt::Display for ErrorKind {
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}: {}", self.kind, self.message)
+        write!(f, "{}: {}", self.kind, self.message)?;
+
+        let mut context = Vec::new();
+        if let Some(ref request) = self.request {
+            context.push(format!("{} {}", request.0, request.1));
+        }
+        if let Some(ref service_type) = self.service_type {
+            context.push(format!("service {}", service_type));
+        }
+        if let Some(request_id) = self.request_id() {
+            context.push(format!("request ID {}", request_id));
+        }
+
+        if context.is_empty() {
+            Ok(())
+        } else {
+            write!(f, " ({})", context.join(", "))
+        }
     }
 }
 
-impl ::std::error::Error for Error {
+impl StdError for Error {
     fn description(&self) -> &str {
         self.kind.description()
     }
 
-    fn cause(&self) -> Option<&dyn ::std::error::Error> {
-        None
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self.source {
+            Some(ref source) => Some(source.as_ref()),
+            None => None,
+        }
     }
 }
 
 impl From<StatusCode> for ErrorKind {
     fn from(value: StatusCode) -> ErrorKind {
         match value {
-            StatusCode::UNAUTHORIZED => ErrorKind::AuthenticationFailed,
+            StatusCode::UNAUTHORIZED => ErrorKind::Unauthorized,
             StatusCode::FORBIDDEN => ErrorKind::AccessDenied,
-            StatusCode::NOT_FOUND => ErrorKind::ResourceNotFound,
+            StatusCode::NOT_FOUND | StatusCode::GONE => ErrorKind::ResourceNotFound,
             StatusCode::NOT_ACCEPTABLE => ErrorKind::IncompatibleApiVersion,
             StatusCode::CONFLICT => ErrorKind::Conflict,
+            StatusCode::PRECONDITION_FAILED => ErrorKind::PreconditionFailed,
+            StatusCode::PAYLOAD_TOO_LARGE => ErrorKind::RequestEntityTooLarge,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => ErrorKind::UnsupportedMediaType,
+            StatusCode::TOO_MANY_REQUESTS => ErrorKind::TooManyRequests,
+            StatusCode::NOT_IMPLEMENTED => ErrorKind::NotImplemented,
             c if c.is_client_error() => ErrorKind::InvalidInput,
             c if c.is_server_error() => ErrorKind::InternalServerError,
             _ => ErrorKind::InvalidResponse,
@@ -198,18 +559,30 @@ impl From<HttpClientError> for Error {
                 .unwrap_or(ErrorKind::ProtocolError)
         };
 
-        let error = Error::new(kind, msg);
+        let mut error = Error::new(kind, msg);
         if let Some(status) = value.status() {
-            error.with_status(status)
-        } else {
-            error
+            error.set_status(status);
         }
+        error.with_source(value)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Error {
+        Error::new(ErrorKind::InvalidResponse, value.to_string()).with_source(value)
     }
 }
 
 #[cfg(test)]
 pub mod test {
-    use super::{Error, ErrorKind};
+    use std::error::Error as _;
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+    use reqwest::{Method, StatusCode, Url};
+
+    use super::{parse_retry_after, Error, ErrorKind};
 
     #[test]
     fn test_error_display() {
@@ -218,4 +591,141 @@ pub mod test {
         let s = format!("{}", error);
         assert_eq!(&s, "Input value(s) are invalid or missing: boom");
     }
+
+    #[test]
+    fn test_error_body() {
+        let error = Error::new(ErrorKind::InvalidInput, "boom").with_body("{\"boom\": 42}");
+        assert_eq!(error.body(), Some("{\"boom\": 42}"));
+        assert!(error.headers().is_none());
+    }
+
+    #[test]
+    fn test_error_display_context() {
+        let mut headers = HeaderMap::new();
+        let _ = headers.insert(
+            "x-openstack-request-id",
+            HeaderValue::from_static("req-1234"),
+        );
+        let url = Url::parse("https://example.com/v1/obj?temp_url_sig=abcd").unwrap();
+        let error = Error::new(ErrorKind::ResourceNotFound, "boom")
+            .with_request(Method::GET, &url)
+            .with_service_type("object-store")
+            .with_headers(headers);
+        assert_eq!(error.method(), Some(&Method::GET));
+        assert_eq!(error.request_id(), Some("req-1234"));
+        assert_eq!(
+            error.to_string(),
+            "Requested resource was not found: boom (GET https://example.com/v1/obj?temp_url_sig=***, \
+             service object-store, request ID req-1234)"
+        );
+    }
+
+    #[test]
+    fn test_predicates() {
+        let error =
+            Error::new(StatusCode::NOT_FOUND.into(), "boom").with_status(StatusCode::NOT_FOUND);
+        assert_eq!(error.status(), Some(StatusCode::NOT_FOUND));
+        assert!(error.is_not_found());
+        assert!(!error.is_conflict());
+        assert!(!error.is_rate_limited());
+        assert!(!error.is_auth_failure());
+
+        assert!(Error::new(ErrorKind::Conflict, "boom").is_conflict());
+        assert!(Error::new(ErrorKind::TooManyRequests, "boom").is_rate_limited());
+        assert!(Error::new(ErrorKind::AuthenticationFailed, "boom").is_auth_failure());
+        assert!(Error::new(ErrorKind::AuthenticationFailed, "boom").is_auth_layer_failure());
+        assert!(Error::new(ErrorKind::CatalogMissing, "boom").is_auth_layer_failure());
+        let error = Error::new(StatusCode::UNAUTHORIZED.into(), "boom");
+        assert!(error.is_auth_failure());
+        assert!(!error.is_auth_layer_failure());
+        assert_eq!(Error::new(ErrorKind::Conflict, "boom").status(), None);
+    }
+
+    #[test]
+    fn test_error_source() {
+        let error = Error::new(ErrorKind::InvalidInput, "boom");
+        assert!(error.source().is_none());
+
+        let json_error = serde_json::from_str::<u8>("boom").unwrap_err();
+        let json_message = json_error.to_string();
+        let error = Error::from(json_error);
+        assert_eq!(error.kind(), ErrorKind::InvalidResponse);
+        let source = error.source().unwrap();
+        assert_eq!(source.to_string(), json_message);
+        assert!(source.downcast_ref::<serde_json::Error>().is_some());
+        // Clones share the source.
+        assert!(error.clone().source().is_some());
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = Utc.with_ymd_and_hms(2015, 10, 21, 7, 27, 0).unwrap();
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT", now),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::from_secs(0))
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let mut headers = HeaderMap::new();
+        let _ = headers.insert(RETRY_AFTER, HeaderValue::from_static("5"));
+        let error = Error::new(ErrorKind::TooManyRequests, "slow down")
+            .with_status(StatusCode::TOO_MANY_REQUESTS)
+            .with_headers(headers.clone());
+        assert_eq!(error.retry_after(), Some(Duration::from_secs(5)));
+
+        let error = Error::new(ErrorKind::InvalidInput, "boom")
+            .with_status(StatusCode::BAD_REQUEST)
+            .with_headers(headers);
+        assert_eq!(error.retry_after(), None);
+
+        let error = Error::new(ErrorKind::TooManyRequests, "slow down")
+            .with_status(StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(error.retry_after(), None);
+    }
+
+    #[test]
+    fn test_kind_from_status() {
+        for (status, kind) in [
+            (StatusCode::BAD_REQUEST, ErrorKind::InvalidInput),
+            (StatusCode::UNAUTHORIZED, ErrorKind::Unauthorized),
+            (StatusCode::FORBIDDEN, ErrorKind::AccessDenied),
+            (StatusCode::NOT_FOUND, ErrorKind::ResourceNotFound),
+            (StatusCode::GONE, ErrorKind::ResourceNotFound),
+            (
+                StatusCode::NOT_ACCEPTABLE,
+                ErrorKind::IncompatibleApiVersion,
+            ),
+            (StatusCode::CONFLICT, ErrorKind::Conflict),
+            (
+                StatusCode::PRECONDITION_FAILED,
+                ErrorKind::PreconditionFailed,
+            ),
+            (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                ErrorKind::RequestEntityTooLarge,
+            ),
+            (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                ErrorKind::UnsupportedMediaType,
+            ),
+            (StatusCode::TOO_MANY_REQUESTS, ErrorKind::TooManyRequests),
+            (StatusCode::IM_A_TEAPOT, ErrorKind::InvalidInput),
+            (StatusCode::NOT_IMPLEMENTED, ErrorKind::NotImplemented),
+            (StatusCode::BAD_GATEWAY, ErrorKind::InternalServerError),
+            (StatusCode::OK, ErrorKind::InvalidResponse),
+        ] {
+            assert_eq!(ErrorKind::from(status), kind, "{}", status);
+        }
+    }
 }
@@ -0,0 +1,448 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Structured error responses of OpenStack services.
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// Structured details of an error response.
+///
+/// OpenStack services do not agree on a single error format. This enumeration covers formats
+/// of the most common services.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorDetails {
+    /// Format used by Compute, Block Storage and many other services.
+    ///
+    /// Example: `{"itemNotFound": {"code": 404, "message": "Server could not be found"}}`.
+    Generic {
+        /// Name of the error (e.g. `itemNotFound`) if provided.
+        name: Option<String>,
+        /// HTTP status code if provided.
+        code: Option<u16>,
+        /// Error message.
+        message: String,
+    },
+    /// Format used by the Network service (Neutron).
+    Neutron {
+        /// Type of the error, e.g. `NetworkNotFound`.
+        error_type: String,
+        /// Error message.
+        message: String,
+        /// Additional details (often empty).
+        detail: String,
+    },
+    /// Format used by the Identity service (Keystone).
+    Keystone {
+        /// HTTP status code.
+        code: u16,
+        /// Title of the error, e.g. `Not Found`.
+        title: String,
+        /// Error message.
+        message: String,
+    },
+    /// Format used by the Orchestration service (Heat).
+    Heat {
+        /// Type of the error, e.g. `EntityNotFound`.
+        error_type: String,
+        /// Title of the error, e.g. `Not Found`.
+        title: String,
+        /// Error message.
+        message: String,
+        /// Generic explanation of the HTTP status.
+        explanation: String,
+        /// Server-side traceback (only in debug mode).
+        traceback: Option<String>,
+    },
+    /// Format used by the Image service (Glance).
+    Glance {
+        /// HTTP status, e.g. `404 Not Found`.
+        code: String,
+        /// Title of the error, e.g. `Not Found`.
+        title: String,
+        /// Error message.
+        message: String,
+    },
+    /// Format used by the Bare Metal service (Ironic).
+    Ironic {
+        /// Fault code, e.g. `Client`.
+        fault_code: Option<String>,
+        /// Error message.
+        fault_string: String,
+        /// Debugging information (only in debug mode).
+        debug_info: Option<String>,
+    },
+}
+
+impl ErrorDetails {
+    /// Error message.
+    pub fn message(&self) -> &str {
+        match self {
+            ErrorDetails::Generic { message, .. }
+            | ErrorDetails::Neutron { message, .. }
+            | ErrorDetails::Keystone { message, .. }
+            | ErrorDetails::Heat { message, .. }
+            | ErrorDetails::Glance { message, .. } => message,
+            ErrorDetails::Ironic { fault_string, .. } => fault_string,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NeutronError {
+    #[serde(rename = "type", default)]
+    error_type: String,
+    message: String,
+    #[serde(default)]
+    detail: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct KeystoneError {
+    code: u16,
+    #[serde(default)]
+    title: String,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct HeatError {
+    #[serde(rename = "type", default)]
+    error_type: String,
+    message: String,
+    #[serde(default)]
+    traceback: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IronicError {
+    #[serde(rename = "faultcode", default)]
+    fault_code: Option<String>,
+    #[serde(rename = "faultstring")]
+    fault_string: String,
+    #[serde(rename = "debuginfo", default)]
+    debug_info: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GenericError {
+    #[serde(default)]
+    code: Option<u16>,
+    #[serde(alias = "faultstring", alias = "title")]
+    message: String,
+}
+
+fn get_str<'a>(map: &'a Map<String, Value>, key: &str) -> Option<&'a str> {
+    map.get(key).and_then(Value::as_str)
+}
+
+fn from_value<T: for<'de> Deserialize<'de>>(value: &Value) -> Option<T> {
+    T::deserialize(value).ok()
+}
+
+fn parse_ironic(value: &Value) -> Option<ErrorDetails> {
+    // Legacy format: JSON inside JSON (sigh)
+    let error: IronicError = match value {
+        Value::String(json) => serde_json::from_str(json).ok()?,
+        other => from_value(other)?,
+    };
+    Some(ErrorDetails::Ironic {
+        fault_code: error.fault_code,
+        fault_string: error.fault_string,
+        debug_info: error.debug_info,
+    })
+}
+
+fn parse_generic(name: Option<&str>, value: &Value) -> Option<ErrorDetails> {
+    let error: GenericError = from_value(value)?;
+    Some(ErrorDetails::Generic {
+        name: name.map(From::from),
+        code: error.code,
+        message: error.message,
+    })
+}
+
+/// Parse an error response body.
+pub(crate) fn parse(text: &str) -> Option<ErrorDetails> {
+    let value: Value = serde_json::from_str(text).ok()?;
+    let map = value.as_object()?;
+
+    if let Some(error) = map.get("NeutronError") {
+        let error: NeutronError = from_value(error)?;
+        return Some(ErrorDetails::Neutron {
+            error_type: error.error_type,
+            message: error.message,
+            detail: error.detail,
+        });
+    }
+
+    if let Some(error) = map.get("error").filter(|v| v.is_object()) {
+        if let Some(explanation) = get_str(map, "explanation") {
+            if let Some(error) = from_value::<HeatError>(error) {
+                return Some(ErrorDetails::Heat {
+                    error_type: error.error_type,
+                    title: get_str(map, "title").unwrap_or_default().into(),
+                    message: error.message,
+                    explanation: explanation.into(),
+                    traceback: error.traceback,
+                });
+            }
+        } else if let Some(error) = from_value::<KeystoneError>(error) {
+            return Some(ErrorDetails::Keystone {
+                code: error.code,
+                title: error.title,
+                message: error.message,
+            });
+        }
+    }
+
+    if let Some(error) = map.get("error_message") {
+        return parse_ironic(error);
+    }
+
+    if let (Some(code), Some(title), Some(message)) = (
+        get_str(map, "code"),
+        get_str(map, "title"),
+        get_str(map, "message"),
+    ) {
+        return Some(ErrorDetails::Glance {
+            code: code.into(),
+            title: title.into(),
+            message: message.into(),
+        });
+    }
+
+    if map.contains_key("faultstring") && map.contains_key("faultcode") {
+        return parse_ironic(&value);
+    }
+
+    if let Some(details) = parse_generic(None, &value) {
+        return Some(details);
+    }
+
+    map.iter()
+        .find_map(|(name, value)| parse_generic(Some(name), value))
+}
+
+/// Default maximum length of an error message built from a non-JSON body.
+pub(crate) const DEFAULT_MAX_MESSAGE_LENGTH: usize = 1024;
+
+const ENTITIES: &[(&str, &str)] = &[
+    ("&nbsp;", " "),
+    ("&lt;", "<"),
+    ("&gt;", ">"),
+    ("&quot;", "\""),
+    ("&#39;", "'"),
+    ("&amp;", "&"),
+];
+
+/// Make a readable message out of a non-JSON body.
+///
+/// Strips HTML tags, collapses whitespace and truncates the result to `max_length` characters.
+pub(crate) fn sanitize(text: &str, max_length: usize) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    if text.trim_start().starts_with('<') {
+        let mut in_tag = false;
+        for c in text.chars() {
+            match c {
+                '<' => in_tag = true,
+                '>' if in_tag => {
+                    in_tag = false;
+                    stripped.push(' ');
+                }
+                _ if in_tag => {}
+                _ => stripped.push(c),
+            }
+        }
+        for (entity, value) in ENTITIES {
+            if stripped.contains(entity) {
+                stripped = stripped.replace(entity, value);
+            }
+        }
+    } else {
+        stripped.push_str(text);
+    }
+
+    let mut result = stripped.split_whitespace().collect::<Vec<_>>().join(" ");
+    if let Some((index, _)) = result.char_indices().nth(max_length) {
+        result.truncate(index);
+        result.push_str("...");
+    }
+    result
+}
+
+/// Extract the error message and details from an error response body.
+pub(crate) fn extract_message(text: &str, max_length: usize) -> (String, Option<ErrorDetails>) {
+    match parse(text) {
+        Some(details) => (details.message().to_string(), Some(details)),
+        None => (sanitize(text, max_length), None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, sanitize, ErrorDetails, DEFAULT_MAX_MESSAGE_LENGTH};
+
+    fn extract_message(text: &str) -> String {
+        super::extract_message(text, DEFAULT_MAX_MESSAGE_LENGTH).0
+    }
+
+    #[test]
+    fn test_plain() {
+        let msg = "<html><body>I failed</body></html>";
+        let result = extract_message(msg);
+        assert_eq!(result, "I failed");
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let msg = "<html>\n<head><title>502 Bad Gateway</title></head>\n<body>\n\
+                   <center><h1>502 Bad Gateway</h1></center>\n<hr><center>nginx &amp; co</center>\n\
+                   </body>\n</html>";
+        assert_eq!(
+            sanitize(msg, 1024),
+            "502 Bad Gateway 502 Bad Gateway nginx & co"
+        );
+        assert_eq!(sanitize(msg, 7), "502 Bad...");
+        assert_eq!(sanitize("  plain\ttext  ", 1024), "plain text");
+        assert_eq!(sanitize("a < b &amp; c", 1024), "a < b &amp; c");
+    }
+
+    #[test]
+    fn test_simple_message() {
+        let msg = r#"{"message": "I failed"}"#;
+        let result = extract_message(msg);
+        assert_eq!(result, "I failed");
+    }
+
+    #[test]
+    fn test_nested_message() {
+        let msg = r#"{"SomethingFailed": {"message": "I failed"}}"#;
+        let result = extract_message(msg);
+        assert_eq!(result, "I failed");
+    }
+
+    #[test]
+    fn test_ironic_message() {
+        let msg = r#"{"error_message": {"faultstring": "I failed"}}"#;
+        let result = extract_message(msg);
+        assert_eq!(result, "I failed");
+    }
+
+    #[test]
+    fn test_ironic_legacy() {
+        let msg = r#"{"error_message": "{\"faultstring\": \"I failed\"}"}"#;
+        let result = extract_message(msg);
+        assert_eq!(result, "I failed");
+    }
+
+    #[test]
+    fn test_neutron() {
+        let body = r#"{"NeutronError": {"type": "NetworkNotFound",
+            "message": "Network abcd could not be found.", "detail": ""}}"#;
+        assert_eq!(
+            parse(body),
+            Some(ErrorDetails::Neutron {
+                error_type: "NetworkNotFound".into(),
+                message: "Network abcd could not be found.".into(),
+                detail: "".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_keystone() {
+        let body = r#"{"error": {"code": 401, "title": "Unauthorized",
+            "message": "The request you have made requires authentication."}}"#;
+        assert_eq!(
+            parse(body),
+            Some(ErrorDetails::Keystone {
+                code: 401,
+                title: "Unauthorized".into(),
+                message: "The request you have made requires authentication.".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_heat() {
+        let body = r#"{"code": 404, "title": "Not Found",
+            "explanation": "The resource could not be found.",
+            "error": {"type": "EntityNotFound", "message": "The Stack (x) could not be found.",
+                      "traceback": null}}"#;
+        assert_eq!(
+            parse(body),
+            Some(ErrorDetails::Heat {
+                error_type: "EntityNotFound".into(),
+                title: "Not Found".into(),
+                message: "The Stack (x) could not be found.".into(),
+                explanation: "The resource could not be found.".into(),
+                traceback: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_glance() {
+        let body = r#"{"code": "404 Not Found", "title": "Not Found",
+            "message": "No image found with ID abcd"}"#;
+        assert_eq!(
+            parse(body),
+            Some(ErrorDetails::Glance {
+                code: "404 Not Found".into(),
+                title: "Not Found".into(),
+                message: "No image found with ID abcd".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_ironic() {
+        let body = concat!(
+            r#"{"error_message": "{\"faultcode\": \"Client\", "#,
+            r#"\"faultstring\": \"Node abcd could not be found.\", \"debuginfo\": null}"}"#
+        );
+        assert_eq!(
+            parse(body),
+            Some(ErrorDetails::Ironic {
+                fault_code: Some("Client".into()),
+                fault_string: "Node abcd could not be found.".into(),
+                debug_info: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_generic() {
+        let body = r#"{"itemNotFound": {"code": 404, "message": "Flavor x could not be found."}}"#;
+        let details = parse(body).unwrap();
+        assert_eq!(
+            details,
+            ErrorDetails::Generic {
+                name: Some("itemNotFound".into()),
+                code: Some(404),
+                message: "Flavor x could not be found.".into(),
+            }
+        );
+        assert_eq!(details.message(), "Flavor x could not be found.");
+    }
+
+    #[test]
+    fn test_unknown() {
+        assert_eq!(parse("<html>oops</html>"), None);
+        assert_eq!(parse(r#"{"foo": "bar"}"#), None);
+        assert_eq!(parse(r#"[1, 2, 3]"#), None);
+    }
+}
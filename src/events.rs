@@ -0,0 +1,164 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Lifecycle events.
+
+use std::fmt;
+use std::sync::Arc;
+
+use chrono::{DateTime, FixedOffset};
+
+use super::Error;
+
+/// An event in the lifecycle of a session or an authentication.
+///
+/// Token events are emitted by the identity authentication types (e.g.
+/// [Password](identity/struct.Password.html)), the rest by the
+/// [Session](struct.Session.html) and the objects using it.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Event {
+    /// The cached token is about to expire and is going to be refreshed.
+    TokenExpiring {
+        /// Expiration time of the current token.
+        expires_at: DateTime<FixedOffset>,
+    },
+    /// A new token has been received.
+    TokenRefreshed {
+        /// Expiration time of the new token.
+        expires_at: DateTime<FixedOffset>,
+    },
+    /// A token could not be received.
+    ///
+    /// The error is also returned to the caller. Useful for reporting identity service outages.
+    TokenRefreshFailed {
+        /// The error that caused the failure.
+        error: Error,
+    },
+    /// A failed request is going to be retried.
+    RequestRetried {
+        /// Number of the retry (starting with 1).
+        attempt: usize,
+        /// The error that caused the retry.
+        error: Error,
+    },
+    /// Cached endpoint information has been dropped after an error.
+    ///
+    /// The endpoint is resolved again on the next request to the service.
+    EndpointInvalidated {
+        /// Service type.
+        service_type: String,
+        /// The error that caused the invalidation.
+        error: Error,
+    },
+    /// A request has been rejected with HTTP 429.
+    ///
+    /// The error contains the service type, the URL and the `Retry-After` duration (if any).
+    RateLimited {
+        /// The resulting error.
+        error: Error,
+    },
+}
+
+/// A handler of lifecycle events.
+///
+/// Implemented for closures:
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use osauth::Event;
+///
+/// let session = osauth::Session::from_env()
+///     .await?
+///     .with_event_handler(|event: &Event| {
+///         if let Event::RateLimited { error } = event {
+///             eprintln!("Slow down: {}", error);
+///         }
+///     });
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+///
+/// Handlers are called synchronously in the request path and must not block.
+pub trait EventHandler: Send + Sync {
+    /// Handle an event.
+    fn handle(&self, event: &Event);
+}
+
+impl<F> EventHandler for F
+where
+    F: Fn(&Event) + Send + Sync,
+{
+    fn handle(&self, event: &Event) {
+        self(event)
+    }
+}
+
+/// An optional event handler.
+#[derive(Clone, Default)]
+pub(crate) struct EventSink {
+    handler: Option<Arc<dyn EventHandler>>,
+}
+
+impl EventSink {
+    /// Create a sink with a handler.
+    pub fn new<H: EventHandler + 'static>(handler: H) -> EventSink {
+        EventSink {
+            handler: Some(Arc::new(handler)),
+        }
+    }
+
+    /// Emit an event if a handler is set.
+    ///
+    /// The event is only created when it is going to be handled.
+    #[inline]
+    pub fn emit<F: FnOnce() -> Event>(&self, event: F) {
+        if let Some(ref handler) = self.handler {
+            handler.handle(&event());
+        }
+    }
+}
+
+impl fmt::Debug for EventSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.handler {
+            Some(..) => f.write_str("EventSink(<handler>)"),
+            None => f.write_str("EventSink(None)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::{Event, EventSink};
+    use crate::{Error, ErrorKind};
+
+    #[test]
+    fn test_emit() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events2 = Arc::clone(&events);
+        let sink = EventSink::new(move |event: &Event| {
+            events2.lock().unwrap().push(format!("{:?}", event));
+        });
+        sink.emit(|| Event::RateLimited {
+            error: Error::new(ErrorKind::TooManyRequests, "slow down"),
+        });
+        assert_eq!(events.lock().unwrap().len(), 1);
+
+        EventSink::default().emit(|| panic!("Event created without a handler"));
+    }
+}
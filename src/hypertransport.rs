@@ -0,0 +1,206 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Transport using `hyper` directly.
+
+use async_trait::async_trait;
+use hyper::client::connect::Connect;
+use hyper::client::HttpConnector;
+use hyper::Body as HyperBody;
+use log::trace;
+use reqwest::{Client, Request, Response, ResponseBuilderExt};
+
+use super::client::Transport;
+use super::{Error, ErrorKind};
+
+/// Transport that sends authenticated requests with a `hyper` client.
+///
+/// Gives full control over connection management, e.g. a custom connector can be used to talk
+/// to a service over a Unix socket or through a tunnel:
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// let client = hyper::Client::builder()
+///     .pool_max_idle_per_host(4)
+///     .build_http();
+/// let session = osauth::Session::from_env()
+///     .await?
+///     .with_transport(osauth::HyperTransport::from_client(client));
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+///
+/// The default connector only supports plain HTTP, use a TLS connector (e.g. from the
+/// `hyper-tls` or `hyper-rustls` crates) for HTTPS endpoints.
+///
+/// Limitations:
+/// * Requests are still built as `reqwest` requests and responses are converted back to
+///   `reqwest` responses, only the sending is done by `hyper`.
+/// * Requests with streaming bodies fail with
+///   [InvalidInput](enum.ErrorKind.html#variant.InvalidInput) since `reqwest` does not expose
+///   them for conversion.
+/// * The authentication requests are still sent by the `reqwest` client of the session.
+///
+/// Available with the `hyper` feature.
+#[derive(Debug, Clone)]
+pub struct HyperTransport<C = HttpConnector> {
+    client: hyper::Client<C, HyperBody>,
+}
+
+impl HyperTransport {
+    /// Create a transport with the default (HTTP-only) connector.
+    pub fn new() -> HyperTransport {
+        HyperTransport::from_client(hyper::Client::new())
+    }
+}
+
+impl Default for HyperTransport {
+    fn default() -> HyperTransport {
+        HyperTransport::new()
+    }
+}
+
+impl<C> HyperTransport<C> {
+    /// Create a transport from an existing `hyper` client.
+    #[inline]
+    pub fn from_client(client: hyper::Client<C, HyperBody>) -> HyperTransport<C> {
+        HyperTransport { client }
+    }
+
+    /// Get a reference to the `hyper` client.
+    #[inline]
+    pub fn inner(&self) -> &hyper::Client<C, HyperBody> {
+        &self.client
+    }
+}
+
+/// Convert a `reqwest` request into a `hyper` one.
+fn into_hyper_request(request: Request) -> Result<hyper::Request<HyperBody>, Error> {
+    let body = match request.body() {
+        Some(body) => match body.as_bytes() {
+            Some(bytes) => HyperBody::from(bytes.to_vec()),
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Streaming request bodies are not supported by the hyper transport",
+                ))
+            }
+        },
+        None => HyperBody::empty(),
+    };
+
+    let mut builder = hyper::Request::builder()
+        .method(request.method().clone())
+        .uri(request.url().as_str())
+        .version(request.version());
+    if let Some(headers) = builder.headers_mut() {
+        *headers = request.headers().clone();
+    }
+    builder.body(body).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Cannot build a request: {}", e),
+        )
+        .with_source(e)
+    })
+}
+
+#[async_trait]
+impl<C> Transport for HyperTransport<C>
+where
+    C: Connect + Clone + Send + Sync + std::fmt::Debug + 'static,
+{
+    async fn execute(&self, _client: &Client, request: Request) -> Result<Response, Error> {
+        let url = request.url().clone();
+        let timeout = request.timeout().copied();
+        let request = into_hyper_request(request)?;
+
+        trace!("Sending a request to {} via hyper", url);
+        let future = self.client.request(request);
+        let response = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, future).await.map_err(|_| {
+                Error::new(
+                    ErrorKind::OperationTimedOut,
+                    format!("Request to {} timed out", url),
+                )
+            })?,
+            None => future.await,
+        }
+        .map_err(|e| {
+            Error::new(ErrorKind::ProtocolError, format!("Request failed: {}", e)).with_source(e)
+        })?;
+
+        let (parts, body) = response.into_parts();
+        let mut builder = http::Response::builder()
+            .status(parts.status)
+            .version(parts.version)
+            .url(url);
+        if let Some(headers) = builder.headers_mut() {
+            *headers = parts.headers;
+        }
+        Ok(builder
+            .body(body)
+            .expect("Response parts are always valid")
+            .into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::{Method, Url};
+
+    use super::HyperTransport;
+    use crate::client::AuthenticatedClient;
+    use crate::utils::test::serve_raw_http;
+
+    #[tokio::test]
+    async fn test_hyper_transport() {
+        let (addr, server) = serve_raw_http(vec![
+            "HTTP/1.1 200 OK\r\ncontent-length: 14\r\n\r\n{\"answer\": 42}".into(),
+        ])
+        .await;
+        let url = format!("http://{}", addr);
+
+        let mut client = AuthenticatedClient::new_noauth(&url).await;
+        client.set_transport(HyperTransport::new());
+        let value: serde_json::Value = client
+            .request(Method::GET, Url::parse(&url).unwrap().join("v2").unwrap())
+            .header("x-test", "value")
+            .fetch()
+            .await
+            .unwrap();
+        assert_eq!(value["answer"], 42);
+
+        let request = server.await.unwrap().remove(0).to_lowercase();
+        assert!(request.starts_with("get /v2 http/1.1\r\n"), "{}", request);
+        assert!(request.contains("x-test: value\r\n"), "{}", request);
+    }
+
+    #[cfg(feature = "stream")]
+    #[test]
+    fn test_streaming_body() {
+        use super::into_hyper_request;
+        use crate::ErrorKind;
+
+        let body = reqwest::Body::wrap_stream(futures::stream::empty::<Result<Vec<u8>, String>>());
+        let request = reqwest::Client::new()
+            .post("http://127.0.0.1/")
+            .body(body)
+            .build()
+            .unwrap();
+        let err = into_hyper_request(request).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
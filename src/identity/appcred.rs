@@ -0,0 +1,381 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Application credentials management.
+
+use std::fmt;
+
+use chrono::{DateTime, FixedOffset};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use super::IdOrName;
+use crate::common::IdAndName;
+use crate::services::IDENTITY;
+use crate::{Error, ErrorKind, Session};
+
+/// An access rule of an application credential.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct AccessRule {
+    /// Rule ID (only set for existing rules).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Service type the rule applies to.
+    pub service: String,
+    /// HTTP method the rule allows.
+    pub method: String,
+    /// API path the rule allows (may contain `*` and `**` wildcards).
+    pub path: String,
+}
+
+/// An application credential.
+#[derive(Clone, Deserialize)]
+#[non_exhaustive]
+pub struct ApplicationCredential {
+    /// Application credential ID.
+    pub id: String,
+    /// Application credential name.
+    pub name: String,
+    /// Description (if any).
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Secret, only returned on creation.
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Expiration time (if any).
+    #[serde(default)]
+    pub expires_at: Option<DateTime<FixedOffset>>,
+    /// ID of the project the credential is scoped to.
+    pub project_id: String,
+    /// Roles delegated to the credential.
+    #[serde(default)]
+    pub roles: Vec<IdAndName>,
+    /// Whether the credential may be used to create other credentials and trusts.
+    #[serde(default)]
+    pub unrestricted: bool,
+    /// Access rules (empty if access is not restricted).
+    #[serde(default)]
+    pub access_rules: Vec<AccessRule>,
+}
+
+/// A request to create an application credential.
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use osauth::identity::{AccessRule, NewApplicationCredential};
+///
+/// let session = osauth::Session::from_env().await?;
+/// let credential = session
+///     .create_application_credential(
+///         NewApplicationCredential::new("ci").with_access_rule(AccessRule {
+///             id: None,
+///             service: "compute".into(),
+///             method: "GET".into(),
+///             path: "/v2.1/servers".into(),
+///         }),
+///     )
+///     .await?;
+/// println!("Created {} with secret {:?}", credential.id, credential.secret);
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[derive(Clone, Serialize)]
+pub struct NewApplicationCredential {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<DateTime<FixedOffset>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    roles: Vec<IdOrName>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unrestricted: Option<bool>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    access_rules: Vec<AccessRule>,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateRoot<'a> {
+    application_credential: &'a NewApplicationCredential,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApplicationCredentialRoot {
+    application_credential: ApplicationCredential,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApplicationCredentialsRoot {
+    application_credentials: Vec<ApplicationCredential>,
+}
+
+impl fmt::Debug for ApplicationCredential {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ApplicationCredential")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("description", &self.description)
+            .field("secret", &self.secret.as_ref().map(|_| "<hidden>"))
+            .field("expires_at", &self.expires_at)
+            .field("project_id", &self.project_id)
+            .field("roles", &self.roles)
+            .field("unrestricted", &self.unrestricted)
+            .field("access_rules", &self.access_rules)
+            .finish()
+    }
+}
+
+impl fmt::Debug for NewApplicationCredential {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NewApplicationCredential")
+            .field("name", &self.name)
+            .field("description", &self.description)
+            .field("secret", &self.secret.as_ref().map(|_| "<hidden>"))
+            .field("expires_at", &self.expires_at)
+            .field("roles", &self.roles)
+            .field("unrestricted", &self.unrestricted)
+            .field("access_rules", &self.access_rules)
+            .finish()
+    }
+}
+
+impl NewApplicationCredential {
+    /// Start creating an application credential with the given name.
+    pub fn new<S: Into<String>>(name: S) -> NewApplicationCredential {
+        NewApplicationCredential {
+            name: name.into(),
+            description: None,
+            secret: None,
+            expires_at: None,
+            roles: Vec::new(),
+            unrestricted: None,
+            access_rules: Vec::new(),
+        }
+    }
+
+    /// Add an access rule.
+    #[inline]
+    pub fn add_access_rule(&mut self, value: AccessRule) {
+        self.access_rules.push(value);
+    }
+
+    /// Add a role to delegate (all roles of the current token by default).
+    #[inline]
+    pub fn add_role(&mut self, value: IdOrName) {
+        self.roles.push(value);
+    }
+
+    /// Set the description.
+    #[inline]
+    pub fn set_description<S: Into<String>>(&mut self, value: S) {
+        self.description = Some(value.into());
+    }
+
+    /// Set the expiration time.
+    #[inline]
+    pub fn set_expires_at(&mut self, value: DateTime<FixedOffset>) {
+        self.expires_at = Some(value);
+    }
+
+    /// Set the secret (generated by the service by default).
+    #[inline]
+    pub fn set_secret<S: Into<String>>(&mut self, value: S) {
+        self.secret = Some(value.into());
+    }
+
+    /// Allow the credential to create other credentials and trusts.
+    #[inline]
+    pub fn set_unrestricted(&mut self, value: bool) {
+        self.unrestricted = Some(value);
+    }
+
+    /// Add an access rule.
+    #[inline]
+    pub fn with_access_rule(mut self, value: AccessRule) -> Self {
+        self.add_access_rule(value);
+        self
+    }
+
+    /// Add a role to delegate (all roles of the current token by default).
+    #[inline]
+    pub fn with_role(mut self, value: IdOrName) -> Self {
+        self.add_role(value);
+        self
+    }
+
+    /// Set the description.
+    #[inline]
+    pub fn with_description<S: Into<String>>(mut self, value: S) -> Self {
+        self.set_description(value);
+        self
+    }
+
+    /// Set the expiration time.
+    #[inline]
+    pub fn with_expires_at(mut self, value: DateTime<FixedOffset>) -> Self {
+        self.set_expires_at(value);
+        self
+    }
+
+    /// Set the secret (generated by the service by default).
+    #[inline]
+    pub fn with_secret<S: Into<String>>(mut self, value: S) -> Self {
+        self.set_secret(value);
+        self
+    }
+
+    /// Allow the credential to create other credentials and trusts.
+    #[inline]
+    pub fn with_unrestricted(mut self, value: bool) -> Self {
+        self.set_unrestricted(value);
+        self
+    }
+}
+
+impl Session {
+    /// Create an application credential for the current user.
+    ///
+    /// The secret of the new credential is only available in the result of this call.
+    pub async fn create_application_credential(
+        &self,
+        credential: NewApplicationCredential,
+    ) -> Result<ApplicationCredential, Error> {
+        let user_id = self.current_user_id().await?;
+        debug!(
+            "Creating application credential {} for user {}",
+            credential.name, user_id
+        );
+        let root: ApplicationCredentialRoot = self
+            .post(IDENTITY, &["users", &user_id, "application_credentials"])
+            .json(&CreateRoot {
+                application_credential: &credential,
+            })
+            .fetch()
+            .await?;
+        Ok(root.application_credential)
+    }
+
+    /// Delete an application credential of the current user.
+    pub async fn delete_application_credential<S: AsRef<str>>(&self, id: S) -> Result<(), Error> {
+        let user_id = self.current_user_id().await?;
+        debug!(
+            "Deleting application credential {} of user {}",
+            id.as_ref(),
+            user_id
+        );
+        let _ = self
+            .delete(
+                IDENTITY,
+                &["users", &user_id, "application_credentials", id.as_ref()],
+            )
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// List application credentials of the current user.
+    pub async fn list_application_credentials(&self) -> Result<Vec<ApplicationCredential>, Error> {
+        let user_id = self.current_user_id().await?;
+        let root: ApplicationCredentialsRoot = self
+            .get_json(IDENTITY, &["users", &user_id, "application_credentials"])
+            .await?;
+        Ok(root.application_credentials)
+    }
+
+    pub(super) async fn current_user_id(&self) -> Result<String, Error> {
+        self.client().get_user_id().await?.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidConfig,
+                "This operation requires an Identity authentication",
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::test;
+
+    const CREATE_REQUEST: &str = r#"
+{
+    "application_credential": {
+        "name": "ci",
+        "description": "For CI",
+        "roles": [{"name": "member"}],
+        "access_rules": [
+            {
+                "service": "compute",
+                "method": "GET",
+                "path": "/v2.1/servers"
+            }
+        ]
+    }
+}"#;
+
+    const CREATE_RESPONSE: &str = r#"
+{
+    "application_credential": {
+        "id": "aa809205ed614a0e854bac92c0768bb9",
+        "name": "ci",
+        "description": "For CI",
+        "secret": "JxE7LajLY75NZgDH1hfu0N_6xS9hQ-Af40W3",
+        "expires_at": null,
+        "project_id": "1a6f364d14a846e2b11bc8e0b3f5b2a7",
+        "roles": [{"id": "4494bc5bea1a4105ad7fbba6a7eb9ef4", "name": "member"}],
+        "unrestricted": false,
+        "access_rules": [
+            {
+                "id": "07d719df00f349ef8de77d542edf010c",
+                "service": "compute",
+                "method": "GET",
+                "path": "/v2.1/servers"
+            }
+        ],
+        "links": {}
+    }
+}"#;
+
+    #[test]
+    fn test_create_request() {
+        let credential = NewApplicationCredential::new("ci")
+            .with_description("For CI")
+            .with_role(IdOrName::from_name("member"))
+            .with_access_rule(AccessRule {
+                id: None,
+                service: "compute".into(),
+                method: "GET".into(),
+                path: "/v2.1/servers".into(),
+            });
+        test::compare(
+            CREATE_REQUEST,
+            CreateRoot {
+                application_credential: &credential,
+            },
+        );
+    }
+
+    #[test]
+    fn test_create_response() {
+        let root: ApplicationCredentialRoot = serde_json::from_str(CREATE_RESPONSE).unwrap();
+        let credential = root.application_credential;
+        assert_eq!(credential.name, "ci");
+        assert_eq!(credential.roles[0].name, "member");
+        assert_eq!(credential.access_rules.len(), 1);
+        assert!(!format!("{:?}", credential).contains("JxE7"));
+    }
+}
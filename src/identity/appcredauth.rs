@@ -0,0 +1,402 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Application credential authentication.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use reqwest::{Client, RequestBuilder, Url};
+use static_assertions::assert_impl_all;
+
+use super::internal::Internal;
+use super::protocol;
+#[cfg(feature = "token-cache")]
+use super::TokenCache;
+use super::{AccessRule, IdOrName, TokenHeader};
+use crate::{
+    AuthType, CatalogEntry, Clock, EndpointFilters, Error, EventHandler, MetricsSink, RetryPolicy,
+};
+
+/// Application credential authentication using Identity API V3.
+///
+/// An application credential is already scoped to a project, so no scope is needed.
+/// It can be referenced either by its ID using [new](#method.new) or by its name and
+/// its owner using [from_name](#method.from_name):
+///
+/// ```rust,no_run
+/// let auth = osauth::identity::ApplicationCredentialAuth::new(
+///     "https://cloud.local/identity",
+///     "aa809205ed614a0e854bac92c0768bb9",
+///     "<a secret>",
+/// )
+/// .expect("Invalid auth_url");
+///
+/// let session = osauth::Session::new(auth);
+/// ```
+///
+/// Credentials created with [access
+/// rules](struct.NewApplicationCredential.html#method.with_access_rule) only allow the listed
+/// API calls. Use [access_rules](#method.access_rules) to check them.
+#[derive(Debug, Clone)]
+pub struct ApplicationCredentialAuth {
+    inner: Internal,
+}
+
+assert_impl_all!(ApplicationCredentialAuth: Send, Sync);
+
+impl ApplicationCredentialAuth {
+    /// Create an application credential authentication from the credential ID.
+    pub fn new<U, S1, S2>(auth_url: U, id: S1, secret: S2) -> Result<Self, Error>
+    where
+        U: AsRef<str>,
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        Self::with_credential(
+            auth_url.as_ref(),
+            IdOrName::Id(id.into()),
+            secret.into(),
+            None,
+        )
+    }
+
+    /// Create an application credential authentication from the credential and user names.
+    pub fn from_name<U, S1, S2, S3, S4>(
+        auth_url: U,
+        name: S1,
+        secret: S2,
+        user_name: S3,
+        user_domain_name: S4,
+    ) -> Result<Self, Error>
+    where
+        U: AsRef<str>,
+        S1: Into<String>,
+        S2: Into<String>,
+        S3: Into<String>,
+        S4: Into<String>,
+    {
+        Self::from_name_and_user(
+            auth_url,
+            name,
+            secret,
+            IdOrName::Name(user_name.into()),
+            IdOrName::Name(user_domain_name.into()),
+        )
+    }
+
+    /// Create an application credential authentication from the credential name and user ID.
+    pub fn from_name_and_user_id<U, S1, S2, S3>(
+        auth_url: U,
+        name: S1,
+        secret: S2,
+        user_id: S3,
+    ) -> Result<Self, Error>
+    where
+        U: AsRef<str>,
+        S1: Into<String>,
+        S2: Into<String>,
+        S3: Into<String>,
+    {
+        Self::from_name_and_user(auth_url, name, secret, IdOrName::Id(user_id.into()), None)
+    }
+
+    /// Create an application credential authentication from the credential name and its owner.
+    ///
+    /// The user domain is only required when the user is referenced by name.
+    pub fn from_name_and_user<U, S1, S2>(
+        auth_url: U,
+        name: S1,
+        secret: S2,
+        user: IdOrName,
+        user_domain: impl Into<Option<IdOrName>>,
+    ) -> Result<Self, Error>
+    where
+        U: AsRef<str>,
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        let user = protocol::User {
+            user,
+            domain: user_domain.into(),
+        };
+        Self::with_credential(
+            auth_url.as_ref(),
+            IdOrName::Name(name.into()),
+            secret.into(),
+            Some(user),
+        )
+    }
+
+    fn with_credential(
+        auth_url: &str,
+        credential: IdOrName,
+        secret: String,
+        user: Option<protocol::User>,
+    ) -> Result<Self, Error> {
+        let body = protocol::AuthRoot {
+            auth: protocol::Auth {
+                identity: protocol::Identity::ApplicationCredential(
+                    protocol::ApplicationCredentialAndSecret {
+                        credential,
+                        secret,
+                        user,
+                    },
+                ),
+                scope: None,
+            },
+        };
+        Ok(Self {
+            inner: Internal::new(auth_url, body)?,
+        })
+    }
+
+    /// Use a persistent token cache.
+    ///
+    /// Tokens are loaded from the cache before authenticating and stored there afterwards.
+    #[cfg(feature = "token-cache")]
+    #[inline]
+    pub fn set_token_cache(&mut self, cache: TokenCache) {
+        self.inner.set_token_cache(cache);
+    }
+
+    /// Set a handler for token events.
+    ///
+    /// [TokenExpiring](../enum.Event.html#variant.TokenExpiring),
+    /// [TokenRefreshed](../enum.Event.html#variant.TokenRefreshed) and
+    /// [TokenRefreshFailed](../enum.Event.html#variant.TokenRefreshFailed) are emitted.
+    #[inline]
+    pub fn set_event_handler<H: EventHandler + 'static>(&mut self, handler: H) {
+        self.inner.set_event_handler(handler);
+    }
+
+    /// Set a sink for authentication metrics.
+    ///
+    /// See [MetricsSink](../trait.MetricsSink.html) for details.
+    #[inline]
+    pub fn set_metrics_sink<S: MetricsSink + 'static>(&mut self, sink: S) {
+        self.inner.set_metrics_sink(sink);
+    }
+
+    /// Set a clock for token validity checks.
+    ///
+    /// Use [ManualClock](../struct.ManualClock.html) to simulate token expiry in tests.
+    #[inline]
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.inner.set_clock(clock);
+    }
+
+    /// Whether to append the API version (`v3`) to the authentication URL.
+    ///
+    /// By default (`None`), the version is appended unless the URL already ends with `/v3`.
+    /// Use `false` if the URL points to the Identity API v3 root under a different path, e.g.
+    /// behind a proxy, and `true` if a path that ends with `v3` is not the API root.
+    #[inline]
+    pub fn set_append_version(&mut self, value: impl Into<Option<bool>>) {
+        self.inner.set_append_version(value.into());
+    }
+
+    /// Set a retry policy for token requests (no retries by default).
+    ///
+    /// Token requests are retried on HTTP 429 and 503 and, unlike other `POST` requests, on
+    /// connection failures, HTTP 502 and 504, e.g. while the Identity service is restarting.
+    /// The policy is independent of the retry policy of the session.
+    #[inline]
+    pub fn set_token_retry_policy(&mut self, value: RetryPolicy) {
+        self.inner.set_retry_policy(value);
+    }
+
+    /// Set the header used to pass the token in authenticated requests.
+    ///
+    /// The default is [XAuthToken](enum.TokenHeader.html#variant.XAuthToken).
+    #[inline]
+    pub fn set_token_header(&mut self, value: TokenHeader) {
+        self.inner.set_token_header(value);
+    }
+
+    /// Share the token with other authentication objects in this process.
+    ///
+    /// Objects with the same authentication URL, credentials and scope use one token and
+    /// refresh it together, so that independently created sessions for the same cloud
+    /// do not authenticate separately.
+    #[inline]
+    pub fn set_shared_cache(&mut self, value: bool) {
+        self.inner.set_shared(value);
+    }
+
+    /// Use the given handler for token events.
+    #[inline]
+    pub fn with_event_handler<H: EventHandler + 'static>(mut self, handler: H) -> Self {
+        self.set_event_handler(handler);
+        self
+    }
+
+    /// Use the given sink for authentication metrics.
+    #[inline]
+    pub fn with_metrics_sink<S: MetricsSink + 'static>(mut self, sink: S) -> Self {
+        self.set_metrics_sink(sink);
+        self
+    }
+
+    /// Use the given clock for token validity checks.
+    #[inline]
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.set_clock(clock);
+        self
+    }
+
+    /// Whether to append the API version (`v3`) to the authentication URL.
+    #[inline]
+    pub fn with_append_version(mut self, value: impl Into<Option<bool>>) -> Self {
+        self.set_append_version(value);
+        self
+    }
+
+    /// Use the given retry policy for token requests.
+    #[inline]
+    pub fn with_token_retry_policy(mut self, value: RetryPolicy) -> Self {
+        self.set_token_retry_policy(value);
+        self
+    }
+
+    /// Use the given header to pass the token in authenticated requests.
+    #[inline]
+    pub fn with_token_header(mut self, value: TokenHeader) -> Self {
+        self.set_token_header(value);
+        self
+    }
+
+    /// Share the token with other authentication objects in this process.
+    #[inline]
+    pub fn with_shared_cache(mut self, value: bool) -> Self {
+        self.set_shared_cache(value);
+        self
+    }
+
+    /// Use a persistent token cache.
+    #[cfg(feature = "token-cache")]
+    #[inline]
+    pub fn with_token_cache(mut self, cache: TokenCache) -> Self {
+        self.set_token_cache(cache);
+        self
+    }
+
+    /// User name or ID (if the credential is referenced by name).
+    #[inline]
+    pub fn user(&self) -> Option<&IdOrName> {
+        self.inner.user()
+    }
+
+    /// Access rules restricting the credential (empty if not restricted).
+    ///
+    /// Authenticates if there is no valid token yet.
+    pub async fn access_rules(&self, client: &Client) -> Result<Vec<AccessRule>, Error> {
+        self.inner.get_access_rules(client).await
+    }
+}
+
+#[async_trait]
+impl AuthType for ApplicationCredentialAuth {
+    /// Authenticate a request.
+    async fn authenticate(
+        &self,
+        client: &Client,
+        request: RequestBuilder,
+    ) -> Result<RequestBuilder, Error> {
+        self.inner.authenticate(client, request).await
+    }
+
+    /// Get a URL for the requested service.
+    async fn get_endpoint(
+        &self,
+        client: &Client,
+        service_type: &str,
+        filters: &EndpointFilters,
+    ) -> Result<Url, Error> {
+        self.inner.get_endpoint(client, service_type, filters).await
+    }
+
+    /// Get all records from the service catalog.
+    async fn get_catalog(&self, client: &Client) -> Result<Vec<CatalogEntry>, Error> {
+        self.inner.get_catalog(client).await
+    }
+
+    /// Get the ID of the authenticated user.
+    async fn get_user_id(&self, client: &Client) -> Result<Option<String>, Error> {
+        self.inner.get_user_id(client).await.map(Some)
+    }
+
+    /// Get names of the roles assigned to the authenticated user.
+    async fn get_roles(&self, client: &Client) -> Result<Vec<String>, Error> {
+        self.inner.get_roles(client).await
+    }
+
+    /// Get the current authentication token.
+    async fn get_token(&self, client: &Client) -> Result<Option<String>, Error> {
+        self.inner.get_token(client).await.map(Some)
+    }
+
+    /// Refresh the cached token and service catalog.
+    async fn refresh(&self, client: &Client) -> Result<(), Error> {
+        self.inner.refresh(client, true).await
+    }
+
+    /// Create a copy with its own token cache, starting with the current token.
+    /// Time when the cached token was issued (if any).
+    fn token_issued_at(&self) -> Option<DateTime<FixedOffset>> {
+        self.inner.token_issued_at()
+    }
+
+    fn detach(&self) -> Option<Arc<dyn AuthType>> {
+        Some(Arc::new(ApplicationCredentialAuth {
+            inner: self.inner.detach(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ApplicationCredentialAuth;
+    use crate::identity::IdOrName;
+
+    #[test]
+    fn test_new() {
+        let auth = ApplicationCredentialAuth::new(
+            "http://127.0.0.1:8080/identity",
+            "aa809205ed614a0e854bac92c0768bb9",
+            "s3cr3t",
+        )
+        .unwrap();
+        assert!(auth.user().is_none());
+        assert_eq!(
+            auth.inner.token_endpoint(),
+            "http://127.0.0.1:8080/identity/v3/auth/tokens"
+        );
+        assert!(!format!("{:?}", auth).contains("s3cr3t"));
+    }
+
+    #[test]
+    fn test_from_name() {
+        let auth = ApplicationCredentialAuth::from_name(
+            "http://127.0.0.1:8080/identity",
+            "ci",
+            "s3cr3t",
+            "admin",
+            "Default",
+        )
+        .unwrap();
+        assert_eq!(auth.user(), Some(&IdOrName::Name("admin".to_string())));
+    }
+}
@@ -14,28 +14,62 @@
 
 //! Internal implementation of the identity authentication.
 
+use std::borrow::Cow;
 use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
 use std::fmt;
 use std::hash::{Hash, Hasher};
-use std::ops::Deref;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
 
-use chrono::{DateTime, Duration, FixedOffset, Local};
-use log::{debug, error, trace};
+use chrono::{DateTime, Duration, FixedOffset};
+use log::{debug, error, trace, warn};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, DATE};
 use reqwest::{Client, RequestBuilder, Response, Url};
+use serde::Deserialize;
 use tokio::sync::{RwLock, RwLockReadGuard};
+use tokio::time::sleep;
 
+use super::passcode::PasscodeSource;
 use super::protocol::{self, AuthRoot};
-use super::{IdOrName, Scope, INVALID_SUBJECT_HEADER, MISSING_SUBJECT_HEADER, TOKEN_MIN_VALIDITY};
+use super::receipt::{AuthReceipt, RECEIPT_HEADER};
+#[cfg(feature = "token-cache")]
+use base64::engine::general_purpose::STANDARD as BASE64;
+#[cfg(feature = "token-cache")]
+use base64::Engine;
+#[cfg(feature = "token-cache")]
+use sha2::{Digest, Sha256};
+
+#[cfg(feature = "token-cache")]
+use super::tokencache::{CachedToken, TokenCache};
+use super::{
+    AccessRule, IdOrName, Scope, TokenHeader, CLOCK_OFFSET_THRESHOLD, INVALID_SUBJECT_HEADER,
+    MISSING_SUBJECT_HEADER, TOKEN_MIN_VALIDITY,
+};
 use crate::catalog::ServiceCatalog;
 use crate::client;
-use crate::{EndpointFilters, Error, ErrorKind};
+use crate::clock::{Clock, SystemClock};
+use crate::events::EventSink;
+#[cfg(feature = "tracing")]
+use crate::instrument;
+use crate::{
+    CatalogEntry, EndpointFilters, Error, ErrorKind, Event, EventHandler, MetricsSink, RetryPolicy,
+};
 
 /// Plain authentication token without additional details.
 #[derive(Clone)]
 pub(crate) struct Token {
     value: String,
     expires_at: DateTime<FixedOffset>,
+    issued_at: Option<DateTime<FixedOffset>>,
     catalog: ServiceCatalog,
+    user_id: String,
+    /// Names of the roles assigned to the user.
+    roles: Vec<String>,
+    /// Access rules of the application credential used to obtain the token.
+    access_rules: Vec<AccessRule>,
+    /// Offset of the server clock relative to the local one.
+    clock_offset: Duration,
 }
 
 static_assertions::assert_eq_size!(Option<Token>, Token);
@@ -46,24 +80,50 @@ impl fmt::Debug for Token {
         self.value.hash(&mut hasher);
         write!(
             f,
-            "Token {{ value: hash({}), catalog: {:?} }}",
+            "Token {{ value: hash({}), user_id: {}, catalog: {:?} }}",
             hasher.finish(),
+            self.user_id,
             self.catalog
         )
     }
 }
 
+type TokenSlot = Arc<RwLock<Option<Token>>>;
+
+lazy_static::lazy_static! {
+    /// Token slots shared between authentication objects in this process.
+    static ref SHARED_TOKENS: Mutex<HashMap<String, Weak<RwLock<Option<Token>>>>> =
+        Mutex::new(HashMap::new());
+}
+
 /// Internal identity authentication object.
 #[derive(Debug)]
 pub(crate) struct Internal {
     body: AuthRoot,
+    auth_url: String,
+    /// Authentication URL with a trailing slash.
+    base_url: Url,
     token_endpoint: String,
-    cached_token: RwLock<Option<Token>>,
+    cached_token: TokenSlot,
+    shared: bool,
+    events: EventSink,
+    metrics: Option<Arc<dyn MetricsSink>>,
+    clock: Arc<dyn Clock>,
+    token_header: TokenHeader,
+    /// Retry policy of token requests.
+    retry_policy: RetryPolicy,
+    /// Authentication receipt to continue multi-factor authentication.
+    receipt: Option<HeaderValue>,
+    /// Source of TOTP passcodes requested on every authentication.
+    passcode: Option<PasscodeSource>,
+    #[cfg(feature = "token-cache")]
+    token_cache: Option<TokenCache>,
 }
 
 impl Internal {
     /// Create a new implementation.
     pub fn new(auth_url: &str, body: AuthRoot) -> Result<Internal, Error> {
+        let original_auth_url = auth_url;
         let mut auth_url = Url::parse(auth_url)
             .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("Invalid auth_url: {}", e)))?;
 
@@ -73,16 +133,24 @@ impl Internal {
             .pop_if_empty()
             .push("");
 
-        let token_endpoint = if auth_url.as_str().ends_with("/v3/") {
-            format!("{}auth/tokens", auth_url)
-        } else {
-            format!("{}v3/auth/tokens", auth_url)
-        };
+        let token_endpoint = token_endpoint(&auth_url, None);
 
         Ok(Internal {
             body,
+            auth_url: original_auth_url.to_string(),
+            base_url: auth_url,
             token_endpoint,
-            cached_token: RwLock::new(None),
+            cached_token: Arc::new(RwLock::new(None)),
+            shared: false,
+            events: EventSink::default(),
+            metrics: None,
+            clock: Arc::new(SystemClock),
+            token_header: TokenHeader::default(),
+            retry_policy: RetryPolicy::default(),
+            receipt: None,
+            passcode: None,
+            #[cfg(feature = "token-cache")]
+            token_cache: None,
         })
     }
 
@@ -109,6 +177,30 @@ impl Internal {
         token.catalog.find_endpoint(service_type, filters)
     }
 
+    /// Get all records from the service catalog.
+    pub async fn get_catalog(&self, client: &Client) -> Result<Vec<CatalogEntry>, Error> {
+        let token = self.cached_token(client).await?;
+        Ok(token.catalog.entries())
+    }
+
+    /// Get the ID of the authenticated user.
+    pub async fn get_user_id(&self, client: &Client) -> Result<String, Error> {
+        let token = self.cached_token(client).await?;
+        Ok(token.user_id.clone())
+    }
+
+    /// Get names of the roles assigned to the authenticated user.
+    pub async fn get_roles(&self, client: &Client) -> Result<Vec<String>, Error> {
+        let token = self.cached_token(client).await?;
+        Ok(token.roles.clone())
+    }
+
+    /// Get the access rules restricting the token (empty if not restricted).
+    pub async fn get_access_rules(&self, client: &Client) -> Result<Vec<AccessRule>, Error> {
+        let token = self.cached_token(client).await?;
+        Ok(token.access_rules.clone())
+    }
+
     /// Get the authentication token string.
     #[inline]
     pub async fn get_token(&self, client: &Client) -> Result<String, Error> {
@@ -116,20 +208,233 @@ impl Internal {
         Ok(token.value.clone())
     }
 
+    /// Time when the cached token was issued (in the local time), if known.
+    ///
+    /// Returns `None` without waiting if the token is being refreshed.
+    pub fn token_issued_at(&self) -> Option<DateTime<FixedOffset>> {
+        let guard = self.cached_token.try_read().ok()?;
+        let token = guard.as_ref()?;
+        token.issued_at.map(|value| value - token.clock_offset)
+    }
+
     /// Add a scope to the authentication.
     pub fn set_scope(&mut self, scope: Scope) {
         self.body.auth.scope = Some(match scope {
             Scope::Project { project, domain } => {
                 protocol::Scope::Project(protocol::Project { project, domain })
             }
+            Scope::Domain(domain) => protocol::Scope::Domain(domain),
         });
+        if self.shared {
+            // The key has changed, so has the slot.
+            self.cached_token = shared_slot(self.cache_key());
+        }
+    }
+
+    /// Whether to append the API version to the authentication URL (`None` to detect).
+    pub fn set_append_version(&mut self, value: Option<bool>) {
+        self.token_endpoint = token_endpoint(&self.base_url, value);
+        if self.shared {
+            // The key has changed, so has the slot.
+            self.cached_token = shared_slot(self.cache_key());
+        }
+    }
+
+    /// Set a handler for token events.
+    pub fn set_event_handler<H: EventHandler + 'static>(&mut self, handler: H) {
+        self.events = EventSink::new(handler);
+    }
+
+    /// Set a sink for authentication metrics.
+    pub fn set_metrics_sink<S: MetricsSink + 'static>(&mut self, sink: S) {
+        self.metrics = Some(Arc::new(sink));
+    }
+
+    /// Set a clock for token validity checks.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Arc::new(clock);
+    }
+
+    /// Set a retry policy for token requests.
+    pub fn set_retry_policy(&mut self, value: RetryPolicy) {
+        self.retry_policy = value;
+    }
+
+    /// Set the header used to pass the token.
+    pub fn set_token_header(&mut self, value: TokenHeader) {
+        self.token_header = value;
+    }
+
+    /// Whether to share the token with other authentication objects in this process.
+    pub fn set_shared(&mut self, value: bool) {
+        if value == self.shared {
+            return;
+        }
+
+        self.shared = value;
+        self.cached_token = if value {
+            shared_slot(self.cache_key())
+        } else {
+            Arc::new(RwLock::new(None))
+        };
+    }
+
+    /// A key that identifies the authentication URL, identity and scope.
+    ///
+    /// The key contains the credentials and must never be logged.
+    pub fn cache_key(&self) -> String {
+        let body = serde_json::to_string(&self.body).expect("AuthRoot is always serializable");
+        format!("{}\n{}", self.token_endpoint, body)
+    }
+
+    /// A key for the persistent token cache: a hex-encoded SHA-256 hash of the cache key.
+    #[cfg(feature = "token-cache")]
+    pub fn persistent_cache_key(&self) -> String {
+        format!("{:x}", Sha256::digest(self.cache_key().as_bytes()))
+    }
+
+    /// The cache ID that python keystoneauth uses for the same authentication.
+    ///
+    /// Mirrors `get_cache_id` of the generic `password` and `token` plugins: a base64-encoded
+    /// SHA-256 hash of the sorted non-empty parameters.
+    #[cfg(feature = "token-cache")]
+    pub fn keystoneauth_cache_id(&self) -> String {
+        let mut elements = vec![("auth_url", self.auth_url.as_str())];
+        match self.body.auth.scope {
+            Some(protocol::Scope::Project(ref prj)) => {
+                push_id_or_name(&mut elements, &prj.project, "project_id", "project_name");
+                if let Some(ref domain) = prj.domain {
+                    push_id_or_name(
+                        &mut elements,
+                        domain,
+                        "project_domain_id",
+                        "project_domain_name",
+                    );
+                }
+            }
+            Some(protocol::Scope::Domain(ref domain)) => {
+                push_id_or_name(&mut elements, domain, "domain_id", "domain_name");
+            }
+            _ => {}
+        }
+        match self.body.auth.identity {
+            protocol::Identity::Password(ref pw)
+            | protocol::Identity::PasswordAndTotp(ref pw, ..) => {
+                push_id_or_name(&mut elements, &pw.user, "user_id", "username");
+                elements.push(("password", pw.password.as_str()));
+                if let Some(ref domain) = pw.domain {
+                    push_id_or_name(&mut elements, domain, "user_domain_id", "user_domain_name");
+                }
+            }
+            protocol::Identity::Token(ref token) => elements.push(("token", token.as_str())),
+            protocol::Identity::Totp(ref user) => {
+                elements.push(("user_id", user.id.as_str()));
+                elements.push(("passcode", user.passcode.as_str()));
+            }
+            protocol::Identity::ApplicationCredential(ref cred) => {
+                push_id_or_name(
+                    &mut elements,
+                    &cred.credential,
+                    "application_credential_id",
+                    "application_credential_name",
+                );
+                elements.push(("application_credential_secret", cred.secret.as_str()));
+                if let Some(ref user) = cred.user {
+                    push_id_or_name(&mut elements, &user.user, "user_id", "username");
+                    if let Some(ref domain) = user.domain {
+                        push_id_or_name(
+                            &mut elements,
+                            domain,
+                            "user_domain_id",
+                            "user_domain_name",
+                        );
+                    }
+                }
+            }
+        }
+        elements.sort_unstable();
+
+        let mut data = Vec::new();
+        for (key, value) in elements {
+            data.extend_from_slice(key.as_bytes());
+            data.extend_from_slice(value.as_bytes());
+        }
+        BASE64.encode(Sha256::digest(&data))
+    }
+
+    /// Use a persistent token cache.
+    #[cfg(feature = "token-cache")]
+    #[inline]
+    pub fn set_token_cache(&mut self, cache: TokenCache) {
+        self.token_cache = Some(cache);
+    }
+
+    /// Send the authentication receipt with token requests.
+    pub fn set_receipt(&mut self, value: &str) -> Result<(), Error> {
+        let mut value = HeaderValue::from_str(value).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid authentication receipt: {}", e),
+            )
+        })?;
+        value.set_sensitive(true);
+        self.receipt = Some(value);
+        Ok(())
+    }
+
+    /// Request a TOTP passcode from the source on every authentication.
+    #[inline]
+    pub fn set_passcode_source(&mut self, value: PasscodeSource) {
+        self.passcode = Some(value);
+    }
+
+    /// Authentication body with a fresh passcode (if a passcode source is set).
+    async fn current_body(&self) -> Result<Cow<'_, AuthRoot>, Error> {
+        let passcode = match self.passcode {
+            Some(ref source) => source.passcode().await?,
+            None => return Ok(Cow::Borrowed(&self.body)),
+        };
+        let mut body = self.body.clone();
+        body.auth.identity = match body.auth.identity {
+            protocol::Identity::Password(pw) | protocol::Identity::PasswordAndTotp(pw, ..) => {
+                protocol::Identity::PasswordAndTotp(pw, passcode)
+            }
+            protocol::Identity::Totp(user) => {
+                protocol::Identity::Totp(protocol::UserAndPasscode { passcode, ..user })
+            }
+            other => other,
+        };
+        Ok(Cow::Owned(body))
+    }
+
+    /// Start multi-factor authentication.
+    ///
+    /// Returns `None` (and caches the token) if no more authentication methods are required.
+    pub async fn request_receipt(&self, client: &Client) -> Result<Option<AuthReceipt>, Error> {
+        match self.refresh(client, true).await {
+            Ok(()) => Ok(None),
+            Err(error) => {
+                let scope = match self.body.auth.scope {
+                    Some(protocol::Scope::Project(ref prj)) => Some(prj),
+                    _ => None,
+                };
+                match AuthReceipt::from_error(&error, &self.auth_url, scope)? {
+                    Some(receipt) => Ok(Some(receipt)),
+                    None => Err(error),
+                }
+            }
+        }
     }
 
     /// User name or ID.
     #[inline]
     pub fn user(&self) -> Option<&IdOrName> {
         match self.body.auth.identity {
-            protocol::Identity::Password(ref pw) => Some(&pw.user),
+            protocol::Identity::Password(ref pw)
+            | protocol::Identity::PasswordAndTotp(ref pw, ..) => Some(&pw.user),
+            protocol::Identity::ApplicationCredential(ref cred) => {
+                cred.user.as_ref().map(|user| &user.user)
+            }
             _ => None,
         }
     }
@@ -147,26 +452,141 @@ impl Internal {
     pub async fn refresh(&self, client: &Client, force: bool) -> Result<(), Error> {
         // This is executed every request at least once, so it's important to start with a read
         // lock. We expect to hit this branch most of the time.
-        if !force && token_alive(&self.cached_token.read().await) {
+        if !force && token_alive(&*self.cached_token.read().await, &*self.clock) {
             return Ok(());
         }
 
         let mut lock = self.cached_token.write().await;
         // Additonal check in case another thread has updated the token while we were waiting for
         // the write lock.
-        if token_alive(&lock) {
+        if !force && token_alive(&lock, &*self.clock) {
             return Ok(());
         }
+        // A forced refresh replaces a token that is still valid (e.g. revoked), not an expiring one.
+        if !force {
+            if let Some(ref token) = *lock {
+                let expires_at = token.expires_at;
+                self.events.emit(|| Event::TokenExpiring { expires_at });
+            }
+        }
 
-        let resp = client
-            .post(&self.token_endpoint)
-            .json(&self.body)
-            .send()
-            .await?;
-        *lock = Some(token_from_response(client::check(resp).await?).await?);
+        #[cfg(feature = "token-cache")]
+        let cache_key = match self.token_cache {
+            Some(ref cache) => {
+                let key = if cache.keystoneauth_compatible() {
+                    self.keystoneauth_cache_id()
+                } else {
+                    self.persistent_cache_key()
+                };
+                // A forced refresh is usually caused by a revoked token, do not reuse it.
+                if !force {
+                    if let Some(cached) = cache.load(key.clone()).await {
+                        // The server clock offset is unknown for cached tokens.
+                        let token = token_from_body(cached.value, &cached.body, Duration::zero());
+                        match token.map(Some) {
+                            Ok(token) if token_alive(&token, &*self.clock) => {
+                                debug!("Using a token from the persistent cache");
+                                *lock = token;
+                                return Ok(());
+                            }
+                            Ok(_) => debug!("Cached token is expired or about to expire"),
+                            Err(e) => debug!("Ignoring an invalid cached token: {}", e),
+                        }
+                    }
+                }
+                Some(key)
+            }
+            None => None,
+        };
+
+        let future = async {
+            let body = self.current_body().await?;
+            let resp = self.send_token_request(client, &body).await?;
+            let resp = client::check(resp).await.map_err(|e| {
+                if e.kind() == ErrorKind::Unauthorized {
+                    e.with_kind(ErrorKind::AuthenticationFailed)
+                } else {
+                    e
+                }
+            })?;
+            let clock_offset = clock_offset(resp.headers(), &*self.clock);
+            let (value, body) = token_from_response(resp).await?;
+            let token = token_from_body(value.clone(), &body, clock_offset)?;
+            #[cfg(feature = "tracing")]
+            let _ = tracing::Span::current()
+                .record("expires_at", tracing::field::display(&token.expires_at));
+            #[cfg(feature = "token-cache")]
+            if let (Some(cache), Some(key)) = (self.token_cache.as_ref(), cache_key) {
+                cache.store(key, CachedToken { value, body }).await;
+            }
+            Ok::<_, Error>(token)
+        };
+        #[cfg(feature = "tracing")]
+        let future =
+            tracing::Instrument::instrument(future, instrument::token(&self.token_endpoint));
+        let started = Instant::now();
+        let token = match future.await {
+            Ok(token) => token,
+            Err(error) => {
+                self.events.emit(|| Event::TokenRefreshFailed {
+                    error: error.clone(),
+                });
+                return Err(error);
+            }
+        };
+        if let Some(ref metrics) = self.metrics {
+            metrics.auth_refreshed(started.elapsed());
+        }
+        let expires_at = token.expires_at;
+        self.events.emit(|| Event::TokenRefreshed { expires_at });
+        *lock = Some(token);
         Ok(())
     }
 
+    /// Send the token request, retrying transient failures according to the retry policy.
+    async fn send_token_request(
+        &self,
+        client: &Client,
+        body: &AuthRoot,
+    ) -> Result<Response, Error> {
+        let policy = &self.retry_policy;
+        policy.start();
+        let mut attempt = 0;
+        loop {
+            let mut request = client.post(&self.token_endpoint).json(body);
+            if let Some(ref receipt) = self.receipt {
+                request = request.header(RECEIPT_HEADER, receipt.clone());
+            }
+            let result = request.send().await.map_err(Error::from);
+            attempt += 1;
+            // Every request issues a new token, so it is safe to repeat on connection failures.
+            let delay = match policy.delay_with(attempt, true, &result) {
+                Some(delay) if policy.allow() => delay,
+                _ => return result,
+            };
+
+            let error = match result {
+                Ok(response) => match client::check(response).await {
+                    Err(error) => error,
+                    // The status is an error status, so this cannot happen.
+                    Ok(response) => return Ok(response),
+                },
+                Err(error) => error,
+            };
+            warn!(
+                "Token request to {} failed, retrying in {:?} ({}/{}): {}",
+                self.token_endpoint,
+                delay,
+                attempt,
+                policy.max_retries(),
+                error
+            );
+            self.events
+                .emit(|| Event::RequestRetried { attempt, error });
+            sleep(delay).await;
+        }
+    }
+
     /// Create an authenticated request.
     pub async fn authenticate(
         &self,
@@ -174,7 +594,51 @@ impl Internal {
         request: RequestBuilder,
     ) -> Result<RequestBuilder, Error> {
         let token = self.get_token(client).await?;
-        Ok(request.header("x-auth-token", token))
+        let value = match self.token_header {
+            TokenHeader::Bearer => HeaderValue::from_str(&format!("Bearer {}", token)),
+            _ => HeaderValue::from_str(&token),
+        };
+        let mut value = value.map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidResponse,
+                format!("Token is not a valid header value: {}", e),
+            )
+        })?;
+        // Hides the token from the Debug output of requests.
+        value.set_sensitive(true);
+        Ok(match self.token_header {
+            TokenHeader::XAuthToken => request.header("x-auth-token", value),
+            TokenHeader::Bearer => request.header(AUTHORIZATION, value),
+            TokenHeader::Custom(ref name) => request.header(name, value),
+        })
+    }
+
+    /// Create a copy with its own token slot, starting with the current token (if any).
+    ///
+    /// The copy does not participate in token sharing.
+    pub fn detach(&self) -> Internal {
+        let token = self
+            .cached_token
+            .try_read()
+            .ok()
+            .and_then(|guard| guard.clone());
+        Internal {
+            body: self.body.clone(),
+            auth_url: self.auth_url.clone(),
+            base_url: self.base_url.clone(),
+            token_endpoint: self.token_endpoint.clone(),
+            cached_token: Arc::new(RwLock::new(token)),
+            shared: false,
+            events: self.events.clone(),
+            metrics: self.metrics.clone(),
+            clock: Arc::clone(&self.clock),
+            token_header: self.token_header.clone(),
+            retry_policy: self.retry_policy.clone(),
+            receipt: self.receipt.clone(),
+            passcode: self.passcode.clone(),
+            #[cfg(feature = "token-cache")]
+            token_cache: self.token_cache.clone(),
+        }
     }
 
     #[cfg(test)]
@@ -187,16 +651,102 @@ impl Clone for Internal {
     fn clone(&self) -> Internal {
         Internal {
             body: self.body.clone(),
+            auth_url: self.auth_url.clone(),
+            base_url: self.base_url.clone(),
             token_endpoint: self.token_endpoint.clone(),
-            cached_token: RwLock::new(None),
+            cached_token: if self.shared {
+                Arc::clone(&self.cached_token)
+            } else {
+                Arc::new(RwLock::new(None))
+            },
+            shared: self.shared,
+            events: self.events.clone(),
+            metrics: self.metrics.clone(),
+            clock: Arc::clone(&self.clock),
+            token_header: self.token_header.clone(),
+            retry_policy: self.retry_policy.clone(),
+            receipt: self.receipt.clone(),
+            passcode: self.passcode.clone(),
+            #[cfg(feature = "token-cache")]
+            token_cache: self.token_cache.clone(),
         }
     }
 }
 
+/// Build the token endpoint from the authentication URL (with a trailing slash).
+///
+/// Without an explicit choice, `v3` is appended unless the URL already ends with it.
+fn token_endpoint(base_url: &Url, append_version: Option<bool>) -> String {
+    let append_version = append_version.unwrap_or_else(|| !base_url.as_str().ends_with("/v3/"));
+    if append_version {
+        format!("{}v3/auth/tokens", base_url)
+    } else {
+        format!("{}auth/tokens", base_url)
+    }
+}
+
+#[cfg(feature = "token-cache")]
+fn push_id_or_name<'a>(
+    elements: &mut Vec<(&'static str, &'a str)>,
+    value: &'a IdOrName,
+    id_key: &'static str,
+    name_key: &'static str,
+) {
+    elements.push(match value {
+        IdOrName::Id(id) => (id_key, id.as_str()),
+        IdOrName::Name(name) => (name_key, name.as_str()),
+    });
+}
+
+/// Find or create a token slot shared in this process.
+fn shared_slot(key: String) -> TokenSlot {
+    let mut slots = SHARED_TOKENS
+        .lock()
+        .expect("Shared token cache lock is poisoned");
+    if let Some(slot) = slots.get(&key).and_then(Weak::upgrade) {
+        trace!("Reusing a shared token slot");
+        return slot;
+    }
+
+    // Drop slots that are no longer used by anyone.
+    slots.retain(|_, slot| slot.strong_count() > 0);
+    let slot = Arc::new(RwLock::new(None));
+    let _ = slots.insert(key, Arc::downgrade(&slot));
+    slot
+}
+
+/// Offset of the server clock (from the `Date` header) relative to the local clock.
+///
+/// Token expiration times are in the server time, so the offset is used to avoid using expired
+/// tokens (or refreshing them too often) on hosts with skewed clocks.
+fn clock_offset(headers: &HeaderMap, clock: &dyn Clock) -> Duration {
+    let server_now = match headers
+        .get(DATE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+    {
+        Some(value) => value,
+        None => return Duration::zero(),
+    };
+    let offset = server_now.signed_duration_since(clock.now());
+    // The Date header has a precision of one second, ignore the rounding and network latency.
+    if offset.num_seconds().abs() < CLOCK_OFFSET_THRESHOLD {
+        Duration::zero()
+    } else {
+        debug!(
+            "Server clock differs from the local one by {} seconds",
+            offset.num_seconds()
+        );
+        offset
+    }
+}
+
 #[inline]
-fn token_alive(token: &impl Deref<Target = Option<Token>>) -> bool {
-    if let Some(value) = token.deref() {
-        let validity_time_left = value.expires_at.signed_duration_since(Local::now());
+fn token_alive(token: &Option<Token>, clock: &dyn Clock) -> bool {
+    if let Some(value) = token {
+        let validity_time_left = value
+            .expires_at
+            .signed_duration_since(clock.now() + value.clock_offset);
         trace!("Token is valid for {:?}", validity_time_left);
         validity_time_left > Duration::minutes(TOKEN_MIN_VALIDITY)
     } else {
@@ -204,7 +754,7 @@ fn token_alive(token: &impl Deref<Target = Option<Token>>) -> bool {
     }
 }
 
-async fn token_from_response(resp: Response) -> Result<Token, Error> {
+async fn token_from_response(resp: Response) -> Result<(String, serde_json::Value), Error> {
     let value = match resp.headers().get("x-subject-token") {
         Some(hdr) => match hdr.to_str() {
             Ok(s) => Ok(s.to_string()),
@@ -230,12 +780,322 @@ async fn token_from_response(resp: Response) -> Result<Token, Error> {
         }
     }?;
 
-    let root = resp.json::<protocol::TokenRoot>().await?;
+    let body = resp.json().await?;
+    Ok((value, body))
+}
+
+fn token_from_body(
+    value: String,
+    body: &serde_json::Value,
+    clock_offset: Duration,
+) -> Result<Token, Error> {
+    let root = protocol::TokenRoot::deserialize(body)?;
     debug!("Received a token expiring at {}", root.token.expires_at);
     trace!("Received catalog: {:?}", root.token.catalog);
     Ok(Token {
         value,
         expires_at: root.token.expires_at,
-        catalog: ServiceCatalog::new(root.token.catalog),
+        issued_at: root.token.issued_at,
+        user_id: root.token.user.id,
+        roles: root.token.roles.into_iter().map(|role| role.name).collect(),
+        access_rules: root
+            .token
+            .application_credential
+            .map(|cred| {
+                debug!(
+                    "The token is issued for application credential {} ({}), restricted: {}",
+                    cred.name, cred.id, cred.restricted
+                );
+                cred.access_rules
+            })
+            .unwrap_or_default(),
+        clock_offset,
+        catalog: ServiceCatalog::new(root.token.catalog)
+            .with_project_id(root.token.project.map(|p| p.id)),
     })
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::super::protocol::{Auth, AuthRoot, Identity, UserAndPassword};
+    use chrono::{Duration, TimeZone, Utc};
+    use reqwest::header::{HeaderMap, HeaderName, DATE};
+    use reqwest::Client;
+
+    use super::super::{Scope, TokenHeader};
+    use super::{clock_offset, token_alive, token_from_body, Internal, Token};
+    use crate::catalog::ServiceCatalog;
+    use crate::clock::{Clock, ManualClock};
+    use crate::common::IdOrName;
+
+    fn internal(auth_url: &str, user: &str) -> Internal {
+        let body = AuthRoot {
+            auth: Auth {
+                identity: Identity::Password(UserAndPassword {
+                    user: IdOrName::from_name(user),
+                    password: "secret".into(),
+                    domain: None,
+                }),
+                scope: None,
+            },
+        };
+        Internal::new(auth_url, body).unwrap()
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let key = internal("https://example.com/identity", "admin").cache_key();
+        assert_eq!(
+            key,
+            internal("https://example.com/identity", "admin").cache_key()
+        );
+        assert_ne!(
+            key,
+            internal("https://example.com/identity", "demo").cache_key()
+        );
+        assert_ne!(
+            key,
+            internal("https://other.com/identity", "admin").cache_key()
+        );
+    }
+
+    #[cfg(feature = "token-cache")]
+    #[test]
+    fn test_persistent_cache_key() {
+        let auth = internal("https://example.com/identity", "admin");
+        let key = auth.persistent_cache_key();
+        assert_eq!(key.len(), 64);
+        assert!(!key.contains("admin"));
+        assert_ne!(
+            key,
+            internal("https://example.com/identity", "demo").persistent_cache_key()
+        );
+    }
+
+    #[cfg(feature = "token-cache")]
+    #[test]
+    fn test_keystoneauth_cache_id() {
+        use super::super::protocol::{Project, Scope};
+
+        let mut auth = internal("https://example.com/identity", "admin");
+        if let Identity::Password(ref mut pw) = auth.body.auth.identity {
+            pw.domain = Some(IdOrName::from_name("Default"));
+        }
+        auth.body.auth.scope = Some(Scope::Project(Project {
+            project: IdOrName::from_name("demo"),
+            domain: Some(IdOrName::from_name("Default")),
+        }));
+        // Values obtained from keystoneauth1.identity.generic.Password.get_cache_id()
+        assert_eq!(
+            auth.keystoneauth_cache_id(),
+            "2mpXVugwR2c1VtxwrSqS6VZiMGtb2N/ZR4g2JmNb4rM="
+        );
+
+        let body = AuthRoot {
+            auth: Auth {
+                identity: Identity::Token("abcd".into()),
+                scope: None,
+            },
+        };
+        let auth = Internal::new("https://example.com/identity", body).unwrap();
+        assert_eq!(
+            auth.keystoneauth_cache_id(),
+            "u+zRLid9FK7wsI3TjoI8/QUdzFP26HfCaOJ29ZaqfnU="
+        );
+    }
+
+    #[test]
+    fn test_shared() {
+        let url = "https://shared.example.com/identity";
+        let mut first = internal(url, "admin");
+        let mut second = internal(url, "admin");
+        assert!(!Arc::ptr_eq(&first.cached_token, &second.cached_token));
+
+        first.set_shared(true);
+        second.set_shared(true);
+        assert!(Arc::ptr_eq(&first.cached_token, &second.cached_token));
+        let clone = first.clone();
+        assert!(Arc::ptr_eq(&first.cached_token, &clone.cached_token));
+
+        second.set_scope(Scope::Project {
+            project: IdOrName::from_name("project"),
+            domain: None,
+        });
+        assert!(!Arc::ptr_eq(&first.cached_token, &second.cached_token));
+
+        first.set_shared(false);
+        assert!(!Arc::ptr_eq(&first.cached_token, &clone.cached_token));
+        let mut other = internal(url, "demo");
+        other.set_shared(true);
+        assert!(!Arc::ptr_eq(&other.cached_token, &clone.cached_token));
+    }
+
+    #[test]
+    fn test_token_from_body() {
+        let body = serde_json::json!({
+            "token": {
+                "expires_at": "2022-01-01T12:00:00Z",
+                "catalog": [],
+                "user": {"id": "user", "name": "admin"},
+                "roles": [
+                    {"id": "1", "name": "member"},
+                    {"id": "2", "name": "reader"}
+                ],
+                "application_credential": {
+                    "id": "abcd",
+                    "name": "ci",
+                    "restricted": true,
+                    "access_rules": [
+                        {"service": "compute", "method": "GET", "path": "/v2.1/servers"}
+                    ]
+                }
+            }
+        });
+        let token = token_from_body("abcd".into(), &body, Duration::zero()).unwrap();
+        assert_eq!(token.user_id, "user");
+        assert_eq!(token.roles, vec!["member", "reader"]);
+        assert_eq!(token.access_rules.len(), 1);
+        assert_eq!(token.access_rules[0].path, "/v2.1/servers");
+        assert!(token.issued_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_token_issued_at() {
+        let auth = internal("https://example.com/identity", "admin");
+        assert!(auth.token_issued_at().is_none());
+
+        let body = serde_json::json!({
+            "token": {
+                "expires_at": "2022-01-01T12:00:00Z",
+                "issued_at": "2022-01-01T11:00:00Z",
+                "catalog": [],
+                "user": {"id": "user", "name": "admin"}
+            }
+        });
+        // The server clock is 30 minutes behind.
+        let token = token_from_body("abcd".into(), &body, Duration::minutes(-30)).unwrap();
+        *auth.cached_token.write().await = Some(token);
+        assert_eq!(
+            auth.token_issued_at().unwrap(),
+            Utc.with_ymd_and_hms(2022, 1, 1, 11, 30, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_clock_offset() {
+        let clock = ManualClock::new(Utc.with_ymd_and_hms(2022, 1, 1, 12, 0, 0).unwrap());
+        let mut headers = HeaderMap::new();
+        assert_eq!(clock_offset(&headers, &clock), Duration::zero());
+
+        let _ = headers.insert(DATE, "Sat, 01 Jan 2022 12:00:02 GMT".parse().unwrap());
+        assert_eq!(clock_offset(&headers, &clock), Duration::zero());
+
+        let _ = headers.insert(DATE, "Sat, 01 Jan 2022 11:30:00 GMT".parse().unwrap());
+        let offset = clock_offset(&headers, &clock);
+        assert_eq!(offset, Duration::minutes(-30));
+
+        // The token expires in 20 minutes in the server time, which is 50 minutes locally.
+        let mut token = Some(Token {
+            value: "abcd".into(),
+            expires_at: (clock.now() - Duration::minutes(10)).into(),
+            issued_at: None,
+            catalog: ServiceCatalog::new(Vec::new()),
+            user_id: "user".into(),
+            roles: Vec::new(),
+            access_rules: Vec::new(),
+            clock_offset: Duration::zero(),
+        });
+        assert!(!token_alive(&token, &clock));
+        token.as_mut().unwrap().clock_offset = offset;
+        assert!(token_alive(&token, &clock));
+        clock.advance(Duration::minutes(15));
+        assert!(!token_alive(&token, &clock));
+
+        let _ = headers.insert(DATE, "garbage".parse().unwrap());
+        assert_eq!(clock_offset(&headers, &clock), Duration::zero());
+    }
+
+    #[tokio::test]
+    async fn test_token_header() {
+        let mut auth = internal("https://example.com/identity", "admin");
+        *auth.cached_token.write().await = Some(Token {
+            value: "abcd".into(),
+            expires_at: (Utc::now() + Duration::days(1)).into(),
+            issued_at: None,
+            catalog: ServiceCatalog::new(Vec::new()),
+            user_id: "user".into(),
+            roles: Vec::new(),
+            access_rules: Vec::new(),
+            clock_offset: Duration::zero(),
+        });
+
+        let client = Client::new();
+        let expected = [
+            (TokenHeader::XAuthToken, "x-auth-token", "abcd"),
+            (TokenHeader::Bearer, "authorization", "Bearer abcd"),
+            (
+                TokenHeader::Custom(HeaderName::from_static("x-seamicro-token")),
+                "x-seamicro-token",
+                "abcd",
+            ),
+        ];
+        for (header, name, value) in expected {
+            auth.set_token_header(header);
+            let request = auth
+                .authenticate(&client, client.get("https://example.com"))
+                .await
+                .unwrap()
+                .build()
+                .unwrap();
+            assert_eq!(request.headers().len(), 1);
+            assert_eq!(request.headers()[name], value);
+            assert!(request.headers()[name].is_sensitive());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_token_request_retried() {
+        use std::time::Duration as StdDuration;
+
+        use crate::utils::test::serve_raw_http;
+        use crate::RetryPolicy;
+
+        let body = serde_json::json!({
+            "token": {
+                "expires_at": "2099-01-01T12:00:00Z",
+                "catalog": [],
+                "user": {"id": "user", "name": "admin"},
+                "roles": []
+            }
+        })
+        .to_string();
+        let unavailable = "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\
+                           Connection: close\r\n\r\n";
+        let (addr, server) = serve_raw_http(vec![
+            unavailable.to_string(),
+            unavailable.to_string(),
+            format!(
+                "HTTP/1.1 201 Created\r\nX-Subject-Token: abcd\r\n\
+                 Content-Type: application/json\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n{}",
+                body.len(),
+                body
+            ),
+        ])
+        .await;
+
+        let client = Client::new();
+        let url = format!("http://{}/identity", addr);
+        let auth = internal(&url, "admin");
+        let err = auth.refresh(&client, true).await.unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::InternalServerError);
+
+        let mut auth = internal(&url, "admin");
+        auth.set_retry_policy(RetryPolicy::new(2).with_initial_delay(StdDuration::from_millis(1)));
+        auth.refresh(&client, true).await.unwrap();
+        assert_eq!(auth.get_token(&client).await.unwrap(), "abcd");
+        assert_eq!(server.await.unwrap().len(), 3);
+    }
+}
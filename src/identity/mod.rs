@@ -14,28 +14,58 @@
 
 //! Authentication using Identity API v3.
 //!
-//! Currently only supports [Password](struct.Password.html) authentication.
-//! Identity API v2 is not and will not be supported.
+//! Supports [Password](struct.Password.html), [Token](struct.Token.html) and
+//! [ApplicationCredentialAuth](struct.ApplicationCredentialAuth.html) authentication.
+//! Multi-factor authentication is supported with [AuthReceipt](struct.AuthReceipt.html) and
+//! [Totp](struct.Totp.html) or with a [PasscodeProvider](trait.PasscodeProvider.html).
+//! Identity API v2 is only supported for password authentication with the `identity-v2`
+//! feature (see [PasswordV2](struct.PasswordV2.html)).
 
+mod appcred;
+mod appcredauth;
 mod internal;
+mod passcode;
 mod password;
 pub(crate) mod protocol;
+mod receipt;
 mod token;
+#[cfg(feature = "token-cache")]
+mod tokencache;
+mod totp;
+mod trust;
+#[cfg(feature = "identity-v2")]
+mod v2;
+
+use reqwest::header::HeaderName;
 
 use super::common::IdOrName;
 
+pub use self::appcred::{AccessRule, ApplicationCredential, NewApplicationCredential};
+pub use self::appcredauth::ApplicationCredentialAuth;
+pub use self::passcode::PasscodeProvider;
 pub use self::password::Password;
+pub use self::receipt::AuthReceipt;
 pub use self::token::Token;
+#[cfg(feature = "token-cache")]
+pub use self::tokencache::TokenCache;
+pub use self::totp::Totp;
+pub use self::trust::{NewTrust, Trust};
+#[cfg(feature = "identity-v2")]
+pub(crate) use self::v2::is_v2_only;
+#[cfg(feature = "identity-v2")]
+pub use self::v2::PasswordV2;
 
 const MISSING_SUBJECT_HEADER: &str = "Missing X-Subject-Token header";
 const INVALID_SUBJECT_HEADER: &str = "Invalid X-Subject-Token header";
+// Minimal difference between the server and the local clocks (in seconds) to take into account.
+const CLOCK_OFFSET_THRESHOLD: i64 = 5;
 // Required validity time in minutes. Here we refresh the token if it expires
 // in 10 minutes or less.
 const TOKEN_MIN_VALIDITY: i64 = 10;
 
 /// A scope of a token.
 ///
-/// Only project scopes are currently supported.
+/// Project and domain scopes are supported.
 #[derive(Debug)]
 pub enum Scope {
     /// A token scoped to a project.
@@ -45,4 +75,41 @@ pub enum Scope {
         /// ID or name of the project domain.
         domain: Option<IdOrName>,
     },
+    /// A token scoped to a domain (domain ID or name).
+    Domain(IdOrName),
+}
+
+/// How the token is passed in authenticated requests.
+///
+/// Keystone-compatible services expect the `X-Auth-Token` header, but some reverse proxies
+/// require a different one:
+///
+/// ```rust,no_run
+/// use osauth::identity::{Password, TokenHeader};
+///
+/// let auth = Password::new(
+///     "https://cloud.local/identity",
+///     "admin",
+///     "pa$$w0rd",
+///     "Default",
+/// )
+/// .expect("Invalid auth_url")
+/// .with_token_header(TokenHeader::Bearer);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TokenHeader {
+    /// The `X-Auth-Token` header (the default).
+    XAuthToken,
+    /// The `Authorization` header with the `Bearer` scheme.
+    Bearer,
+    /// A custom header with the token as its value.
+    Custom(HeaderName),
+}
+
+#[allow(clippy::derivable_impls)] // #[default] requires a newer Rust
+impl Default for TokenHeader {
+    fn default() -> TokenHeader {
+        TokenHeader::XAuthToken
+    }
 }
@@ -0,0 +1,82 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Passcode providers for TOTP authentication.
+
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::Error;
+
+/// A source of TOTP passcodes.
+///
+/// Passcodes expire quickly, so a provider is invoked every time a token is requested
+/// (including refreshes). It is implemented for async closures returning a passcode:
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use osauth::identity::Password;
+///
+/// let auth = Password::new("https://cloud.local/identity", "admin", "pa$$w0rd", "Default")?
+///     .with_passcode_provider(|| async {
+///         // e.g. ask the user or generate from a shared secret
+///         Ok("123456".to_string())
+///     });
+/// let session = osauth::Session::new(auth).await?;
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[async_trait]
+pub trait PasscodeProvider: Send + Sync {
+    /// Get a fresh passcode.
+    async fn passcode(&self) -> Result<String, Error>;
+}
+
+#[async_trait]
+impl<F, Fut> PasscodeProvider for F
+where
+    F: Fn() -> Fut + Send + Sync,
+    Fut: Future<Output = Result<String, Error>> + Send,
+{
+    async fn passcode(&self) -> Result<String, Error> {
+        self().await
+    }
+}
+
+/// A shared passcode provider.
+#[derive(Clone)]
+pub(crate) struct PasscodeSource(Arc<dyn PasscodeProvider>);
+
+impl PasscodeSource {
+    /// Create a source from a provider.
+    pub fn new<P: PasscodeProvider + 'static>(provider: P) -> PasscodeSource {
+        PasscodeSource(Arc::new(provider))
+    }
+
+    /// Get a fresh passcode.
+    #[inline]
+    pub async fn passcode(&self) -> Result<String, Error> {
+        self.0.passcode().await
+    }
+}
+
+impl fmt::Debug for PasscodeSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("PasscodeSource(<provider>)")
+    }
+}
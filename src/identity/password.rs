@@ -14,15 +14,23 @@
 
 //! Password authentication.
 
+use std::sync::Arc;
+
 use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
 use reqwest::{Client, RequestBuilder, Url};
 use static_assertions::assert_impl_all;
 
 use super::internal::Internal;
+use super::passcode::PasscodeSource;
 use super::protocol;
-use super::Scope;
+#[cfg(feature = "token-cache")]
+use super::TokenCache;
+use super::{AuthReceipt, PasscodeProvider, Scope, TokenHeader};
 use crate::common::IdOrName;
-use crate::{AuthType, EndpointFilters, Error};
+use crate::{
+    AuthType, CatalogEntry, Clock, EndpointFilters, Error, EventHandler, MetricsSink, RetryPolicy,
+};
 
 /// Password authentication using Identity API V3.
 ///
@@ -33,8 +41,7 @@ use crate::{AuthType, EndpointFilters, Error};
 /// 3. Name of the project to use.
 /// 4. Domain of the project.
 ///
-/// Note: currently only names are supported for user, user domain and project domain. ID support is
-/// coming later.
+/// Use [new_with_user](#method.new_with_user) to reference the user or the user domain by ID.
 ///
 /// Start with creating a `Password` object using [new](#method.new), then add a project scope
 /// with [with_project_scope](#method.with_project_scope):
@@ -86,7 +93,8 @@ use crate::{AuthType, EndpointFilters, Error};
 ///
 /// The authentication token is cached while it's still valid or until
 /// [refresh](../trait.AuthType.html#tymethod.refresh) is called.
-/// Clones of a `Password` also start with an empty cache.
+/// Clones of a `Password` also start with an empty cache unless
+/// [set_shared_cache](#method.set_shared_cache) is used.
 #[derive(Debug, Clone)]
 pub struct Password {
     inner: Internal,
@@ -107,11 +115,32 @@ impl Password {
         S1: Into<String>,
         S2: Into<String>,
         S3: Into<String>,
+    {
+        Password::new_with_user(
+            auth_url,
+            IdOrName::Name(user_name.into()),
+            password,
+            IdOrName::Name(user_domain_name.into()),
+        )
+    }
+
+    /// Create a password authentication with a user referenced by ID or name.
+    ///
+    /// The user domain is only required when the user is referenced by name.
+    pub fn new_with_user<U, S>(
+        auth_url: U,
+        user: IdOrName,
+        password: S,
+        user_domain: impl Into<Option<IdOrName>>,
+    ) -> Result<Password, Error>
+    where
+        U: AsRef<str>,
+        S: Into<String>,
     {
         let pw = protocol::UserAndPassword {
-            user: IdOrName::Name(user_name.into()),
+            user,
             password: password.into(),
-            domain: Some(IdOrName::Name(user_domain_name.into())),
+            domain: user_domain.into(),
         };
         let body = protocol::AuthRoot {
             auth: protocol::Auth {
@@ -156,6 +185,101 @@ impl Password {
         self
     }
 
+    /// Use a persistent token cache.
+    ///
+    /// Tokens are loaded from the cache before authenticating and stored there afterwards.
+    #[cfg(feature = "token-cache")]
+    #[inline]
+    pub fn set_token_cache(&mut self, cache: TokenCache) {
+        self.inner.set_token_cache(cache);
+    }
+
+    /// Set a handler for token events.
+    ///
+    /// [TokenExpiring](../enum.Event.html#variant.TokenExpiring),
+    /// [TokenRefreshed](../enum.Event.html#variant.TokenRefreshed) and
+    /// [TokenRefreshFailed](../enum.Event.html#variant.TokenRefreshFailed) are emitted.
+    #[inline]
+    pub fn set_event_handler<H: EventHandler + 'static>(&mut self, handler: H) {
+        self.inner.set_event_handler(handler);
+    }
+
+    /// Request a TOTP passcode from the provider on every authentication.
+    ///
+    /// The password and the passcode are then sent together, so that the token can be renewed
+    /// without user interaction other than providing a fresh passcode.
+    #[inline]
+    pub fn set_passcode_provider<P: PasscodeProvider + 'static>(&mut self, provider: P) {
+        self.inner
+            .set_passcode_source(PasscodeSource::new(provider));
+    }
+
+    /// Set a sink for authentication metrics.
+    ///
+    /// See [MetricsSink](../trait.MetricsSink.html) for details.
+    #[inline]
+    pub fn set_metrics_sink<S: MetricsSink + 'static>(&mut self, sink: S) {
+        self.inner.set_metrics_sink(sink);
+    }
+
+    /// Set a clock for token validity checks.
+    ///
+    /// Use [ManualClock](../struct.ManualClock.html) to simulate token expiry in tests.
+    #[inline]
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.inner.set_clock(clock);
+    }
+
+    /// Whether to append the API version (`v3`) to the authentication URL.
+    ///
+    /// By default (`None`), the version is appended unless the URL already ends with `/v3`.
+    /// Use `false` if the URL points to the Identity API v3 root under a different path, e.g.
+    /// behind a proxy, and `true` if a path that ends with `v3` is not the API root.
+    ///
+    /// ```rust
+    /// let mut auth = osauth::identity::Password::new(
+    ///     "https://cloud.local/keystone/main",
+    ///     "admin",
+    ///     "pa$$w0rd",
+    ///     "Default",
+    /// )?;
+    /// // Tokens are requested from https://cloud.local/keystone/main/auth/tokens
+    /// auth.set_append_version(false);
+    /// # Ok::<(), osauth::Error>(())
+    /// ```
+    #[inline]
+    pub fn set_append_version(&mut self, value: impl Into<Option<bool>>) {
+        self.inner.set_append_version(value.into());
+    }
+
+    /// Set a retry policy for token requests (no retries by default).
+    ///
+    /// Token requests are retried on HTTP 429 and 503 and, unlike other `POST` requests, on
+    /// connection failures, HTTP 502 and 504, e.g. while the Identity service is restarting.
+    /// The policy is independent of the retry policy of the session.
+    #[inline]
+    pub fn set_token_retry_policy(&mut self, value: RetryPolicy) {
+        self.inner.set_retry_policy(value);
+    }
+
+    /// Set the header used to pass the token in authenticated requests.
+    ///
+    /// The default is [XAuthToken](enum.TokenHeader.html#variant.XAuthToken).
+    #[inline]
+    pub fn set_token_header(&mut self, value: TokenHeader) {
+        self.inner.set_token_header(value);
+    }
+
+    /// Share the token with other authentication objects in this process.
+    ///
+    /// Objects with the same authentication URL, credentials and scope use one token and
+    /// refresh it together, so that independently created sessions for the same cloud
+    /// do not authenticate separately.
+    #[inline]
+    pub fn set_shared_cache(&mut self, value: bool) {
+        self.inner.set_shared(value);
+    }
+
     /// Add a scope to the authentication.
     #[inline]
     pub fn with_scope(mut self, scope: Scope) -> Self {
@@ -163,6 +287,70 @@ impl Password {
         self
     }
 
+    /// Use the given handler for token events.
+    #[inline]
+    pub fn with_event_handler<H: EventHandler + 'static>(mut self, handler: H) -> Self {
+        self.set_event_handler(handler);
+        self
+    }
+
+    /// Request a TOTP passcode from the provider on every authentication.
+    #[inline]
+    pub fn with_passcode_provider<P: PasscodeProvider + 'static>(mut self, provider: P) -> Self {
+        self.set_passcode_provider(provider);
+        self
+    }
+
+    /// Use the given sink for authentication metrics.
+    #[inline]
+    pub fn with_metrics_sink<S: MetricsSink + 'static>(mut self, sink: S) -> Self {
+        self.set_metrics_sink(sink);
+        self
+    }
+
+    /// Use the given clock for token validity checks.
+    #[inline]
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.set_clock(clock);
+        self
+    }
+
+    /// Whether to append the API version (`v3`) to the authentication URL.
+    #[inline]
+    pub fn with_append_version(mut self, value: impl Into<Option<bool>>) -> Self {
+        self.set_append_version(value);
+        self
+    }
+
+    /// Use the given retry policy for token requests.
+    #[inline]
+    pub fn with_token_retry_policy(mut self, value: RetryPolicy) -> Self {
+        self.set_token_retry_policy(value);
+        self
+    }
+
+    /// Use the given header to pass the token in authenticated requests.
+    #[inline]
+    pub fn with_token_header(mut self, value: TokenHeader) -> Self {
+        self.set_token_header(value);
+        self
+    }
+
+    /// Share the token with other authentication objects in this process.
+    #[inline]
+    pub fn with_shared_cache(mut self, value: bool) -> Self {
+        self.set_shared_cache(value);
+        self
+    }
+
+    /// Use a persistent token cache.
+    #[cfg(feature = "token-cache")]
+    #[inline]
+    pub fn with_token_cache(mut self, cache: TokenCache) -> Self {
+        self.set_token_cache(cache);
+        self
+    }
+
     /// User name or ID.
     #[inline]
     pub fn user(&self) -> &IdOrName {
@@ -174,6 +362,17 @@ impl Password {
     pub fn project(&self) -> Option<&IdOrName> {
         self.inner.project()
     }
+
+    /// Start multi-factor authentication.
+    ///
+    /// Authenticates with the password and returns a receipt if the Identity service requires
+    /// more authentication methods, e.g. a TOTP passcode. Returns `None` if the password is
+    /// enough, in which case the token is cached and this object can be used directly.
+    ///
+    /// See [AuthReceipt](struct.AuthReceipt.html) for an example.
+    pub async fn request_receipt(&self, client: &Client) -> Result<Option<AuthReceipt>, Error> {
+        self.inner.request_receipt(client).await
+    }
 }
 
 #[async_trait]
@@ -197,10 +396,42 @@ impl AuthType for Password {
         self.inner.get_endpoint(client, service_type, filters).await
     }
 
+    /// Get all records from the service catalog.
+    async fn get_catalog(&self, client: &Client) -> Result<Vec<CatalogEntry>, Error> {
+        self.inner.get_catalog(client).await
+    }
+
+    /// Get the ID of the authenticated user.
+    async fn get_user_id(&self, client: &Client) -> Result<Option<String>, Error> {
+        self.inner.get_user_id(client).await.map(Some)
+    }
+
+    /// Get names of the roles assigned to the authenticated user.
+    async fn get_roles(&self, client: &Client) -> Result<Vec<String>, Error> {
+        self.inner.get_roles(client).await
+    }
+
+    /// Get the current authentication token.
+    async fn get_token(&self, client: &Client) -> Result<Option<String>, Error> {
+        self.inner.get_token(client).await.map(Some)
+    }
+
     /// Refresh the cached token and service catalog.
     async fn refresh(&self, client: &Client) -> Result<(), Error> {
         self.inner.refresh(client, true).await
     }
+
+    /// Create a copy with its own token cache, starting with the current token.
+    /// Time when the cached token was issued (if any).
+    fn token_issued_at(&self) -> Option<DateTime<FixedOffset>> {
+        self.inner.token_issued_at()
+    }
+
+    fn detach(&self) -> Option<Arc<dyn AuthType>> {
+        Some(Arc::new(Password {
+            inner: self.inner.detach(),
+        }))
+    }
 }
 
 #[cfg(test)]
@@ -223,6 +454,14 @@ pub mod test {
         assert_eq!(id.user(), &IdOrName::Name("admin".to_string()));
     }
 
+    #[test]
+    fn test_identity_debug() {
+        let id = Password::new("http://127.0.0.1:8080/", "admin", "pa$$w0rd", "Default").unwrap();
+        let debug = format!("{:?}", id);
+        assert!(debug.contains("admin"), "{}", debug);
+        assert!(!debug.contains("pa$$w0rd"), "{}", debug);
+    }
+
     #[test]
     fn test_identity_new_invalid() {
         Password::new("http://127.0.0.1 8080/", "admin", "pa$$w0rd", "Default")
@@ -344,4 +583,39 @@ pub mod test {
             "http://127.0.0.1:8080/v3/auth/tokens"
         );
     }
+
+    #[test]
+    fn test_token_endpoint_append_version() {
+        let id = Password::new(
+            "http://127.0.0.1:8080/keystone/main",
+            "user",
+            "pa$$w0rd",
+            "example.com",
+        )
+        .unwrap()
+        .with_append_version(false);
+        assert_eq!(
+            id.inner.token_endpoint(),
+            "http://127.0.0.1:8080/keystone/main/auth/tokens"
+        );
+
+        let id = Password::new(
+            "http://127.0.0.1:8080/api/v3",
+            "user",
+            "pa$$w0rd",
+            "example.com",
+        )
+        .unwrap()
+        .with_append_version(true);
+        assert_eq!(
+            id.inner.token_endpoint(),
+            "http://127.0.0.1:8080/api/v3/v3/auth/tokens"
+        );
+
+        let id = id.with_append_version(None);
+        assert_eq!(
+            id.inner.token_endpoint(),
+            "http://127.0.0.1:8080/api/v3/auth/tokens"
+        );
+    }
 }
@@ -14,14 +14,17 @@
 
 //! Identity V3 JSON structures and protocol bits.
 
+use std::fmt;
+
 use chrono::{DateTime, FixedOffset};
 use serde::ser::SerializeStruct;
 use serde::{Deserialize, Serialize, Serializer};
 
-use crate::common::IdOrName;
+use super::AccessRule;
+use crate::common::{IdAndName, IdOrName};
 
 /// User and password.
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Serialize)]
 pub struct UserAndPassword {
     #[serde(flatten)]
     pub user: IdOrName,
@@ -30,13 +33,46 @@ pub struct UserAndPassword {
     pub domain: Option<IdOrName>,
 }
 
+/// A reference to a user in a domain.
+#[derive(Clone, Debug, Serialize)]
+pub struct User {
+    #[serde(flatten)]
+    pub user: IdOrName,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub domain: Option<IdOrName>,
+}
+
+/// Application credential and its secret.
+#[derive(Clone, Serialize)]
+pub struct ApplicationCredentialAndSecret {
+    #[serde(flatten)]
+    pub credential: IdOrName,
+    pub secret: String,
+    /// The owner, required when the credential is referenced by name.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub user: Option<User>,
+}
+
+/// User and a TOTP passcode.
+#[derive(Clone, Serialize)]
+pub struct UserAndPasscode {
+    pub id: String,
+    pub passcode: String,
+}
+
 /// Authentication identity.
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub enum Identity {
     /// Authentication with a user and a password.
     Password(UserAndPassword),
     /// Authentication with a token.
     Token(String),
+    /// Authentication with an application credential.
+    ApplicationCredential(ApplicationCredentialAndSecret),
+    /// Authentication with a TOTP passcode.
+    Totp(UserAndPasscode),
+    /// Authentication with a user, a password and a TOTP passcode.
+    PasswordAndTotp(UserAndPassword, String),
 }
 
 /// A reference to a project in a domain.
@@ -91,20 +127,53 @@ pub struct Endpoint {
 pub struct CatalogRecord {
     #[serde(rename = "type")]
     pub service_type: String,
+    #[serde(default)]
+    pub name: Option<String>,
     pub endpoints: Vec<Endpoint>,
 }
 
-/// A root catalog response.
+/// An application credential used to obtain a token.
 #[derive(Clone, Debug, Deserialize)]
-pub struct CatalogRoot {
-    pub catalog: Vec<CatalogRecord>,
+pub struct TokenApplicationCredential {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub restricted: bool,
+    #[serde(default)]
+    pub access_rules: Vec<AccessRule>,
 }
 
 /// An authentication token with embedded catalog.
 #[derive(Clone, Debug, Deserialize)]
 pub struct Token {
     pub expires_at: DateTime<FixedOffset>,
+    #[serde(default)]
+    pub issued_at: Option<DateTime<FixedOffset>>,
     pub catalog: Vec<CatalogRecord>,
+    #[serde(default)]
+    pub project: Option<IdAndName>,
+    pub user: IdAndName,
+    #[serde(default)]
+    pub roles: Vec<IdAndName>,
+    #[serde(default)]
+    pub application_credential: Option<TokenApplicationCredential>,
+}
+
+/// An authentication receipt (returned when more authentication methods are required).
+#[derive(Clone, Debug, Deserialize)]
+pub struct Receipt {
+    pub expires_at: DateTime<FixedOffset>,
+    #[serde(default)]
+    pub methods: Vec<String>,
+    pub user: IdAndName,
+}
+
+/// An authentication receipt response root.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReceiptRoot {
+    pub receipt: Receipt,
+    #[serde(default)]
+    pub required_auth_methods: Vec<Vec<String>>,
 }
 
 /// A token response root.
@@ -113,16 +182,77 @@ pub struct TokenRoot {
     pub token: Token,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Serialize)]
 struct PasswordAuth<'a> {
     user: &'a UserAndPassword,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Serialize)]
 struct TokenAuth<'a> {
     id: &'a str,
 }
 
+#[derive(Serialize)]
+struct TotpAuth<'a, T> {
+    user: &'a T,
+}
+
+#[derive(Serialize)]
+struct UserNameAndPasscode<'a> {
+    #[serde(flatten)]
+    user: &'a IdOrName,
+    passcode: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    domain: &'a Option<IdOrName>,
+}
+
+impl fmt::Debug for UserAndPasscode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UserAndPasscode")
+            .field("id", &self.id)
+            .field("passcode", &"<hidden>")
+            .finish()
+    }
+}
+
+impl fmt::Debug for ApplicationCredentialAndSecret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ApplicationCredentialAndSecret")
+            .field("credential", &self.credential)
+            .field("secret", &"<hidden>")
+            .field("user", &self.user)
+            .finish()
+    }
+}
+
+impl fmt::Debug for UserAndPassword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UserAndPassword")
+            .field("user", &self.user)
+            .field("password", &"<hidden>")
+            .field("domain", &self.domain)
+            .finish()
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Identity::Password(user) => f.debug_tuple("Password").field(user).finish(),
+            Identity::Token(..) => f.debug_tuple("Token").field(&"<hidden>").finish(),
+            Identity::ApplicationCredential(cred) => {
+                f.debug_tuple("ApplicationCredential").field(cred).finish()
+            }
+            Identity::Totp(user) => f.debug_tuple("Totp").field(user).finish(),
+            Identity::PasswordAndTotp(user, ..) => f
+                .debug_tuple("PasswordAndTotp")
+                .field(user)
+                .field(&"<hidden>")
+                .finish(),
+        }
+    }
+}
+
 impl IdOrName {
     /// Create an ID from anything that can be converted to a string.
     #[inline]
@@ -152,6 +282,24 @@ impl Serialize for Identity {
                 inner.serialize_field("methods", &["token"])?;
                 inner.serialize_field("token", &TokenAuth { id: token })?;
             }
+            Identity::ApplicationCredential(ref cred) => {
+                inner.serialize_field("methods", &["application_credential"])?;
+                inner.serialize_field("application_credential", cred)?;
+            }
+            Identity::Totp(ref user) => {
+                inner.serialize_field("methods", &["totp"])?;
+                inner.serialize_field("totp", &TotpAuth { user })?;
+            }
+            Identity::PasswordAndTotp(ref user, ref passcode) => {
+                inner.serialize_field("methods", &["password", "totp"])?;
+                inner.serialize_field("password", &PasswordAuth { user })?;
+                let totp_user = UserNameAndPasscode {
+                    user: &user.user,
+                    passcode,
+                    domain: &user.domain,
+                };
+                inner.serialize_field("totp", &TotpAuth { user: &totp_user })?;
+            }
         }
         inner.end()
     }
@@ -254,6 +402,100 @@ mod test {
     }
 }"#;
 
+    const APPCRED_NAME: &str = r#"
+{
+    "auth": {
+        "identity": {
+            "methods": [
+                "application_credential"
+            ],
+            "application_credential": {
+                "name": "ci",
+                "secret": "s3cr3t",
+                "user": {
+                    "name": "admin",
+                    "domain": {
+                        "name": "Default"
+                    }
+                }
+            }
+        }
+    }
+}"#;
+
+    const APPCRED_TOKEN: &str = r#"
+{
+    "token": {
+        "methods": ["application_credential"],
+        "expires_at": "2022-01-01T12:00:00.000000Z",
+        "catalog": [],
+        "user": {"id": "ee4dfb6e5540447cb3741905149d9b6e", "name": "admin"},
+        "project": {"id": "1a6f364d14a846e2b11bc8e0b3f5b2a7", "name": "demo"},
+        "application_credential": {
+            "id": "aa809205ed614a0e854bac92c0768bb9",
+            "name": "ci",
+            "restricted": true,
+            "access_rules": [
+                {
+                    "id": "abcdef",
+                    "service": "compute",
+                    "method": "GET",
+                    "path": "/v2.1/servers"
+                }
+            ]
+        }
+    }
+}"#;
+
+    const PASSWORD_AND_TOTP: &str = r#"
+{
+    "auth": {
+        "identity": {
+            "methods": [
+                "password",
+                "totp"
+            ],
+            "password": {
+                "user": {
+                    "name": "admin",
+                    "domain": {
+                        "name": "Default"
+                    },
+                    "password": "devstacker"
+                }
+            },
+            "totp": {
+                "user": {
+                    "name": "admin",
+                    "domain": {
+                        "name": "Default"
+                    },
+                    "passcode": "123456"
+                }
+            }
+        }
+    }
+}"#;
+
+    #[test]
+    fn test_password_and_totp() {
+        let value = AuthRoot {
+            auth: Auth {
+                identity: Identity::PasswordAndTotp(
+                    UserAndPassword {
+                        user: IdOrName::from_name("admin"),
+                        password: "devstacker".to_string(),
+                        domain: Some(IdOrName::from_name("Default")),
+                    },
+                    "123456".to_string(),
+                ),
+                scope: None,
+            },
+        };
+        assert!(!format!("{:?}", value).contains("123456"));
+        test::compare(PASSWORD_AND_TOTP, value);
+    }
+
     #[test]
     fn test_password_name_unscoped() {
         let value = AuthRoot {
@@ -299,6 +541,35 @@ mod test {
         test::compare(PASSWORD_ID_SYSTEM_SCOPE, value);
     }
 
+    #[test]
+    fn test_appcred_name() {
+        let value = AuthRoot {
+            auth: Auth {
+                identity: Identity::ApplicationCredential(ApplicationCredentialAndSecret {
+                    credential: IdOrName::from_name("ci"),
+                    secret: "s3cr3t".into(),
+                    user: Some(User {
+                        user: IdOrName::from_name("admin"),
+                        domain: Some(IdOrName::from_name("Default")),
+                    }),
+                }),
+                scope: None,
+            },
+        };
+        assert!(!format!("{:?}", value).contains("s3cr3t"));
+        test::compare(APPCRED_NAME, value);
+    }
+
+    #[test]
+    fn test_appcred_token() {
+        let root: TokenRoot = serde_json::from_str(APPCRED_TOKEN).unwrap();
+        let cred = root.token.application_credential.unwrap();
+        assert_eq!(cred.name, "ci");
+        assert!(cred.restricted);
+        assert_eq!(cred.access_rules.len(), 1);
+        assert_eq!(cred.access_rules[0].service, "compute");
+    }
+
     #[test]
     fn test_token_scoped_with_name() {
         let value = AuthRoot {
@@ -0,0 +1,257 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Authentication receipts for multi-factor authentication.
+
+use std::fmt;
+
+use chrono::{DateTime, FixedOffset};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use super::{protocol, IdOrName};
+use crate::{Error, ErrorKind};
+
+/// Header carrying an authentication receipt.
+pub(crate) const RECEIPT_HEADER: &str = "openstack-auth-receipt";
+
+/// An authentication receipt: the state of a partially completed multi-factor authentication.
+///
+/// The Identity service issues a receipt when some, but not all, of the required authentication
+/// methods have succeeded. Obtain it with
+/// [Password::request_receipt](struct.Password.html#method.request_receipt) and complete the
+/// authentication with [Totp::from_receipt](struct.Totp.html#method.from_receipt).
+///
+/// A receipt can be serialized, so that the remaining methods can be provided in a separate
+/// invocation of a program (e.g. after prompting the user for a TOTP passcode):
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// use osauth::identity::{AuthReceipt, Password, Totp};
+///
+/// // First invocation
+/// let auth = Password::new("https://cloud.local/identity", "admin", "pa$$w0rd", "Default")?;
+/// if let Some(receipt) = auth.request_receipt(&reqwest::Client::new()).await? {
+///     std::fs::write("receipt.json", serde_json::to_vec(&receipt)?)?;
+/// }
+///
+/// // Second invocation
+/// let receipt: AuthReceipt = serde_json::from_slice(&std::fs::read("receipt.json")?)?;
+/// let auth = Totp::from_receipt(receipt, "123456")?;
+/// let session = osauth::Session::new(auth).await?;
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+///
+/// The receipt is only valid for a short time (5 minutes by default), see
+/// [expires_at](#method.expires_at).
+#[derive(Clone, Deserialize, Serialize)]
+pub struct AuthReceipt {
+    auth_url: String,
+    receipt: String,
+    expires_at: DateTime<FixedOffset>,
+    user_id: String,
+    #[serde(default)]
+    methods: Vec<String>,
+    #[serde(default)]
+    required_auth_methods: Vec<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    project: Option<IdOrName>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    project_domain: Option<IdOrName>,
+}
+
+impl fmt::Debug for AuthReceipt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AuthReceipt")
+            .field("auth_url", &self.auth_url)
+            .field("receipt", &"<hidden>")
+            .field("expires_at", &self.expires_at)
+            .field("user_id", &self.user_id)
+            .field("methods", &self.methods)
+            .field("required_auth_methods", &self.required_auth_methods)
+            .field("project", &self.project)
+            .field("project_domain", &self.project_domain)
+            .finish()
+    }
+}
+
+impl AuthReceipt {
+    /// Extract a receipt from a failed token request (if it contains one).
+    pub(crate) fn from_error(
+        error: &Error,
+        auth_url: &str,
+        scope: Option<&protocol::Project>,
+    ) -> Result<Option<AuthReceipt>, Error> {
+        let receipt = match error.headers().and_then(|h| h.get(RECEIPT_HEADER)) {
+            Some(value) => value.to_str().map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidResponse,
+                    format!("Invalid authentication receipt: {}", e),
+                )
+            })?,
+            None => return Ok(None),
+        };
+        let root: protocol::ReceiptRoot = serde_json::from_str(error.body().unwrap_or_default())
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidResponse,
+                    format!("Invalid authentication receipt response: {}", e),
+                )
+            })?;
+        debug!(
+            "Received an authentication receipt expiring at {}, required methods: {:?}",
+            root.receipt.expires_at, root.required_auth_methods
+        );
+        Ok(Some(AuthReceipt {
+            auth_url: auth_url.into(),
+            receipt: receipt.into(),
+            expires_at: root.receipt.expires_at,
+            user_id: root.receipt.user.id,
+            methods: root.receipt.methods,
+            required_auth_methods: root.required_auth_methods,
+            project: scope.map(|prj| prj.project.clone()),
+            project_domain: scope.and_then(|prj| prj.domain.clone()),
+        }))
+    }
+
+    /// Authentication URL of the Identity service.
+    #[inline]
+    pub fn auth_url(&self) -> &str {
+        &self.auth_url
+    }
+
+    /// Expiration time of the receipt.
+    #[inline]
+    pub fn expires_at(&self) -> &DateTime<FixedOffset> {
+        &self.expires_at
+    }
+
+    /// Authentication methods that have already succeeded.
+    #[inline]
+    pub fn methods(&self) -> &[String] {
+        &self.methods
+    }
+
+    /// Project name or ID (if project scoped).
+    #[inline]
+    pub fn project(&self) -> Option<&IdOrName> {
+        self.project.as_ref()
+    }
+
+    /// Receipt value as passed in the `Openstack-Auth-Receipt` header.
+    #[inline]
+    pub(crate) fn receipt(&self) -> &str {
+        &self.receipt
+    }
+
+    /// Project domain name or ID (if project scoped).
+    #[inline]
+    pub(crate) fn project_domain(&self) -> Option<&IdOrName> {
+        self.project_domain.as_ref()
+    }
+
+    /// Sets of authentication methods, any of which completes the authentication.
+    #[inline]
+    pub fn required_auth_methods(&self) -> &[Vec<String>] {
+        &self.required_auth_methods
+    }
+
+    /// ID of the user being authenticated.
+    #[inline]
+    pub fn user_id(&self) -> &str {
+        &self.user_id
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::header::HeaderMap;
+    use serde_json::json;
+
+    use super::super::protocol;
+    use super::AuthReceipt;
+    use crate::common::IdOrName;
+    use crate::{Error, ErrorKind};
+
+    const RECEIPT_RESPONSE: &str = r#"
+{
+    "receipt": {
+        "methods": ["password"],
+        "user": {"id": "ee4dfb6e5540447cb3741905149d9b6e", "name": "admin"},
+        "expires_at": "2022-01-01T12:05:00.000000Z",
+        "issued_at": "2022-01-01T12:00:00.000000Z"
+    },
+    "required_auth_methods": [["password", "totp"]]
+}"#;
+
+    fn error(body: &str, receipt: Option<&'static str>) -> Error {
+        let mut headers = HeaderMap::new();
+        if let Some(receipt) = receipt {
+            let _ = headers.insert(super::RECEIPT_HEADER, receipt.parse().unwrap());
+        }
+        Error::new(ErrorKind::AuthenticationFailed, "Need more methods")
+            .with_body(body)
+            .with_headers(headers)
+    }
+
+    #[test]
+    fn test_from_error() {
+        let scope = protocol::Project {
+            project: IdOrName::from_name("demo"),
+            domain: Some(IdOrName::from_id("default")),
+        };
+        let receipt = AuthReceipt::from_error(
+            &error(RECEIPT_RESPONSE, Some("abcd")),
+            "https://cloud.local/identity",
+            Some(&scope),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(receipt.receipt(), "abcd");
+        assert_eq!(receipt.user_id(), "ee4dfb6e5540447cb3741905149d9b6e");
+        assert_eq!(receipt.methods(), &["password".to_string()]);
+        assert_eq!(
+            receipt.required_auth_methods(),
+            &[vec!["password".to_string(), "totp".to_string()]]
+        );
+        assert!(!format!("{:?}", receipt).contains("abcd"));
+
+        let value = serde_json::to_value(&receipt).unwrap();
+        assert_eq!(value["project"], json!({"name": "demo"}));
+        let restored: AuthReceipt = serde_json::from_value(value).unwrap();
+        assert_eq!(restored.receipt(), "abcd");
+        assert_eq!(restored.project(), Some(&IdOrName::from_name("demo")));
+        assert_eq!(
+            restored.project_domain(),
+            Some(&IdOrName::from_id("default"))
+        );
+    }
+
+    #[test]
+    fn test_from_error_no_receipt() {
+        let err = error(r#"{"error": {"message": "Unauthorized"}}"#, None);
+        assert!(AuthReceipt::from_error(&err, "https://cloud.local", None)
+            .unwrap()
+            .is_none());
+        let err = error("garbage", Some("abcd"));
+        assert_eq!(
+            AuthReceipt::from_error(&err, "https://cloud.local", None)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidResponse
+        );
+    }
+}
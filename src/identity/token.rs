@@ -14,14 +14,21 @@
 
 //! Token authentication.
 
+use std::sync::Arc;
+
 use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
 use reqwest::{Client, RequestBuilder, Url};
 use static_assertions::assert_impl_all;
 
 use super::internal::Internal;
 use super::protocol;
-use super::{IdOrName, Scope};
-use crate::{AuthType, EndpointFilters, Error};
+#[cfg(feature = "token-cache")]
+use super::TokenCache;
+use super::{IdOrName, Scope, TokenHeader};
+use crate::{
+    AuthType, CatalogEntry, Clock, EndpointFilters, Error, EventHandler, MetricsSink, RetryPolicy,
+};
 
 /// Token authentication using Identity API V3.
 ///
@@ -35,20 +42,30 @@ use crate::{AuthType, EndpointFilters, Error};
 /// with [with_project_scope](#method.with_project_scope):
 ///
 /// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
 /// use osauth::common::IdOrName;
 /// let auth = osauth::identity::Token::new(
 ///     "https://cloud.local/identity",
 ///     "<a token>",
-/// )
-/// .expect("Invalid auth_url")
+/// )?
 /// .with_project_scope(IdOrName::from_name("project1"), IdOrName::from_id("default"));
 ///
-/// let session = osauth::Session::new(auth);
+/// let session = osauth::Session::new(auth).await?;
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
 /// ```
 ///
+/// This is useful when a token is received from an external system (e.g. a dashboard or a
+/// federation callback): a new token scoped to the project is requested with the `token`
+/// authentication method. With [CloudConfig](../struct.CloudConfig.html) (or
+/// [Session::from_env](../struct.Session.html#method.from_env)), token authentication is used
+/// when a token is provided, e.g. in the `OS_TOKEN` environment variable.
+///
 /// The authentication token is cached while it's still valid or until
 /// [refresh](../trait.AuthType.html#tymethod.refresh) is called.
-/// Clones of a `Token` also start with an empty cache.
+/// Clones of a `Token` also start with an empty cache unless
+/// [set_shared_cache](#method.set_shared_cache) is used.
 #[derive(Debug, Clone)]
 pub struct Token {
     inner: Internal,
@@ -106,6 +123,79 @@ impl Token {
         self
     }
 
+    /// Use a persistent token cache.
+    ///
+    /// Tokens are loaded from the cache before authenticating and stored there afterwards.
+    #[cfg(feature = "token-cache")]
+    #[inline]
+    pub fn set_token_cache(&mut self, cache: TokenCache) {
+        self.inner.set_token_cache(cache);
+    }
+
+    /// Set a handler for token events.
+    ///
+    /// [TokenExpiring](../enum.Event.html#variant.TokenExpiring),
+    /// [TokenRefreshed](../enum.Event.html#variant.TokenRefreshed) and
+    /// [TokenRefreshFailed](../enum.Event.html#variant.TokenRefreshFailed) are emitted.
+    #[inline]
+    pub fn set_event_handler<H: EventHandler + 'static>(&mut self, handler: H) {
+        self.inner.set_event_handler(handler);
+    }
+
+    /// Set a sink for authentication metrics.
+    ///
+    /// See [MetricsSink](../trait.MetricsSink.html) for details.
+    #[inline]
+    pub fn set_metrics_sink<S: MetricsSink + 'static>(&mut self, sink: S) {
+        self.inner.set_metrics_sink(sink);
+    }
+
+    /// Set a clock for token validity checks.
+    ///
+    /// Use [ManualClock](../struct.ManualClock.html) to simulate token expiry in tests.
+    #[inline]
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.inner.set_clock(clock);
+    }
+
+    /// Whether to append the API version (`v3`) to the authentication URL.
+    ///
+    /// By default (`None`), the version is appended unless the URL already ends with `/v3`.
+    /// Use `false` if the URL points to the Identity API v3 root under a different path, e.g.
+    /// behind a proxy, and `true` if a path that ends with `v3` is not the API root.
+    #[inline]
+    pub fn set_append_version(&mut self, value: impl Into<Option<bool>>) {
+        self.inner.set_append_version(value.into());
+    }
+
+    /// Set a retry policy for token requests (no retries by default).
+    ///
+    /// Token requests are retried on HTTP 429 and 503 and, unlike other `POST` requests, on
+    /// connection failures, HTTP 502 and 504, e.g. while the Identity service is restarting.
+    /// The policy is independent of the retry policy of the session.
+    #[inline]
+    pub fn set_token_retry_policy(&mut self, value: RetryPolicy) {
+        self.inner.set_retry_policy(value);
+    }
+
+    /// Set the header used to pass the token in authenticated requests.
+    ///
+    /// The default is [XAuthToken](enum.TokenHeader.html#variant.XAuthToken).
+    #[inline]
+    pub fn set_token_header(&mut self, value: TokenHeader) {
+        self.inner.set_token_header(value);
+    }
+
+    /// Share the token with other authentication objects in this process.
+    ///
+    /// Objects with the same authentication URL, credentials and scope use one token and
+    /// refresh it together, so that independently created sessions for the same cloud
+    /// do not authenticate separately.
+    #[inline]
+    pub fn set_shared_cache(&mut self, value: bool) {
+        self.inner.set_shared(value);
+    }
+
     /// Add a scope to the authentication.
     #[inline]
     pub fn with_scope(mut self, scope: Scope) -> Self {
@@ -113,6 +203,63 @@ impl Token {
         self
     }
 
+    /// Use the given handler for token events.
+    #[inline]
+    pub fn with_event_handler<H: EventHandler + 'static>(mut self, handler: H) -> Self {
+        self.set_event_handler(handler);
+        self
+    }
+
+    /// Use the given sink for authentication metrics.
+    #[inline]
+    pub fn with_metrics_sink<S: MetricsSink + 'static>(mut self, sink: S) -> Self {
+        self.set_metrics_sink(sink);
+        self
+    }
+
+    /// Use the given clock for token validity checks.
+    #[inline]
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.set_clock(clock);
+        self
+    }
+
+    /// Whether to append the API version (`v3`) to the authentication URL.
+    #[inline]
+    pub fn with_append_version(mut self, value: impl Into<Option<bool>>) -> Self {
+        self.set_append_version(value);
+        self
+    }
+
+    /// Use the given retry policy for token requests.
+    #[inline]
+    pub fn with_token_retry_policy(mut self, value: RetryPolicy) -> Self {
+        self.set_token_retry_policy(value);
+        self
+    }
+
+    /// Use the given header to pass the token in authenticated requests.
+    #[inline]
+    pub fn with_token_header(mut self, value: TokenHeader) -> Self {
+        self.set_token_header(value);
+        self
+    }
+
+    /// Share the token with other authentication objects in this process.
+    #[inline]
+    pub fn with_shared_cache(mut self, value: bool) -> Self {
+        self.set_shared_cache(value);
+        self
+    }
+
+    /// Use a persistent token cache.
+    #[cfg(feature = "token-cache")]
+    #[inline]
+    pub fn with_token_cache(mut self, cache: TokenCache) -> Self {
+        self.set_token_cache(cache);
+        self
+    }
+
     /// Project name or ID (if project scoped).
     #[inline]
     pub fn project(&self) -> Option<&IdOrName> {
@@ -141,10 +288,42 @@ impl AuthType for Token {
         self.inner.get_endpoint(client, service_type, filters).await
     }
 
+    /// Get all records from the service catalog.
+    async fn get_catalog(&self, client: &Client) -> Result<Vec<CatalogEntry>, Error> {
+        self.inner.get_catalog(client).await
+    }
+
+    /// Get the ID of the authenticated user.
+    async fn get_user_id(&self, client: &Client) -> Result<Option<String>, Error> {
+        self.inner.get_user_id(client).await.map(Some)
+    }
+
+    /// Get names of the roles assigned to the authenticated user.
+    async fn get_roles(&self, client: &Client) -> Result<Vec<String>, Error> {
+        self.inner.get_roles(client).await
+    }
+
+    /// Get the current authentication token.
+    async fn get_token(&self, client: &Client) -> Result<Option<String>, Error> {
+        self.inner.get_token(client).await.map(Some)
+    }
+
     /// Refresh the cached token and service catalog.
     async fn refresh(&self, client: &Client) -> Result<(), Error> {
         self.inner.refresh(client, true).await
     }
+
+    /// Create a copy with its own token cache, starting with the current token.
+    /// Time when the cached token was issued (if any).
+    fn token_issued_at(&self) -> Option<DateTime<FixedOffset>> {
+        self.inner.token_issued_at()
+    }
+
+    fn detach(&self) -> Option<Arc<dyn AuthType>> {
+        Some(Arc::new(Token {
+            inner: self.inner.detach(),
+        }))
+    }
 }
 
 #[cfg(test)]
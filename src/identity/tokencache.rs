@@ -0,0 +1,339 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Persistent token cache.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use fs4::FileExt;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{Error, ErrorKind};
+
+/// A token as stored on disk.
+///
+/// Uses the format of `get_auth_state` in python keystoneauth.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct CachedToken {
+    /// Value of the X-Subject-Token header.
+    #[serde(rename = "auth_token", alias = "value")]
+    pub value: String,
+    /// Raw body of the token response.
+    pub body: Value,
+}
+
+impl fmt::Debug for CachedToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CachedToken")
+            .field("value", &"<hidden>")
+            .finish()
+    }
+}
+
+/// Persistent cache of authentication tokens.
+///
+/// Allows short-lived processes (e.g. CLI tools) to reuse tokens instead of re-authenticating
+/// on every run. Tokens are stored in separate files keyed by the authentication URL, identity
+/// and scope. Files are written atomically with permissions limited to the current user and
+/// protected by advisory locks so that several processes can share a cache. File operations run
+/// on the blocking thread pool of tokio.
+///
+/// A cache failure never fails authentication: a warning is logged and a new token is
+/// requested instead.
+///
+/// Tokens are stored in the format of the python keystoneauth `get_auth_state` call. With
+/// [keystoneauth compatibility](#method.set_keystoneauth_compatible) enabled, files are also
+/// named after the keystoneauth cache ID (`get_cache_id`, with `+` and `/` replaced by `-` and
+/// `_`), so that Python tools can share the cache with a few lines of code.
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// let auth = osauth::identity::Password::new(
+///     "https://cloud.local/identity",
+///     "admin",
+///     "pa$$w0rd",
+///     "Default",
+/// )?
+/// .with_token_cache(osauth::identity::TokenCache::from_xdg()?);
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[derive(Clone, Debug)]
+pub struct TokenCache {
+    directory: PathBuf,
+    keystoneauth_compatible: bool,
+}
+
+impl TokenCache {
+    /// Create a token cache in the given directory.
+    ///
+    /// The directory is created on the first write.
+    #[inline]
+    pub fn new<P: Into<PathBuf>>(directory: P) -> TokenCache {
+        TokenCache {
+            directory: directory.into(),
+            keystoneauth_compatible: false,
+        }
+    }
+
+    /// Create a token cache in the user's cache directory (e.g. `~/.cache/osauth/tokens`).
+    pub fn from_xdg() -> Result<TokenCache, Error> {
+        let directory = dirs::cache_dir().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidConfig,
+                "Cannot determine the cache directory for the token cache",
+            )
+        })?;
+        Ok(TokenCache::new(directory.join("osauth").join("tokens")))
+    }
+
+    /// Directory with cached tokens.
+    #[inline]
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Whether files are named after the keystoneauth cache ID.
+    #[inline]
+    pub fn keystoneauth_compatible(&self) -> bool {
+        self.keystoneauth_compatible
+    }
+
+    /// Whether to name files after the keystoneauth cache ID (disabled by default).
+    ///
+    /// The cache ID only covers the authentication URL, credentials and scope, so it is
+    /// less specific than the default key. Tokens from a different cache layout are not found
+    /// after this setting changes.
+    #[inline]
+    pub fn set_keystoneauth_compatible(&mut self, value: bool) {
+        self.keystoneauth_compatible = value;
+    }
+
+    /// Whether to name files after the keystoneauth cache ID (disabled by default).
+    #[inline]
+    pub fn with_keystoneauth_compatible(mut self, value: bool) -> Self {
+        self.set_keystoneauth_compatible(value);
+        self
+    }
+
+    /// Load a token by its key.
+    pub(crate) async fn load(&self, key: String) -> Option<CachedToken> {
+        let cache = self.clone();
+        match blocking(move || cache.try_load(&key)).await {
+            Ok(result) => result,
+            Err(e) => {
+                warn!(
+                    "Cannot load a cached token from {}: {}",
+                    self.directory.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Store a token under its key.
+    pub(crate) async fn store(&self, key: String, token: CachedToken) {
+        let cache = self.clone();
+        if let Err(e) = blocking(move || cache.try_store(&key, &token)).await {
+            warn!(
+                "Cannot store a token in {}: {}",
+                self.directory.display(),
+                e
+            );
+        }
+    }
+
+    fn try_load(&self, key: &str) -> io::Result<Option<CachedToken>> {
+        let key = file_stem(key);
+        let path = self.directory.join(format!("{}.json", key));
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let _lock = self.lock(&key, false)?;
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let token = serde_json::from_slice(&data)?;
+        debug!("Loaded a cached token from {}", path.display());
+        Ok(Some(token))
+    }
+
+    fn try_store(&self, key: &str, token: &CachedToken) -> io::Result<()> {
+        let key = file_stem(key);
+        create_private_dir(&self.directory)?;
+        let _lock = self.lock(&key, true)?;
+
+        let path = self.directory.join(format!("{}.json", key));
+        // The exclusive lock guarantees that nobody else is writing the temporary file.
+        let tmp_path = self.directory.join(format!("{}.json.tmp", key));
+        let mut file = open_private(&tmp_path)?;
+        serde_json::to_writer(&mut file, token)?;
+        file.flush()?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        debug!("Stored a token in {}", path.display());
+        Ok(())
+    }
+
+    fn lock(&self, key: &str, exclusive: bool) -> io::Result<File> {
+        let file = open_private(&self.directory.join(format!("{}.lock", key)))?;
+        if exclusive {
+            FileExt::lock_exclusive(&file)?;
+        } else {
+            FileExt::lock_shared(&file)?;
+        }
+        // The lock is released when the file is closed.
+        Ok(file)
+    }
+}
+
+/// Make a key usable as a file name (keystoneauth cache IDs use the standard base64 alphabet).
+fn file_stem(key: &str) -> String {
+    key.replace('+', "-").replace('/', "_")
+}
+
+#[cfg(unix)]
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(path)
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    fs::create_dir_all(path)
+}
+
+#[cfg(unix)]
+fn open_private(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn open_private(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+}
+
+/// Run blocking file operations without stalling the async runtime.
+#[allow(clippy::io_other_error)] // io::Error::other requires a newer Rust
+async fn blocking<F, T>(operation: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(operation)
+        .await
+        .unwrap_or_else(|e| Err(io::Error::new(io::ErrorKind::Other, e)))
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{file_stem, CachedToken, TokenCache};
+
+    #[tokio::test]
+    async fn test_store_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = TokenCache::new(dir.path().join("tokens"));
+        assert!(cache.load("key".into()).await.is_none());
+        cache
+            .store(
+                "key".into(),
+                CachedToken {
+                    value: "abcd".into(),
+                    body: json!({"token": {}}),
+                },
+            )
+            .await;
+        let token = cache.load("key".into()).await.unwrap();
+        assert_eq!(token.value, "abcd");
+        assert_eq!(token.body, json!({"token": {}}));
+        assert!(cache.load("other".into()).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_keystoneauth_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = TokenCache::new(dir.path()).with_keystoneauth_compatible(true);
+        let key = "u+zRLid9FK7wsI3TjoI8/QUdzFP26HfCaOJ29ZaqfnU=";
+        assert_eq!(
+            file_stem(key),
+            "u-zRLid9FK7wsI3TjoI8_QUdzFP26HfCaOJ29ZaqfnU="
+        );
+        cache
+            .store(
+                key.into(),
+                CachedToken {
+                    value: "abcd".into(),
+                    body: json!({"token": {}}),
+                },
+            )
+            .await;
+        let path = dir.path().join(format!("{}.json", file_stem(key)));
+        let stored: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap();
+        assert_eq!(stored, json!({"auth_token": "abcd", "body": {"token": {}}}));
+
+        // Files written by earlier versions can still be read.
+        std::fs::write(
+            dir.path().join("old.json"),
+            r#"{"value": "efgh", "body": {}}"#,
+        )
+        .unwrap();
+        assert_eq!(cache.load("old".into()).await.unwrap().value, "efgh");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache = TokenCache::new(dir.path());
+        cache
+            .store(
+                "key".into(),
+                CachedToken {
+                    value: "abcd".into(),
+                    body: json!({}),
+                },
+            )
+            .await;
+        let meta = std::fs::metadata(dir.path().join("key.json")).unwrap();
+        assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+    }
+}
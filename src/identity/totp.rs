@@ -0,0 +1,297 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! TOTP authentication.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use reqwest::{Client, RequestBuilder, Url};
+use static_assertions::assert_impl_all;
+
+use super::internal::Internal;
+use super::passcode::PasscodeSource;
+use super::protocol;
+#[cfg(feature = "token-cache")]
+use super::TokenCache;
+use super::{AuthReceipt, IdOrName, PasscodeProvider, Scope, TokenHeader};
+use crate::{
+    AuthType, CatalogEntry, Clock, EndpointFilters, Error, EventHandler, MetricsSink, RetryPolicy,
+};
+
+/// TOTP (time-based one-time password) authentication using Identity API V3.
+///
+/// Completes a multi-factor authentication started with
+/// [Password::request_receipt](struct.Password.html#method.request_receipt), see
+/// [AuthReceipt](struct.AuthReceipt.html) for an example.
+///
+/// The passcode and the receipt are only valid for a short time, so the token cannot be renewed
+/// once it expires: [refresh](../trait.AuthType.html#tymethod.refresh) fails and a new
+/// receipt has to be requested.
+#[derive(Debug, Clone)]
+pub struct Totp {
+    inner: Internal,
+}
+
+assert_impl_all!(Totp: Send, Sync);
+
+impl Totp {
+    /// Continue authentication from a receipt using the given passcode.
+    ///
+    /// The scope is taken from the receipt.
+    pub fn from_receipt<S: Into<String>>(receipt: AuthReceipt, passcode: S) -> Result<Self, Error> {
+        let body = protocol::AuthRoot {
+            auth: protocol::Auth {
+                identity: protocol::Identity::Totp(protocol::UserAndPasscode {
+                    id: receipt.user_id().into(),
+                    passcode: passcode.into(),
+                }),
+                scope: None,
+            },
+        };
+        let mut inner = Internal::new(receipt.auth_url(), body)?;
+        inner.set_receipt(receipt.receipt())?;
+        if let Some(project) = receipt.project() {
+            inner.set_scope(Scope::Project {
+                project: project.clone(),
+                domain: receipt.project_domain().cloned(),
+            });
+        }
+        Ok(Self { inner })
+    }
+
+    /// Use a persistent token cache.
+    ///
+    /// Tokens are loaded from the cache before authenticating and stored there afterwards.
+    #[cfg(feature = "token-cache")]
+    #[inline]
+    pub fn set_token_cache(&mut self, cache: TokenCache) {
+        self.inner.set_token_cache(cache);
+    }
+
+    /// Set a handler for token events.
+    ///
+    /// [TokenExpiring](../enum.Event.html#variant.TokenExpiring),
+    /// [TokenRefreshed](../enum.Event.html#variant.TokenRefreshed) and
+    /// [TokenRefreshFailed](../enum.Event.html#variant.TokenRefreshFailed) are emitted.
+    #[inline]
+    pub fn set_event_handler<H: EventHandler + 'static>(&mut self, handler: H) {
+        self.inner.set_event_handler(handler);
+    }
+
+    /// Request a TOTP passcode from the provider on every authentication.
+    ///
+    /// The provider replaces the passcode passed on creation. Note that the receipt still expires.
+    #[inline]
+    pub fn set_passcode_provider<P: PasscodeProvider + 'static>(&mut self, provider: P) {
+        self.inner
+            .set_passcode_source(PasscodeSource::new(provider));
+    }
+
+    /// Set a sink for authentication metrics.
+    ///
+    /// See [MetricsSink](../trait.MetricsSink.html) for details.
+    #[inline]
+    pub fn set_metrics_sink<S: MetricsSink + 'static>(&mut self, sink: S) {
+        self.inner.set_metrics_sink(sink);
+    }
+
+    /// Set a clock for token validity checks.
+    ///
+    /// Use [ManualClock](../struct.ManualClock.html) to simulate token expiry in tests.
+    #[inline]
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.inner.set_clock(clock);
+    }
+
+    /// Whether to append the API version (`v3`) to the authentication URL.
+    ///
+    /// By default (`None`), the version is appended unless the URL already ends with `/v3`.
+    /// Use `false` if the URL points to the Identity API v3 root under a different path, e.g.
+    /// behind a proxy, and `true` if a path that ends with `v3` is not the API root.
+    #[inline]
+    pub fn set_append_version(&mut self, value: impl Into<Option<bool>>) {
+        self.inner.set_append_version(value.into());
+    }
+
+    /// Set a retry policy for token requests (no retries by default).
+    ///
+    /// Token requests are retried on HTTP 429 and 503 and, unlike other `POST` requests, on
+    /// connection failures, HTTP 502 and 504, e.g. while the Identity service is restarting.
+    /// The policy is independent of the retry policy of the session.
+    #[inline]
+    pub fn set_token_retry_policy(&mut self, value: RetryPolicy) {
+        self.inner.set_retry_policy(value);
+    }
+
+    /// Set the header used to pass the token in authenticated requests.
+    ///
+    /// The default is [XAuthToken](enum.TokenHeader.html#variant.XAuthToken).
+    #[inline]
+    pub fn set_token_header(&mut self, value: TokenHeader) {
+        self.inner.set_token_header(value);
+    }
+
+    /// Use the given handler for token events.
+    #[inline]
+    pub fn with_event_handler<H: EventHandler + 'static>(mut self, handler: H) -> Self {
+        self.set_event_handler(handler);
+        self
+    }
+
+    /// Request a TOTP passcode from the provider on every authentication.
+    #[inline]
+    pub fn with_passcode_provider<P: PasscodeProvider + 'static>(mut self, provider: P) -> Self {
+        self.set_passcode_provider(provider);
+        self
+    }
+
+    /// Use the given sink for authentication metrics.
+    #[inline]
+    pub fn with_metrics_sink<S: MetricsSink + 'static>(mut self, sink: S) -> Self {
+        self.set_metrics_sink(sink);
+        self
+    }
+
+    /// Use the given clock for token validity checks.
+    #[inline]
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.set_clock(clock);
+        self
+    }
+
+    /// Whether to append the API version (`v3`) to the authentication URL.
+    #[inline]
+    pub fn with_append_version(mut self, value: impl Into<Option<bool>>) -> Self {
+        self.set_append_version(value);
+        self
+    }
+
+    /// Use the given retry policy for token requests.
+    #[inline]
+    pub fn with_token_retry_policy(mut self, value: RetryPolicy) -> Self {
+        self.set_token_retry_policy(value);
+        self
+    }
+
+    /// Use the given header to pass the token in authenticated requests.
+    #[inline]
+    pub fn with_token_header(mut self, value: TokenHeader) -> Self {
+        self.set_token_header(value);
+        self
+    }
+
+    /// Use a persistent token cache.
+    #[cfg(feature = "token-cache")]
+    #[inline]
+    pub fn with_token_cache(mut self, cache: TokenCache) -> Self {
+        self.set_token_cache(cache);
+        self
+    }
+
+    /// Project name or ID (if project scoped).
+    #[inline]
+    pub fn project(&self) -> Option<&IdOrName> {
+        self.inner.project()
+    }
+}
+
+#[async_trait]
+impl AuthType for Totp {
+    /// Authenticate a request.
+    async fn authenticate(
+        &self,
+        client: &Client,
+        request: RequestBuilder,
+    ) -> Result<RequestBuilder, Error> {
+        self.inner.authenticate(client, request).await
+    }
+
+    /// Get a URL for the requested service.
+    async fn get_endpoint(
+        &self,
+        client: &Client,
+        service_type: &str,
+        filters: &EndpointFilters,
+    ) -> Result<Url, Error> {
+        self.inner.get_endpoint(client, service_type, filters).await
+    }
+
+    /// Get all records from the service catalog.
+    async fn get_catalog(&self, client: &Client) -> Result<Vec<CatalogEntry>, Error> {
+        self.inner.get_catalog(client).await
+    }
+
+    /// Get the ID of the authenticated user.
+    async fn get_user_id(&self, client: &Client) -> Result<Option<String>, Error> {
+        self.inner.get_user_id(client).await.map(Some)
+    }
+
+    /// Get names of the roles assigned to the authenticated user.
+    async fn get_roles(&self, client: &Client) -> Result<Vec<String>, Error> {
+        self.inner.get_roles(client).await
+    }
+
+    /// Get the current authentication token.
+    async fn get_token(&self, client: &Client) -> Result<Option<String>, Error> {
+        self.inner.get_token(client).await.map(Some)
+    }
+
+    /// Refresh the cached token and service catalog.
+    async fn refresh(&self, client: &Client) -> Result<(), Error> {
+        self.inner.refresh(client, true).await
+    }
+
+    /// Create a copy with its own token cache, starting with the current token.
+    /// Time when the cached token was issued (if any).
+    fn token_issued_at(&self) -> Option<DateTime<FixedOffset>> {
+        self.inner.token_issued_at()
+    }
+
+    fn detach(&self) -> Option<Arc<dyn AuthType>> {
+        Some(Arc::new(Totp {
+            inner: self.inner.detach(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::DateTime;
+
+    use super::Totp;
+    use crate::identity::{AuthReceipt, IdOrName};
+
+    #[test]
+    fn test_from_receipt() {
+        let receipt: AuthReceipt = serde_json::from_value(serde_json::json!({
+            "auth_url": "https://cloud.local/identity",
+            "receipt": "abcd",
+            "expires_at": DateTime::parse_from_rfc3339("2022-01-01T12:05:00Z").unwrap(),
+            "user_id": "ee4dfb6e5540447cb3741905149d9b6e",
+            "project": {"name": "demo"}
+        }))
+        .unwrap();
+        let auth = Totp::from_receipt(receipt, "123456").unwrap();
+        assert_eq!(auth.project(), Some(&IdOrName::from_name("demo")));
+        assert_eq!(
+            auth.inner.token_endpoint(),
+            "https://cloud.local/identity/v3/auth/tokens"
+        );
+        let debug = format!("{:?}", auth);
+        assert!(!debug.contains("123456"));
+        assert!(!debug.contains("abcd"));
+    }
+}
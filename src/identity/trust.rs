@@ -0,0 +1,344 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Trusts management.
+
+use chrono::{DateTime, FixedOffset};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use super::IdOrName;
+use crate::common::IdAndName;
+use crate::services::IDENTITY;
+use crate::{Error, Session};
+
+/// A trust (delegation of roles from one user to another).
+#[derive(Clone, Debug, Deserialize)]
+#[non_exhaustive]
+pub struct Trust {
+    /// Trust ID.
+    pub id: String,
+    /// ID of the user delegating the roles.
+    pub trustor_user_id: String,
+    /// ID of the user receiving the roles.
+    pub trustee_user_id: String,
+    /// ID of the project the roles are delegated on.
+    #[serde(default)]
+    pub project_id: Option<String>,
+    /// Whether the trustee acts on behalf of the trustor.
+    #[serde(default)]
+    pub impersonation: bool,
+    /// Roles delegated by the trust.
+    #[serde(default)]
+    pub roles: Vec<IdAndName>,
+    /// Expiration time (if any).
+    #[serde(default)]
+    pub expires_at: Option<DateTime<FixedOffset>>,
+    /// Whether the trustee may delegate the roles further.
+    #[serde(default)]
+    pub allow_redelegation: bool,
+    /// How many more times the roles may be delegated.
+    #[serde(default)]
+    pub redelegation_count: Option<u32>,
+    /// How many more times the trust may be used to get a token (unlimited if `None`).
+    #[serde(default)]
+    pub remaining_uses: Option<u32>,
+}
+
+/// A request to create a trust.
+///
+/// The current user becomes the trustor:
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use osauth::common::IdOrName;
+/// use osauth::identity::NewTrust;
+///
+/// let session = osauth::Session::from_env().await?;
+/// let trust = session
+///     .create_trust(
+///         NewTrust::new("3ec3164f750146be97f21559ee4d9c51", "1a6f364d14a846e2b11bc8e0b3f5b2a7")
+///             .with_role(IdOrName::from_name("member"))
+///             .with_redelegation_count(1),
+///     )
+///     .await?;
+/// println!("Created trust {}", trust.id);
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[derive(Clone, Debug, Serialize)]
+pub struct NewTrust {
+    trustee_user_id: String,
+    project_id: String,
+    impersonation: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    roles: Vec<IdOrName>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<DateTime<FixedOffset>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allow_redelegation: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redelegation_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remaining_uses: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateTrust<'a> {
+    trustor_user_id: &'a str,
+    #[serde(flatten)]
+    trust: &'a NewTrust,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateRoot<'a> {
+    trust: CreateTrust<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TrustRoot {
+    trust: Trust,
+}
+
+#[derive(Debug, Deserialize)]
+struct TrustsRoot {
+    trusts: Vec<Trust>,
+}
+
+impl NewTrust {
+    /// Start creating a trust for the given trustee on the given project.
+    pub fn new<S1, S2>(trustee_user_id: S1, project_id: S2) -> NewTrust
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        NewTrust {
+            trustee_user_id: trustee_user_id.into(),
+            project_id: project_id.into(),
+            impersonation: false,
+            roles: Vec::new(),
+            expires_at: None,
+            allow_redelegation: None,
+            redelegation_count: None,
+            remaining_uses: None,
+        }
+    }
+
+    /// Add a role to delegate.
+    #[inline]
+    pub fn add_role(&mut self, value: IdOrName) {
+        self.roles.push(value);
+    }
+
+    /// Set whether the trustee may delegate the roles further.
+    #[inline]
+    pub fn set_allow_redelegation(&mut self, value: bool) {
+        self.allow_redelegation = Some(value);
+    }
+
+    /// Set the expiration time.
+    #[inline]
+    pub fn set_expires_at(&mut self, value: DateTime<FixedOffset>) {
+        self.expires_at = Some(value);
+    }
+
+    /// Set whether the trustee acts on behalf of the trustor (disabled by default).
+    #[inline]
+    pub fn set_impersonation(&mut self, value: bool) {
+        self.impersonation = value;
+    }
+
+    /// Set the maximum depth of redelegation (also allows redelegation).
+    #[inline]
+    pub fn set_redelegation_count(&mut self, value: u32) {
+        self.allow_redelegation = Some(true);
+        self.redelegation_count = Some(value);
+    }
+
+    /// Set how many times the trust may be used to get a token (unlimited by default).
+    #[inline]
+    pub fn set_remaining_uses(&mut self, value: u32) {
+        self.remaining_uses = Some(value);
+    }
+
+    /// Add a role to delegate.
+    #[inline]
+    pub fn with_role(mut self, value: IdOrName) -> Self {
+        self.add_role(value);
+        self
+    }
+
+    /// Set whether the trustee may delegate the roles further.
+    #[inline]
+    pub fn with_allow_redelegation(mut self, value: bool) -> Self {
+        self.set_allow_redelegation(value);
+        self
+    }
+
+    /// Set the expiration time.
+    #[inline]
+    pub fn with_expires_at(mut self, value: DateTime<FixedOffset>) -> Self {
+        self.set_expires_at(value);
+        self
+    }
+
+    /// Set whether the trustee acts on behalf of the trustor (disabled by default).
+    #[inline]
+    pub fn with_impersonation(mut self, value: bool) -> Self {
+        self.set_impersonation(value);
+        self
+    }
+
+    /// Set the maximum depth of redelegation (also allows redelegation).
+    #[inline]
+    pub fn with_redelegation_count(mut self, value: u32) -> Self {
+        self.set_redelegation_count(value);
+        self
+    }
+
+    /// Set how many times the trust may be used to get a token (unlimited by default).
+    #[inline]
+    pub fn with_remaining_uses(mut self, value: u32) -> Self {
+        self.set_remaining_uses(value);
+        self
+    }
+}
+
+impl Session {
+    /// Create a trust with the current user as the trustor.
+    pub async fn create_trust(&self, trust: NewTrust) -> Result<Trust, Error> {
+        let user_id = self.current_user_id().await?;
+        debug!(
+            "Creating a trust from user {} to user {} on project {}",
+            user_id, trust.trustee_user_id, trust.project_id
+        );
+        let root: TrustRoot = self
+            .post(IDENTITY, &["OS-TRUST", "trusts"])
+            .json(&CreateRoot {
+                trust: CreateTrust {
+                    trustor_user_id: &user_id,
+                    trust: &trust,
+                },
+            })
+            .fetch()
+            .await?;
+        Ok(root.trust)
+    }
+
+    /// Delete a trust.
+    pub async fn delete_trust<S: AsRef<str>>(&self, id: S) -> Result<(), Error> {
+        debug!("Deleting trust {}", id.as_ref());
+        let _ = self
+            .delete(IDENTITY, &["OS-TRUST", "trusts", id.as_ref()])
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// Get a trust by its ID.
+    pub async fn get_trust<S: AsRef<str>>(&self, id: S) -> Result<Trust, Error> {
+        let root: TrustRoot = self
+            .get_json(IDENTITY, &["OS-TRUST", "trusts", id.as_ref()])
+            .await?;
+        Ok(root.trust)
+    }
+
+    /// List trusts created by the current user.
+    pub async fn list_trusts(&self) -> Result<Vec<Trust>, Error> {
+        self.list_trusts_by("trustor_user_id").await
+    }
+
+    /// List trusts delegating roles to the current user.
+    pub async fn list_received_trusts(&self) -> Result<Vec<Trust>, Error> {
+        self.list_trusts_by("trustee_user_id").await
+    }
+
+    async fn list_trusts_by(&self, field: &str) -> Result<Vec<Trust>, Error> {
+        let user_id = self.current_user_id().await?;
+        let root: TrustsRoot = self
+            .get(IDENTITY, &["OS-TRUST", "trusts"])
+            .query(&[(field, &user_id)])
+            .fetch()
+            .await?;
+        Ok(root.trusts)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::test;
+
+    const CREATE_REQUEST: &str = r#"
+{
+    "trust": {
+        "trustor_user_id": "a0fdfd3e0b1b4c6a8ef2e64c8b1e3e1c",
+        "trustee_user_id": "3ec3164f750146be97f21559ee4d9c51",
+        "project_id": "1a6f364d14a846e2b11bc8e0b3f5b2a7",
+        "impersonation": false,
+        "roles": [{"name": "member"}],
+        "allow_redelegation": true,
+        "redelegation_count": 2
+    }
+}"#;
+
+    const CREATE_RESPONSE: &str = r#"
+{
+    "trust": {
+        "id": "394998fa61f14736b1f0c1f322882949",
+        "trustor_user_id": "a0fdfd3e0b1b4c6a8ef2e64c8b1e3e1c",
+        "trustee_user_id": "3ec3164f750146be97f21559ee4d9c51",
+        "project_id": "1a6f364d14a846e2b11bc8e0b3f5b2a7",
+        "impersonation": false,
+        "roles": [{"id": "4494bc5bea1a4105ad7fbba6a7eb9ef4", "name": "member"}],
+        "roles_links": {},
+        "expires_at": null,
+        "allow_redelegation": true,
+        "redelegation_count": 2,
+        "remaining_uses": null,
+        "links": {}
+    }
+}"#;
+
+    #[test]
+    fn test_create_request() {
+        let trust = NewTrust::new(
+            "3ec3164f750146be97f21559ee4d9c51",
+            "1a6f364d14a846e2b11bc8e0b3f5b2a7",
+        )
+        .with_role(IdOrName::from_name("member"))
+        .with_redelegation_count(2);
+        test::compare(
+            CREATE_REQUEST,
+            CreateRoot {
+                trust: CreateTrust {
+                    trustor_user_id: "a0fdfd3e0b1b4c6a8ef2e64c8b1e3e1c",
+                    trust: &trust,
+                },
+            },
+        );
+    }
+
+    #[test]
+    fn test_create_response() {
+        let root: TrustRoot = serde_json::from_str(CREATE_RESPONSE).unwrap();
+        let trust = root.trust;
+        assert_eq!(trust.id, "394998fa61f14736b1f0c1f322882949");
+        assert_eq!(trust.roles[0].name, "member");
+        assert!(trust.allow_redelegation);
+        assert_eq!(trust.redelegation_count, Some(2));
+        assert!(trust.remaining_uses.is_none());
+    }
+}
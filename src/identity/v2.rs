@@ -0,0 +1,567 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Legacy password authentication using Identity API v2.0.
+
+use std::fmt;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use log::{debug, trace};
+use reqwest::header::HeaderValue;
+use reqwest::{Client, RequestBuilder, Url};
+use serde::{Deserialize, Serialize};
+use static_assertions::assert_impl_all;
+use tokio::sync::{RwLock, RwLockReadGuard};
+
+use super::protocol::{CatalogRecord, Endpoint};
+use super::TOKEN_MIN_VALIDITY;
+use crate::catalog::ServiceCatalog;
+use crate::client;
+use crate::common::{IdOrName, Version};
+use crate::{AuthType, CatalogEntry, EndpointFilters, Error, ErrorKind};
+
+#[derive(Clone, Serialize)]
+struct PasswordCredentials {
+    username: String,
+    password: String,
+}
+
+impl fmt::Debug for PasswordCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PasswordCredentials")
+            .field("username", &self.username)
+            .field("password", &"<hidden>")
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Auth {
+    password_credentials: PasswordCredentials,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tenant_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tenant_name: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct AuthRoot {
+    auth: Auth,
+}
+
+#[derive(Debug, Deserialize)]
+struct TenantRecord {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenRecord {
+    id: String,
+    expires: DateTime<FixedOffset>,
+    #[serde(default)]
+    tenant: Option<TenantRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RoleRecord {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserRecord {
+    id: String,
+    #[serde(default)]
+    roles: Vec<RoleRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EndpointRecord {
+    #[serde(default)]
+    region: String,
+    #[serde(rename = "publicURL")]
+    public_url: Option<String>,
+    #[serde(rename = "internalURL")]
+    internal_url: Option<String>,
+    #[serde(rename = "adminURL")]
+    admin_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ServiceRecord {
+    #[serde(rename = "type")]
+    service_type: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    endpoints: Vec<EndpointRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Access {
+    token: TokenRecord,
+    #[serde(default)]
+    service_catalog: Vec<ServiceRecord>,
+    user: UserRecord,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccessRoot {
+    access: Access,
+}
+
+/// Convert a v2.0 service record into the v3 format (one endpoint per interface).
+impl From<ServiceRecord> for CatalogRecord {
+    fn from(value: ServiceRecord) -> CatalogRecord {
+        let mut endpoints = Vec::with_capacity(value.endpoints.len() * 3);
+        for endp in value.endpoints {
+            let urls = [
+                ("public", endp.public_url),
+                ("internal", endp.internal_url),
+                ("admin", endp.admin_url),
+            ];
+            for (interface, url) in urls {
+                if let Some(url) = url {
+                    endpoints.push(Endpoint {
+                        interface: interface.to_string(),
+                        region: endp.region.clone(),
+                        url,
+                    });
+                }
+            }
+        }
+        CatalogRecord {
+            service_type: value.service_type,
+            name: value.name,
+            endpoints,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Token {
+    value: String,
+    expires_at: DateTime<FixedOffset>,
+    catalog: ServiceCatalog,
+    user_id: String,
+    roles: Vec<String>,
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Token")
+            .field("value", &"<hidden>")
+            .field("expires_at", &self.expires_at)
+            .field("user_id", &self.user_id)
+            .finish()
+    }
+}
+
+impl Token {
+    fn is_alive(&self) -> bool {
+        let validity_time_left = self.expires_at.signed_duration_since(Utc::now());
+        trace!("Token is valid for {:?}", validity_time_left);
+        validity_time_left > Duration::minutes(TOKEN_MIN_VALIDITY)
+    }
+}
+
+/// Password authentication using the legacy Identity API v2.0.
+///
+/// Only use it with clouds that do not provide Identity API v3. When loading the configuration
+/// from `clouds.yaml` or environment variables, this authentication type is selected
+/// automatically if the Identity service does not advertise v3 (or explicitly with the
+/// `v2password` authentication type).
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use osauth::common::IdOrName;
+///
+/// let auth = osauth::identity::PasswordV2::new(
+///     "https://cloud.local/identity",
+///     "admin",
+///     "pa$$w0rd",
+/// )?
+/// .with_tenant(IdOrName::from_name("project1"));
+///
+/// let session = osauth::Session::new(auth).await?;
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+///
+/// Available only with the `identity-v2` feature.
+#[derive(Debug)]
+pub struct PasswordV2 {
+    body: AuthRoot,
+    token_endpoint: String,
+    cached_token: RwLock<Option<Token>>,
+}
+
+assert_impl_all!(PasswordV2: Send, Sync);
+
+impl Clone for PasswordV2 {
+    fn clone(&self) -> PasswordV2 {
+        PasswordV2 {
+            body: self.body.clone(),
+            token_endpoint: self.token_endpoint.clone(),
+            cached_token: RwLock::new(None),
+        }
+    }
+}
+
+impl PasswordV2 {
+    /// Create a password authentication.
+    pub fn new<U, S1, S2>(auth_url: U, user_name: S1, password: S2) -> Result<PasswordV2, Error>
+    where
+        U: AsRef<str>,
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        let mut auth_url = Url::parse(auth_url.as_ref())
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("Invalid auth_url: {}", e)))?;
+
+        let _ = auth_url
+            .path_segments_mut()
+            .map_err(|_| Error::new(ErrorKind::InvalidConfig, "Invalid auth_url: wrong schema?"))?
+            .pop_if_empty()
+            .push("");
+
+        let token_endpoint = if auth_url.as_str().ends_with("/v2.0/") {
+            format!("{}tokens", auth_url)
+        } else {
+            format!("{}v2.0/tokens", auth_url)
+        };
+
+        Ok(PasswordV2 {
+            body: AuthRoot {
+                auth: Auth {
+                    password_credentials: PasswordCredentials {
+                        username: user_name.into(),
+                        password: password.into(),
+                    },
+                    tenant_id: None,
+                    tenant_name: None,
+                },
+            },
+            token_endpoint,
+            cached_token: RwLock::new(None),
+        })
+    }
+
+    /// Scope authentication to the given tenant (project).
+    pub fn set_tenant(&mut self, tenant: IdOrName) {
+        let auth = &mut self.body.auth;
+        match tenant {
+            IdOrName::Id(id) => {
+                auth.tenant_id = Some(id);
+                auth.tenant_name = None;
+            }
+            IdOrName::Name(name) => {
+                auth.tenant_id = None;
+                auth.tenant_name = Some(name);
+            }
+        }
+    }
+
+    /// Scope authentication to the given tenant (project).
+    #[inline]
+    pub fn with_tenant(mut self, tenant: IdOrName) -> Self {
+        self.set_tenant(tenant);
+        self
+    }
+
+    /// User name.
+    #[inline]
+    pub fn user_name(&self) -> &str {
+        &self.body.auth.password_credentials.username
+    }
+
+    async fn cached_token(&self, client: &Client) -> Result<RwLockReadGuard<'_, Token>, Error> {
+        self.refresh_token(client, false).await?;
+        let guard = self.cached_token.read().await;
+        // unwrap is safe because refresh_token unconditionally populates the token
+        Ok(RwLockReadGuard::try_map(guard, |opt| opt.as_ref()).unwrap())
+    }
+
+    async fn refresh_token(&self, client: &Client, force: bool) -> Result<(), Error> {
+        if !force && self.token_alive(&*self.cached_token.read().await) {
+            return Ok(());
+        }
+
+        let mut lock = self.cached_token.write().await;
+        if !force && self.token_alive(&lock) {
+            return Ok(());
+        }
+
+        let resp = client
+            .post(&self.token_endpoint)
+            .json(&self.body)
+            .send()
+            .await?;
+        let resp = client::check(resp).await.map_err(|e| {
+            if e.kind() == ErrorKind::Unauthorized {
+                e.with_kind(ErrorKind::AuthenticationFailed)
+            } else {
+                e
+            }
+        })?;
+        let root: AccessRoot = resp.json().await?;
+        debug!(
+            "Received a v2.0 token expiring at {}",
+            root.access.token.expires
+        );
+        let tenant_id = root.access.token.tenant.map(|t| t.id);
+        *lock = Some(Token {
+            value: root.access.token.id,
+            expires_at: root.access.token.expires,
+            catalog: ServiceCatalog::new(
+                root.access
+                    .service_catalog
+                    .into_iter()
+                    .map(From::from)
+                    .collect(),
+            )
+            .with_project_id(tenant_id),
+            user_id: root.access.user.id,
+            roles: root
+                .access
+                .user
+                .roles
+                .into_iter()
+                .map(|role| role.name)
+                .collect(),
+        });
+        Ok(())
+    }
+
+    #[inline]
+    fn token_alive(&self, token: &Option<Token>) -> bool {
+        token.as_ref().map(Token::is_alive).unwrap_or(false)
+    }
+}
+
+#[async_trait]
+impl AuthType for PasswordV2 {
+    /// Authenticate a request.
+    async fn authenticate(
+        &self,
+        client: &Client,
+        request: RequestBuilder,
+    ) -> Result<RequestBuilder, Error> {
+        let token = self.cached_token(client).await?;
+        let mut value = HeaderValue::from_str(&token.value).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidResponse,
+                format!("Token is not a valid header value: {}", e),
+            )
+        })?;
+        value.set_sensitive(true);
+        Ok(request.header("x-auth-token", value))
+    }
+
+    /// Get a URL for the requested service.
+    async fn get_endpoint(
+        &self,
+        client: &Client,
+        service_type: &str,
+        filters: &EndpointFilters,
+    ) -> Result<Url, Error> {
+        let token = self.cached_token(client).await?;
+        token.catalog.find_endpoint(service_type, filters)
+    }
+
+    /// Get all records from the service catalog.
+    async fn get_catalog(&self, client: &Client) -> Result<Vec<CatalogEntry>, Error> {
+        let token = self.cached_token(client).await?;
+        Ok(token.catalog.entries())
+    }
+
+    /// Get the ID of the authenticated user.
+    async fn get_user_id(&self, client: &Client) -> Result<Option<String>, Error> {
+        let token = self.cached_token(client).await?;
+        Ok(Some(token.user_id.clone()))
+    }
+
+    /// Get names of the roles assigned to the authenticated user.
+    async fn get_roles(&self, client: &Client) -> Result<Vec<String>, Error> {
+        let token = self.cached_token(client).await?;
+        Ok(token.roles.clone())
+    }
+
+    /// Get the current authentication token.
+    async fn get_token(&self, client: &Client) -> Result<Option<String>, Error> {
+        let token = self.cached_token(client).await?;
+        Ok(Some(token.value.clone()))
+    }
+
+    /// Refresh the cached token and service catalog.
+    async fn refresh(&self, client: &Client) -> Result<(), Error> {
+        self.refresh_token(client, true).await
+    }
+}
+
+/// Versions advertised by the Identity service.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum IdentityRoot {
+    Values { versions: VersionValues },
+    Multiple { versions: Vec<Version> },
+    One { version: Version },
+}
+
+#[derive(Debug, Deserialize)]
+struct VersionValues {
+    values: Vec<Version>,
+}
+
+impl IdentityRoot {
+    fn supports_v3(&self) -> bool {
+        let versions = match self {
+            IdentityRoot::Values { versions } => &versions.values[..],
+            IdentityRoot::Multiple { versions } => &versions[..],
+            IdentityRoot::One { version } => std::slice::from_ref(version),
+        };
+        versions.iter().any(|version| version.id.0 == 3)
+    }
+}
+
+/// Check whether the Identity service at `auth_url` only supports API v2.0.
+///
+/// Returns `false` if the discovery fails, so that v3 is used by default.
+pub(crate) async fn is_v2_only(client: &Client, auth_url: &str) -> bool {
+    let path = auth_url.trim_end_matches('/');
+    if path.ends_with("/v3") {
+        return false;
+    }
+    if path.ends_with("/v2.0") {
+        return true;
+    }
+
+    // Keystone responds with 300 Multiple Choices, so the status is not checked.
+    let root = match client.get(auth_url).send().await {
+        Ok(resp) => resp.json::<IdentityRoot>().await,
+        Err(e) => Err(e),
+    };
+    match root {
+        Ok(root) if !root.supports_v3() => {
+            debug!("Identity service at {} does not support v3", auth_url);
+            true
+        }
+        Ok(_) => false,
+        Err(e) => {
+            debug!("Identity version discovery at {} failed: {}", auth_url, e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{AccessRoot, CatalogRecord, IdentityRoot, PasswordV2};
+    use crate::catalog::ServiceCatalog;
+    use crate::common::IdOrName;
+    use crate::EndpointFilters;
+
+    #[test]
+    fn test_token_endpoint() {
+        let auth = PasswordV2::new("http://127.0.0.1:5000", "admin", "pa$$w0rd").unwrap();
+        assert_eq!(auth.token_endpoint, "http://127.0.0.1:5000/v2.0/tokens");
+        let auth = PasswordV2::new("http://127.0.0.1/identity/v2.0/", "admin", "pa$$w0rd").unwrap();
+        assert_eq!(auth.token_endpoint, "http://127.0.0.1/identity/v2.0/tokens");
+        assert_eq!(auth.user_name(), "admin");
+        assert!(!format!("{:?}", auth).contains("pa$$w0rd"));
+    }
+
+    #[test]
+    fn test_body() {
+        let auth = PasswordV2::new("http://127.0.0.1:5000", "admin", "pa$$w0rd")
+            .unwrap()
+            .with_tenant(IdOrName::from_name("demo"));
+        assert_eq!(
+            serde_json::to_value(&auth.body).unwrap(),
+            json!({
+                "auth": {
+                    "passwordCredentials": {"username": "admin", "password": "pa$$w0rd"},
+                    "tenantName": "demo"
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_catalog() {
+        let root: AccessRoot = serde_json::from_value(json!({
+            "access": {
+                "token": {
+                    "id": "abcd",
+                    "expires": "2022-01-01T12:00:00Z",
+                    "tenant": {"id": "1234", "name": "demo"}
+                },
+                "serviceCatalog": [{
+                    "type": "compute",
+                    "name": "nova",
+                    "endpoints": [{
+                        "region": "RegionOne",
+                        "publicURL": "https://nova.local/v2.1/$(tenant_id)s",
+                        "internalURL": "http://10.0.0.1/v2.1/$(tenant_id)s"
+                    }]
+                }],
+                "user": {"id": "5678", "name": "admin", "roles": [{"name": "member"}]}
+            }
+        }))
+        .unwrap();
+        assert_eq!(root.access.user.roles[0].name, "member");
+        let catalog = ServiceCatalog::new(
+            root.access
+                .service_catalog
+                .into_iter()
+                .map(CatalogRecord::from)
+                .collect(),
+        )
+        .with_project_id(Some("1234".into()));
+        let entries = catalog.entries();
+        assert_eq!(entries[0].endpoints.len(), 2);
+        let url = catalog
+            .find_endpoint("compute", &EndpointFilters::default())
+            .unwrap();
+        assert_eq!(url.as_str(), "https://nova.local/v2.1/1234");
+    }
+
+    #[test]
+    fn test_discovery() {
+        let root: IdentityRoot = serde_json::from_value(json!({
+            "versions": {"values": [
+                {"id": "v3.14", "status": "stable", "links": []},
+                {"id": "v2.0", "status": "deprecated", "links": []}
+            ]}
+        }))
+        .unwrap();
+        assert!(root.supports_v3());
+        let root: IdentityRoot = serde_json::from_value(json!({
+            "versions": {"values": [{"id": "v2.0", "status": "stable", "links": []}]}
+        }))
+        .unwrap();
+        assert!(!root.supports_v3());
+        let root: IdentityRoot = serde_json::from_value(json!({
+            "version": {"id": "v2.0", "status": "stable", "links": []}
+        }))
+        .unwrap();
+        assert!(!root.supports_v3());
+    }
+}
@@ -0,0 +1,242 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for the Image service (Glance).
+
+use std::error::Error as StdError;
+use std::sync::{Arc, Mutex};
+
+use async_stream::try_stream;
+use bytes::Bytes;
+use futures::stream::{Stream, StreamExt, TryStream, TryStreamExt};
+use log::{debug, warn};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Body, StatusCode};
+use serde::Deserialize;
+
+use super::checksum::{verify, Algorithm, Hasher};
+use super::services::IMAGE;
+use super::{Error, Session};
+
+/// Checksum fields of an image.
+#[derive(Debug, Deserialize)]
+struct ImageChecksums {
+    #[serde(default)]
+    checksum: Option<String>,
+    #[serde(default)]
+    os_hash_algo: Option<String>,
+    #[serde(default)]
+    os_hash_value: Option<String>,
+}
+
+impl ImageChecksums {
+    /// Pick the strongest supported checksum.
+    fn expected(self) -> Option<(Algorithm, String)> {
+        if let (Some(algo), Some(value)) = (self.os_hash_algo, self.os_hash_value) {
+            match Algorithm::from_name(&algo) {
+                Some(algorithm) => return Some((algorithm, value)),
+                None => debug!("Unsupported hash algorithm {}, falling back to MD5", algo),
+            }
+        }
+
+        self.checksum.map(|value| (Algorithm::Md5, value))
+    }
+}
+
+/// Upload data of an image.
+///
+/// The image must be in the `queued` state. The data is streamed to the service
+/// without buffering it in memory.
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// let session = osauth::Session::from_env().await?;
+/// let data = futures::stream::iter(vec![Ok::<_, std::io::Error>(vec![0u8; 1024])]);
+/// osauth::image::upload_image_data(&session, "<image ID>", data).await?;
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+pub async fn upload_image_data<I, S>(session: &Session, image_id: I, data: S) -> Result<(), Error>
+where
+    I: AsRef<str>,
+    S: TryStream + Send + Sync + 'static,
+    S::Error: Into<Box<dyn StdError + Send + Sync>>,
+    Bytes: From<S::Ok>,
+{
+    put_image_data(session, image_id.as_ref(), Body::wrap_stream(data)).await
+}
+
+/// Wrap a stream of bytes (out of the generic context to help type inference).
+fn bytes_body<S>(data: S) -> Body
+where
+    S: Stream<Item = Result<Bytes, Box<dyn StdError + Send + Sync>>> + Send + Sync + 'static,
+{
+    Body::wrap_stream(data)
+}
+
+async fn put_image_data(session: &Session, image_id: &str, body: Body) -> Result<(), Error> {
+    debug!("Uploading data for image {}", image_id);
+    let _ = session
+        .put(IMAGE, &["images", image_id, "file"])
+        .header(CONTENT_TYPE, "application/octet-stream")
+        .body(body)
+        .send()
+        .await?;
+    debug!("Finished uploading data for image {}", image_id);
+    Ok(())
+}
+
+/// Upload data of an image, verifying that the service has received it intact.
+///
+/// Same as [upload_image_data](fn.upload_image_data.html), but the MD5 checksum of the data is
+/// calculated during the upload and compared with the `checksum` reported by the service
+/// afterwards. Fails with [ChecksumMismatch](../enum.ErrorKind.html#variant.ChecksumMismatch)
+/// if they differ.
+pub async fn upload_image_data_verified<I, S>(
+    session: &Session,
+    image_id: I,
+    data: S,
+) -> Result<(), Error>
+where
+    I: AsRef<str>,
+    S: TryStream + Send + Sync + 'static,
+    S::Error: Into<Box<dyn StdError + Send + Sync>>,
+    Bytes: From<S::Ok>,
+{
+    let image_id = image_id.as_ref();
+    let hasher = Arc::new(Mutex::new(Hasher::new(Algorithm::Md5)));
+    let hasher2 = Arc::clone(&hasher);
+    let data = data.map_ok(move |chunk| {
+        let chunk = Bytes::from(chunk);
+        hasher2
+            .lock()
+            .expect("Hasher lock is poisoned")
+            .update(&chunk);
+        chunk
+    });
+    put_image_data(session, image_id, bytes_body(data.map_err(Into::into))).await?;
+    let hasher = hasher.lock().expect("Hasher lock is poisoned").clone();
+
+    let checksums: ImageChecksums = session.get_json(IMAGE, &["images", image_id]).await?;
+    match checksums.checksum {
+        Some(value) => verify(
+            &format!("image {}", image_id),
+            Algorithm::Md5,
+            &value,
+            hasher,
+        ),
+        None => {
+            warn!(
+                "Image {} has no checksum after upload, data was not verified",
+                image_id
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Download data of an image, verifying its checksum.
+///
+/// The checksum is verified after the last chunk has been received, an error is returned
+/// as the last item of the stream on mismatch. The secure hash (`os_hash_value`) is used
+/// when its algorithm is supported, otherwise the MD5 `checksum` is used.
+pub fn download_image_data<I>(
+    session: &Session,
+    image_id: I,
+) -> impl Stream<Item = Result<Bytes, Error>> + '_
+where
+    I: Into<String>,
+{
+    let image_id = image_id.into();
+    try_stream! {
+        let checksums: ImageChecksums = session.get_json(IMAGE, &["images", &image_id]).await?;
+        let expected = checksums.expected();
+        if expected.is_none() {
+            warn!("Image {} has no checksum, data will not be verified", image_id);
+        }
+
+        let response = session.get(IMAGE, &["images", &image_id, "file"]).send().await?;
+        if response.status() == StatusCode::NO_CONTENT {
+            debug!("Image {} has no data", image_id);
+            return;
+        }
+
+        let mut hasher = expected.as_ref().map(|(algorithm, _)| Hasher::new(*algorithm));
+        let mut body = response.bytes_stream();
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk?;
+            if let Some(ref mut hasher) = hasher {
+                hasher.update(&chunk);
+            }
+            yield chunk;
+        }
+
+        if let (Some(hasher), Some((algorithm, value))) = (hasher, expected) {
+            verify(&format!("image {}", image_id), algorithm, &value, hasher)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ImageChecksums;
+    use crate::checksum::{verify, Algorithm, Hasher};
+    use crate::ErrorKind;
+
+    #[test]
+    fn test_expected_prefers_secure_hash() {
+        let checksums: ImageChecksums = serde_json::from_str(
+            r#"{"checksum": "md5", "os_hash_algo": "sha512", "os_hash_value": "sha"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            checksums.expected(),
+            Some((Algorithm::Sha512, "sha".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_expected_fallback_md5() {
+        let checksums: ImageChecksums = serde_json::from_str(
+            r#"{"checksum": "md5", "os_hash_algo": "blake2", "os_hash_value": "blake"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            checksums.expected(),
+            Some((Algorithm::Md5, "md5".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_expected_none() {
+        let checksums: ImageChecksums = serde_json::from_str(r#"{"checksum": null}"#).unwrap();
+        assert_eq!(checksums.expected(), None);
+    }
+
+    #[test]
+    fn test_verify() {
+        let mut hasher = Hasher::new(Algorithm::Md5);
+        hasher.update(b"abc");
+        verify(
+            "id",
+            Algorithm::Md5,
+            "900150983CD24FB0D6963F7D28E17F72",
+            hasher.clone(),
+        )
+        .unwrap();
+        let err = verify("id", Algorithm::Md5, "abcd", hasher).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ChecksumMismatch);
+    }
+}
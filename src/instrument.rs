@@ -0,0 +1,178 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Instrumentation with `tracing`.
+
+use std::future::Future;
+use std::time::Instant;
+
+use reqwest::{Method, Response, Url};
+use tracing::field::Empty;
+use tracing::{debug_span, Instrument, Span};
+
+use super::error::request_id;
+use super::Error;
+
+/// Run an HTTP request in a span, recording its outcome.
+///
+/// The span is called `http_request` and has fields `method`, `url`, `status`, `request_id`
+/// and `duration_ms`.
+pub(crate) async fn request<F>(method: &Method, url: &Url, future: F) -> Result<Response, Error>
+where
+    F: Future<Output = Result<Response, Error>>,
+{
+    let span = debug_span!(
+        "http_request",
+        method = %method,
+        url = %url,
+        status = Empty,
+        request_id = Empty,
+        duration_ms = Empty,
+    );
+    let started = Instant::now();
+    let result = future.instrument(span.clone()).await;
+    let _ = span.record("duration_ms", started.elapsed().as_millis() as u64);
+    match result {
+        Ok(ref response) => {
+            let _ = span.record("status", response.status().as_u16());
+            if let Some(value) = request_id(response.headers()) {
+                let _ = span.record("request_id", value);
+            }
+            tracing::debug!(parent: &span, "HTTP request finished");
+        }
+        Err(ref e) => tracing::debug!(parent: &span, error = %e, "HTTP request failed"),
+    }
+    result
+}
+
+/// A span for a request to an OpenStack service.
+///
+/// The span is called `service_request` and has fields `service`, `status` and `request_id`.
+pub(crate) fn service(service_type: &str) -> Span {
+    debug_span!(
+        "service_request",
+        service = service_type,
+        status = Empty,
+        request_id = Empty,
+    )
+}
+
+/// Record the outcome of a service request in the current span.
+pub(crate) fn record_result(result: &Result<Response, Error>) {
+    let span = Span::current();
+    let (status, id) = match result {
+        Ok(response) => (Some(response.status()), request_id(response.headers())),
+        Err(e) => (e.status(), e.request_id()),
+    };
+    if let Some(status) = status {
+        let _ = span.record("status", status.as_u16());
+    }
+    if let Some(id) = id {
+        let _ = span.record("request_id", id);
+    }
+}
+
+/// A span for a token request.
+pub(crate) fn token(auth_url: &str) -> Span {
+    debug_span!("token_request", auth_url = auth_url, expires_at = Empty)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fmt::Debug;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use reqwest::{Method, StatusCode, Url};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    use super::request;
+
+    #[derive(Default)]
+    struct Fields(Vec<(String, String)>);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0.push((field.name().into(), format!("{:?}", value)));
+        }
+    }
+
+    #[derive(Default)]
+    struct TestSubscriber {
+        next_id: AtomicU64,
+        fields: Arc<Mutex<Vec<(String, String)>>>,
+    }
+
+    impl Subscriber for TestSubscriber {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut fields = Fields::default();
+            span.record(&mut fields);
+            self.fields.lock().unwrap().extend(fields.0);
+            Id::from_u64(self.next_id.fetch_add(1, Ordering::SeqCst) + 1)
+        }
+
+        fn record(&self, _span: &Id, values: &Record<'_>) {
+            let mut fields = Fields::default();
+            values.record(&mut fields);
+            self.fields.lock().unwrap().extend(fields.0);
+        }
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, _event: &Event<'_>) {}
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    #[tokio::test]
+    async fn test_request() {
+        let subscriber = TestSubscriber::default();
+        let fields = Arc::clone(&subscriber.fields);
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let url = Url::parse("http://127.0.0.1/v2").unwrap();
+        let response = request(&Method::GET, &url, async {
+            Ok(http::Response::builder()
+                .status(StatusCode::ACCEPTED)
+                .header("x-openstack-request-id", "req-1")
+                .body("")
+                .unwrap()
+                .into())
+        })
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let fields = fields.lock().unwrap();
+        let get = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+        assert_eq!(get("method").as_deref(), Some("GET"));
+        assert_eq!(get("url").as_deref(), Some("http://127.0.0.1/v2"));
+        assert_eq!(get("status").as_deref(), Some("202"));
+        assert_eq!(get("request_id").as_deref(), Some("\"req-1\""));
+        assert!(get("duration_ms").is_some());
+    }
+}
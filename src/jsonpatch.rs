@@ -0,0 +1,103 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! JSON Patch (RFC 6902) operations.
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// Content type of JSON Patch documents.
+pub(crate) const JSON_PATCH_CONTENT_TYPE: &str = "application/json-patch+json";
+
+/// Content type of JSON Merge Patch (RFC 7396) documents.
+pub(crate) const MERGE_PATCH_CONTENT_TYPE: &str = "application/merge-patch+json";
+
+/// A single JSON Patch (RFC 6902) operation.
+///
+/// Paths are JSON pointers, e.g. `/extra/owner`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum JsonPatchOp {
+    /// Add a value (or replace an existing one for objects).
+    Add {
+        /// Path to the value.
+        path: String,
+        /// Value to add.
+        value: Value,
+    },
+    /// Remove a value.
+    Remove {
+        /// Path to the value.
+        path: String,
+    },
+    /// Replace an existing value.
+    Replace {
+        /// Path to the value.
+        path: String,
+        /// New value.
+        value: Value,
+    },
+}
+
+impl JsonPatchOp {
+    /// Create an `add` operation.
+    #[inline]
+    pub fn add<P: Into<String>, V: Into<Value>>(path: P, value: V) -> JsonPatchOp {
+        JsonPatchOp::Add {
+            path: path.into(),
+            value: value.into(),
+        }
+    }
+
+    /// Create a `remove` operation.
+    #[inline]
+    pub fn remove<P: Into<String>>(path: P) -> JsonPatchOp {
+        JsonPatchOp::Remove { path: path.into() }
+    }
+
+    /// Create a `replace` operation.
+    #[inline]
+    pub fn replace<P: Into<String>, V: Into<Value>>(path: P, value: V) -> JsonPatchOp {
+        JsonPatchOp::Replace {
+            path: path.into(),
+            value: value.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::JsonPatchOp;
+    use crate::common::test;
+
+    const PATCH: &str = r#"
+[
+    {"op": "add", "path": "/extra/owner", "value": "me"},
+    {"op": "remove", "path": "/description"},
+    {"op": "replace", "path": "/properties", "value": {"cpus": 8}}
+]"#;
+
+    #[test]
+    fn test_serialize() {
+        let patch = vec![
+            JsonPatchOp::add("/extra/owner", "me"),
+            JsonPatchOp::remove("/description"),
+            JsonPatchOp::replace("/properties", json!({"cpus": 8})),
+        ];
+        test::compare(PATCH, patch);
+    }
+}
@@ -34,6 +34,10 @@
 //!
 //! This crate requires Rust 2021 edition and rustc version 1.58.0 or newer.
 //!
+//! WebAssembly targets (`wasm32-unknown-unknown`) are not supported: the crate relies on
+//! `tokio` networking and timers, on `Send` futures for authentication and transports, and on
+//! parts of the `reqwest` API that its WebAssembly backend does not provide.
+//!
 //! OpenStack releases starting with Train are officially supported, although support for
 //! releases older than 1.5 years is best-effort and may be dropped without a prior warning
 //! (it will not be considered a breaking change).
@@ -64,10 +68,48 @@
 //! # Features
 //!
 //! * `native-tls` or `rustls` add TLS support with two alternative implementations, `native-tls`
-//!   is enabled by default.
+//!   is enabled by default. Use `default-rustls` with `default-features = false` to select
+//!   `rustls`. Custom root certificates can be configured via
+//!   [ClientOptions](struct.ClientOptions.html).
 //! * `stream` adds [get_json_paginated](struct.Session.html#method.get_json_paginated) and
 //!   [get_json_query_paginated](struct.Session.html#method.get_json_query_paginated) to `Session`
-//!   and `Adapter`; enabled by default.
+//!   and `Adapter` as well as incremental deserialization of large collections with
+//!   [fetch_stream](struct.ServiceRequestBuilder.html#method.fetch_stream); enabled by default.
+//! * `checksum` adds verification of transferred data: the [image](image/index.html) helpers
+//!   and checksum options of [ResumableDownload](object_storage/struct.ResumableDownload.html)
+//!   and [LargeObjectUpload](object_storage/struct.LargeObjectUpload.html); enabled by default.
+//! * `derive` adds `#[derive(QueryParams)]` for [query parameter](trait.QueryParams.html)
+//!   structures; enabled by `stream`.
+//! * `identity-v2` adds [PasswordV2](identity/struct.PasswordV2.html) for legacy clouds that
+//!   only provide Identity API v2.0.
+//! * `hyper` adds [HyperTransport](struct.HyperTransport.html) that sends authenticated requests
+//!   with a `hyper` client and a custom connector, and the [IP family](enum.IpFamily.html)
+//!   preference in `ClientOptions`. This feature comes from the optional `hyper` dependency,
+//!   so it is also enabled by `test-support`.
+//! * `reqwest-middleware` implements [Transport](client/trait.Transport.html) for
+//!   `reqwest_middleware::ClientWithMiddleware`, so that authenticated requests go through a
+//!   `reqwest-middleware` stack (see
+//!   [Session::with_middleware](struct.Session.html#method.with_middleware)).
+//! * `s3` adds [S3Auth](struct.S3Auth.html) for S3-compatible APIs.
+//! * `tempurl` adds [TempUrl](object_storage/struct.TempUrl.html) for generating Swift temporary
+//!   URLs; enabled by default.
+//! * `test-support` adds a [mock Identity service](testsupport/struct.MockKeystone.html) for
+//!   integration testing of authentication flows without a real cloud and a
+//!   [failure injecting transport](testsupport/struct.FaultInjector.html) for chaos testing
+//!   and transports that [record](testsupport/struct.RecordingTransport.html) and
+//!   [replay](testsupport/struct.ReplayTransport.html) HTTP interactions.
+//! * `token-cache` adds a persistent [TokenCache](identity/struct.TokenCache.html) that lets
+//!   short-lived processes reuse tokens between runs.
+//! * `tower` implements `tower::Service` for
+//!   [AuthenticatedClient](client/struct.AuthenticatedClient.html), so that `tower` middleware
+//!   can wrap OpenStack calls.
+//! * `tracing` emits `tracing` spans with structured fields: `service_request` (`service`,
+//!   `status`, `request_id`) for requests to OpenStack services, `http_request` (`method`, `url`,
+//!   `status`, `request_id`, `duration_ms`) for each HTTP request and `token_request`
+//!   (`auth_url`, `expires_at`) for token requests. The `log` messages are emitted as before.
+//! * `websocket` adds authenticated [WebSocket](struct.WebSocket.html) connections via
+//!   [Session::connect_websocket](struct.Session.html#method.connect_websocket) and
+//!   [ServiceRequestBuilder::connect_websocket](struct.ServiceRequestBuilder.html#method.connect_websocket).
 
 #![crate_name = "osauth"]
 #![crate_type = "lib"]
@@ -77,7 +119,6 @@
 // Taken from https://github.com/rust-unofficial/patterns/
 #![deny(
     bare_trait_objects,
-    const_err,
     dead_code,
     improper_ctypes,
     missing_copy_implementations,
@@ -88,7 +129,6 @@
     overflowing_literals,
     path_statements,
     patterns_in_fns_without_body,
-    private_in_public,
     trivial_casts,
     trivial_numeric_casts,
     unconditional_recursion,
@@ -106,34 +146,105 @@
 
 mod adapter;
 mod apiversion;
+mod audit;
 mod auth;
 mod basic;
 mod cache;
+#[cfg(feature = "test-support")]
+mod cassette;
 mod catalog;
+#[cfg(feature = "checksum")]
+mod checksum;
 pub mod client;
+mod clientoptions;
+mod clock;
+mod coalesce;
 pub mod common;
+mod discovery;
 mod endpointfilters;
 mod error;
+mod errordetails;
+mod events;
+#[cfg(feature = "hyper")]
+mod hypertransport;
 pub mod identity;
+#[cfg(feature = "checksum")]
+pub mod image;
+#[cfg(feature = "tracing")]
+mod instrument;
+mod jsonpatch;
 mod loading;
 mod macros;
+mod metrics;
+#[cfg(feature = "reqwest-middleware")]
+mod middleware;
+pub mod object_storage;
+mod pool;
+mod precondition;
+mod probe;
 mod protocol;
+mod query;
+mod range;
+mod redirect;
+mod retry;
+#[cfg(feature = "s3")]
+mod s3;
 pub mod services;
 mod session;
+mod shutdown;
+mod snapshot;
+mod stats;
 #[cfg(feature = "stream")]
 mod stream;
-mod url;
+#[cfg(feature = "test-support")]
+pub mod testsupport;
+#[cfg(feature = "tower")]
+mod towerservice;
+pub mod url;
+mod useragent;
 mod utils;
+mod waiter;
+#[cfg(feature = "websocket")]
+mod websocket;
 
 pub use crate::adapter::Adapter;
 pub use crate::apiversion::ApiVersion;
+pub use crate::audit::{AuditRecord, AuditSink};
 pub use crate::auth::{AuthType, NoAuth};
 pub use crate::basic::BasicAuth;
+pub use crate::catalog::{CatalogEndpoint, CatalogEntry};
+pub use crate::clientoptions::ClientOptions;
+#[cfg(feature = "hyper")]
+pub use crate::clientoptions::IpFamily;
+pub use crate::clock::{Clock, ManualClock, SystemClock};
+pub use crate::discovery::clear_discovery_cache;
 pub use crate::endpointfilters::{EndpointFilters, InterfaceType, ValidInterfaces};
 pub use crate::error::{Error, ErrorKind};
-pub use crate::loading::CloudConfig;
+pub use crate::errordetails::ErrorDetails;
+pub use crate::events::{Event, EventHandler};
+#[cfg(feature = "hyper")]
+pub use crate::hypertransport::HyperTransport;
+pub use crate::jsonpatch::JsonPatchOp;
+pub use crate::loading::{CloudConfig, CloudRegistry};
+pub use crate::metrics::{Histogram, LatencyHistograms, MetricsSink};
+pub use crate::pool::{HostPoolTransport, PoolOptions};
+pub use crate::precondition::Precondition;
+pub use crate::probe::ServiceProbe;
+pub use crate::query::{QueryParams, QueryValue};
+pub use crate::range::{ByteRange, ContentRange};
+pub use crate::redirect::RedirectPolicy;
+pub use crate::retry::{Jitter, RetryBudget, RetryPolicy};
+#[cfg(feature = "s3")]
+pub use crate::s3::S3Auth;
 pub use crate::session::{ServiceRequestBuilder, Session};
+pub use crate::snapshot::{ApiVersionSnapshot, AuthSnapshot, ServiceSnapshot, SessionSnapshot};
+pub use crate::stats::{ServiceStats, SessionStats};
 #[cfg(feature = "stream")]
 pub use crate::stream::PaginatedResource;
+pub use crate::waiter::Waiter;
+#[cfg(feature = "websocket")]
+pub use crate::websocket::WebSocket;
 #[cfg(feature = "stream")]
 pub use osauth_derive::PaginatedResource;
+#[cfg(feature = "derive")]
+pub use osauth_derive::QueryParams;
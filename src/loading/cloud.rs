@@ -16,9 +16,11 @@
 
 use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::fmt;
 use std::str::FromStr;
 use std::sync::Arc;
 
+use log::debug;
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
 
@@ -26,15 +28,33 @@ use super::config::from_config;
 use super::env::from_env;
 use crate::client::AuthenticatedClient;
 use crate::common::IdOrName;
-use crate::identity::{Password, Scope, Token};
-use crate::{AuthType, BasicAuth, Error, ErrorKind, InterfaceType, NoAuth, Session};
+#[cfg(feature = "identity-v2")]
+use crate::identity::{is_v2_only, PasswordV2};
+use crate::identity::{ApplicationCredentialAuth, Password, Scope, Token};
+use crate::services::{BAREMETAL, COMPUTE};
+use crate::{
+    ApiVersion, AuthSnapshot, AuthType, BasicAuth, ClientOptions, Error, ErrorKind, NoAuth,
+    RedirectPolicy, Session, ValidInterfaces,
+};
 
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 #[cfg_attr(test, derive(PartialEq, Eq))]
 pub(crate) struct Auth {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) application_credential_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) application_credential_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) application_credential_secret: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) auth_url: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) default_domain: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) domain_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) domain_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) endpoint: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) password: Option<String>,
@@ -49,16 +69,70 @@ pub(crate) struct Auth {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) token: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) user_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) username: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) user_domain_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) user_domain_name: Option<String>,
 }
 
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Auth")
+            .field("application_credential_id", &self.application_credential_id)
+            .field(
+                "application_credential_name",
+                &self.application_credential_name,
+            )
+            .field(
+                "application_credential_secret",
+                &self
+                    .application_credential_secret
+                    .as_ref()
+                    .map(|_| "<hidden>"),
+            )
+            .field("auth_url", &self.auth_url)
+            .field("default_domain", &self.default_domain)
+            .field("domain_id", &self.domain_id)
+            .field("domain_name", &self.domain_name)
+            .field("endpoint", &self.endpoint)
+            .field("password", &self.password.as_ref().map(|_| "<hidden>"))
+            .field("project_id", &self.project_id)
+            .field("project_name", &self.project_name)
+            .field("project_domain_id", &self.project_domain_id)
+            .field("project_domain_name", &self.project_domain_name)
+            .field("token", &self.token.as_ref().map(|_| "<hidden>"))
+            .field("user_id", &self.user_id)
+            .field("username", &self.username)
+            .field("user_domain_id", &self.user_domain_id)
+            .field("user_domain_name", &self.user_domain_name)
+            .finish()
+    }
+}
+
 /// Cloud configuration.
 ///
 /// This is a source from which sessions and authentications can be created.
 /// It can be loaded from a `clouds.yaml` configuration file or from environment variables.
 /// Additionally, the configuration can be serialized and deserialized.
+///
+/// Loading and [validation](#method.validate) are synchronous and do not require an async
+/// runtime, so they can be used in build scripts or configuration checkers. Creating a session
+/// is a separate asynchronous step:
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// // Synchronous: only reads clouds.yaml.
+/// let config = osauth::CloudConfig::from_config("devstack")?;
+/// config.validate()?;
+/// // Asynchronous: authenticates against the cloud.
+/// let session = config.create_session().await?;
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
 #[cfg_attr(test, derive(PartialEq, Eq))]
 pub struct CloudConfig {
@@ -74,6 +148,8 @@ pub struct CloudConfig {
     pub(crate) region_name: Option<String>,
     #[serde(flatten)]
     pub(crate) options: HashMap<String, serde_yaml::Value>,
+    #[serde(skip)]
+    pub(crate) client_options: ClientOptions,
 }
 
 #[inline]
@@ -81,26 +157,82 @@ fn require(value: Option<String>, message: &str) -> Result<String, Error> {
     value.ok_or_else(|| Error::new(ErrorKind::InvalidConfig, message))
 }
 
-fn project_scope(
-    project_id: Option<String>,
-    project_name: Option<String>,
-    project_domain_id: Option<String>,
-    project_domain_name: Option<String>,
-) -> Option<Scope> {
-    let project_domain = project_domain_id
-        .map(IdOrName::Id)
-        .or_else(|| project_domain_name.map(IdOrName::Name))
-        .unwrap_or_else(|| IdOrName::from_name("Default"));
-    project_id
+#[inline]
+fn id_or_name(id: &Option<String>, name: &Option<String>) -> Option<IdOrName> {
+    id.clone()
         .map(IdOrName::Id)
-        .or_else(|| project_name.map(IdOrName::Name))
-        .map(|project| Scope::Project {
-            project,
-            domain: Some(project_domain),
-        })
+        .or_else(|| name.clone().map(IdOrName::Name))
 }
 
 impl Auth {
+    /// The domain to use when none is provided: `default_domain` (an ID) or `Default`.
+    fn default_domain(&self) -> IdOrName {
+        self.default_domain
+            .clone()
+            .map(IdOrName::Id)
+            .unwrap_or_else(|| IdOrName::from_name("Default"))
+    }
+
+    /// A project scope if a project is provided, otherwise a domain scope if a domain is.
+    ///
+    /// A project ID is unique, so the project domain is only used with a project name.
+    fn scope(&self) -> Option<Scope> {
+        if let Some(project_id) = &self.project_id {
+            Some(Scope::Project {
+                project: IdOrName::Id(project_id.clone()),
+                domain: None,
+            })
+        } else if let Some(project_name) = &self.project_name {
+            let domain = id_or_name(&self.project_domain_id, &self.project_domain_name)
+                .unwrap_or_else(|| self.default_domain());
+            Some(Scope::Project {
+                project: IdOrName::Name(project_name.clone()),
+                domain: Some(domain),
+            })
+        } else {
+            id_or_name(&self.domain_id, &self.domain_name).map(Scope::Domain)
+        }
+    }
+
+    /// The user and, if the user is referenced by name, its domain.
+    fn user(&self) -> Option<(IdOrName, Option<IdOrName>)> {
+        if let Some(user_id) = &self.user_id {
+            Some((IdOrName::Id(user_id.clone()), None))
+        } else {
+            self.username.clone().map(|username| {
+                let domain = id_or_name(&self.user_domain_id, &self.user_domain_name)
+                    .unwrap_or_else(|| self.default_domain());
+                (IdOrName::Name(username), Some(domain))
+            })
+        }
+    }
+
+    fn create_application_credential_auth(self) -> Result<ApplicationCredentialAuth, Error> {
+        let user = self.user();
+        let auth_url = require(
+            self.auth_url,
+            "Application credential authentication requires an authentication URL",
+        )?;
+        let secret = require(
+            self.application_credential_secret,
+            "Application credential authentication requires a secret",
+        )?;
+        if let Some(id) = self.application_credential_id {
+            return ApplicationCredentialAuth::new(&auth_url, id, secret);
+        }
+        let name = require(
+            self.application_credential_name,
+            "Application credential authentication requires an ID or a name",
+        )?;
+        let (user, user_domain) = user.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidConfig,
+                "Application credential authentication by name requires a user name or ID",
+            )
+        })?;
+        ApplicationCredentialAuth::from_name_and_user(&auth_url, name, secret, user, user_domain)
+    }
+
     fn create_basic_auth(self) -> Result<BasicAuth, Error> {
         let endpoint = require(
             self.endpoint,
@@ -126,30 +258,50 @@ impl Auth {
     }
 
     fn create_password_auth(self) -> Result<Password, Error> {
+        let scope = self.scope();
+        let (user, user_domain) = self.user().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidConfig,
+                "Password authentication requires a user name or ID",
+            )
+        })?;
         let auth_url = require(
             self.auth_url,
             "Password authentication requires an authentication URL",
         )?;
-        let username = require(self.username, "Password authentication requires a username")?;
         let password = require(self.password, "Password authentication requires a password")?;
-        let user_domain = self
-            .user_domain_name
-            .unwrap_or_else(|| String::from("Default"));
-        let mut id = Password::new(&auth_url, username, password, user_domain)?;
-
-        if let Some(scope) = project_scope(
-            self.project_id,
-            self.project_name,
-            self.project_domain_id,
-            self.project_domain_name,
-        ) {
+        let mut id = Password::new_with_user(&auth_url, user, password, user_domain)?;
+
+        if let Some(scope) = scope {
             id.set_scope(scope);
         }
 
         Ok(id)
     }
 
+    #[cfg(feature = "identity-v2")]
+    fn create_password_v2_auth(self) -> Result<PasswordV2, Error> {
+        let auth_url = require(
+            self.auth_url,
+            "Password authentication requires an authentication URL",
+        )?;
+        let username = require(self.username, "Password authentication requires a username")?;
+        let password = require(self.password, "Password authentication requires a password")?;
+        let mut id = PasswordV2::new(&auth_url, username, password)?;
+
+        if let Some(tenant) = self
+            .project_id
+            .map(IdOrName::Id)
+            .or_else(|| self.project_name.map(IdOrName::Name))
+        {
+            id.set_tenant(tenant);
+        }
+
+        Ok(id)
+    }
+
     fn create_token_auth(self) -> Result<Token, Error> {
+        let scope = self.scope();
         let auth_url = require(
             self.auth_url,
             "Token authentication requires an authentication URL",
@@ -157,32 +309,61 @@ impl Auth {
         let token = require(self.token, "Token authentication requires a token")?;
         let mut id = Token::new(&auth_url, token)?;
 
-        if let Some(scope) = project_scope(
-            self.project_id,
-            self.project_name,
-            self.project_domain_id,
-            self.project_domain_name,
-        ) {
+        if let Some(scope) = scope {
             id.set_scope(scope);
         }
 
         Ok(id)
     }
 
-    fn create_auth(self, auth_type: Option<String>) -> Result<Arc<dyn AuthType>, Error> {
-        let auth_type = auth_type.unwrap_or_else(|| {
+    /// The authentication type, detected from the credentials if not provided.
+    fn auth_type(&self, auth_type: Option<String>) -> String {
+        auth_type.unwrap_or_else(|| {
             if self.token.is_some() {
                 "v3token"
+            } else if self.application_credential_secret.is_some() {
+                "v3applicationcredential"
             } else {
                 "password"
             }
             .into()
-        });
+        })
+    }
+
+    /// Authentication parameters without the secrets.
+    fn snapshot(&self, auth_type: &str) -> AuthSnapshot {
+        AuthSnapshot {
+            auth_type: Some(auth_type.into()),
+            auth_url: self.auth_url.clone(),
+            endpoint: self.endpoint.clone(),
+            user_id: self.user_id.clone(),
+            username: self.username.clone(),
+            user_domain_id: self.user_domain_id.clone(),
+            user_domain_name: self.user_domain_name.clone(),
+            project_id: self.project_id.clone(),
+            project_name: self.project_name.clone(),
+            project_domain_id: self.project_domain_id.clone(),
+            project_domain_name: self.project_domain_name.clone(),
+            domain_id: self.domain_id.clone(),
+            domain_name: self.domain_name.clone(),
+            default_domain: self.default_domain.clone(),
+            application_credential_id: self.application_credential_id.clone(),
+            application_credential_name: self.application_credential_name.clone(),
+        }
+    }
+
+    fn create_auth(self, auth_type: String) -> Result<Arc<dyn AuthType>, Error> {
+        #[cfg(feature = "identity-v2")]
+        if auth_type == "v2password" {
+            return Ok(Arc::new(self.create_password_v2_auth()?));
+        }
 
         Ok(if auth_type == "password" {
             Arc::new(self.create_password_auth()?)
         } else if auth_type == "v3token" {
             Arc::new(self.create_token_auth()?)
+        } else if auth_type == "v3applicationcredential" {
+            Arc::new(self.create_application_credential_auth()?)
         } else if auth_type == "http_basic" {
             Arc::new(self.create_basic_auth()?)
         } else if auth_type == "none" {
@@ -201,7 +382,7 @@ impl Auth {
 pub(crate) struct SessionConfig {
     pub(crate) client: AuthenticatedClient,
     pub(crate) endpoint_overrides: HashMap<String, Url>,
-    pub(crate) interface: Option<InterfaceType>,
+    pub(crate) interface: Option<ValidInterfaces>,
     pub(crate) region_name: Option<String>,
 }
 
@@ -216,6 +397,145 @@ impl CloudConfig {
         from_env()
     }
 
+    /// Check that a session can be created from this configuration.
+    ///
+    /// Validates the authentication parameters, endpoint overrides, API versions, the interface
+    /// and the [strict security mode](#method.set_strict_security), and reads the CA certificate
+    /// (if any). Does not contact the cloud and does not require an async runtime.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.clone().create_session_config().map(|_| ())
+    }
+
+    /// Options of the HTTP client for sessions created from this configuration.
+    #[inline]
+    pub fn client_options(&self) -> &ClientOptions {
+        &self.client_options
+    }
+
+    /// Set options of the HTTP client for sessions created from this configuration.
+    ///
+    /// These options are not part of the serialized configuration.
+    #[inline]
+    pub fn set_client_options(&mut self, value: ClientOptions) {
+        self.client_options = value;
+    }
+
+    /// Set options of the HTTP client for sessions created from this configuration.
+    #[inline]
+    pub fn with_client_options(mut self, value: ClientOptions) -> Self {
+        self.set_client_options(value);
+        self
+    }
+
+    /// Enable or disable the strict security mode.
+    ///
+    /// In this mode, sessions are not created from configurations that disable TLS certificate
+    /// verification (`verify: false` or `insecure: true`) or send credentials over plaintext
+    /// HTTP (in the authentication URL, the endpoint or endpoint overrides). Redirects are only
+    /// followed within the same origin.
+    ///
+    /// Can also be enabled with `strict_security: true` in `clouds.yaml` or with the
+    /// `OS_STRICT_SECURITY` environment variable.
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session = osauth::CloudConfig::from_env()?
+    ///     .with_strict_security(true)
+    ///     .create_session()
+    ///     .await?;
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    #[inline]
+    pub fn set_strict_security(&mut self, value: bool) {
+        let _ = self
+            .options
+            .insert("strict_security".into(), serde_yaml::Value::Bool(value));
+    }
+
+    /// Enable or disable the strict security mode.
+    #[inline]
+    pub fn with_strict_security(mut self, value: bool) -> Self {
+        self.set_strict_security(value);
+        self
+    }
+
+    /// Read a boolean option.
+    fn flag(&self, key: &str) -> Result<Option<bool>, Error> {
+        let value = match self.options.get(key) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let result = match value {
+            serde_yaml::Value::Bool(value) => Some(*value),
+            serde_yaml::Value::String(value) => match value.to_lowercase().as_str() {
+                "true" | "yes" | "1" => Some(true),
+                "false" | "no" | "0" => Some(false),
+                _ => None,
+            },
+            _ => None,
+        };
+        result.map(Some).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidConfig,
+                format!("{} must be a boolean, got {:?}", key, value),
+            )
+        })
+    }
+
+    /// Whether the strict security mode is enabled.
+    fn is_strict_security(&self) -> Result<bool, Error> {
+        Ok(self.flag("strict_security")?.unwrap_or(false))
+    }
+
+    /// Check the configuration for the strict security mode.
+    fn check_security(&self) -> Result<(), Error> {
+        if !self.is_strict_security()? {
+            return Ok(());
+        }
+
+        if self.flag("verify")? == Some(false) || self.flag("insecure")? == Some(true) {
+            return Err(Error::new(
+                ErrorKind::InvalidConfig,
+                "TLS certificate verification cannot be disabled in the strict security mode",
+            ));
+        }
+
+        let mut urls = Vec::new();
+        if let Some(ref auth) = self.auth {
+            if let Some(ref auth_url) = auth.auth_url {
+                urls.push(("auth_url", auth_url.as_str()));
+            }
+            if let Some(ref endpoint) = auth.endpoint {
+                urls.push(("endpoint", endpoint.as_str()));
+            }
+        }
+        for (key, value) in &self.options {
+            if let (true, serde_yaml::Value::String(value)) =
+                (key.ends_with("_endpoint_override"), value)
+            {
+                urls.push((key.as_str(), value.as_str()));
+            }
+        }
+        for (key, value) in urls {
+            if let Ok(url) = Url::parse(value) {
+                if url.scheme() == "http" {
+                    return Err(Error::new(
+                        ErrorKind::InvalidConfig,
+                        format!(
+                            "{} `{}` uses plaintext HTTP, which is not allowed in the strict \
+                             security mode",
+                            key, value
+                        ),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     fn create_endpoint_overrides(&self) -> Result<HashMap<String, Url>, Error> {
         let mut result = HashMap::with_capacity(self.options.len());
         for (ref key, ref value) in &self.options {
@@ -244,20 +564,39 @@ impl CloudConfig {
 
     #[inline]
     pub(crate) fn create_session_config(self) -> Result<SessionConfig, Error> {
+        self.check_security()?;
+        let strict_security = self.is_strict_security()?;
         let endpoint_overrides = self.create_endpoint_overrides()?;
-        let auth = if let Some(auth_info) = self.auth {
-            auth_info.create_auth(self.auth_type)?
+        let (auth, snapshot): (Arc<dyn AuthType>, _) = if let Some(auth_info) = self.auth {
+            let auth_type = auth_info.auth_type(self.auth_type);
+            let snapshot = auth_info.snapshot(&auth_type);
+            (auth_info.create_auth(auth_type)?, snapshot)
         } else if self.auth_type.map(|x| x == "none").unwrap_or(false) {
-            Arc::new(NoAuth::new_without_endpoint())
+            let snapshot = AuthSnapshot {
+                auth_type: Some("none".into()),
+                ..AuthSnapshot::default()
+            };
+            (Arc::new(NoAuth::new_without_endpoint()), snapshot)
         } else {
             return Err(Error::new(
                 ErrorKind::InvalidInput,
                 "Credentials can be missing only for none authentication",
             ));
         };
-        let client = AuthenticatedClient::new_internal(super::get_client(self.cacert)?, auth);
+        let (inner, host_pools) = super::get_clients(self.cacert, &self.client_options)?;
+        let mut client = AuthenticatedClient::new_internal(inner, auth);
+        client.set_auth_snapshot(snapshot);
+        if let Some(transport) = host_pools {
+            client.set_transport(transport);
+        }
+        set_api_versions(&self.options, &mut client)?;
+        if strict_security {
+            // Credentials are removed on cross-origin redirects, but do not even try.
+            let max_redirects = client.redirect_policy().max_redirects();
+            client.set_redirect_policy(RedirectPolicy::SameOrigin(max_redirects));
+        }
         let interface = if let Some(interface) = self.interface {
-            Some(InterfaceType::from_str(&interface)?)
+            Some(ValidInterfaces::from_str(&interface)?)
         } else {
             None
         };
@@ -270,10 +609,53 @@ impl CloudConfig {
         })
     }
 
+    /// Switch password authentication to Identity API v2.0 if v3 is not available.
+    #[cfg(feature = "identity-v2")]
+    async fn detect_identity_v2(mut self) -> Result<CloudConfig, Error> {
+        // Do not contact an insecure authentication URL.
+        self.check_security()?;
+        let auth = match self.auth {
+            Some(ref auth) => auth,
+            None => return Ok(self),
+        };
+        let is_password = match self.auth_type.as_deref() {
+            Some("password") => true,
+            Some(_) => false,
+            None => auth.token.is_none(),
+        };
+        if let (true, Some(auth_url)) = (is_password, auth.auth_url.as_deref()) {
+            let client = super::get_client(self.cacert.clone(), &self.client_options)?;
+            if is_v2_only(&client, auth_url).await {
+                debug!("Using Identity API v2.0 with {}", auth_url);
+                self.auth_type = Some("v2password".into());
+            }
+        }
+        Ok(self)
+    }
+
     /// Create a session from this configuration.
+    ///
+    /// Fails with `InvalidConfig` if the configuration is insecure and the
+    /// [strict security mode](#method.set_strict_security) is enabled.
+    ///
+    /// With the `identity-v2` feature, password authentication uses Identity API v2.0 if the
+    /// Identity service does not support v3.
     pub async fn create_session(self) -> Result<Session, Error> {
-        let mut config = self.create_session_config()?;
-        config.client.refresh().await?;
+        #[cfg(feature = "identity-v2")]
+        let config = self.detect_identity_v2().await?;
+        #[cfg(not(feature = "identity-v2"))]
+        let config = self;
+        let mut result = config.create_session_lazy()?;
+        result.refresh().await?;
+        Ok(result)
+    }
+
+    /// Create a session from this configuration without authenticating.
+    ///
+    /// The authentication happens on the first request or on an explicit call to
+    /// [Session::refresh](../struct.Session.html#method.refresh).
+    pub fn create_session_lazy(self) -> Result<Session, Error> {
+        let config = self.create_session_config()?;
         let mut result = Session::new_with_authenticated_client(config.client)
             .with_endpoint_overrides(config.endpoint_overrides);
         result.endpoint_filters_mut().region = config.region_name;
@@ -299,6 +681,44 @@ impl CloudConfig {
     }
 }
 
+/// Set default API versions from options like `baremetal_api_version`.
+fn set_api_versions(
+    options: &HashMap<String, serde_yaml::Value>,
+    client: &mut AuthenticatedClient,
+) -> Result<(), Error> {
+    for (ref key, ref value) in options {
+        if let Some(service_type) = key.strip_suffix("_api_version") {
+            let value = match value {
+                serde_yaml::Value::String(value) => value.clone(),
+                serde_yaml::Value::Number(value) => value.to_string(),
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidConfig,
+                        format!("{} must be a string, got {:?}", key, value),
+                    ))
+                }
+            };
+            // Other tools use major versions (e.g. compute_api_version: 2) to pick an API.
+            if !value.contains('.') {
+                debug!("Ignoring {} {} without a minor version", key, value);
+                continue;
+            }
+            let version = ApiVersion::from_str(&value).map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidConfig,
+                    format!("Invalid {} `{}`: {}", key, value, e),
+                )
+            })?;
+            match service_type {
+                "compute" => client.set_default_api_version(COMPUTE, version),
+                "baremetal" => client.set_default_api_version(BAREMETAL, version),
+                _ => debug!("Ignoring {}: API versions are not supported", key),
+            }
+        }
+    }
+    Ok(())
+}
+
 impl TryFrom<CloudConfig> for NoAuth {
     type Error = Error;
 
@@ -369,6 +789,9 @@ mod test_cloud_config {
     use reqwest::Url;
 
     use super::{Auth, CloudConfig};
+    use crate::common::IdOrName;
+    use crate::identity::Scope;
+    use crate::{AuthSnapshot, ErrorKind, RedirectPolicy};
 
     #[test]
     fn test_endpoint_overrides_empty() {
@@ -425,6 +848,88 @@ mod test_cloud_config {
         assert!(cfg.create_endpoint_overrides().is_err());
     }
 
+    fn strict_config(auth_url: &str) -> CloudConfig {
+        CloudConfig {
+            auth: Some(Auth {
+                auth_url: Some(auth_url.into()),
+                token: Some("abcdef".into()),
+                ..Auth::default()
+            }),
+            ..CloudConfig::default()
+        }
+        .with_strict_security(true)
+    }
+
+    #[test]
+    fn test_strict_security() {
+        let sscfg = strict_config("https://127.0.0.1/identity")
+            .create_session_config()
+            .unwrap();
+        assert_eq!(
+            sscfg.client.redirect_policy(),
+            RedirectPolicy::SameOrigin(10)
+        );
+
+        let err = strict_config("http://127.0.0.1/identity")
+            .create_session_config()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidConfig);
+        assert!(err.to_string().contains("auth_url"));
+
+        let mut cfg = strict_config("https://127.0.0.1/identity");
+        let _ = cfg
+            .options
+            .insert("verify".into(), serde_yaml::Value::Bool(false));
+        let err = cfg.create_session_config().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidConfig);
+
+        let mut cfg = strict_config("https://127.0.0.1/identity");
+        let _ = cfg.options.insert(
+            "compute_endpoint_override".into(),
+            "http://127.0.0.1/compute".into(),
+        );
+        let err = cfg.create_session_config().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidConfig);
+        assert!(err.to_string().contains("compute_endpoint_override"));
+
+        let mut cfg = strict_config("https://127.0.0.1/identity");
+        let _ = cfg
+            .options
+            .insert("strict_security".into(), "banana".into());
+        assert!(cfg.create_session_config().is_err());
+    }
+
+    #[test]
+    fn test_strict_security_disabled() {
+        let mut cfg = strict_config("http://127.0.0.1/identity").with_strict_security(false);
+        let _ = cfg
+            .options
+            .insert("verify".into(), serde_yaml::Value::Bool(false));
+        let sscfg = cfg.create_session_config().unwrap();
+        assert_eq!(sscfg.client.redirect_policy(), RedirectPolicy::default());
+    }
+
+    #[test]
+    fn test_validate() {
+        let cfg = CloudConfig {
+            auth: Some(Auth {
+                auth_url: Some("http://127.0.0.1/identity".into()),
+                token: Some("abcdef".into()),
+                ..Auth::default()
+            }),
+            interface: Some("internal".into()),
+            ..CloudConfig::default()
+        };
+        cfg.validate().unwrap();
+
+        let cfg = CloudConfig {
+            interface: Some("private".into()),
+            ..cfg
+        };
+        assert!(cfg.validate().is_err());
+        assert!(CloudConfig::default().validate().is_err());
+    }
+
     #[test]
     fn test_create_session_config_no_auth() {
         let cfg = CloudConfig::default();
@@ -473,6 +978,135 @@ mod test_cloud_config {
         );
     }
 
+    #[test]
+    fn test_create_session_config_application_credential() {
+        let mut cfg = CloudConfig {
+            auth: Some(Auth {
+                auth_url: Some("http://127.0.0.1/identity".into()),
+                application_credential_name: Some("ci".into()),
+                application_credential_secret: Some("s3cr3t".into()),
+                ..Auth::default()
+            }),
+            ..CloudConfig::default()
+        };
+        assert!(cfg.clone().create_session_config().is_err());
+        cfg.auth.as_mut().unwrap().username = Some("vasya".into());
+        let _ = cfg.create_session_config().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_session_snapshot_auth() {
+        let cfg = CloudConfig {
+            auth: Some(Auth {
+                auth_url: Some("http://127.0.0.1/identity".into()),
+                username: Some("vasya".into()),
+                user_domain_name: Some("Users".into()),
+                password: Some("hacker".into()),
+                project_name: Some("admin".into()),
+                ..Auth::default()
+            }),
+            ..CloudConfig::default()
+        };
+        let snapshot = cfg.create_session_lazy().unwrap().snapshot().await;
+        assert_eq!(
+            snapshot.auth,
+            Some(AuthSnapshot {
+                auth_type: Some("password".into()),
+                auth_url: Some("http://127.0.0.1/identity".into()),
+                username: Some("vasya".into()),
+                user_domain_name: Some("Users".into()),
+                project_name: Some("admin".into()),
+                ..AuthSnapshot::default()
+            })
+        );
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert!(!json.contains("hacker"), "{}", json);
+    }
+
+    #[test]
+    fn test_auth_scope() {
+        let mut auth = Auth {
+            default_domain: Some("d3f4ult".into()),
+            domain_name: Some("admins".into()),
+            ..Auth::default()
+        };
+        assert!(matches!(
+            auth.scope(),
+            Some(Scope::Domain(IdOrName::Name(ref name))) if name == "admins"
+        ));
+        assert_eq!(auth.default_domain(), IdOrName::from_id("d3f4ult"));
+
+        auth.project_name = Some("demo".into());
+        assert!(matches!(
+            auth.scope(),
+            Some(Scope::Project {
+                project: IdOrName::Name(ref project),
+                domain: Some(IdOrName::Id(ref domain)),
+            }) if project == "demo" && domain == "d3f4ult"
+        ));
+
+        auth.default_domain = None;
+        auth.project_domain_name = Some("users".into());
+        assert!(matches!(
+            auth.scope(),
+            Some(Scope::Project {
+                domain: Some(IdOrName::Name(ref domain)),
+                ..
+            }) if domain == "users"
+        ));
+        assert_eq!(auth.default_domain(), IdOrName::from_name("Default"));
+
+        auth.project_id = Some("1a6f364d14a846e2b11bc8e0b3f5b2a7".into());
+        assert!(matches!(
+            auth.scope(),
+            Some(Scope::Project {
+                project: IdOrName::Id(ref project),
+                domain: None,
+            }) if project == "1a6f364d14a846e2b11bc8e0b3f5b2a7"
+        ));
+    }
+
+    #[test]
+    fn test_auth_user() {
+        let mut auth = Auth {
+            username: Some("vasya".into()),
+            user_domain_id: Some("d3f4ult".into()),
+            ..Auth::default()
+        };
+        assert_eq!(
+            auth.user(),
+            Some((
+                IdOrName::from_name("vasya"),
+                Some(IdOrName::from_id("d3f4ult"))
+            ))
+        );
+
+        auth.user_id = Some("ee4dfb6e5540447cb3741905149d9b6e".into());
+        assert_eq!(
+            auth.user(),
+            Some((IdOrName::from_id("ee4dfb6e5540447cb3741905149d9b6e"), None))
+        );
+
+        assert!(Auth::default().user().is_none());
+    }
+
+    #[cfg(feature = "identity-v2")]
+    #[test]
+    fn test_create_session_config_password_v2() {
+        let cfg = CloudConfig {
+            auth_type: Some("v2password".into()),
+            auth: Some(Auth {
+                auth_url: Some("http://127.0.0.1/identity".into()),
+                username: Some("vasya".into()),
+                password: Some("hacker".into()),
+                project_name: Some("admin".into()),
+                ..Auth::default()
+            }),
+            ..CloudConfig::default()
+        };
+        let _ = cfg.create_session_config().unwrap();
+    }
+
     #[test]
     #[cfg(any(feature = "native-tls", feature = "rustls"))]
     fn test_create_session_config_with_region_and_cacert() {
@@ -158,6 +158,7 @@ fn read_yaml(filename: &str, default_root_key: Option<&str>) -> Result<serde_yam
             ErrorKind::InvalidConfig,
             format!("Cannot read {}: {}", filename, e),
         )
+        .with_source(e)
     })?;
 
     match serde_yaml::from_reader(content).map_err(|e| {
@@ -165,6 +166,7 @@ fn read_yaml(filename: &str, default_root_key: Option<&str>) -> Result<serde_yam
             ErrorKind::InvalidConfig,
             format!("Cannot parse {}: {}", filename, e),
         )
+        .with_source(e)
     })? {
         serde_yaml::Value::Mapping(mapping) => Ok(mapping),
         other => Err(Error::new(
@@ -174,38 +176,55 @@ fn read_yaml(filename: &str, default_root_key: Option<&str>) -> Result<serde_yam
     }
 }
 
-fn from_files(
-    name: &str,
+fn all_from_files(
     mut clouds: serde_yaml::Mapping,
     clouds_public: serde_yaml::Mapping,
     secure: serde_yaml::Mapping,
-) -> Result<CloudConfig, Error> {
+) -> Result<HashMap<String, CloudConfig>, Error> {
     utils::merge_mappings(secure, &mut clouds, true);
 
     inject_profiles(&clouds_public, &mut clouds)?;
 
-    let mut clouds_root: Root = serde_yaml::from_value(serde_yaml::Value::Mapping(clouds))
-        .map_err(|e| {
+    let clouds_root: Root =
+        serde_yaml::from_value(serde_yaml::Value::Mapping(clouds)).map_err(|e| {
             Error::new(
                 ErrorKind::InvalidConfig,
                 format!("Cannot parse the merged cloud configuration: {}", e),
             )
+            .with_source(e)
         })?;
 
-    clouds_root
-        .clouds
-        .clouds
+    Ok(clouds_root.clouds.clouds)
+}
+
+fn pick(name: &str, mut clouds: HashMap<String, CloudConfig>) -> Result<CloudConfig, Error> {
+    clouds
         .remove(name)
         .ok_or_else(|| Error::new(ErrorKind::InvalidConfig, format!("No such cloud: {}", name)))
 }
 
+#[cfg(test)]
+fn from_files(
+    name: &str,
+    clouds: serde_yaml::Mapping,
+    clouds_public: serde_yaml::Mapping,
+    secure: serde_yaml::Mapping,
+) -> Result<CloudConfig, Error> {
+    pick(name, all_from_files(clouds, clouds_public, secure)?)
+}
+
 /// Create a `Session` from a `clouds.yaml` configuration file.
 pub fn from_config(cloud_name: &str) -> Result<CloudConfig, Error> {
+    pick(cloud_name, all_from_config()?)
+}
+
+/// Load all clouds from a `clouds.yaml` configuration file.
+pub fn all_from_config() -> Result<HashMap<String, CloudConfig>, Error> {
     let clouds = read_yaml("clouds.yaml", None)?;
     let clouds_public = read_yaml("clouds-public.yaml", Some("public-clouds"))?;
     let secure = read_yaml("secure.yaml", Some("clouds"))?;
 
-    from_files(cloud_name, clouds, clouds_public, secure)
+    all_from_files(clouds, clouds_public, secure)
 }
 
 #[cfg(test)]
@@ -14,14 +14,18 @@
 
 //! Support for `OS_` environment variables.
 
+use std::collections::HashMap;
 use std::env::{self, VarError};
+use std::str::FromStr;
 
 use super::cloud::{Auth, CloudConfig};
-use crate::Error;
+use crate::{EndpointFilters, Error, ValidInterfaces};
 
 // This is only used for unit testing.
 trait Environment {
     fn get(&self, name: &'static str) -> Result<String, VarError>;
+
+    fn vars(&self) -> Vec<(String, String)>;
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -31,16 +35,58 @@ impl Environment for RealEnvironment {
     fn get(&self, name: &'static str) -> Result<String, VarError> {
         env::var(name)
     }
+
+    fn vars(&self) -> Vec<(String, String)> {
+        env::vars_os()
+            .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+            .collect()
+    }
+}
+
+/// Per-service options, e.g. `OS_BAREMETAL_ENDPOINT_OVERRIDE` becomes
+/// `baremetal_endpoint_override`.
+const SERVICE_OPTIONS: &[&str] = &["_ENDPOINT_OVERRIDE", "_API_VERSION"];
+
+/// Global options, e.g. `OS_INSECURE` becomes `insecure`.
+const GLOBAL_OPTIONS: &[&str] = &["INSECURE", "STRICT_SECURITY"];
+
+/// Extract global options and per-service options from `OS_<SERVICE>_<OPTION>` variables.
+fn get_options<E: Environment>(env: &E) -> HashMap<String, serde_yaml::Value> {
+    env.vars()
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let name = key.strip_prefix("OS_")?;
+            if GLOBAL_OPTIONS.contains(&name) {
+                return Some((name.to_lowercase(), value.into()));
+            }
+            let service = SERVICE_OPTIONS
+                .iter()
+                .find_map(|suffix| name.strip_suffix(suffix))?;
+            if service.is_empty() {
+                return None;
+            }
+            Some((name.to_lowercase(), value.into()))
+        })
+        .collect()
 }
 
 #[inline]
 fn _from_env<E: Environment>(env: E) -> Result<CloudConfig, Error> {
     if let Ok(cloud_name) = env.get("OS_CLOUD") {
-        return CloudConfig::from_config(cloud_name);
+        let mut config = CloudConfig::from_config(cloud_name)?;
+        // Environment variables take priority over the configuration file.
+        config.options.extend(get_options(&env));
+        return Ok(config);
     }
 
     let auth = Auth {
+        application_credential_id: env.get("OS_APPLICATION_CREDENTIAL_ID").ok(),
+        application_credential_name: env.get("OS_APPLICATION_CREDENTIAL_NAME").ok(),
+        application_credential_secret: env.get("OS_APPLICATION_CREDENTIAL_SECRET").ok(),
         auth_url: env.get("OS_AUTH_URL").ok(),
+        default_domain: env.get("OS_DEFAULT_DOMAIN").ok(),
+        domain_id: env.get("OS_DOMAIN_ID").ok(),
+        domain_name: env.get("OS_DOMAIN_NAME").ok(),
         endpoint: env.get("OS_ENDPOINT").ok(),
         password: env.get("OS_PASSWORD").ok(),
         project_id: env.get("OS_PROJECT_ID").ok(),
@@ -48,7 +94,9 @@ fn _from_env<E: Environment>(env: E) -> Result<CloudConfig, Error> {
         project_domain_id: env.get("OS_PROJECT_DOMAIN_ID").ok(),
         project_domain_name: env.get("OS_PROJECT_DOMAIN_NAME").ok(),
         token: env.get("OS_TOKEN").ok(),
+        user_id: env.get("OS_USER_ID").ok(),
         username: env.get("OS_USERNAME").ok(),
+        user_domain_id: env.get("OS_USER_DOMAIN_ID").ok(),
         user_domain_name: env.get("OS_USER_DOMAIN_NAME").ok(),
     };
 
@@ -56,19 +104,44 @@ fn _from_env<E: Environment>(env: E) -> Result<CloudConfig, Error> {
         auth: Some(auth),
         auth_type: env.get("OS_AUTH_TYPE").ok(),
         cacert: env.get("OS_CACERT").ok(),
-        interface: env.get("OS_INTERFACE").ok(),
+        interface: get_interface(&env),
         region_name: env.get("OS_REGION_NAME").ok(),
-        options: Default::default(),
+        options: get_options(&env),
+        client_options: Default::default(),
     };
 
     Ok(config)
 }
 
+#[inline]
+fn get_interface<E: Environment>(env: &E) -> Option<String> {
+    env.get("OS_INTERFACE")
+        .or_else(|_| env.get("OS_ENDPOINT_TYPE"))
+        .ok()
+}
+
+#[inline]
+fn _filters_from_env<E: Environment>(env: E) -> Result<EndpointFilters, Error> {
+    let mut filters = EndpointFilters::default();
+    if let Some(interface) = get_interface(&env) {
+        filters.set_interfaces(ValidInterfaces::from_str(&interface)?);
+    }
+    if let Ok(region) = env.get("OS_REGION_NAME") {
+        filters.set_region(region);
+    }
+    Ok(filters)
+}
+
 /// Create a `Session` from environment variables.
 pub fn from_env() -> Result<CloudConfig, Error> {
     _from_env(RealEnvironment)
 }
 
+/// Create `EndpointFilters` from environment variables.
+pub fn filters_from_env() -> Result<EndpointFilters, Error> {
+    _filters_from_env(RealEnvironment)
+}
+
 #[cfg(test)]
 pub mod test {
     use std::collections::HashMap;
@@ -76,19 +149,30 @@ pub mod test {
 
     use maplit::hashmap;
 
-    use super::{Environment, _from_env};
+    use super::{_filters_from_env, _from_env, CloudConfig, Environment};
+    use crate::{ApiVersion, ErrorKind, InterfaceType, RedirectPolicy, ValidInterfaces};
 
     impl Environment for HashMap<&'static str, &'static str> {
         fn get(&self, name: &'static str) -> Result<String, VarError> {
             self.get(name)
                 .cloned()
                 .map(From::from)
-                .ok_or_else(|| VarError::NotPresent)
+                .ok_or(VarError::NotPresent)
+        }
+
+        fn vars(&self) -> Vec<(String, String)> {
+            self.iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
         }
     }
 
     fn check(env: impl Environment) {
-        let _ = _from_env(env).unwrap().create_session_config().unwrap();
+        check_config(_from_env(env).unwrap());
+    }
+
+    fn check_config(config: CloudConfig) {
+        let _ = config.create_session_config().unwrap();
     }
 
     #[test]
@@ -132,6 +216,59 @@ pub mod test {
         check(env);
     }
 
+    #[test]
+    fn test_password_domain_scope() {
+        let env = hashmap! {
+            "OS_AUTH_URL" => "http://example.com",
+            "OS_USERNAME" => "admin",
+            "OS_PASSWORD" => "password",
+            "OS_DOMAIN_NAME" => "admins",
+        };
+
+        let config = _from_env(env).unwrap();
+        let auth = config.auth.as_ref().unwrap();
+        assert_eq!(auth.domain_name.as_deref(), Some("admins"));
+        check_config(config);
+    }
+
+    #[test]
+    fn test_password_default_domain() {
+        let env = hashmap! {
+            "OS_AUTH_URL" => "http://example.com",
+            "OS_USERNAME" => "admin",
+            "OS_PASSWORD" => "password",
+            "OS_PROJECT_NAME" => "admin",
+            "OS_DEFAULT_DOMAIN" => "default",
+        };
+
+        let config = _from_env(env).unwrap();
+        let auth = config.auth.as_ref().unwrap();
+        assert_eq!(auth.default_domain.as_deref(), Some("default"));
+        check_config(config);
+    }
+
+    #[test]
+    fn test_password_with_ids() {
+        let env = hashmap! {
+            "OS_AUTH_URL" => "http://example.com",
+            "OS_USER_ID" => "ee4dfb6e5540447cb3741905149d9b6e",
+            "OS_PASSWORD" => "password",
+            "OS_PROJECT_ID" => "1a6f364d14a846e2b11bc8e0b3f5b2a7",
+        };
+
+        check(env);
+    }
+
+    #[test]
+    fn test_password_no_user() {
+        let env = hashmap! {
+            "OS_AUTH_URL" => "http://example.com",
+            "OS_PASSWORD" => "password",
+        };
+
+        assert!(_from_env(env).unwrap().create_session_config().is_err());
+    }
+
     #[test]
     fn test_token_no_domains() {
         let env = hashmap! {
@@ -180,6 +317,162 @@ pub mod test {
         check(env);
     }
 
+    #[test]
+    fn test_interface_fallback() {
+        let env = hashmap! {
+            "OS_AUTH_URL" => "http://example.com",
+            "OS_TOKEN" => "abcdef",
+            "OS_INTERFACE" => "internal,public",
+        };
+
+        let config = _from_env(env).unwrap().create_session_config().unwrap();
+        assert_eq!(
+            *config.interface.unwrap(),
+            [InterfaceType::Internal, InterfaceType::Public]
+        );
+    }
+
+    #[test]
+    fn test_endpoint_type() {
+        let env = hashmap! {
+            "OS_AUTH_URL" => "http://example.com",
+            "OS_TOKEN" => "abcdef",
+            "OS_ENDPOINT_TYPE" => "internalURL",
+        };
+
+        let config = _from_env(env).unwrap().create_session_config().unwrap();
+        assert_eq!(*config.interface.unwrap(), [InterfaceType::Internal]);
+    }
+
+    #[test]
+    fn test_endpoint_overrides() {
+        let env = hashmap! {
+            "OS_AUTH_URL" => "http://example.com",
+            "OS_TOKEN" => "abcdef",
+            "OS_BAREMETAL_ENDPOINT_OVERRIDE" => "http://127.0.0.1/baremetal",
+            "OS_BAREMETAL_INTROSPECTION_ENDPOINT_OVERRIDE" => "http://127.0.0.1:5050/",
+            "OS_ENDPOINT_OVERRIDE" => "http://127.0.0.1/nothing",
+        };
+
+        let config = _from_env(env).unwrap().create_session_config().unwrap();
+        assert_eq!(config.endpoint_overrides.len(), 3);
+        assert_eq!(
+            config.endpoint_overrides["baremetal"].as_str(),
+            "http://127.0.0.1/baremetal"
+        );
+        assert_eq!(
+            config.endpoint_overrides["baremetal-introspection"].as_str(),
+            "http://127.0.0.1:5050/"
+        );
+    }
+
+    #[test]
+    fn test_endpoint_overrides_invalid() {
+        let env = hashmap! {
+            "OS_AUTH_URL" => "http://example.com",
+            "OS_TOKEN" => "abcdef",
+            "OS_BAREMETAL_ENDPOINT_OVERRIDE" => "?! banana",
+        };
+
+        assert!(_from_env(env).unwrap().create_session_config().is_err());
+    }
+
+    #[test]
+    fn test_api_versions() {
+        let env = hashmap! {
+            "OS_AUTH_URL" => "http://example.com",
+            "OS_TOKEN" => "abcdef",
+            "OS_BAREMETAL_API_VERSION" => "1.42",
+            "OS_COMPUTE_API_VERSION" => "2",
+            "OS_VOLUME_API_VERSION" => "3.10",
+        };
+
+        let config = _from_env(env).unwrap().create_session_config().unwrap();
+        assert_eq!(
+            config.client.default_api_version("baremetal"),
+            Some(ApiVersion(1, 42))
+        );
+        assert!(config.client.default_api_version("compute").is_none());
+        assert!(config.client.default_api_version("volume").is_none());
+    }
+
+    #[test]
+    fn test_api_versions_invalid() {
+        let env = hashmap! {
+            "OS_AUTH_URL" => "http://example.com",
+            "OS_TOKEN" => "abcdef",
+            "OS_COMPUTE_API_VERSION" => "2.banana",
+        };
+
+        assert!(_from_env(env).unwrap().create_session_config().is_err());
+    }
+
+    #[test]
+    fn test_strict_security() {
+        let env = hashmap! {
+            "OS_AUTH_URL" => "http://example.com",
+            "OS_TOKEN" => "abcdef",
+            "OS_STRICT_SECURITY" => "true",
+        };
+
+        let err = _from_env(env).unwrap().create_session_config().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidConfig);
+
+        let env = hashmap! {
+            "OS_AUTH_URL" => "https://example.com",
+            "OS_TOKEN" => "abcdef",
+            "OS_STRICT_SECURITY" => "1",
+            "OS_INSECURE" => "true",
+        };
+
+        let err = _from_env(env).unwrap().create_session_config().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidConfig);
+
+        let env = hashmap! {
+            "OS_AUTH_URL" => "https://example.com",
+            "OS_TOKEN" => "abcdef",
+            "OS_STRICT_SECURITY" => "1",
+        };
+
+        let config = _from_env(env).unwrap().create_session_config().unwrap();
+        assert_eq!(
+            config.client.redirect_policy(),
+            RedirectPolicy::SameOrigin(10)
+        );
+    }
+
+    #[test]
+    fn test_filters_from_env() {
+        let env = hashmap! {
+            "OS_REGION_NAME" => "Lapland",
+            "OS_INTERFACE" => "admin,internal",
+            "OS_ENDPOINT_TYPE" => "public",
+        };
+
+        let filters = _filters_from_env(env).unwrap();
+        assert_eq!(filters.region.as_ref().unwrap(), "Lapland");
+        assert_eq!(
+            *filters.interfaces,
+            [InterfaceType::Admin, InterfaceType::Internal]
+        );
+    }
+
+    #[test]
+    fn test_filters_from_env_empty() {
+        let filters = _filters_from_env(hashmap! {}).unwrap();
+        assert!(filters.region.is_none());
+        assert_eq!(filters.interfaces, ValidInterfaces::default());
+    }
+
+    #[test]
+    fn test_filters_from_env_invalid_interface() {
+        let env = hashmap! {
+            "OS_ENDPOINT_TYPE" => "private",
+        };
+
+        assert!(_filters_from_env(env).is_err());
+    }
+
     #[test]
     fn test_none() {
         let env = hashmap! {
@@ -17,17 +17,37 @@
 #[cfg(any(feature = "native-tls", feature = "rustls"))]
 use std::fs;
 
-#[cfg(any(feature = "native-tls", feature = "rustls"))]
-use reqwest::Certificate;
 use reqwest::Client;
 
-use crate::{Error, ErrorKind};
+use crate::{ClientOptions, Error, ErrorKind, HostPoolTransport};
+
+/// Create an HTTP client with the provided CA certificate and options.
+#[cfg(feature = "identity-v2")]
+#[inline]
+fn get_client(cacert: Option<String>, options: &ClientOptions) -> Result<Client, Error> {
+    build_with_cacert(cacert, options, ClientOptions::build)
+}
 
-/// Create an HTTP client with the provided CA certificate.
+/// Create an HTTP client and a transport with per-host connection pools (if configured).
 #[inline]
-#[allow(unused_mut)] // mut builder unused with --no-default-features
-fn get_client(cacert: Option<String>) -> Result<Client, Error> {
-    let mut builder = Client::builder();
+fn get_clients(
+    cacert: Option<String>,
+    options: &ClientOptions,
+) -> Result<(Client, Option<HostPoolTransport>), Error> {
+    build_with_cacert(cacert, options, |options| {
+        Ok((options.build()?, options.build_host_pools()?))
+    })
+}
+
+/// Add the provided CA certificate to the options and build something from them.
+fn build_with_cacert<T, F>(
+    cacert: Option<String>,
+    options: &ClientOptions,
+    build: F,
+) -> Result<T, Error>
+where
+    F: FnOnce(&ClientOptions) -> Result<T, Error>,
+{
     #[cfg(any(feature = "native-tls", feature = "rustls"))]
     if let Some(cert_path) = cacert {
         let cert_content = fs::read(&cert_path).map_err(|e| {
@@ -35,16 +55,17 @@ fn get_client(cacert: Option<String>) -> Result<Client, Error> {
                 ErrorKind::InvalidConfig,
                 format!("Cannot open cacert file {}: {}", cert_path, e),
             )
+            .with_source(e)
         })?;
 
-        let cert = Certificate::from_pem(&cert_content).map_err(|e| {
+        let mut options = options.clone();
+        options.add_root_certificate_pem(cert_content);
+        return build(&options).map_err(|e| {
             Error::new(
                 ErrorKind::InvalidConfig,
-                format!("Cannot parse {} as PEM: {}", cert_path, e),
+                format!("Cannot use {} as a CA certificate: {}", cert_path, e),
             )
-        })?;
-
-        builder = builder.add_root_certificate(cert);
+        });
     }
 
     #[cfg(not(any(feature = "native-tls", feature = "rustls")))]
@@ -55,11 +76,14 @@ fn get_client(cacert: Option<String>) -> Result<Client, Error> {
         ));
     }
 
-    Ok(builder.build().expect("Cannot initialize HTTP backend"))
+    build(options)
 }
 
 mod cloud;
 mod config;
 mod env;
+mod registry;
 
 pub use cloud::CloudConfig;
+pub(crate) use env::filters_from_env;
+pub use registry::CloudRegistry;
@@ -0,0 +1,229 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Registry of clouds and their sessions.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use log::debug;
+use tokio::sync::{Mutex, OnceCell};
+
+use super::cloud::CloudConfig;
+use super::config::all_from_config;
+use crate::{Error, ErrorKind, Session};
+
+/// Cloud name and region (if any).
+type Key = (String, Option<String>);
+
+/// A registry of clouds that creates and caches sessions for them.
+///
+/// Sessions are created on the first request for a cloud (and optionally a region) and clones
+/// of them are handed out afterwards. Sessions for different regions of one cloud share the
+/// authentication.
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// let registry = osauth::CloudRegistry::from_config()?;
+/// for cloud in registry.cloud_names() {
+///     let session = registry.session(cloud, None).await?;
+///     let servers: serde_json::Value = session
+///         .get_json(osauth::services::COMPUTE, &["servers"])
+///         .await?;
+///     println!("{}: {}", cloud, servers);
+/// }
+/// let session = registry.session("mycloud", Some("RegionTwo")).await?;
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[derive(Debug, Default)]
+pub struct CloudRegistry {
+    clouds: HashMap<String, CloudConfig>,
+    sessions: Mutex<HashMap<Key, Arc<OnceCell<Session>>>>,
+}
+
+impl CloudRegistry {
+    /// Create a registry with the given clouds.
+    pub fn new(clouds: HashMap<String, CloudConfig>) -> CloudRegistry {
+        CloudRegistry {
+            clouds,
+            sessions: Mutex::default(),
+        }
+    }
+
+    /// Create a registry with all clouds from the configuration files.
+    ///
+    /// See [Session::from_config](struct.Session.html#method.from_config) for details.
+    pub fn from_config() -> Result<CloudRegistry, Error> {
+        Ok(CloudRegistry::new(all_from_config()?))
+    }
+
+    /// Add a cloud (or replace an existing one).
+    ///
+    /// Cached sessions of the replaced cloud are dropped.
+    pub fn add_cloud<S: Into<String>>(&mut self, name: S, config: CloudConfig) {
+        let name = name.into();
+        self.sessions
+            .get_mut()
+            .retain(|(cloud, _), _| *cloud != name);
+        let _ = self.clouds.insert(name, config);
+    }
+
+    /// Add a cloud (or replace an existing one).
+    #[inline]
+    pub fn with_cloud<S: Into<String>>(mut self, name: S, config: CloudConfig) -> Self {
+        self.add_cloud(name, config);
+        self
+    }
+
+    /// Configuration of a cloud.
+    #[inline]
+    pub fn cloud(&self, name: &str) -> Option<&CloudConfig> {
+        self.clouds.get(name)
+    }
+
+    /// Names of all known clouds.
+    pub fn cloud_names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.clouds.keys().map(String::as_str)
+    }
+
+    /// Get a session for the cloud, optionally in a different region.
+    ///
+    /// Without a region, the region from the cloud configuration is used. The session is
+    /// created and authenticated on the first call for the cloud. Creating a session for one
+    /// cloud does not block requests for sessions of other clouds.
+    pub async fn session(&self, cloud: &str, region: Option<&str>) -> Result<Session, Error> {
+        let config = self.clouds.get(cloud).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidConfig,
+                format!("No such cloud: {}", cloud),
+            )
+        })?;
+
+        let base = self
+            .slot(cloud, None)
+            .await
+            .get_or_try_init(|| async {
+                debug!("Creating a session for cloud {}", cloud);
+                config.clone().create_session().await
+            })
+            .await?
+            .clone();
+
+        match region {
+            Some(region) => Ok(self
+                .slot(cloud, Some(region))
+                .await
+                .get_or_init(|| async { base.with_region(region) })
+                .await
+                .clone()),
+            None => Ok(base),
+        }
+    }
+
+    /// Get a slot for a session, so that it is only created once.
+    async fn slot(&self, cloud: &str, region: Option<&str>) -> Arc<OnceCell<Session>> {
+        let key = (cloud.to_string(), region.map(From::from));
+        Arc::clone(self.sessions.lock().await.entry(key).or_default())
+    }
+
+    /// Drop cached sessions of a cloud, so that they are created again on the next request.
+    pub async fn invalidate(&self, cloud: &str) {
+        self.sessions
+            .lock()
+            .await
+            .retain(|(name, _), _| name != cloud);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::net::TcpListener;
+
+    use super::CloudRegistry;
+    use crate::{CloudConfig, ErrorKind};
+
+    fn config() -> CloudConfig {
+        serde_yaml::from_str(
+            r#"
+auth_type: none
+auth:
+  endpoint: http://127.0.0.1:6385
+region_name: RegionOne"#,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_session() {
+        let registry = CloudRegistry::new(HashMap::new()).with_cloud("cloud", config());
+        assert_eq!(registry.cloud_names().collect::<Vec<_>>(), vec!["cloud"]);
+
+        let session = registry.session("cloud", None).await.unwrap();
+        assert_eq!(
+            session.endpoint_filters().region.as_deref(),
+            Some("RegionOne")
+        );
+        let session = registry.session("cloud", Some("RegionTwo")).await.unwrap();
+        assert_eq!(
+            session.endpoint_filters().region.as_deref(),
+            Some("RegionTwo")
+        );
+        assert_eq!(registry.sessions.lock().await.len(), 2);
+
+        registry.invalidate("cloud").await;
+        assert!(registry.sessions.lock().await.is_empty());
+
+        let err = registry.session("missing", None).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidConfig);
+    }
+
+    #[tokio::test]
+    async fn test_session_does_not_block_other_clouds() {
+        // Accepts connections, but never answers.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let slow: CloudConfig = serde_yaml::from_str(&format!(
+            r#"
+auth:
+  auth_url: http://{}/identity
+  username: admin
+  password: pa$$w0rd
+  project_name: admin
+  user_domain_name: Default
+  project_domain_name: Default"#,
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+        let registry = Arc::new(
+            CloudRegistry::new(HashMap::new())
+                .with_cloud("slow", slow)
+                .with_cloud("fast", config()),
+        );
+
+        let registry2 = Arc::clone(&registry);
+        let slow_session = tokio::spawn(async move { registry2.session("slow", None).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let _ = tokio::time::timeout(Duration::from_secs(5), registry.session("fast", None))
+            .await
+            .expect("Session creation is blocked by another cloud")
+            .unwrap();
+        assert!(!slow_session.is_finished());
+        slow_session.abort();
+    }
+}
@@ -258,8 +258,6 @@ macro_rules! protocol_enum {
 pub mod test {
     use serde_json;
 
-    use crate::protocol_enum;
-
     protocol_enum! {
         enum ImageStatus {
             Queued = "queued",
@@ -0,0 +1,289 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Metrics collection.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::{Method, StatusCode};
+
+/// A sink for metrics.
+///
+/// Implement this trait to feed request and authentication metrics into a telemetry system of
+/// your choice. All methods have empty default implementations:
+///
+/// ```rust,no_run
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::time::Duration;
+///
+/// #[derive(Debug, Default)]
+/// struct Counter(AtomicUsize);
+///
+/// impl osauth::MetricsSink for Counter {
+///     fn request_finished(
+///         &self,
+///         _method: &reqwest::Method,
+///         _service_type: Option<&str>,
+///         _status: Option<reqwest::StatusCode>,
+///         _duration: Duration,
+///     ) {
+///         let _ = self.0.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+///
+/// # async fn example() -> Result<(), osauth::Error> {
+/// let session = osauth::Session::from_env()
+///     .await?
+///     .with_metrics_sink(Counter::default());
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+///
+/// Request metrics are reported by the [Session](struct.Session.html) (or
+/// [AuthenticatedClient](client/struct.AuthenticatedClient.html)), authentication metrics by
+/// the identity authentication types (e.g.
+/// [Password::set_metrics_sink](identity/struct.Password.html#method.set_metrics_sink)). Use an
+/// `Arc` to share one sink between them. The methods are called synchronously in the request
+/// path and must not block.
+pub trait MetricsSink: Debug + Send + Sync {
+    /// A request is about to be sent.
+    ///
+    /// The service type is only known for requests made through a `Session`.
+    fn request_started(&self, _method: &Method, _service_type: Option<&str>) {}
+
+    /// A request has finished.
+    ///
+    /// The status is `None` if no response has been received. The duration is measured until
+    /// receiving the response headers.
+    fn request_finished(
+        &self,
+        _method: &Method,
+        _service_type: Option<&str>,
+        _status: Option<StatusCode>,
+        _duration: Duration,
+    ) {
+    }
+
+    /// A new token has been received.
+    fn auth_refreshed(&self, _duration: Duration) {}
+}
+
+impl<T: MetricsSink + ?Sized> MetricsSink for Arc<T> {
+    fn request_started(&self, method: &Method, service_type: Option<&str>) {
+        (**self).request_started(method, service_type)
+    }
+
+    fn request_finished(
+        &self,
+        method: &Method,
+        service_type: Option<&str>,
+        status: Option<StatusCode>,
+        duration: Duration,
+    ) {
+        (**self).request_finished(method, service_type, status, duration)
+    }
+
+    fn auth_refreshed(&self, duration: Duration) {
+        (**self).auth_refreshed(duration)
+    }
+}
+
+/// Upper bounds of histogram buckets in milliseconds.
+const BUCKETS_MS: &[u64] = &[10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// Latency histogram of a service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    sum: Duration,
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram {
+            // The last bucket is for everything above the largest bound.
+            counts: vec![0; BUCKETS_MS.len() + 1],
+            sum: Duration::default(),
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let index = BUCKETS_MS
+            .iter()
+            .position(|bound| duration <= Duration::from_millis(*bound))
+            .unwrap_or(BUCKETS_MS.len());
+        self.counts[index] += 1;
+        self.sum += duration;
+    }
+
+    /// Number of observed requests.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Total duration of observed requests.
+    #[inline]
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    /// Buckets as pairs of an upper bound and a number of requests within it.
+    ///
+    /// The counts are not cumulative. The last bucket has no upper bound.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        BUCKETS_MS
+            .iter()
+            .map(|bound| Some(Duration::from_millis(*bound)))
+            .chain(std::iter::once(None))
+            .zip(self.counts.iter().copied())
+    }
+
+    /// Estimate a quantile (e.g. 0.99) as the upper bound of the bucket containing it.
+    ///
+    /// Returns `None` if nothing has been observed. For the last bucket the largest bound is
+    /// returned.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((count as f64) * quantile.clamp(0.0, 1.0)).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (index, bucket) in self.counts.iter().enumerate() {
+            seen += bucket;
+            if seen >= rank {
+                let bound = BUCKETS_MS.get(index).or_else(|| BUCKETS_MS.last());
+                return bound.map(|ms| Duration::from_millis(*ms));
+            }
+        }
+        None
+    }
+}
+
+/// A metrics sink that tracks latency histograms per service type.
+///
+/// Use an `Arc` to keep access to the histograms:
+///
+/// ```rust,no_run
+/// use std::sync::Arc;
+///
+/// # async fn example() -> Result<(), osauth::Error> {
+/// let latency = Arc::new(osauth::LatencyHistograms::default());
+/// let session = osauth::Session::from_env()
+///     .await?
+///     .with_metrics_sink(Arc::clone(&latency));
+/// // ... make some requests ...
+/// for (service, histogram) in latency.histograms() {
+///     println!("{}: p99 {:?}", service, histogram.quantile(0.99));
+/// }
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+///
+/// Only requests made through a [Session](struct.Session.html) (which have a service type)
+/// are tracked.
+#[derive(Debug, Default)]
+pub struct LatencyHistograms {
+    services: Mutex<HashMap<String, Histogram>>,
+}
+
+impl LatencyHistograms {
+    /// Histograms by service type.
+    pub fn histograms(&self) -> HashMap<String, Histogram> {
+        self.services
+            .lock()
+            .expect("Histogram lock is poisoned")
+            .clone()
+    }
+
+    /// Histogram for the given service type.
+    pub fn histogram(&self, service_type: &str) -> Option<Histogram> {
+        self.services
+            .lock()
+            .expect("Histogram lock is poisoned")
+            .get(service_type)
+            .cloned()
+    }
+}
+
+impl MetricsSink for LatencyHistograms {
+    fn request_finished(
+        &self,
+        _method: &Method,
+        service_type: Option<&str>,
+        _status: Option<StatusCode>,
+        duration: Duration,
+    ) {
+        if let Some(service_type) = service_type {
+            self.services
+                .lock()
+                .expect("Histogram lock is poisoned")
+                .entry(service_type.to_string())
+                .or_default()
+                .observe(duration);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use reqwest::{Method, StatusCode};
+
+    use super::{Histogram, LatencyHistograms, MetricsSink};
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.quantile(0.5), None);
+        for ms in &[5, 20, 20, 200, 60000] {
+            histogram.observe(Duration::from_millis(*ms));
+        }
+        assert_eq!(histogram.count(), 5);
+        assert_eq!(histogram.sum(), Duration::from_millis(60245));
+        assert_eq!(histogram.quantile(0.0), Some(Duration::from_millis(10)));
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_millis(25)));
+        assert_eq!(histogram.quantile(0.8), Some(Duration::from_millis(250)));
+        assert_eq!(histogram.quantile(1.0), Some(Duration::from_millis(10000)));
+        let buckets: Vec<_> = histogram.buckets().collect();
+        assert_eq!(buckets[0], (Some(Duration::from_millis(10)), 1));
+        assert_eq!(buckets[1], (Some(Duration::from_millis(25)), 2));
+        assert_eq!(buckets[10], (None, 1));
+    }
+
+    #[test]
+    fn test_latency_histograms() {
+        let latency = LatencyHistograms::default();
+        let duration = Duration::from_millis(42);
+        latency.request_finished(
+            &Method::GET,
+            Some("compute"),
+            Some(StatusCode::OK),
+            duration,
+        );
+        latency.request_finished(&Method::GET, Some("compute"), None, duration);
+        latency.request_finished(&Method::GET, None, Some(StatusCode::OK), duration);
+        assert_eq!(latency.histograms().len(), 1);
+        assert_eq!(latency.histogram("compute").unwrap().count(), 2);
+        assert!(latency.histogram("network").is_none());
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright 2023 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Transport using `reqwest-middleware`.
+
+use async_trait::async_trait;
+use reqwest::{Client, Request, Response};
+use reqwest_middleware::{ClientWithMiddleware, Error as MiddlewareError};
+
+use super::client::Transport;
+use super::{Error, ErrorKind};
+
+/// Sends authenticated requests through the middleware stack.
+///
+/// The `client` argument is ignored: the requests are sent by the `reqwest` client wrapped by
+/// the middleware.
+#[async_trait]
+impl Transport for ClientWithMiddleware {
+    async fn execute(&self, _client: &Client, request: Request) -> Result<Response, Error> {
+        ClientWithMiddleware::execute(self, request)
+            .await
+            .map_err(Error::from)
+    }
+}
+
+impl From<MiddlewareError> for Error {
+    fn from(value: MiddlewareError) -> Error {
+        match value {
+            MiddlewareError::Reqwest(err) => Error::from(err),
+            MiddlewareError::Middleware(err) => Error::new(
+                ErrorKind::ProtocolError,
+                format!("Middleware error: {:#}", err),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use async_trait::async_trait;
+    use reqwest::{Client, Request, Response};
+    use reqwest_middleware::{ClientBuilder, Middleware, Next, Result};
+    use task_local_extensions::Extensions;
+
+    use crate::services::OBJECT_STORAGE;
+    use crate::utils::test::{serve_raw_http, NO_CONTENT};
+    use crate::{ErrorKind, NoAuth, Session};
+
+    #[derive(Debug)]
+    struct AddHeader;
+
+    #[async_trait]
+    impl Middleware for AddHeader {
+        async fn handle(
+            &self,
+            mut req: Request,
+            extensions: &mut Extensions,
+            next: Next<'_>,
+        ) -> Result<Response> {
+            let _ = req
+                .headers_mut()
+                .insert("x-middleware", "yes".parse().unwrap());
+            next.run(req, extensions).await
+        }
+    }
+
+    #[derive(Debug)]
+    struct Reject;
+
+    #[async_trait]
+    impl Middleware for Reject {
+        #[allow(clippy::io_other_error)] // io::Error::other requires a newer Rust
+        async fn handle(
+            &self,
+            _req: Request,
+            _extensions: &mut Extensions,
+            _next: Next<'_>,
+        ) -> Result<Response> {
+            let err = io::Error::new(io::ErrorKind::Other, "rejected");
+            Err(reqwest_middleware::Error::middleware(err))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_middleware_transport() {
+        let (addr, server) = serve_raw_http(vec![NO_CONTENT.into()]).await;
+        let url = format!("http://{}/v1/", addr).parse().unwrap();
+        let session = Session::new(NoAuth::new_without_endpoint())
+            .await
+            .unwrap()
+            .with_endpoint_override(OBJECT_STORAGE, url)
+            .with_middleware(ClientBuilder::new(Client::new()).with(AddHeader).build());
+
+        let response = session.get(OBJECT_STORAGE, &["c"]).send().await.unwrap();
+        assert_eq!(response.status(), 204);
+        let requests = server.await.unwrap();
+        assert!(requests[0].to_lowercase().contains("x-middleware: yes\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_middleware_error() {
+        let session = Session::new(NoAuth::new_without_endpoint())
+            .await
+            .unwrap()
+            .with_endpoint_override(OBJECT_STORAGE, "http://127.0.0.1:1/".parse().unwrap())
+            .with_middleware(ClientBuilder::new(Client::new()).with(Reject).build());
+
+        let err = session
+            .get(OBJECT_STORAGE, &["c"])
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ProtocolError);
+        assert!(err.to_string().contains("rejected"), "{}", err);
+    }
+}
@@ -0,0 +1,383 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Resumable downloads.
+
+use async_stream::try_stream;
+use bytes::Bytes;
+use futures::stream::{Stream, StreamExt};
+use log::{debug, warn};
+#[cfg(feature = "checksum")]
+use reqwest::header::HeaderMap;
+use reqwest::header::{ETAG, IF_MATCH};
+
+use super::object_path;
+#[cfg(feature = "checksum")]
+use crate::checksum::{verify, Algorithm, Hasher};
+use crate::services::OBJECT_STORAGE;
+use crate::{ByteRange, ContentRange, Error, ErrorKind, Event, Session};
+
+#[cfg(feature = "checksum")]
+const SLO_HEADER: &str = "x-static-large-object";
+#[cfg(feature = "checksum")]
+const DLO_HEADER: &str = "x-object-manifest";
+
+/// A download of an object that is resumed after connection failures.
+///
+/// Interrupted transfers are resumed with a `Range` request. The `If-Match` header is used with
+/// the `ETag` of the first response to make sure the object has not changed in between. The total
+/// length is checked against `Content-Length` on completion. With the `checksum` feature, the MD5
+/// checksum of the data can also be verified against the `ETag` (except for large objects, which
+/// have no such checksum).
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use futures::TryStreamExt;
+/// use osauth::object_storage::ResumableDownload;
+///
+/// let session = osauth::Session::from_env().await?;
+/// let chunks: Vec<_> = ResumableDownload::new("my-container", "big-object")
+///     .with_max_retries(5)
+///     .into_stream(&session)
+///     .try_collect()
+///     .await?;
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ResumableDownload {
+    container: String,
+    object: String,
+    max_retries: usize,
+    #[cfg(feature = "checksum")]
+    verify_checksum: bool,
+}
+
+impl ResumableDownload {
+    /// Create a download of the object.
+    pub fn new<C: Into<String>, O: Into<String>>(container: C, object: O) -> ResumableDownload {
+        ResumableDownload {
+            container: container.into(),
+            object: object.into(),
+            max_retries: 3,
+            #[cfg(feature = "checksum")]
+            verify_checksum: false,
+        }
+    }
+
+    /// Start the download, returning a stream of chunks.
+    pub fn into_stream(self, session: &Session) -> impl Stream<Item = Result<Bytes, Error>> + '_ {
+        try_stream! {
+            let mut received: u64 = 0;
+            let mut expected = None;
+            let mut etag = None;
+            #[cfg(feature = "checksum")]
+            let mut checksum = None;
+            #[cfg(feature = "checksum")]
+            let mut hasher = None;
+            let mut retries = 0;
+
+            loop {
+                let mut request =
+                    session.get(OBJECT_STORAGE, object_path(&self.container, &self.object));
+                if received > 0 {
+                    request = request.with_range(ByteRange::From(received));
+                    if let Some(ref etag) = etag {
+                        request = request.header(IF_MATCH, etag);
+                    }
+                }
+
+                let response = match request.send().await {
+                    Ok(response) => response,
+                    Err(err)
+                        if err.kind() == ErrorKind::ProtocolError && retries < self.max_retries =>
+                    {
+                        retries += 1;
+                        warn!(
+                            "Request for {}/{} failed, retrying ({}/{}): {}",
+                            self.container, self.object, retries, self.max_retries, err
+                        );
+                        session.client().emit(|| Event::RequestRetried {
+                            attempt: retries,
+                            error: err,
+                        });
+                        continue;
+                    }
+                    Err(err) => Err(err)?,
+                };
+
+                if received == 0 {
+                    expected = response.content_length();
+                    etag = response.headers().get(ETAG).cloned();
+                    #[cfg(feature = "checksum")]
+                    if self.verify_checksum {
+                        checksum = expected_md5(response.headers(), &self.container, &self.object);
+                        hasher = checksum.as_ref().map(|_| Hasher::new(Algorithm::Md5));
+                    }
+                } else {
+                    match ContentRange::from_response(&response)? {
+                        Some(range) if range.start() == received => {}
+                        Some(range) => Err(Error::new(
+                            ErrorKind::InvalidResponse,
+                            format!(
+                                "Requested data starting with byte {}, received from byte {}",
+                                received,
+                                range.start()
+                            ),
+                        ))?,
+                        None => Err(Error::new(
+                            ErrorKind::InvalidResponse,
+                            "The server does not support resuming downloads",
+                        ))?,
+                    }
+                }
+
+                let mut interrupted = false;
+                let mut body = response.bytes_stream();
+                while let Some(chunk) = body.next().await {
+                    match chunk {
+                        Ok(chunk) => {
+                            received += chunk.len() as u64;
+                            #[cfg(feature = "checksum")]
+                            if let Some(ref mut hasher) = hasher {
+                                hasher.update(&chunk);
+                            }
+                            // Only consecutive failures count against the limit.
+                            retries = 0;
+                            yield chunk;
+                        }
+                        Err(err) if retries < self.max_retries => {
+                            retries += 1;
+                            warn!(
+                                "Download of {}/{} interrupted after {} bytes, resuming ({}/{}): {}",
+                                self.container,
+                                self.object,
+                                received,
+                                retries,
+                                self.max_retries,
+                                err
+                            );
+                            session.client().emit(|| Event::RequestRetried {
+                                attempt: retries,
+                                error: Error::from(err),
+                            });
+                            interrupted = true;
+                            break;
+                        }
+                        Err(err) => Err(Error::from(err))?,
+                    }
+                }
+
+                if !interrupted {
+                    break;
+                }
+            }
+
+            verify_length(expected, received)?;
+            #[cfg(feature = "checksum")]
+            if let (Some(hasher), Some(checksum)) = (hasher, checksum) {
+                let what = format!("object {}/{}", self.container, self.object);
+                verify(&what, Algorithm::Md5, &checksum, hasher)?;
+            }
+            debug!(
+                "Downloaded {} bytes from {}/{}",
+                received, self.container, self.object
+            );
+        }
+    }
+
+    /// Maximum number of consecutive retries (3 by default).
+    #[inline]
+    pub fn set_max_retries(&mut self, value: usize) {
+        self.max_retries = value;
+    }
+
+    /// Whether to verify the MD5 checksum of the data (disabled by default).
+    ///
+    /// Requires the `checksum` feature. If enabled, the stream returns an error with kind
+    /// [ChecksumMismatch](../enum.ErrorKind.html#variant.ChecksumMismatch) as its last item
+    /// when the data does not match the `ETag` of the object. Large objects are not verified
+    /// since their `ETag` is not a checksum of the data.
+    #[cfg(feature = "checksum")]
+    #[inline]
+    pub fn set_verify_checksum(&mut self, value: bool) {
+        self.verify_checksum = value;
+    }
+
+    /// Maximum number of consecutive retries (3 by default).
+    #[inline]
+    pub fn with_max_retries(mut self, value: usize) -> Self {
+        self.set_max_retries(value);
+        self
+    }
+
+    /// Whether to verify the MD5 checksum of the data (disabled by default).
+    #[cfg(feature = "checksum")]
+    #[inline]
+    pub fn with_verify_checksum(mut self, value: bool) -> Self {
+        self.set_verify_checksum(value);
+        self
+    }
+}
+
+/// Expected MD5 checksum of an object from its `ETag`.
+#[cfg(feature = "checksum")]
+fn expected_md5(headers: &HeaderMap, container: &str, object: &str) -> Option<String> {
+    if headers.contains_key(SLO_HEADER) || headers.contains_key(DLO_HEADER) {
+        debug!(
+            "{}/{} is a large object, its checksum cannot be verified",
+            container, object
+        );
+        return None;
+    }
+
+    let etag = headers
+        .get(ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim_matches('"').to_string());
+    if etag.is_none() {
+        warn!(
+            "No ETag for {}/{}, its checksum will not be verified",
+            container, object
+        );
+    }
+    etag
+}
+
+fn verify_length(expected: Option<u64>, received: u64) -> Result<(), Error> {
+    match expected {
+        Some(expected) if expected != received => Err(Error::new(
+            ErrorKind::InvalidResponse,
+            format!("Expected {} bytes, received {}", expected, received),
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use futures::TryStreamExt;
+    #[cfg(feature = "checksum")]
+    use reqwest::header::{HeaderMap, HeaderValue, ETAG};
+
+    #[cfg(feature = "checksum")]
+    use super::expected_md5;
+    use super::{verify_length, ResumableDownload};
+    use crate::services::OBJECT_STORAGE;
+    use crate::utils::test::serve_raw_http;
+    use crate::{Error, ErrorKind, NoAuth, Session};
+
+    /// Response announcing `length` bytes that is cut off after `body`.
+    fn truncated(status: &str, headers: &str, length: usize, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\n{}\r\n{}",
+            status, length, headers, body
+        )
+    }
+
+    async fn download(addr: SocketAddr, max_retries: usize) -> Result<Vec<u8>, Error> {
+        let url = format!("http://{}/v1/AUTH_test/", addr).parse().unwrap();
+        let session = Session::new(NoAuth::new_without_endpoint())
+            .await
+            .unwrap()
+            .with_endpoint_override(OBJECT_STORAGE, url);
+        let chunks: Vec<_> = ResumableDownload::new("c", "o")
+            .with_max_retries(max_retries)
+            .into_stream(&session)
+            .try_collect()
+            .await?;
+        Ok(chunks.concat())
+    }
+
+    #[tokio::test]
+    async fn test_resume() {
+        let (addr, server) = serve_raw_http(vec![
+            truncated("200 OK", "ETag: \"abcd\"\r\n", 10, "hello"),
+            truncated(
+                "206 Partial Content",
+                "Content-Range: bytes 5-9/10\r\n",
+                5,
+                "world",
+            ),
+        ])
+        .await;
+
+        let data = download(addr, 3).await.unwrap();
+        assert_eq!(data, b"helloworld");
+
+        let requests = server.await.unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].starts_with("GET /v1/AUTH_test/c/o "));
+        assert!(!requests[0].to_lowercase().contains("range:"));
+        let resumed = requests[1].to_lowercase();
+        assert!(resumed.contains("range: bytes=5-\r\n"));
+        assert!(resumed.contains("if-match: \"abcd\"\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_resume_wrong_range() {
+        let (addr, server) = serve_raw_http(vec![
+            truncated("200 OK", "ETag: \"abcd\"\r\n", 10, "hello"),
+            truncated(
+                "206 Partial Content",
+                "Content-Range: bytes 3-9/10\r\n",
+                7,
+                "loworld",
+            ),
+        ])
+        .await;
+
+        let err = download(addr, 3).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidResponse);
+        assert_eq!(server.await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_resume_retry_limit() {
+        // No data is received, so the failures are consecutive.
+        let (addr, server) = serve_raw_http(vec![
+            truncated("200 OK", "", 10, ""),
+            truncated("200 OK", "", 10, ""),
+            truncated("200 OK", "", 10, "helloworld"),
+        ])
+        .await;
+
+        assert!(download(addr, 1).await.is_err());
+        server.abort();
+    }
+
+    #[cfg(feature = "checksum")]
+    #[test]
+    fn test_expected_md5() {
+        let mut headers = HeaderMap::new();
+        assert!(expected_md5(&headers, "c", "o").is_none());
+        let _ = headers.insert(ETAG, HeaderValue::from_static("\"abcd\""));
+        assert_eq!(expected_md5(&headers, "c", "o").as_deref(), Some("abcd"));
+        let _ = headers.insert("x-static-large-object", HeaderValue::from_static("True"));
+        assert!(expected_md5(&headers, "c", "o").is_none());
+    }
+
+    #[test]
+    fn test_verify_length() {
+        verify_length(Some(42), 42).unwrap();
+        verify_length(None, 42).unwrap();
+        assert_eq!(
+            verify_length(Some(42), 41).unwrap_err().kind(),
+            ErrorKind::InvalidResponse
+        );
+    }
+}
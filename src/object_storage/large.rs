@@ -0,0 +1,445 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Large object uploads.
+
+use std::cmp;
+use std::fmt;
+use std::mem;
+
+use async_stream::try_stream;
+use chrono::Utc;
+use futures::pin_mut;
+use futures::stream::{Stream, StreamExt, TryStreamExt};
+use log::{debug, trace};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::header::ETAG;
+use reqwest::StatusCode;
+use serde::Serialize;
+
+use super::object_path;
+#[cfg(feature = "checksum")]
+use crate::checksum::{Algorithm, Hasher};
+use crate::services::OBJECT_STORAGE;
+use crate::{Error, ErrorKind, Session};
+
+const MANIFEST_HEADER: &str = "x-object-manifest";
+
+/// Characters to escape in the manifest header (everything except unreserved and slashes).
+const MANIFEST_ESCAPE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Kind of the large object manifest.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ManifestKind {
+    /// Static large object: the manifest lists all segments explicitly.
+    Static,
+    /// Dynamic large object: the manifest refers to a prefix of segment names.
+    Dynamic,
+}
+
+/// A segment of an uploaded large object.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Segment {
+    /// Container with the segment.
+    pub container: String,
+    /// Name of the segment object.
+    pub name: String,
+    /// ETag of the segment as returned by the service.
+    pub etag: Option<String>,
+    /// Size of the segment in bytes.
+    pub size: u64,
+}
+
+/// An upload of a large object split into segments.
+///
+/// The data is read from a stream and buffered in memory segment by segment, so the memory
+/// consumption is limited by the segment size multiplied by the concurrency.
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use osauth::object_storage::LargeObjectUpload;
+///
+/// let session = osauth::Session::from_env().await?;
+/// let data = futures::stream::iter(vec![Ok::<_, std::io::Error>(vec![0u8; 1024])]);
+/// let segments = LargeObjectUpload::new(100 * 1024 * 1024)
+///     .with_concurrency(8)
+///     .upload(&session, "my-container", "big-object", data)
+///     .await?;
+/// println!("Uploaded {} segments", segments.len());
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[derive(Debug, Clone)]
+pub struct LargeObjectUpload {
+    segment_size: usize,
+    concurrency: usize,
+    manifest_kind: ManifestKind,
+    segment_container: Option<String>,
+    #[cfg(feature = "checksum")]
+    verify_checksums: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct ManifestEntry<'s> {
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    etag: Option<&'s str>,
+    size_bytes: u64,
+}
+
+#[allow(clippy::derivable_impls)] // #[default] requires a newer Rust
+impl Default for ManifestKind {
+    fn default() -> ManifestKind {
+        ManifestKind::Static
+    }
+}
+
+impl fmt::Display for ManifestKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ManifestKind::Static => "slo",
+            ManifestKind::Dynamic => "dlo",
+        })
+    }
+}
+
+impl LargeObjectUpload {
+    /// Create an upload with the given segment size (in bytes).
+    pub fn new(segment_size: usize) -> LargeObjectUpload {
+        LargeObjectUpload {
+            segment_size,
+            concurrency: 4,
+            manifest_kind: ManifestKind::default(),
+            segment_container: None,
+            #[cfg(feature = "checksum")]
+            verify_checksums: false,
+        }
+    }
+
+    /// Upload the data and create the manifest object.
+    ///
+    /// Segments are stored in the segment container (`<container>_segments` by default), which
+    /// is created if needed. Returns the list of uploaded segments in order. Empty data results
+    /// in an empty object without a manifest.
+    pub async fn upload<C, O, S, B, E>(
+        &self,
+        session: &Session,
+        container: C,
+        object: O,
+        data: S,
+    ) -> Result<Vec<Segment>, Error>
+    where
+        C: AsRef<str>,
+        O: AsRef<str>,
+        S: Stream<Item = Result<B, E>> + Send,
+        B: AsRef<[u8]> + Send,
+        E: fmt::Display,
+    {
+        if self.segment_size == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Segment size must be positive",
+            ));
+        }
+
+        let container = container.as_ref();
+        let object = object.as_ref();
+        let segment_container = self
+            .segment_container
+            .clone()
+            .unwrap_or_else(|| format!("{}_segments", container));
+        let _ = session
+            .put(OBJECT_STORAGE, &[&segment_container])
+            .send()
+            .await?;
+
+        let prefix = format!(
+            "{}/{}/{}/{}/",
+            object,
+            self.manifest_kind,
+            Utc::now().format("%s.%6f"),
+            self.segment_size
+        );
+        debug!(
+            "Uploading {} in segments of {} bytes to {}/{}",
+            object, self.segment_size, segment_container, prefix
+        );
+
+        let mut segments = split(data, self.segment_size)
+            .enumerate()
+            .map(|(index, result)| result.map(|data| (index, data)))
+            .map_ok(|(index, data)| {
+                let name = format!("{}{:08}", prefix, index);
+                let segment_container = &segment_container;
+                let checksum = self.segment_checksum(&data);
+                async move {
+                    upload_segment(session, segment_container, name, data, checksum)
+                        .await
+                        .map(|segment| (index, segment))
+                }
+            })
+            .try_buffer_unordered(cmp::max(self.concurrency, 1))
+            .try_collect::<Vec<_>>()
+            .await?;
+        segments.sort_by_key(|(index, _)| *index);
+        let segments: Vec<Segment> = segments.into_iter().map(|(_, segment)| segment).collect();
+
+        let request = session.put(OBJECT_STORAGE, object_path(container, object));
+        let _ = if segments.is_empty() {
+            debug!("No data provided for {}, creating an empty object", object);
+            request.send().await?
+        } else {
+            match self.manifest_kind {
+                ManifestKind::Static => {
+                    let manifest = segments
+                        .iter()
+                        .map(|segment| ManifestEntry {
+                            path: format!("/{}/{}", segment.container, segment.name),
+                            etag: segment.etag.as_deref(),
+                            size_bytes: segment.size,
+                        })
+                        .collect::<Vec<_>>();
+                    request
+                        .query(&[("multipart-manifest", "put")])
+                        .json(&manifest)
+                        .send()
+                        .await?
+                }
+                ManifestKind::Dynamic => {
+                    let manifest = format!("{}/{}", segment_container, prefix);
+                    request
+                        .header(
+                            MANIFEST_HEADER,
+                            utf8_percent_encode(&manifest, MANIFEST_ESCAPE).to_string(),
+                        )
+                        .body(Vec::new())
+                        .send()
+                        .await?
+                }
+            }
+        };
+
+        debug!("Uploaded {} with {} segment(s)", object, segments.len());
+        Ok(segments)
+    }
+
+    /// Maximum number of segments to upload in parallel (4 by default).
+    #[inline]
+    pub fn set_concurrency(&mut self, value: usize) {
+        self.concurrency = value;
+    }
+
+    /// Kind of the manifest to create (static by default).
+    #[inline]
+    pub fn set_manifest_kind(&mut self, value: ManifestKind) {
+        self.manifest_kind = value;
+    }
+
+    /// Container to store segments in.
+    #[inline]
+    pub fn set_segment_container<T: Into<String>>(&mut self, value: T) {
+        self.segment_container = Some(value.into());
+    }
+
+    /// Maximum number of segments to upload in parallel (4 by default).
+    #[inline]
+    pub fn with_concurrency(mut self, value: usize) -> Self {
+        self.set_concurrency(value);
+        self
+    }
+
+    /// Kind of the manifest to create (static by default).
+    #[inline]
+    pub fn with_manifest_kind(mut self, value: ManifestKind) -> Self {
+        self.set_manifest_kind(value);
+        self
+    }
+
+    /// Container to store segments in.
+    #[inline]
+    pub fn with_segment_container<T: Into<String>>(mut self, value: T) -> Self {
+        self.set_segment_container(value);
+        self
+    }
+
+    /// Whether to verify MD5 checksums of segments (disabled by default).
+    ///
+    /// Requires the `checksum` feature. If enabled, the checksum is sent with each segment so that the service rejects corrupted
+    /// data, and the upload fails with
+    /// [ChecksumMismatch](../enum.ErrorKind.html#variant.ChecksumMismatch).
+    #[cfg(feature = "checksum")]
+    #[inline]
+    pub fn set_verify_checksums(&mut self, value: bool) {
+        self.verify_checksums = value;
+    }
+
+    /// Whether to verify MD5 checksums of segments (disabled by default).
+    #[cfg(feature = "checksum")]
+    #[inline]
+    pub fn with_verify_checksums(mut self, value: bool) -> Self {
+        self.set_verify_checksums(value);
+        self
+    }
+
+    /// MD5 checksum to send with a segment, if verification is enabled.
+    #[cfg(feature = "checksum")]
+    fn segment_checksum(&self, data: &[u8]) -> Option<String> {
+        if self.verify_checksums {
+            let mut hasher = Hasher::new(Algorithm::Md5);
+            hasher.update(data);
+            Some(hasher.finish())
+        } else {
+            None
+        }
+    }
+
+    #[cfg(not(feature = "checksum"))]
+    #[inline]
+    fn segment_checksum(&self, _data: &[u8]) -> Option<String> {
+        None
+    }
+}
+
+async fn upload_segment(
+    session: &Session,
+    container: &str,
+    name: String,
+    data: Vec<u8>,
+    checksum: Option<String>,
+) -> Result<Segment, Error> {
+    let size = data.len() as u64;
+    trace!("Uploading segment {}/{} of {} bytes", container, name, size);
+    let mut request = session.put(OBJECT_STORAGE, object_path(container, &name));
+    if let Some(ref checksum) = checksum {
+        request = request.header(ETAG, checksum);
+    }
+    let response = match request.body(data).send().await {
+        // Swift responds with 422 if the data does not match the ETag.
+        Err(e) if checksum.is_some() && e.status() == Some(StatusCode::UNPROCESSABLE_ENTITY) => {
+            return Err(Error::new(
+                ErrorKind::ChecksumMismatch,
+                format!("Segment {}/{} was corrupted during upload", container, name),
+            ))
+        }
+        result => result?,
+    };
+    let etag = response
+        .headers()
+        .get(ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim_matches('"').to_string());
+    if let (Some(ref checksum), Some(ref etag)) = (&checksum, &etag) {
+        if !checksum.eq_ignore_ascii_case(etag) {
+            return Err(Error::new(
+                ErrorKind::ChecksumMismatch,
+                format!(
+                    "MD5 checksum mismatch for segment {}/{}: expected {}, got {}",
+                    container, name, checksum, etag
+                ),
+            ));
+        }
+    }
+    Ok(Segment {
+        container: container.to_string(),
+        name,
+        etag,
+        size,
+    })
+}
+
+/// Split a stream of chunks into segments of the given size.
+fn split<S, B, E>(data: S, segment_size: usize) -> impl Stream<Item = Result<Vec<u8>, Error>>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: fmt::Display,
+{
+    try_stream! {
+        pin_mut!(data);
+        let mut buffer = Vec::with_capacity(segment_size);
+        while let Some(chunk) = data.next().await {
+            let chunk = chunk.map_err(|e| {
+                Error::new(ErrorKind::InvalidInput, format!("Failed to read the data: {}", e))
+            })?;
+            let mut chunk = chunk.as_ref();
+            while !chunk.is_empty() {
+                let size = cmp::min(segment_size - buffer.len(), chunk.len());
+                buffer.extend_from_slice(&chunk[..size]);
+                chunk = &chunk[size..];
+                if buffer.len() == segment_size {
+                    yield mem::replace(&mut buffer, Vec::with_capacity(segment_size));
+                }
+            }
+        }
+
+        if !buffer.is_empty() {
+            yield buffer;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::stream::{self, TryStreamExt};
+
+    use super::split;
+    use crate::ErrorKind;
+
+    #[tokio::test]
+    async fn test_split() {
+        let data = stream::iter(vec![
+            Ok::<_, String>(vec![1u8, 2, 3]),
+            Ok(vec![4, 5]),
+            Ok(vec![]),
+            Ok(vec![6, 7, 8, 9, 10, 11]),
+        ]);
+        let result: Vec<Vec<u8>> = split(data, 4).try_collect().await.unwrap();
+        assert_eq!(
+            result,
+            vec![vec![1, 2, 3, 4], vec![5, 6, 7, 8], vec![9, 10, 11]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_split_exact() {
+        let data = stream::iter(vec![Ok::<_, String>(vec![1u8, 2]), Ok(vec![3, 4])]);
+        let result: Vec<Vec<u8>> = split(data, 2).try_collect().await.unwrap();
+        assert_eq!(result, vec![vec![1, 2], vec![3, 4]]);
+    }
+
+    #[tokio::test]
+    async fn test_split_empty() {
+        let data = stream::iter(Vec::<Result<Vec<u8>, String>>::new());
+        let result: Vec<Vec<u8>> = split(data, 2).try_collect().await.unwrap();
+        assert!(result.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_split_error() {
+        let data = stream::iter(vec![Ok(vec![1u8, 2]), Err("boom".to_string())]);
+        let err = split(data, 4)
+            .try_collect::<Vec<Vec<u8>>>()
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,307 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Account and container metadata.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use log::debug;
+use reqwest::Method;
+
+use crate::client::NO_PATH;
+use crate::services::OBJECT_STORAGE;
+use crate::{Error, ErrorKind, Session};
+
+/// Changes to account or container metadata.
+///
+/// Keys are case-insensitive and are sent without the `X-Account-Meta-` or `X-Container-Meta-`
+/// prefix. Removals are sent as `X-Remove-*-Meta-*` headers since empty headers may be dropped
+/// by proxies on the way to the service.
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use osauth::object_storage::{self, MetadataUpdate};
+///
+/// let session = osauth::Session::from_env().await?;
+/// let update = MetadataUpdate::new()
+///     .with("owner", "me")
+///     .without("obsolete");
+/// object_storage::update_container_metadata(&session, "my-container", &update).await?;
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[derive(Clone, Default)]
+pub struct MetadataUpdate {
+    set: HashMap<String, String>,
+    delete: BTreeSet<String>,
+}
+
+impl fmt::Debug for MetadataUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Temporary URL keys are secrets.
+        let set: HashMap<_, _> = self
+            .set
+            .iter()
+            .map(|(key, value)| {
+                if key.to_lowercase().starts_with("temp-url-key") {
+                    (key, "<hidden>")
+                } else {
+                    (key, value.as_str())
+                }
+            })
+            .collect();
+        f.debug_struct("MetadataUpdate")
+            .field("set", &set)
+            .field("delete", &self.delete)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Target {
+    Account,
+    Container,
+}
+
+impl Target {
+    fn prefix(self) -> &'static str {
+        match self {
+            Target::Account => "x-account-meta-",
+            Target::Container => "x-container-meta-",
+        }
+    }
+
+    fn remove_prefix(self) -> &'static str {
+        match self {
+            Target::Account => "x-remove-account-meta-",
+            Target::Container => "x-remove-container-meta-",
+        }
+    }
+}
+
+impl MetadataUpdate {
+    /// Create an empty update.
+    #[inline]
+    pub fn new() -> MetadataUpdate {
+        MetadataUpdate::default()
+    }
+
+    /// Delete a metadata item.
+    pub fn delete<K: Into<String>>(&mut self, key: K) {
+        let key = key.into().to_lowercase();
+        let _ = self.set.remove(&key);
+        let _ = self.delete.insert(key);
+    }
+
+    /// Whether the update is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty() && self.delete.is_empty()
+    }
+
+    /// Set a metadata item.
+    ///
+    /// Setting an empty value is equivalent to deleting the item.
+    pub fn set<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        let key = key.into().to_lowercase();
+        let value = value.into();
+        if value.is_empty() {
+            self.delete(key);
+        } else {
+            let _ = self.delete.remove(&key);
+            let _ = self.set.insert(key, value);
+        }
+    }
+
+    /// Set a metadata item.
+    ///
+    /// Setting an empty value is equivalent to deleting the item.
+    #[inline]
+    pub fn with<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.set(key, value);
+        self
+    }
+
+    /// Delete a metadata item.
+    #[inline]
+    pub fn without<K: Into<String>>(mut self, key: K) -> Self {
+        self.delete(key);
+        self
+    }
+
+    fn to_headers(&self, target: Target) -> Result<HeaderMap, Error> {
+        let mut result = HeaderMap::with_capacity(self.set.len() + self.delete.len());
+        for (key, value) in &self.set {
+            let value = HeaderValue::from_bytes(value.as_bytes()).map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Invalid value of metadata item {}: {}", key, e),
+                )
+            })?;
+            let _ = result.insert(header_name(target.prefix(), key)?, value);
+        }
+        for key in &self.delete {
+            let _ = result.insert(
+                header_name(target.remove_prefix(), key)?,
+                HeaderValue::from_static("x"),
+            );
+        }
+        Ok(result)
+    }
+}
+
+fn header_name(prefix: &str, key: &str) -> Result<HeaderName, Error> {
+    HeaderName::from_bytes(format!("{}{}", prefix, key).as_bytes()).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid metadata key {}: {}", key, e),
+        )
+    })
+}
+
+fn from_headers(headers: &HeaderMap, target: Target) -> HashMap<String, String> {
+    headers
+        .iter()
+        .filter_map(|(name, value)| {
+            name.as_str().strip_prefix(target.prefix()).map(|key| {
+                (
+                    key.to_string(),
+                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Get metadata of the current account.
+///
+/// Keys are returned in lower case without the `X-Account-Meta-` prefix.
+pub async fn get_account_metadata(session: &Session) -> Result<HashMap<String, String>, Error> {
+    let response = session
+        .request(OBJECT_STORAGE, Method::HEAD, NO_PATH)
+        .send()
+        .await?;
+    Ok(from_headers(response.headers(), Target::Account))
+}
+
+/// Get metadata of a container.
+///
+/// Keys are returned in lower case without the `X-Container-Meta-` prefix.
+pub async fn get_container_metadata<C: AsRef<str>>(
+    session: &Session,
+    container: C,
+) -> Result<HashMap<String, String>, Error> {
+    let response = session
+        .request(OBJECT_STORAGE, Method::HEAD, &[container.as_ref()])
+        .send()
+        .await?;
+    Ok(from_headers(response.headers(), Target::Container))
+}
+
+/// Update metadata of the current account.
+///
+/// Items that are not mentioned in the update are not changed.
+pub async fn update_account_metadata(
+    session: &Session,
+    update: &MetadataUpdate,
+) -> Result<(), Error> {
+    let headers = update.to_headers(Target::Account)?;
+    debug!("Updating account metadata with {:?}", update);
+    let _ = session
+        .post(OBJECT_STORAGE, NO_PATH)
+        .headers(headers)
+        .send()
+        .await?;
+    Ok(())
+}
+
+/// Update metadata of a container.
+///
+/// Items that are not mentioned in the update are not changed.
+pub async fn update_container_metadata<C: AsRef<str>>(
+    session: &Session,
+    container: C,
+    update: &MetadataUpdate,
+) -> Result<(), Error> {
+    let container = container.as_ref();
+    let headers = update.to_headers(Target::Container)?;
+    debug!(
+        "Updating metadata of container {} with {:?}",
+        container, update
+    );
+    let _ = session
+        .post(OBJECT_STORAGE, &[container])
+        .headers(headers)
+        .send()
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use http::header::{HeaderMap, HeaderValue};
+
+    use super::{from_headers, MetadataUpdate, Target};
+
+    #[test]
+    fn test_debug_hides_temp_url_keys() {
+        let update = MetadataUpdate::default()
+            .with("Temp-URL-Key", "secret")
+            .with("owner", "me");
+        let debug = format!("{:?}", update);
+        assert!(debug.contains("me"), "{}", debug);
+        assert!(!debug.contains("secret"), "{}", debug);
+    }
+
+    #[test]
+    fn test_update_headers() {
+        let update = MetadataUpdate::new()
+            .with("Owner", "me")
+            .with("empty", "")
+            .without("obsolete");
+        let headers = update.to_headers(Target::Container).unwrap();
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers["x-container-meta-owner"], "me");
+        assert_eq!(headers["x-remove-container-meta-empty"], "x");
+        assert_eq!(headers["x-remove-container-meta-obsolete"], "x");
+    }
+
+    #[test]
+    fn test_update_set_after_delete() {
+        let update = MetadataUpdate::new().without("key").with("key", "value");
+        let headers = update.to_headers(Target::Account).unwrap();
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers["x-account-meta-key"], "value");
+    }
+
+    #[test]
+    fn test_update_invalid_key() {
+        let update = MetadataUpdate::new().with("in valid", "value");
+        assert!(update.to_headers(Target::Account).is_err());
+    }
+
+    #[test]
+    fn test_from_headers() {
+        let mut headers = HeaderMap::new();
+        let _ = headers.insert("x-account-meta-owner", HeaderValue::from_static("me"));
+        let _ = headers.insert("x-container-meta-color", HeaderValue::from_static("red"));
+        let _ = headers.insert("content-length", HeaderValue::from_static("0"));
+        let result = from_headers(&headers, Target::Account);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result["owner"], "me");
+    }
+}
@@ -0,0 +1,41 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for the Object Storage service (Swift).
+
+#[cfg(feature = "stream")]
+mod download;
+#[cfg(feature = "stream")]
+mod large;
+mod metadata;
+#[cfg(feature = "tempurl")]
+mod tempurl;
+
+#[cfg(feature = "stream")]
+pub use download::ResumableDownload;
+#[cfg(feature = "stream")]
+pub use large::{LargeObjectUpload, ManifestKind, Segment};
+pub use metadata::{
+    get_account_metadata, get_container_metadata, update_account_metadata,
+    update_container_metadata, MetadataUpdate,
+};
+#[cfg(feature = "tempurl")]
+pub use tempurl::{get_temp_url_key, TempUrl, TempUrlDigest};
+
+/// Path to an object, slashes in the object name are treated as path separators.
+#[cfg(any(feature = "stream", feature = "tempurl"))]
+#[inline]
+fn object_path<'s>(container: &'s str, object: &'s str) -> impl Iterator<Item = &'s str> + Send {
+    std::iter::once(container).chain(object.split('/'))
+}
@@ -0,0 +1,246 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Temporary URLs.
+
+use std::fmt;
+
+use chrono::{DateTime, TimeZone};
+use hmac::{Hmac, Mac};
+use log::{debug, error};
+use percent_encoding::percent_decode_str;
+use reqwest::{Method, Url};
+use sha1::Sha1;
+use sha2::Sha256;
+
+use super::object_path;
+use crate::client::NO_PATH;
+use crate::services::OBJECT_STORAGE;
+use crate::{Error, ErrorKind, Session};
+
+const TEMP_URL_KEY_HEADER: &str = "x-account-meta-temp-url-key";
+
+/// Digest algorithm used to sign temporary URLs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TempUrlDigest {
+    /// HMAC-SHA1 (deprecated in Swift, but may be the only option in older deployments).
+    Sha1,
+    /// HMAC-SHA256.
+    Sha256,
+}
+
+/// A temporary URL generator.
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use osauth::object_storage::TempUrl;
+///
+/// let session = osauth::Session::from_env().await?;
+/// let expires_at = chrono::Utc::now() + chrono::Duration::hours(1);
+/// let url = TempUrl::new(reqwest::Method::GET, expires_at)
+///     .generate(&session, "my-container", "path/to/object")
+///     .await?;
+/// println!("Download from {}", url);
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[derive(Clone)]
+pub struct TempUrl {
+    method: Method,
+    expires: i64,
+    digest: TempUrlDigest,
+    key: Option<String>,
+}
+
+#[allow(clippy::derivable_impls)] // #[default] requires a newer Rust
+impl Default for TempUrlDigest {
+    fn default() -> TempUrlDigest {
+        TempUrlDigest::Sha256
+    }
+}
+
+impl fmt::Debug for TempUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TempUrl")
+            .field("method", &self.method)
+            .field("expires", &self.expires)
+            .field("digest", &self.digest)
+            .field("key", &self.key.as_ref().map(|_| "<hidden>"))
+            .finish()
+    }
+}
+
+impl TempUrl {
+    /// Create a temporary URL generator for the method and the expiration time.
+    ///
+    /// The account temporary URL key is fetched from the service unless provided via
+    /// [set_key](#method.set_key).
+    pub fn new<Tz: TimeZone>(method: Method, expires_at: DateTime<Tz>) -> TempUrl {
+        TempUrl {
+            method,
+            expires: expires_at.timestamp(),
+            digest: TempUrlDigest::default(),
+            key: None,
+        }
+    }
+
+    /// Generate a temporary URL for the object.
+    ///
+    /// Slashes in the object name are treated as path separators.
+    pub async fn generate<C, O>(
+        &self,
+        session: &Session,
+        container: C,
+        object: O,
+    ) -> Result<Url, Error>
+    where
+        C: AsRef<str>,
+        O: AsRef<str>,
+    {
+        let path = object_path(container.as_ref(), object.as_ref());
+        let url = session.get_endpoint(OBJECT_STORAGE, path).await?;
+        let key = match self.key {
+            Some(ref key) => key.clone(),
+            None => get_temp_url_key(session).await?.ok_or_else(|| {
+                error!("No temporary URL key is set for the account");
+                Error::new(
+                    ErrorKind::InvalidConfig,
+                    "The account does not have a temporary URL key",
+                )
+            })?,
+        };
+        Ok(self.sign(url, &key))
+    }
+
+    /// Digest algorithm to use.
+    #[inline]
+    pub fn set_digest(&mut self, value: TempUrlDigest) {
+        self.digest = value;
+    }
+
+    /// Temporary URL key to use instead of fetching it from the service.
+    #[inline]
+    pub fn set_key<T: Into<String>>(&mut self, value: T) {
+        self.key = Some(value.into());
+    }
+
+    /// Digest algorithm to use.
+    #[inline]
+    pub fn with_digest(mut self, value: TempUrlDigest) -> Self {
+        self.set_digest(value);
+        self
+    }
+
+    /// Temporary URL key to use instead of fetching it from the service.
+    #[inline]
+    pub fn with_key<T: Into<String>>(mut self, value: T) -> Self {
+        self.set_key(value);
+        self
+    }
+
+    fn sign(&self, mut url: Url, key: &str) -> Url {
+        // Swift signs the decoded path.
+        let path = percent_decode_str(url.path()).decode_utf8_lossy();
+        let body = format!("{}\n{}\n{}", self.method, self.expires, path);
+        let signature = match self.digest {
+            TempUrlDigest::Sha1 => {
+                let mut mac = Hmac::<Sha1>::new_from_slice(key.as_bytes())
+                    .expect("HMAC accepts keys of any size");
+                mac.update(body.as_bytes());
+                format!("{:x}", mac.finalize().into_bytes())
+            }
+            TempUrlDigest::Sha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())
+                    .expect("HMAC accepts keys of any size");
+                mac.update(body.as_bytes());
+                format!("{:x}", mac.finalize().into_bytes())
+            }
+        };
+        debug!(
+            "Generated a temporary URL for {} {} expiring at {}",
+            self.method, path, self.expires
+        );
+
+        let _ = url
+            .query_pairs_mut()
+            .append_pair("temp_url_sig", &signature)
+            .append_pair("temp_url_expires", &self.expires.to_string());
+        url
+    }
+}
+
+/// Fetch the temporary URL key of the current account.
+///
+/// Returns `None` if the key is not set.
+pub async fn get_temp_url_key(session: &Session) -> Result<Option<String>, Error> {
+    let response = session
+        .request(OBJECT_STORAGE, Method::HEAD, NO_PATH)
+        .send()
+        .await?;
+    match response.headers().get(TEMP_URL_KEY_HEADER) {
+        Some(value) => value.to_str().map(|s| Some(s.to_string())).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidResponse,
+                format!("Invalid temporary URL key header: {}", e),
+            )
+        }),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+    use reqwest::{Method, Url};
+
+    use super::{TempUrl, TempUrlDigest};
+
+    const ENDPOINT: &str = "https://example.com/v1/AUTH_test/container/dir/my%20object";
+
+    #[test]
+    fn test_sign_sha256() {
+        let temp_url = TempUrl::new(Method::GET, Utc.timestamp_opt(1700000000, 0).unwrap());
+        let url = temp_url.sign(Url::parse(ENDPOINT).unwrap(), "secret");
+        assert_eq!(
+            url.as_str(),
+            format!(
+                "{}?temp_url_sig={}&temp_url_expires=1700000000",
+                ENDPOINT, "2767c8b1d54591142736037a90987254c7081a9104e3b1cb46b083bc66f64499"
+            )
+        );
+    }
+
+    #[test]
+    fn test_sign_sha1() {
+        let temp_url = TempUrl::new(Method::GET, Utc.timestamp_opt(1700000000, 0).unwrap())
+            .with_digest(TempUrlDigest::Sha1);
+        let url = temp_url.sign(Url::parse(ENDPOINT).unwrap(), "secret");
+        assert_eq!(
+            url.as_str(),
+            format!(
+                "{}?temp_url_sig={}&temp_url_expires=1700000000",
+                ENDPOINT, "f75f777effc5c0acf0729a3c97655dae7658dd14"
+            )
+        );
+    }
+
+    #[test]
+    fn test_debug_hides_key() {
+        let temp_url =
+            TempUrl::new(Method::PUT, Utc.timestamp_opt(1700000000, 0).unwrap()).with_key("secret");
+        assert!(!format!("{:?}", temp_url).contains("secret"));
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Connection pool settings.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::{Client, ClientBuilder, Request, Response};
+
+use super::client::Transport;
+use super::Error;
+
+/// Settings of a pool of idle (keep-alive) connections.
+///
+/// Can be set for all hosts with
+/// [ClientOptions::set_pool_options](struct.ClientOptions.html#method.set_pool_options) or for a
+/// specific service host with
+/// [ClientOptions::add_host_pool_options](struct.ClientOptions.html#method.add_host_pool_options).
+/// Chatty services benefit from more idle connections, while rarely used services should not
+/// hold sockets open.
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// let options = osauth::ClientOptions::new()
+///     .with_pool_options(osauth::PoolOptions::new().with_max_idle(2))
+///     .with_host_pool_options(
+///         "network.cloud.example.com",
+///         osauth::PoolOptions::new()
+///             .with_max_idle(32)
+///             .with_idle_timeout(Duration::from_secs(300)),
+///     );
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolOptions {
+    idle_timeout: Option<Duration>,
+    max_idle: Option<usize>,
+}
+
+impl PoolOptions {
+    /// Create default options (the `reqwest` defaults are used).
+    #[inline]
+    pub fn new() -> PoolOptions {
+        PoolOptions::default()
+    }
+
+    /// Time after which idle connections are closed.
+    #[inline]
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// Maximum number of idle connections per host.
+    #[inline]
+    pub fn max_idle(&self) -> Option<usize> {
+        self.max_idle
+    }
+
+    /// Time after which idle connections are closed (90 seconds by default).
+    #[inline]
+    pub fn set_idle_timeout(&mut self, value: impl Into<Option<Duration>>) {
+        self.idle_timeout = value.into();
+    }
+
+    /// Maximum number of idle connections per host (unlimited by default).
+    ///
+    /// Use zero to disable keep-alive completely.
+    #[inline]
+    pub fn set_max_idle(&mut self, value: impl Into<Option<usize>>) {
+        self.max_idle = value.into();
+    }
+
+    /// Time after which idle connections are closed (90 seconds by default).
+    #[inline]
+    pub fn with_idle_timeout(mut self, value: impl Into<Option<Duration>>) -> Self {
+        self.set_idle_timeout(value);
+        self
+    }
+
+    /// Maximum number of idle connections per host (unlimited by default).
+    #[inline]
+    pub fn with_max_idle(mut self, value: impl Into<Option<usize>>) -> Self {
+        self.set_max_idle(value);
+        self
+    }
+
+    /// Apply the options to an HTTP client builder.
+    pub(crate) fn apply(&self, mut builder: ClientBuilder) -> ClientBuilder {
+        if let Some(timeout) = self.idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+        if let Some(max_idle) = self.max_idle {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+        builder
+    }
+}
+
+/// Transport using separate connection pools for some hosts.
+///
+/// Created by [ClientOptions::build_host_pools](struct.ClientOptions.html#method.build_host_pools)
+/// and installed automatically by
+/// [CloudConfig::create_session](struct.CloudConfig.html#method.create_session). Requests to
+/// other hosts are sent with the inner client of the session.
+///
+/// Requests made by the authentication type itself do not go through the transport and always
+/// use the inner client.
+#[derive(Debug, Clone)]
+pub struct HostPoolTransport {
+    clients: HashMap<String, Client>,
+}
+
+impl HostPoolTransport {
+    #[inline]
+    pub(crate) fn new(clients: HashMap<String, Client>) -> HostPoolTransport {
+        HostPoolTransport { clients }
+    }
+
+    /// Whether a separate pool is used for the host.
+    #[inline]
+    pub fn has_host<S: AsRef<str>>(&self, host: S) -> bool {
+        self.clients.contains_key(&host.as_ref().to_lowercase())
+    }
+}
+
+#[async_trait]
+impl Transport for HostPoolTransport {
+    async fn execute(&self, client: &Client, request: Request) -> Result<Response, Error> {
+        let client = request
+            .url()
+            .host_str()
+            .and_then(|host| self.clients.get(&host.to_lowercase()))
+            .unwrap_or(client);
+        client.execute(request).await.map_err(Error::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use reqwest::{Client, Method, Url};
+
+    use super::PoolOptions;
+    use crate::client::Transport;
+    use crate::utils::test::{serve_raw_http, NO_CONTENT};
+    use crate::ClientOptions;
+
+    #[test]
+    fn test_build_host_pools() {
+        let options = ClientOptions::new()
+            .with_pool_options(PoolOptions::new().with_max_idle(1))
+            .with_host_pool_options(
+                "Network.Example.Com",
+                PoolOptions::new()
+                    .with_max_idle(16)
+                    .with_idle_timeout(Duration::from_secs(300)),
+            );
+        let _ = options.build().unwrap();
+        let transport = options.build_host_pools().unwrap().unwrap();
+        assert!(transport.has_host("network.example.com"));
+        assert!(transport.has_host("NETWORK.example.com"));
+        assert!(!transport.has_host("compute.example.com"));
+
+        assert!(ClientOptions::new().build_host_pools().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_host_pool_transport() {
+        let (addr, server) = serve_raw_http(vec![NO_CONTENT.into()]).await;
+
+        let transport = ClientOptions::new()
+            .with_host_pool_options("127.0.0.1", PoolOptions::new().with_max_idle(0))
+            .build_host_pools()
+            .unwrap()
+            .unwrap();
+        let url = Url::parse(&format!("http://{}/", addr)).unwrap();
+        let request = reqwest::Request::new(Method::GET, url);
+        let response = transport.execute(&Client::new(), request).await.unwrap();
+        assert_eq!(response.status(), 204);
+        let _ = server.await.unwrap();
+    }
+}
@@ -0,0 +1,197 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conditional requests.
+
+use chrono::{DateTime, Utc};
+use http::header::{
+    HeaderMap, HeaderName, HeaderValue, ETAG, IF_MATCH, IF_UNMODIFIED_SINCE, LAST_MODIFIED,
+};
+use reqwest::Response;
+
+use super::{Error, ErrorKind};
+
+/// Version of a resource used for conditional (optimistic concurrency) writes.
+///
+/// Capture it from the response to a GET request and pass it to the following PUT or PATCH
+/// request with
+/// [with_precondition](struct.ServiceRequestBuilder.html#method.with_precondition). The write
+/// fails with [PreconditionFailed](enum.ErrorKind.html#variant.PreconditionFailed) if the
+/// resource has been modified in between:
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use osauth::services::OBJECT_STORAGE;
+/// use osauth::ErrorKind;
+///
+/// let session = osauth::Session::from_env().await?;
+/// loop {
+///     let (mut config, precondition) = session
+///         .get(OBJECT_STORAGE, &["my-container", "config.json"])
+///         .fetch_with_precondition::<serde_json::Value>()
+///         .await?;
+///     config["counter"] = (config["counter"].as_i64().unwrap_or(0) + 1).into();
+///     let mut request = session
+///         .put(OBJECT_STORAGE, &["my-container", "config.json"])
+///         .json(&config);
+///     if let Some(ref precondition) = precondition {
+///         request = request.with_precondition(precondition);
+///     }
+///     match request.send().await {
+///         Ok(_) => break,
+///         Err(e) if e.kind() == ErrorKind::PreconditionFailed => continue,
+///         Err(e) => return Err(e),
+///     }
+/// }
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Precondition {
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+}
+
+/// Whether the entity tag is weak (cannot be used with `If-Match`).
+fn is_weak(etag: &HeaderValue) -> bool {
+    etag.as_bytes().starts_with(b"W/")
+}
+
+impl Precondition {
+    /// Require the resource to have the given entity tag (`If-Match`).
+    ///
+    /// The value must include quotes, e.g. `"\"abcd\""`, as returned in the `ETag` header.
+    pub fn etag<S: AsRef<str>>(value: S) -> Result<Precondition, Error> {
+        let etag = HeaderValue::from_str(value.as_ref()).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid entity tag: {}", e),
+            )
+        })?;
+        Ok(Precondition {
+            etag: Some(etag),
+            last_modified: None,
+        })
+    }
+
+    /// Require the resource to be unmodified since the given time (`If-Unmodified-Since`).
+    pub fn unmodified_since(value: DateTime<Utc>) -> Precondition {
+        let value = value.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        Precondition {
+            etag: None,
+            last_modified: Some(HeaderValue::from_str(&value).expect("HTTP date is valid ASCII")),
+        }
+    }
+
+    /// Capture the version of a resource from response headers.
+    ///
+    /// Uses the `ETag` header unless it is missing or weak, then the `Last-Modified` header.
+    /// Returns `None` if neither can be used.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Precondition> {
+        let etag = headers.get(ETAG).filter(|etag| !is_weak(etag)).cloned();
+        let last_modified = headers.get(LAST_MODIFIED).cloned();
+        if etag.is_none() && last_modified.is_none() {
+            None
+        } else {
+            Some(Precondition {
+                etag,
+                last_modified,
+            })
+        }
+    }
+
+    /// Capture the version of a resource from a response.
+    ///
+    /// See [from_headers](#method.from_headers) for details.
+    #[inline]
+    pub fn from_response(response: &Response) -> Option<Precondition> {
+        Precondition::from_headers(response.headers())
+    }
+
+    /// Entity tag (if known).
+    #[inline]
+    pub fn entity_tag(&self) -> Option<&str> {
+        self.etag.as_ref().and_then(|value| value.to_str().ok())
+    }
+
+    /// Last modification time as an HTTP date (if known).
+    #[inline]
+    pub fn last_modified(&self) -> Option<&str> {
+        self.last_modified
+            .as_ref()
+            .and_then(|value| value.to_str().ok())
+    }
+
+    /// The header to send with the request.
+    pub(crate) fn header(&self) -> (HeaderName, HeaderValue) {
+        match (&self.etag, &self.last_modified) {
+            (Some(etag), _) => (IF_MATCH, etag.clone()),
+            (None, Some(last_modified)) => (IF_UNMODIFIED_SINCE, last_modified.clone()),
+            (None, None) => unreachable!("Precondition without a value"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+    use http::header::{
+        HeaderMap, HeaderValue, ETAG, IF_MATCH, IF_UNMODIFIED_SINCE, LAST_MODIFIED,
+    };
+
+    use super::Precondition;
+    use crate::ErrorKind;
+
+    const DATE: &str = "Sat, 01 Jan 2022 12:30:00 GMT";
+
+    #[test]
+    fn test_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert!(Precondition::from_headers(&headers).is_none());
+
+        let _ = headers.insert(LAST_MODIFIED, HeaderValue::from_static(DATE));
+        let _ = headers.insert(ETAG, HeaderValue::from_static("W/\"weak\""));
+        let precondition = Precondition::from_headers(&headers).unwrap();
+        assert!(precondition.entity_tag().is_none());
+        assert_eq!(
+            precondition.header(),
+            (IF_UNMODIFIED_SINCE, DATE.parse().unwrap())
+        );
+
+        let _ = headers.insert(ETAG, HeaderValue::from_static("\"strong\""));
+        let precondition = Precondition::from_headers(&headers).unwrap();
+        assert_eq!(precondition.entity_tag(), Some("\"strong\""));
+        assert_eq!(precondition.last_modified(), Some(DATE));
+        assert_eq!(
+            precondition.header(),
+            (IF_MATCH, "\"strong\"".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_explicit() {
+        let precondition = Precondition::etag("\"abcd\"").unwrap();
+        assert_eq!(
+            precondition.header(),
+            (IF_MATCH, "\"abcd\"".parse().unwrap())
+        );
+        let err = Precondition::etag("\n").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        let time = Utc.with_ymd_and_hms(2022, 1, 1, 12, 30, 0).unwrap();
+        let precondition = Precondition::unmodified_since(time);
+        assert_eq!(precondition.last_modified(), Some(DATE));
+    }
+}
@@ -0,0 +1,128 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Service availability probing.
+
+use std::time::{Duration, Instant};
+
+use log::debug;
+use reqwest::{Method, StatusCode, Url};
+
+use super::client::{self, AuthenticatedClient};
+use super::common::{Version, VersionRoot};
+use super::{AuthType, Error};
+
+/// Result of probing a service.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ServiceProbe {
+    /// Endpoint that was probed.
+    pub endpoint: Url,
+    /// HTTP status code (if the service responded at all).
+    pub status: Option<StatusCode>,
+    /// Versions reported by the service (if it returned a version document).
+    pub versions: Vec<Version>,
+    /// Time until the response (or the failure) was received.
+    pub latency: Duration,
+    /// Error that occurred when accessing the service (if any).
+    pub error: Option<Error>,
+}
+
+impl ServiceProbe {
+    /// Whether the service responded with a non-error HTTP status.
+    #[inline]
+    pub fn is_reachable(&self) -> bool {
+        self.status
+            .map(|x| !x.is_client_error() && !x.is_server_error())
+            .unwrap_or(false)
+    }
+}
+
+/// Probe the endpoint with a GET request.
+pub(crate) async fn probe<A: AuthType + ?Sized>(
+    client: &AuthenticatedClient<A>,
+    endpoint: Url,
+) -> ServiceProbe {
+    debug!("Probing endpoint {}", endpoint);
+    let started = Instant::now();
+    let result = client
+        .request(Method::GET, endpoint.clone())
+        .send_unchecked()
+        .await;
+    let latency = started.elapsed();
+
+    let response = match result {
+        Ok(response) => response,
+        Err(error) => {
+            debug!("Endpoint {} is not reachable: {}", endpoint, error);
+            return ServiceProbe {
+                endpoint,
+                status: None,
+                versions: Vec::new(),
+                latency,
+                error: Some(error),
+            };
+        }
+    };
+
+    let status = response.status();
+    let (versions, error) = if !status.is_client_error() && !status.is_server_error() {
+        match response.json::<VersionRoot>().await {
+            Ok(root) => (root.into_versions(), None),
+            Err(error) => {
+                debug!("No version document received from {}: {}", endpoint, error);
+                (Vec::new(), None)
+            }
+        }
+    } else {
+        (Vec::new(), client::check(response).await.err())
+    };
+
+    debug!(
+        "Endpoint {} responded with {} in {:?}",
+        endpoint, status, latency
+    );
+    ServiceProbe {
+        endpoint,
+        status: Some(status),
+        versions,
+        latency,
+        error,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::{StatusCode, Url};
+
+    use super::ServiceProbe;
+
+    #[test]
+    fn test_is_reachable() {
+        let mut probe = ServiceProbe {
+            endpoint: Url::parse("http://127.0.0.1").unwrap(),
+            status: None,
+            versions: Vec::new(),
+            latency: Default::default(),
+            error: None,
+        };
+        assert!(!probe.is_reachable());
+        probe.status = Some(StatusCode::OK);
+        assert!(probe.is_reachable());
+        probe.status = Some(StatusCode::MULTIPLE_CHOICES);
+        assert!(probe.is_reachable());
+        probe.status = Some(StatusCode::SERVICE_UNAVAILABLE);
+        assert!(!probe.is_reachable());
+    }
+}
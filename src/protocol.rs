@@ -15,106 +15,21 @@
 //! JSON structures and protocol bits for the Identity V3 API.
 
 use std::convert::TryFrom;
-use std::iter::{DoubleEndedIterator, FusedIterator};
-use std::vec::IntoIter;
 
 use log::{debug, error, trace, warn};
+use reqwest::header::HOST;
 use reqwest::{Method, Url};
-use serde::Deserialize;
 
-use super::client::AuthenticatedClient;
-use super::common::Version;
+use super::client::{self, AuthenticatedClient};
+use super::common::{Version, VersionRoot};
+use super::discovery;
 use super::services::ServiceType;
 use super::url;
-use super::{ApiVersion, Error, ErrorKind};
-
-/// A result of a version discovery endpoint.
-#[derive(Clone, Debug, Deserialize)]
-#[serde(untagged)]
-pub enum Root {
-    /// Multiple major versions.
-    MultipleVersions { versions: Vec<Version> },
-    /// Single major version.
-    OneVersion { version: Version },
-}
-
-#[derive(Debug, Clone)]
-enum IntoStableIterInner {
-    Many(IntoIter<Version>),
-    One(Option<Version>),
-}
-
-/// An iterator over stable versions.
-#[derive(Debug)]
-pub struct IntoStableIter(IntoStableIterInner);
-
-impl Iterator for IntoStableIter {
-    type Item = Version;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.0 {
-            IntoStableIterInner::Many(ref mut inner) => {
-                for next in inner {
-                    if next.is_stable() {
-                        return Some(next);
-                    }
-                }
-
-                None
-            }
-            IntoStableIterInner::One(ref mut opt) => opt.take(),
-        }
-    }
-}
-
-impl DoubleEndedIterator for IntoStableIter {
-    fn next_back(&mut self) -> Option<Self::Item> {
-        match self.0 {
-            IntoStableIterInner::Many(ref mut inner) => {
-                while let Some(next) = inner.next_back() {
-                    if next.is_stable() {
-                        return Some(next);
-                    }
-                }
-
-                None
-            }
-            IntoStableIterInner::One(ref mut opt) => opt.take(),
-        }
-    }
-}
-
-impl FusedIterator for IntoStableIter {}
-
-impl Root {
-    /// Sort versions from lowest to highest (using unstable sorting).
-    #[inline]
-    pub fn sort(&mut self) {
-        if let Root::MultipleVersions {
-            versions: ref mut vers,
-        } = self
-        {
-            vers.sort_unstable();
-        }
-    }
-
-    /// Create an iterator over stable versions.
-    pub fn into_stable_iter(self) -> IntoStableIter {
-        match self {
-            Root::MultipleVersions { versions: vers } => {
-                IntoStableIter(IntoStableIterInner::Many(vers.into_iter()))
-            }
-            Root::OneVersion { version: ver } => {
-                let stable = if ver.is_stable() { Some(ver) } else { None };
-                IntoStableIter(IntoStableIterInner::One(stable))
-            }
-        }
-    }
-}
+use super::{ApiVersion, AuthType, Error, ErrorKind};
 
 /// Information about API endpoint.
-#[derive(Debug)]
-#[cfg_attr(test, derive(PartialEq, Eq, Clone))]
+#[derive(Debug, Clone)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
 pub struct ServiceInfo {
     /// Root endpoint.
     pub root_url: Url,
@@ -130,8 +45,8 @@ impl TryFrom<Version> for ServiceInfo {
     type Error = Error;
 
     fn try_from(value: Version) -> Result<ServiceInfo, Error> {
-        let endpoint = match value.links.into_iter().find(|x| &x.rel == "self") {
-            Some(link) => link.href,
+        let endpoint = match value.self_link() {
+            Some(link) => link.clone(),
             None => {
                 return Err(Error::new(
                     ErrorKind::InvalidResponse,
@@ -143,20 +58,39 @@ impl TryFrom<Version> for ServiceInfo {
         Ok(ServiceInfo {
             root_url: endpoint,
             major_version: Some(value.id),
-            current_version: value.version.map(From::from),
-            minimum_version: value.min_version.map(From::from),
+            current_version: value.version,
+            minimum_version: value.min_version,
         })
     }
 }
 
 #[inline]
-async fn fetch_root(
+async fn fetch_root<A: AuthType + ?Sized>(
     catalog_type: &'static str,
     endpoint: Url,
-    client: &AuthenticatedClient,
-) -> Result<Root, Error> {
+    host: Option<&str>,
+    client: &AuthenticatedClient<A>,
+) -> Result<VersionRoot, Error> {
+    if let Some(root) = discovery::get(&endpoint, host) {
+        debug!(
+            "Using cached {} service info from {}",
+            catalog_type, endpoint
+        );
+        return Ok(root);
+    }
+
     debug!("Fetching {} service info from {}", catalog_type, endpoint);
-    client.request(Method::GET, endpoint).fetch().await
+    let request = client
+        .request(Method::GET, endpoint.clone())
+        .accept_json_by_default();
+    let response = match host {
+        Some(host) => request.header(HOST, host).send().await?,
+        None => request.send().await?,
+    };
+    let headers = response.headers().clone();
+    let root = client::read_json(response).await?;
+    discovery::put(endpoint, host, &headers, &root);
+    Ok(root)
 }
 
 impl ServiceInfo {
@@ -188,14 +122,17 @@ impl ServiceInfo {
             .max()
     }
 
-    fn from_root<Srv: ServiceType>(mut value: Root, service: Srv) -> Result<ServiceInfo, Error> {
+    fn from_root<Srv: ServiceType>(
+        mut value: VersionRoot,
+        service: Srv,
+    ) -> Result<ServiceInfo, Error> {
         trace!(
             "Available major versions for {} service: {:?}",
             service.catalog_type(),
             value
         );
 
-        if let Root::OneVersion { version: ver } = value {
+        if let VersionRoot::OneVersion { version: ver } = value {
             if service.major_version_supported(ver.id) {
                 if !ver.is_stable() {
                     warn!(
@@ -241,10 +178,13 @@ impl ServiceInfo {
     }
 
     /// Generic code to extract a `ServiceInfo` from a URL.
-    pub async fn fetch<Srv: ServiceType>(
+    ///
+    /// The `host` overrides the `Host` header of discovery requests.
+    pub async fn fetch<Srv: ServiceType, A: AuthType + ?Sized>(
         service: Srv,
         endpoint: Url,
-        client: &AuthenticatedClient,
+        host: Option<&str>,
+        client: &AuthenticatedClient<A>,
     ) -> Result<ServiceInfo, Error> {
         let fallback = ServiceInfo {
             root_url: endpoint.clone(),
@@ -267,7 +207,7 @@ impl ServiceInfo {
         let secure = endpoint.scheme() == "https";
         let catalog_type = service.catalog_type();
 
-        let root = match fetch_root(catalog_type, endpoint.clone(), client).await {
+        let root = match fetch_root(catalog_type, endpoint.clone(), host, client).await {
             Ok(root) => root,
             Err(e) if e.kind() == ErrorKind::ResourceNotFound => {
                 if url::is_root(&endpoint) {
@@ -279,7 +219,7 @@ impl ServiceInfo {
                     return Err(err);
                 } else {
                     debug!("Got HTTP 404 from {}, trying parent endpoint", endpoint);
-                    fetch_root(catalog_type, url::pop(endpoint), client).await?
+                    fetch_root(catalog_type, url::pop(endpoint), host, client).await?
                 }
             }
             Err(e) => return Err(e),
@@ -313,8 +253,8 @@ pub(crate) mod test {
 
     use reqwest::Url;
 
-    use super::{Root, ServiceInfo};
-    use crate::common::{Link, Version, VersionStatus};
+    use super::ServiceInfo;
+    use crate::common::{Link, Version, VersionRoot, VersionStatus};
     use crate::services::ServiceType;
     use crate::{ApiVersion, ErrorKind};
 
@@ -375,7 +315,7 @@ pub(crate) mod test {
     #[test]
     fn test_root_into_service_info_one_version() {
         let url = Url::parse("https://example.com/v1.2").unwrap();
-        let root = Root::OneVersion {
+        let root = VersionRoot::OneVersion {
             version: Version {
                 id: ApiVersion(1, 2),
                 links: vec![Link {
@@ -396,7 +336,7 @@ pub(crate) mod test {
     #[test]
     fn test_root_into_service_info_one_version_unsupported() {
         let url = Url::parse("https://example.com/v1.0").unwrap();
-        let root = Root::OneVersion {
+        let root = VersionRoot::OneVersion {
             version: Version {
                 id: ApiVersion(1, 0),
                 links: vec![Link {
@@ -418,7 +358,7 @@ pub(crate) mod test {
     #[test]
     fn test_root_into_service_info_versions() {
         let url = Url::parse("https://example.com/v1.2").unwrap();
-        let root = Root::MultipleVersions {
+        let root = VersionRoot::MultipleVersions {
             versions: vec![
                 Version {
                     id: ApiVersion(1, 0),
@@ -470,7 +410,7 @@ pub(crate) mod test {
 
     #[test]
     fn test_root_into_service_info_versions_unsupported() {
-        let root = Root::MultipleVersions {
+        let root = VersionRoot::MultipleVersions {
             versions: vec![
                 Version {
                     id: ApiVersion(1, 0),
@@ -513,9 +453,9 @@ pub(crate) mod test {
                 min_version: None,
             })
             .collect();
-        let mut root = Root::MultipleVersions { versions: vers };
+        let mut root = VersionRoot::MultipleVersions { versions: vers };
         root.sort();
-        if let Root::MultipleVersions { versions: res } = root {
+        if let VersionRoot::MultipleVersions { versions: res } = root {
             let idx = res.into_iter().map(|v| v.id.0).collect::<Vec<_>>();
             assert_eq!(idx, vec![1, 2, 3]);
         } else {
@@ -532,9 +472,9 @@ pub(crate) mod test {
             version: None,
             min_version: None,
         };
-        let mut root = Root::OneVersion { version: ver };
+        let mut root = VersionRoot::OneVersion { version: ver };
         root.sort();
-        if let Root::OneVersion { version: res } = root {
+        if let VersionRoot::OneVersion { version: res } = root {
             assert_eq!(res.id.0, 2);
         } else {
             unreachable!();
@@ -553,9 +493,9 @@ pub(crate) mod test {
                 min_version: None,
             })
             .collect();
-        let mut root = Root::MultipleVersions { versions: vers };
+        let mut root = VersionRoot::MultipleVersions { versions: vers };
         root.sort();
-        if let Root::MultipleVersions { versions: res } = root {
+        if let VersionRoot::MultipleVersions { versions: res } = root {
             let idx = res.into_iter().map(|v| v.id.0).collect::<Vec<_>>();
             assert_eq!(idx, vec![1, 2, 3]);
         } else {
@@ -579,7 +519,7 @@ pub(crate) mod test {
                 min_version: None,
             })
             .collect();
-        let root = Root::MultipleVersions { versions: vers };
+        let root = VersionRoot::MultipleVersions { versions: vers };
         let idx = root
             .into_stable_iter()
             .map(|ver| ver.id.0)
@@ -603,7 +543,7 @@ pub(crate) mod test {
                 min_version: None,
             })
             .collect();
-        let root = Root::MultipleVersions { versions: vers };
+        let root = VersionRoot::MultipleVersions { versions: vers };
         let mut idx = root.into_stable_iter().map(|ver| ver.id.0);
         assert_eq!(idx.next_back(), Some(2));
         assert_eq!(idx.next_back(), Some(3));
@@ -620,7 +560,7 @@ pub(crate) mod test {
             version: None,
             min_version: None,
         };
-        let root = Root::OneVersion { version: ver };
+        let root = VersionRoot::OneVersion { version: ver };
         let idx = root
             .into_stable_iter()
             .map(|ver| ver.id.0)
@@ -637,7 +577,7 @@ pub(crate) mod test {
             version: None,
             min_version: None,
         };
-        let root = Root::OneVersion { version: ver };
+        let root = VersionRoot::OneVersion { version: ver };
         let mut idx = root.into_stable_iter().map(|ver| ver.id.0);
         assert!(idx.next().is_none());
     }
@@ -651,7 +591,7 @@ pub(crate) mod test {
             version: None,
             min_version: None,
         };
-        let root = Root::OneVersion { version: ver };
+        let root = VersionRoot::OneVersion { version: ver };
         let mut idx = root.into_stable_iter().map(|ver| ver.id.0);
         assert_eq!(idx.next_back(), Some(2));
         assert!(idx.next_back().is_none());
@@ -686,12 +626,12 @@ pub(crate) mod test {
 
     #[test]
     fn test_parse_root_one_version() {
-        let root: Root = serde_json::from_str(COMPUTE_ONE).unwrap();
+        let root: VersionRoot = serde_json::from_str(COMPUTE_ONE).unwrap();
         match root {
-            Root::OneVersion { version } => {
+            VersionRoot::OneVersion { version } => {
                 assert_eq!(version.id, ApiVersion(2, 1));
             }
-            Root::MultipleVersions { .. } => panic!("Unexpected multiple versions"),
+            VersionRoot::MultipleVersions { .. } => panic!("Unexpected multiple versions"),
         }
     }
 }
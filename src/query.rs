@@ -0,0 +1,212 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Query parameters in the OpenStack conventions.
+
+/// A structure that can be converted into query parameters.
+///
+/// Usually derived with the `derive` feature (enabled by `stream`), which follows the OpenStack
+/// conventions: `None` values are skipped, lists are sent as repeated keys and booleans are
+/// lowercase. Fields can be customized with the `query` attribute:
+///
+/// * `rename = "name"` uses a different name for the key,
+/// * `comma` sends a list as one comma-separated value (e.g. for `fields`),
+/// * `title_case` sends booleans as `True` and `False`,
+/// * `skip` ignores the field.
+///
+#[cfg_attr(feature = "derive", doc = "```rust")]
+#[cfg_attr(not(feature = "derive"), doc = "```rust,ignore")]
+/// use osauth::QueryParams;
+///
+/// #[derive(Debug, Default, QueryParams)]
+/// struct ServerQuery {
+///     #[query(rename = "all_tenants")]
+///     all_projects: Option<bool>,
+///     status: Vec<String>,
+///     #[query(comma)]
+///     fields: Vec<String>,
+///     limit: Option<usize>,
+/// }
+///
+/// let query = ServerQuery {
+///     all_projects: Some(true),
+///     status: vec!["ACTIVE".into(), "ERROR".into()],
+///     fields: vec!["id".into(), "name".into()],
+///     ..ServerQuery::default()
+/// };
+/// assert_eq!(
+///     query.query_pairs(),
+///     vec![
+///         ("all_tenants".to_string(), "true".to_string()),
+///         ("status".to_string(), "ACTIVE".to_string()),
+///         ("status".to_string(), "ERROR".to_string()),
+///         ("fields".to_string(), "id,name".to_string()),
+///     ]
+/// );
+/// ```
+///
+/// Use [append_query](struct.ServiceRequestBuilder.html#method.append_query) to send the
+/// parameters:
+///
+/// ```rust,no_run
+/// # use osauth::QueryParams;
+/// # struct ServerQuery { limit: Option<usize> }
+/// # impl QueryParams for ServerQuery {
+/// #     fn query_pairs(&self) -> Vec<(String, String)> {
+/// #         self.limit.iter().map(|x| ("limit".into(), x.to_string())).collect()
+/// #     }
+/// # }
+/// # async fn example() -> Result<(), osauth::Error> {
+/// let session = osauth::Session::from_env().await?;
+/// let query = ServerQuery { limit: Some(10) };
+/// let servers: serde_json::Value = session
+///     .get(osauth::services::COMPUTE, &["servers"])
+///     .append_query(query.query_pairs())
+///     .fetch()
+///     .await?;
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+pub trait QueryParams {
+    /// Convert into key-value pairs in the order of fields.
+    fn query_pairs(&self) -> Vec<(String, String)>;
+}
+
+/// A value that can be used in [QueryParams](trait.QueryParams.html).
+///
+/// Implement this trait for custom types (e.g. enums) used in query structures.
+pub trait QueryValue {
+    /// Append the string representation(s) of the value.
+    ///
+    /// Scalar values append one string, lists append one string per item, `None` appends
+    /// nothing.
+    fn append_to(&self, values: &mut Vec<String>);
+}
+
+impl<T: QueryValue + ?Sized> QueryValue for &T {
+    #[inline]
+    fn append_to(&self, values: &mut Vec<String>) {
+        (**self).append_to(values)
+    }
+}
+
+impl<T: QueryValue> QueryValue for Option<T> {
+    fn append_to(&self, values: &mut Vec<String>) {
+        if let Some(ref value) = self {
+            value.append_to(values);
+        }
+    }
+}
+
+impl<T: QueryValue> QueryValue for [T] {
+    fn append_to(&self, values: &mut Vec<String>) {
+        for value in self {
+            value.append_to(values);
+        }
+    }
+}
+
+impl<T: QueryValue> QueryValue for Vec<T> {
+    #[inline]
+    fn append_to(&self, values: &mut Vec<String>) {
+        self.as_slice().append_to(values)
+    }
+}
+
+impl QueryValue for str {
+    #[inline]
+    fn append_to(&self, values: &mut Vec<String>) {
+        values.push(self.to_string());
+    }
+}
+
+impl QueryValue for String {
+    #[inline]
+    fn append_to(&self, values: &mut Vec<String>) {
+        values.push(self.clone());
+    }
+}
+
+impl QueryValue for bool {
+    #[inline]
+    fn append_to(&self, values: &mut Vec<String>) {
+        values.push(if *self { "true" } else { "false" }.to_string());
+    }
+}
+
+macro_rules! query_value_to_string {
+    ($($t:ty),+) => {
+        $(
+            impl QueryValue for $t {
+                #[inline]
+                fn append_to(&self, values: &mut Vec<String>) {
+                    values.push(self.to_string());
+                }
+            }
+        )+
+    };
+}
+
+query_value_to_string!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize, f32, f64);
+
+#[cfg(test)]
+mod test {
+    use super::{QueryParams, QueryValue};
+
+    fn values<T: QueryValue + ?Sized>(value: &T) -> Vec<String> {
+        let mut result = Vec::new();
+        value.append_to(&mut result);
+        result
+    }
+
+    #[test]
+    fn test_query_values() {
+        assert_eq!(values("abc"), vec!["abc"]);
+        assert_eq!(values(&String::from("abc")), vec!["abc"]);
+        assert_eq!(values(&true), vec!["true"]);
+        assert_eq!(values(&false), vec!["false"]);
+        assert_eq!(values(&42_u32), vec!["42"]);
+        assert_eq!(values(&-1_i64), vec!["-1"]);
+        assert_eq!(values(&Some(1)), vec!["1"]);
+        assert!(values(&None::<u8>).is_empty());
+        assert_eq!(values(&vec!["a", "b"]), vec!["a", "b"]);
+        assert_eq!(values(&Some(vec![1, 2])), vec!["1", "2"]);
+        assert!(values(&Vec::<String>::new()).is_empty());
+    }
+
+    struct Manual {
+        name: Option<String>,
+    }
+
+    impl QueryParams for Manual {
+        fn query_pairs(&self) -> Vec<(String, String)> {
+            let mut values = Vec::new();
+            self.name.append_to(&mut values);
+            values.into_iter().map(|v| ("name".into(), v)).collect()
+        }
+    }
+
+    #[test]
+    fn test_manual_query_params() {
+        let query = Manual {
+            name: Some("test".into()),
+        };
+        assert_eq!(
+            query.query_pairs(),
+            vec![("name".to_string(), "test".to_string())]
+        );
+        assert!(Manual { name: None }.query_pairs().is_empty());
+    }
+}
@@ -0,0 +1,263 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Range requests and partial content.
+
+use std::fmt;
+
+use http::header::{HeaderMap, HeaderValue, CONTENT_RANGE};
+use reqwest::{Response, StatusCode};
+
+use super::{Error, ErrorKind};
+
+/// A range of bytes to request with the `Range` header.
+///
+/// Pass it to [with_range](struct.ServiceRequestBuilder.html#method.with_range) and use
+/// [ContentRange](struct.ContentRange.html) to interpret the response:
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use osauth::services::OBJECT_STORAGE;
+/// use osauth::{ByteRange, ContentRange};
+///
+/// let session = osauth::Session::from_env().await?;
+/// let response = session
+///     .get(OBJECT_STORAGE, &["my-container", "big-object"])
+///     .with_range(ByteRange::new(0, 1023)?)
+///     .send()
+///     .await?;
+/// if let Some(range) = ContentRange::from_response(&response)? {
+///     println!("Received {} bytes, {:?} remaining", range.len(), range.remaining());
+/// }
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// Bytes from the first position to the last position (inclusive).
+    Bounded(u64, u64),
+    /// Bytes starting with the given position.
+    From(u64),
+    /// The given number of the last bytes.
+    Last(u64),
+}
+
+impl ByteRange {
+    /// Bytes from `start` to `end` (inclusive).
+    pub fn new(start: u64, end: u64) -> Result<ByteRange, Error> {
+        if end < start {
+            Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid byte range {}-{}", start, end),
+            ))
+        } else {
+            Ok(ByteRange::Bounded(start, end))
+        }
+    }
+
+    /// The value of the `Range` header.
+    pub(crate) fn header_value(&self) -> HeaderValue {
+        HeaderValue::from_str(&self.to_string()).expect("byte range is valid ASCII")
+    }
+}
+
+impl fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ByteRange::Bounded(start, end) => write!(f, "bytes={}-{}", start, end),
+            ByteRange::From(start) => write!(f, "bytes={}-", start),
+            ByteRange::Last(length) => write!(f, "bytes=-{}", length),
+        }
+    }
+}
+
+/// A range of bytes returned in a partial content (206) response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentRange {
+    start: u64,
+    end: u64,
+    total: Option<u64>,
+}
+
+fn invalid(value: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidResponse,
+        format!("Invalid Content-Range header: {}", value),
+    )
+}
+
+impl ContentRange {
+    /// Parse the value of the `Content-Range` header, e.g. `bytes 0-1023/4096`.
+    pub fn parse(value: &str) -> Result<ContentRange, Error> {
+        let (range, total) = value
+            .trim()
+            .strip_prefix("bytes ")
+            .and_then(|rest| rest.split_once('/'))
+            .ok_or_else(|| invalid(value))?;
+        let (start, end) = range.split_once('-').ok_or_else(|| invalid(value))?;
+        let start: u64 = start.trim().parse().map_err(|_| invalid(value))?;
+        let end: u64 = end.trim().parse().map_err(|_| invalid(value))?;
+        let total = match total.trim() {
+            "*" => None,
+            total => Some(total.parse::<u64>().map_err(|_| invalid(value))?),
+        };
+        if end < start || matches!(total, Some(total) if end >= total) {
+            return Err(invalid(value));
+        }
+        Ok(ContentRange { start, end, total })
+    }
+
+    /// Get the range from response headers (if present).
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<ContentRange>, Error> {
+        headers
+            .get(CONTENT_RANGE)
+            .map(|value| {
+                value
+                    .to_str()
+                    .map_err(|_| invalid("<non-ASCII>"))
+                    .and_then(ContentRange::parse)
+            })
+            .transpose()
+    }
+
+    /// Get the range from a response.
+    ///
+    /// Returns `None` unless the status is `206 Partial Content`, i.e. when the server has
+    /// ignored the `Range` header and is returning the whole content.
+    pub fn from_response(response: &Response) -> Result<Option<ContentRange>, Error> {
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return Ok(None);
+        }
+        match ContentRange::from_headers(response.headers())? {
+            Some(range) => Ok(Some(range)),
+            None => Err(Error::new(
+                ErrorKind::InvalidResponse,
+                "Partial content response without a Content-Range header",
+            )),
+        }
+    }
+
+    /// First byte position (inclusive).
+    #[inline]
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// Last byte position (inclusive).
+    #[inline]
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// Number of bytes in the range.
+    #[inline]
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Total length of the content (if known).
+    #[inline]
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+
+    /// Number of bytes after the end of the range (if the total length is known).
+    #[inline]
+    pub fn remaining(&self) -> Option<u64> {
+        self.total.map(|total| total - self.end - 1)
+    }
+
+    /// Range that covers the remaining bytes (if any).
+    ///
+    /// If the total length is unknown, requests everything after the end of the range.
+    pub fn next_range(&self) -> Option<ByteRange> {
+        match self.remaining() {
+            Some(0) => None,
+            _ => Some(ByteRange::From(self.end + 1)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http::header::{HeaderMap, HeaderValue, CONTENT_RANGE};
+
+    use super::{ByteRange, ContentRange};
+    use crate::ErrorKind;
+
+    #[test]
+    fn test_byte_range() {
+        assert_eq!(
+            ByteRange::new(0, 1023).unwrap().header_value(),
+            "bytes=0-1023"
+        );
+        assert_eq!(ByteRange::From(1024).to_string(), "bytes=1024-");
+        assert_eq!(ByteRange::Last(500).to_string(), "bytes=-500");
+        assert_eq!(
+            ByteRange::new(2, 1).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let range = ContentRange::parse("bytes 0-1023/4096").unwrap();
+        assert_eq!(range.start(), 0);
+        assert_eq!(range.end(), 1023);
+        assert_eq!(range.len(), 1024);
+        assert_eq!(range.total(), Some(4096));
+        assert_eq!(range.remaining(), Some(3072));
+        assert_eq!(range.next_range(), Some(ByteRange::From(1024)));
+
+        let range = ContentRange::parse("bytes 3072-4095/4096").unwrap();
+        assert_eq!(range.remaining(), Some(0));
+        assert_eq!(range.next_range(), None);
+
+        let range = ContentRange::parse("bytes 10-19/*").unwrap();
+        assert_eq!(range.total(), None);
+        assert_eq!(range.remaining(), None);
+        assert_eq!(range.next_range(), Some(ByteRange::From(20)));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for value in &[
+            "",
+            "bytes */4096",
+            "items 0-1/2",
+            "bytes 0-1023",
+            "bytes 10-5/100",
+            "bytes 0-100/100",
+            "bytes a-b/c",
+        ] {
+            assert_eq!(
+                ContentRange::parse(value).unwrap_err().kind(),
+                ErrorKind::InvalidResponse,
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert!(ContentRange::from_headers(&headers).unwrap().is_none());
+        let _ = headers.insert(CONTENT_RANGE, HeaderValue::from_static("bytes 0-9/100"));
+        let range = ContentRange::from_headers(&headers).unwrap().unwrap();
+        assert_eq!(range.len(), 10);
+    }
+}
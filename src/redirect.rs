@@ -0,0 +1,248 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Redirect handling.
+
+use http::header::{
+    CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, HOST, LOCATION, TRANSFER_ENCODING,
+};
+use log::debug;
+use reqwest::redirect::Policy;
+use reqwest::{Client, Method, Request, Response, StatusCode};
+
+/// Headers that are removed when a redirect leaves the original host.
+const SENSITIVE_HEADERS: &[&str] = &[
+    "authorization",
+    "cookie",
+    "proxy-authorization",
+    "x-auth-token",
+];
+
+/// Default maximum number of redirects to follow.
+const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+/// How to handle HTTP redirects in authenticated requests.
+///
+/// Redirects are followed by the [Session](struct.Session.html) (or
+/// [AuthenticatedClient](client/struct.AuthenticatedClient.html)) rather than by the HTTP
+/// client, so that credentials (`X-Auth-Token`, `Authorization` and cookies) are never sent to
+/// a different host than the original request. The HTTP clients created by this crate do not
+/// follow redirects themselves; disable redirects when providing a custom client:
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// let client = reqwest::Client::builder()
+///     .redirect(reqwest::redirect::Policy::none())
+///     .build()
+///     .expect("Cannot create an HTTP client");
+/// let session = osauth::Session::new_with_client(client, osauth::NoAuth::new_without_endpoint())
+///     .await?
+///     .with_redirect_policy(osauth::RedirectPolicy::SameOrigin(5));
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RedirectPolicy {
+    /// Do not follow redirects, return them as responses.
+    None,
+    /// Follow at most the given number of redirects within the same origin (scheme, host and
+    /// port), return redirects to other origins as responses.
+    SameOrigin(usize),
+    /// Follow at most the given number of redirects, removing credentials when leaving the
+    /// original origin (the default with 10 redirects).
+    Limited(usize),
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> RedirectPolicy {
+        RedirectPolicy::Limited(DEFAULT_MAX_REDIRECTS)
+    }
+}
+
+impl RedirectPolicy {
+    /// Maximum number of redirects to follow.
+    #[inline]
+    pub(crate) fn max_redirects(self) -> usize {
+        match self {
+            RedirectPolicy::None => 0,
+            RedirectPolicy::SameOrigin(max) | RedirectPolicy::Limited(max) => max,
+        }
+    }
+}
+
+/// Create an HTTP client that does not follow redirects.
+pub(crate) fn default_client() -> Client {
+    Client::builder()
+        .redirect(Policy::none())
+        .build()
+        .expect("Cannot create an HTTP client")
+}
+
+/// Prepare a request following the redirect in the response.
+///
+/// Returns `None` if the response is not a redirect or the redirect must not be followed.
+/// The `request` must be a copy of the request that caused the response.
+pub(crate) fn follow(
+    policy: RedirectPolicy,
+    mut request: Request,
+    response: &Response,
+) -> Option<Request> {
+    let status = response.status();
+    let location = response.headers().get(LOCATION)?.to_str().ok()?;
+    let url = request.url().join(location).ok()?;
+
+    let same_origin = url.origin() == request.url().origin();
+    match policy {
+        RedirectPolicy::Limited(..) => {}
+        RedirectPolicy::SameOrigin(..) if same_origin => {}
+        _ => return None,
+    }
+
+    match status {
+        StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND | StatusCode::SEE_OTHER => {
+            // Same behavior as browsers and reqwest: the body is dropped.
+            *request.body_mut() = None;
+            let headers = request.headers_mut();
+            for name in &[
+                TRANSFER_ENCODING,
+                CONTENT_ENCODING,
+                CONTENT_TYPE,
+                CONTENT_LENGTH,
+            ] {
+                let _ = headers.remove(name);
+            }
+            if request.method() != Method::HEAD {
+                *request.method_mut() = Method::GET;
+            }
+        }
+        StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT => {}
+        _ => return None,
+    }
+
+    if !same_origin {
+        debug!(
+            "Removing credentials from a redirect from {} to another host",
+            request.url().origin().ascii_serialization()
+        );
+        let headers = request.headers_mut();
+        for name in SENSITIVE_HEADERS {
+            let _ = headers.remove(*name);
+        }
+        // Tokens in custom headers are marked as sensitive.
+        let custom: Vec<_> = headers
+            .iter()
+            .filter(|(_, value)| value.is_sensitive())
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in custom {
+            let _ = headers.remove(name);
+        }
+        // A Host override only applies to the original endpoint.
+        let _ = headers.remove(HOST);
+    }
+
+    *request.url_mut() = url;
+    Some(request)
+}
+
+#[cfg(test)]
+mod test {
+    use http::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE, HOST, LOCATION};
+    use reqwest::{Client, Method, Request, Response, StatusCode, Url};
+
+    use super::{follow, RedirectPolicy};
+
+    fn request(method: Method) -> Request {
+        Client::new()
+            .request(
+                method,
+                Url::parse("https://cloud.local/compute/v2.1").unwrap(),
+            )
+            .header("x-auth-token", "abcd")
+            .header(AUTHORIZATION, "Basic abcd")
+            .header(CONTENT_TYPE, "application/json")
+            .body("{}")
+            .build()
+            .unwrap()
+    }
+
+    fn redirect(status: StatusCode, location: &'static str) -> Response {
+        http::Response::builder()
+            .status(status)
+            .header(LOCATION, HeaderValue::from_static(location))
+            .body("")
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn test_same_origin() {
+        let response = redirect(StatusCode::TEMPORARY_REDIRECT, "/compute/v2.1/");
+        let next = follow(RedirectPolicy::default(), request(Method::POST), &response).unwrap();
+        assert_eq!(next.url().as_str(), "https://cloud.local/compute/v2.1/");
+        assert_eq!(next.method(), Method::POST);
+        assert_eq!(next.headers()["x-auth-token"], "abcd");
+        assert!(next.body().is_some());
+    }
+
+    #[test]
+    fn test_cross_origin_strips_credentials() {
+        let response = redirect(StatusCode::FOUND, "https://evil.local/");
+        let next = follow(RedirectPolicy::default(), request(Method::POST), &response).unwrap();
+        assert_eq!(next.url().as_str(), "https://evil.local/");
+        assert_eq!(next.method(), Method::GET);
+        assert!(next.headers().get("x-auth-token").is_none());
+        assert!(next.headers().get(AUTHORIZATION).is_none());
+        assert!(next.headers().get(CONTENT_TYPE).is_none());
+        assert!(next.body().is_none());
+    }
+
+    #[test]
+    fn test_cross_origin_strips_host() {
+        let mut request = request(Method::GET);
+        let _ = request
+            .headers_mut()
+            .insert(HOST, HeaderValue::from_static("compute.cloud.local"));
+        let response = redirect(StatusCode::TEMPORARY_REDIRECT, "/compute/v2.1/");
+        let next = follow(RedirectPolicy::default(), request, &response).unwrap();
+        assert_eq!(next.headers()[HOST], "compute.cloud.local");
+
+        let response = redirect(StatusCode::TEMPORARY_REDIRECT, "https://other.local/");
+        let next = follow(RedirectPolicy::default(), next, &response).unwrap();
+        assert!(next.headers().get(HOST).is_none());
+    }
+
+    #[test]
+    fn test_cross_origin_strips_sensitive() {
+        let mut value = HeaderValue::from_static("abcd");
+        value.set_sensitive(true);
+        let mut request = request(Method::GET);
+        let _ = request.headers_mut().insert("x-custom-token", value);
+        let response = redirect(StatusCode::FOUND, "https://evil.local/");
+        let next = follow(RedirectPolicy::default(), request, &response).unwrap();
+        assert!(next.headers().get("x-custom-token").is_none());
+    }
+
+    #[test]
+    fn test_not_followed() {
+        let response = redirect(StatusCode::FOUND, "https://evil.local/");
+        for policy in &[RedirectPolicy::None, RedirectPolicy::SameOrigin(10)] {
+            assert!(follow(*policy, request(Method::GET), &response).is_none());
+        }
+        let response = redirect(StatusCode::NOT_MODIFIED, "/");
+        assert!(follow(RedirectPolicy::default(), request(Method::GET), &response).is_none());
+    }
+}
@@ -0,0 +1,498 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Retrying failed requests.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::{Method, Response, StatusCode};
+
+use super::error::retry_after;
+use super::{Error, ErrorKind};
+
+/// Number of requests whose retry allowance can be accumulated by a budget.
+const BUDGET_WINDOW: f64 = 100.0;
+
+/// How to randomize the delay between retries.
+///
+/// Randomization prevents clients that failed at the same time from retrying at the same time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Jitter {
+    /// Use the exponential delay as it is.
+    None,
+    /// A random delay between zero and the exponential delay (the default).
+    Full,
+    /// A random delay between half of the exponential delay and the whole of it.
+    Equal,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for Jitter {
+    fn default() -> Jitter {
+        Jitter::Full
+    }
+}
+
+/// A random number in the range `[0, 1)`.
+fn random_fraction() -> f64 {
+    // Every RandomState has different keys, so the hash of nothing is random enough for jitter.
+    let value = RandomState::new().build_hasher().finish();
+    (value >> 11) as f64 / (1u64 << 53) as f64
+}
+
+impl Jitter {
+    fn apply(self, delay: Duration) -> Duration {
+        match self {
+            Jitter::None => delay,
+            Jitter::Full => delay.mul_f64(random_fraction()),
+            Jitter::Equal => delay / 2 + (delay / 2).mul_f64(random_fraction()),
+        }
+    }
+}
+
+/// A limit on the fraction of requests that are retried.
+///
+/// When a service is struggling, retries multiply the load on it. A budget stops retrying once
+/// retries exceed the given fraction of requests (on top of a small number of retries that are
+/// always allowed). Clones of a budget share it, so a budget set on a
+/// [Session](struct.Session.html) applies to all its clones:
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use osauth::{RetryBudget, RetryPolicy};
+///
+/// let session = osauth::Session::from_env()
+///     .await?
+///     // Retry at most 10% of requests, up to 3 times each.
+///     .with_retry_policy(RetryPolicy::new(3).with_budget(RetryBudget::new(0.1, 10)));
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetryBudget {
+    ratio: f64,
+    min_retries: usize,
+    balance: Arc<Mutex<f64>>,
+}
+
+impl RetryBudget {
+    /// Create a budget allowing the given fraction of requests to be retried.
+    ///
+    /// Additionally, `min_retries` retries are allowed regardless of the number of requests,
+    /// so that clients that send few requests can still retry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ratio` is not between 0 and 1.
+    pub fn new(ratio: f64, min_retries: usize) -> RetryBudget {
+        assert!(
+            (0.0..=1.0).contains(&ratio),
+            "Retry budget ratio must be between 0 and 1, got {}",
+            ratio
+        );
+        RetryBudget {
+            ratio,
+            min_retries,
+            balance: Arc::new(Mutex::new(min_retries as f64)),
+        }
+    }
+
+    /// Fraction of requests that may be retried.
+    #[inline]
+    pub fn ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    /// Number of retries allowed regardless of the number of requests.
+    #[inline]
+    pub fn min_retries(&self) -> usize {
+        self.min_retries
+    }
+
+    /// Record a new request.
+    pub(crate) fn deposit(&self) {
+        let limit = self.min_retries as f64 + self.ratio * BUDGET_WINDOW;
+        let mut balance = self.balance.lock().expect("Retry budget lock poisoned");
+        *balance = (*balance + self.ratio).min(limit);
+    }
+
+    /// Try to spend a retry, returns `false` if the budget is exhausted.
+    pub(crate) fn withdraw(&self) -> bool {
+        let mut balance = self.balance.lock().expect("Retry budget lock poisoned");
+        if *balance >= 1.0 {
+            *balance -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// How to retry requests that failed because of transient issues.
+///
+/// Retries are disabled by default. With a retry policy, the following failures are retried
+/// with an exponentially growing, randomized delay:
+/// * HTTP 429 (Too Many Requests) and 503 (Service Unavailable), honoring the `Retry-After`
+///   header if it is present;
+/// * connection failures, HTTP 502 (Bad Gateway) and 504 (Gateway Timeout) for idempotent
+///   requests only (`GET`, `HEAD`, `OPTIONS`, `PUT` and `DELETE`).
+///
+/// Requests with streaming bodies are never retried. Every retry is reported as an
+/// [Event::RequestRetried](enum.Event.html#variant.RequestRetried).
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use std::time::Duration;
+/// use osauth::{Jitter, RetryPolicy};
+///
+/// let session = osauth::Session::from_env().await?.with_retry_policy(
+///     RetryPolicy::new(5)
+///         .with_initial_delay(Duration::from_millis(200))
+///         .with_jitter(Jitter::Equal),
+/// );
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_retries: usize,
+    initial_delay: Duration,
+    max_delay: Duration,
+    backoff: f64,
+    jitter: Jitter,
+    budget: Option<RetryBudget>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy::new(0)
+    }
+}
+
+impl RetryPolicy {
+    /// Create a policy with the given maximum number of retries per request.
+    pub fn new(max_retries: usize) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            backoff: 2.0,
+            jitter: Jitter::default(),
+            budget: None,
+        }
+    }
+
+    /// Multiplier of the delay after each retry.
+    #[inline]
+    pub fn backoff(&self) -> f64 {
+        self.backoff
+    }
+
+    /// Retry budget (if any).
+    #[inline]
+    pub fn budget(&self) -> Option<&RetryBudget> {
+        self.budget.as_ref()
+    }
+
+    /// Delay before the first retry.
+    #[inline]
+    pub fn initial_delay(&self) -> Duration {
+        self.initial_delay
+    }
+
+    /// Randomization of the delay.
+    #[inline]
+    pub fn jitter(&self) -> Jitter {
+        self.jitter
+    }
+
+    /// Maximum delay between retries.
+    #[inline]
+    pub fn max_delay(&self) -> Duration {
+        self.max_delay
+    }
+
+    /// Maximum number of retries per request.
+    #[inline]
+    pub fn max_retries(&self) -> usize {
+        self.max_retries
+    }
+
+    /// Set the multiplier of the delay after each retry (2.0 by default).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is less than 1.0.
+    #[inline]
+    pub fn set_backoff(&mut self, value: f64) {
+        assert!(value >= 1.0, "Backoff must be at least 1.0");
+        self.backoff = value;
+    }
+
+    /// Set a retry budget shared by all requests using this policy.
+    #[inline]
+    pub fn set_budget(&mut self, value: impl Into<Option<RetryBudget>>) {
+        self.budget = value.into();
+    }
+
+    /// Set the delay before the first retry (500 ms by default).
+    #[inline]
+    pub fn set_initial_delay(&mut self, value: Duration) {
+        self.initial_delay = value;
+    }
+
+    /// Set the randomization of the delay (full jitter by default).
+    #[inline]
+    pub fn set_jitter(&mut self, value: Jitter) {
+        self.jitter = value;
+    }
+
+    /// Set the maximum delay between retries (30 seconds by default).
+    ///
+    /// A request is not retried if the server asks to wait longer than that.
+    #[inline]
+    pub fn set_max_delay(&mut self, value: Duration) {
+        self.max_delay = value;
+    }
+
+    /// Set the multiplier of the delay after each retry (2.0 by default).
+    #[inline]
+    pub fn with_backoff(mut self, value: f64) -> Self {
+        self.set_backoff(value);
+        self
+    }
+
+    /// Set a retry budget shared by all requests using this policy.
+    #[inline]
+    pub fn with_budget(mut self, value: impl Into<Option<RetryBudget>>) -> Self {
+        self.set_budget(value);
+        self
+    }
+
+    /// Set the delay before the first retry (500 ms by default).
+    #[inline]
+    pub fn with_initial_delay(mut self, value: Duration) -> Self {
+        self.set_initial_delay(value);
+        self
+    }
+
+    /// Set the randomization of the delay (full jitter by default).
+    #[inline]
+    pub fn with_jitter(mut self, value: Jitter) -> Self {
+        self.set_jitter(value);
+        self
+    }
+
+    /// Set the maximum delay between retries (30 seconds by default).
+    #[inline]
+    pub fn with_max_delay(mut self, value: Duration) -> Self {
+        self.set_max_delay(value);
+        self
+    }
+
+    /// Whether retries are enabled.
+    #[inline]
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.max_retries > 0
+    }
+
+    /// Record a new request in the budget.
+    pub(crate) fn start(&self) {
+        if let Some(ref budget) = self.budget {
+            budget.deposit();
+        }
+    }
+
+    /// Delay before the given retry (starting with 1) if the request should be retried.
+    ///
+    /// Does not take the budget into account.
+    pub(crate) fn delay(
+        &self,
+        attempt: usize,
+        method: &Method,
+        result: &Result<Response, Error>,
+    ) -> Option<Duration> {
+        let idempotent = matches!(
+            *method,
+            Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE
+        );
+        self.delay_with(attempt, idempotent, result)
+    }
+
+    /// Delay before the given retry of a request that may or may not be repeated safely.
+    pub(crate) fn delay_with(
+        &self,
+        attempt: usize,
+        idempotent: bool,
+        result: &Result<Response, Error>,
+    ) -> Option<Duration> {
+        if attempt > self.max_retries {
+            return None;
+        }
+
+        let requested = match result {
+            Ok(response) => match response.status() {
+                StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+                    retry_after(response.status(), response.headers())
+                }
+                StatusCode::BAD_GATEWAY | StatusCode::GATEWAY_TIMEOUT if idempotent => None,
+                _ => return None,
+            },
+            Err(err) if err.kind() == ErrorKind::ProtocolError && idempotent => None,
+            Err(_) => return None,
+        };
+
+        match requested {
+            Some(delay) if delay > self.max_delay => None,
+            Some(delay) => Some(delay),
+            None => {
+                let exponent = (attempt - 1).min(i32::MAX as usize) as i32;
+                let factor = self.backoff.powi(exponent);
+                let max = self.max_delay.as_secs_f64();
+                let delay = (self.initial_delay.as_secs_f64() * factor).min(max);
+                Some(self.jitter.apply(Duration::from_secs_f64(delay)))
+            }
+        }
+    }
+
+    /// Try to spend a retry from the budget.
+    pub(crate) fn allow(&self) -> bool {
+        self.budget.as_ref().map(|b| b.withdraw()).unwrap_or(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use reqwest::{Method, Response, StatusCode};
+
+    use super::{Jitter, RetryBudget, RetryPolicy};
+    use crate::{Error, ErrorKind};
+
+    fn response(status: StatusCode, retry_after: Option<&str>) -> Result<Response, Error> {
+        let mut builder = http::Response::builder().status(status);
+        if let Some(value) = retry_after {
+            builder = builder.header("retry-after", value);
+        }
+        Ok(builder.body("").unwrap().into())
+    }
+
+    #[test]
+    fn test_jitter() {
+        let delay = Duration::from_secs(10);
+        assert_eq!(Jitter::None.apply(delay), delay);
+        for _ in 0..100 {
+            assert!(Jitter::Full.apply(delay) <= delay);
+            let equal = Jitter::Equal.apply(delay);
+            assert!(equal >= delay / 2 && equal <= delay);
+        }
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy::new(3)
+            .with_jitter(Jitter::None)
+            .with_initial_delay(Duration::from_secs(1))
+            .with_max_delay(Duration::from_secs(3));
+        let unavailable = response(StatusCode::SERVICE_UNAVAILABLE, None);
+        assert_eq!(
+            policy.delay(1, &Method::POST, &unavailable),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            policy.delay(2, &Method::POST, &unavailable),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(
+            policy.delay(3, &Method::POST, &unavailable),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(policy.delay(4, &Method::POST, &unavailable), None);
+
+        let bad_gateway = response(StatusCode::BAD_GATEWAY, None);
+        assert!(policy.delay(1, &Method::GET, &bad_gateway).is_some());
+        assert!(policy.delay(1, &Method::POST, &bad_gateway).is_none());
+
+        let error = Err(Error::new(ErrorKind::ProtocolError, "connection reset"));
+        assert!(policy.delay(1, &Method::DELETE, &error).is_some());
+        assert!(policy.delay(1, &Method::PATCH, &error).is_none());
+
+        let not_found = response(StatusCode::NOT_FOUND, None);
+        assert!(policy.delay(1, &Method::GET, &not_found).is_none());
+        let ok = response(StatusCode::OK, None);
+        assert!(policy.delay(1, &Method::GET, &ok).is_none());
+    }
+
+    #[test]
+    fn test_delay_retry_after() {
+        let policy = RetryPolicy::new(3).with_max_delay(Duration::from_secs(10));
+        let limited = response(StatusCode::TOO_MANY_REQUESTS, Some("5"));
+        assert_eq!(
+            policy.delay(1, &Method::GET, &limited),
+            Some(Duration::from_secs(5))
+        );
+        let limited = response(StatusCode::TOO_MANY_REQUESTS, Some("60"));
+        assert_eq!(policy.delay(1, &Method::GET, &limited), None);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let policy = RetryPolicy::default();
+        assert!(!policy.is_enabled());
+        let unavailable = response(StatusCode::SERVICE_UNAVAILABLE, None);
+        assert_eq!(policy.delay(1, &Method::GET, &unavailable), None);
+    }
+
+    #[test]
+    fn test_budget() {
+        let policy = RetryPolicy::new(3).with_budget(RetryBudget::new(0.5, 1));
+        policy.start();
+        // 1 (minimum) + 0.5 (request)
+        assert!(policy.allow());
+        assert!(!policy.allow());
+        policy.start();
+        assert!(policy.allow());
+        assert!(!policy.allow());
+
+        // The balance is shared by clones.
+        let clone = policy.clone();
+        clone.start();
+        clone.start();
+        assert!(policy.allow());
+
+        // The balance is limited.
+        for _ in 0..1000 {
+            policy.start();
+        }
+        for _ in 0..51 {
+            assert!(policy.allow());
+        }
+        assert!(!policy.allow());
+
+        assert!(RetryPolicy::new(1).allow());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_budget_invalid_ratio() {
+        let _ = RetryBudget::new(1.5, 0);
+    }
+}
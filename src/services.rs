@@ -64,6 +64,7 @@ macro_rules! service {
         #[non_exhaustive]
         pub struct $cls;
 
+        #[allow(clippy::new_without_default)]
         impl $cls {
             /// Create a new service type.
             pub const fn new() -> $cls {
@@ -98,6 +99,7 @@ macro_rules! service {
         #[non_exhaustive]
         pub struct $cls;
 
+        #[allow(clippy::new_without_default)]
         impl $cls {
             /// Create a new service type.
             pub const fn new() -> $cls {
@@ -137,6 +139,11 @@ pub struct GenericService {
 #[non_exhaustive]
 pub struct ComputeService;
 
+/// Identity service (v3).
+#[derive(Copy, Clone, Debug)]
+#[non_exhaustive]
+pub struct IdentityService;
+
 service! {
     #[doc = "Bare Metal service."]
     BAREMETAL: BareMetalService -> "baremetal", header "x-openstack-ironic-api-version"
@@ -187,6 +194,7 @@ impl ServiceType for GenericService {
     }
 }
 
+#[allow(clippy::new_without_default)]
 impl ComputeService {
     /// Create a Compute service type.
     pub const fn new() -> ComputeService {
@@ -214,5 +222,26 @@ impl VersionedService for ComputeService {
     }
 }
 
+#[allow(clippy::new_without_default)]
+impl IdentityService {
+    /// Create an Identity service type.
+    pub const fn new() -> IdentityService {
+        IdentityService
+    }
+}
+
+impl ServiceType for IdentityService {
+    fn catalog_type(&self) -> &'static str {
+        "identity"
+    }
+
+    fn major_version_supported(&self, version: ApiVersion) -> bool {
+        version.0 == 3
+    }
+}
+
 /// Compute service.
 pub const COMPUTE: ComputeService = ComputeService::new();
+
+/// Identity service (v3).
+pub const IDENTITY: IdentityService = IdentityService::new();
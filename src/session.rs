@@ -15,30 +15,50 @@
 //! Session structure definition.
 
 use std::collections::HashMap;
+use std::mem;
 use std::sync::Arc;
 use std::time::Duration;
 
 #[cfg(feature = "stream")]
 use async_trait::async_trait;
+use chrono::Utc;
 #[cfg(feature = "stream")]
-use futures::Stream;
-use http::header::{HeaderMap, HeaderName, HeaderValue};
+use futures::stream::{self, Stream, StreamExt};
+use http::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, HOST, RANGE};
 use http::Error as HttpError;
-use reqwest::{Body, Client, Method, Response, Url};
+use reqwest::{Body, Client, Method, Request, Response, Url};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use static_assertions::assert_impl_all;
+#[cfg(feature = "tracing")]
+use tracing::Instrument;
 
+use super::audit::AuditSink;
 use super::cache::EndpointCache;
-use super::client::{self, AuthenticatedClient, RequestBuilder, NO_PATH};
+use super::catalog;
+use super::client::{self, AuthenticatedClient, RequestBuilder, Transport, NO_PATH};
+use super::coalesce::{self, Coalescer};
+use super::events::EventHandler;
+#[cfg(feature = "tracing")]
+use super::instrument;
+use super::jsonpatch::{JSON_PATCH_CONTENT_TYPE, MERGE_PATCH_CONTENT_TYPE};
 use super::loading::CloudConfig;
+use super::metrics::MetricsSink;
+use super::probe;
 use super::protocol::ServiceInfo;
+use super::redirect;
 use super::services::{ServiceType, VersionedService};
 use super::url as url_utils;
-use super::{Adapter, ApiVersion, AuthType, EndpointFilters, Error, InterfaceType};
+use super::{
+    Adapter, ApiVersion, AuthType, ByteRange, CatalogEntry, EndpointFilters, Error, InterfaceType,
+    JsonPatchOp, Precondition, RedirectPolicy, RetryPolicy, ServiceProbe, SessionSnapshot,
+    SessionStats,
+};
 
 #[cfg(feature = "stream")]
-use super::stream::{paginated, FetchNext, PaginatedResource};
+use super::stream::{json_items, paginated, FetchNext, PaginatedResource};
+#[cfg(feature = "websocket")]
+use super::websocket::{self, WebSocket};
 
 /// An OpenStack API session.
 ///
@@ -49,10 +69,28 @@ use super::stream::{paginated, FetchNext, PaginatedResource};
 ///
 /// All clones of one session share the same authentication and endpoint cache. Use
 /// [with_auth_type](#method.with_auth_type) to detach a session.
-#[derive(Debug, Clone)]
-pub struct Session {
-    client: AuthenticatedClient,
+///
+/// Version discovery documents are additionally cached for all sessions in the process according
+/// to their `Cache-Control` header (for 5 minutes if it is missing, for one day at most). Use
+/// [clear_discovery_cache](fn.clear_discovery_cache.html) to drop them.
+///
+/// The authentication type is dynamic by default. Use [new_typed](#method.new_typed) to create
+/// a session with a concrete authentication type, avoiding dynamic dispatch on every request.
+#[derive(Debug)]
+pub struct Session<A: ?Sized = dyn AuthType> {
+    client: AuthenticatedClient<A>,
     endpoint_cache: Arc<EndpointCache>,
+    coalescer: Option<Arc<Coalescer>>,
+}
+
+impl<A: ?Sized> Clone for Session<A> {
+    fn clone(&self) -> Session<A> {
+        Session {
+            client: self.client.clone(),
+            endpoint_cache: Arc::clone(&self.endpoint_cache),
+            coalescer: self.coalescer.clone(),
+        }
+    }
 }
 
 assert_impl_all!(Session: Sync, Send);
@@ -62,20 +100,36 @@ impl Session {
     ///
     /// The resulting session will use the default endpoint interface (usually, public).
     pub async fn new<Auth: AuthType + 'static>(auth_type: Auth) -> Result<Session, Error> {
-        Session::new_with_client(Client::new(), auth_type).await
+        Session::new_with_client(redirect::default_client(), auth_type).await
     }
 
-    /// Create a new session with a given authenticated client.
-    pub fn new_with_authenticated_client(client: AuthenticatedClient) -> Session {
-        Session {
-            client,
-            endpoint_cache: Arc::new(EndpointCache::new()),
-        }
+    /// Create a new session with a given authentication plugin without authenticating.
+    ///
+    /// The authentication happens on the first request or on an explicit call to
+    /// [refresh](#method.refresh).
+    #[inline]
+    pub fn new_lazy<Auth: AuthType + 'static>(auth_type: Auth) -> Session {
+        Session::new_lazy_with_client(redirect::default_client(), auth_type)
+    }
+
+    /// Create a new session with a given authentication plugin and an HTTP client without
+    /// authenticating.
+    ///
+    /// The authentication happens on the first request or on an explicit call to
+    /// [refresh](#method.refresh).
+    #[inline]
+    pub fn new_lazy_with_client<Auth: AuthType + 'static>(
+        client: Client,
+        auth_type: Auth,
+    ) -> Session {
+        Session::new_with_authenticated_client(AuthenticatedClient::new_lazy(client, auth_type))
     }
 
     /// Create a new session with a given authentication plugin and an HTTP client.
     ///
     /// The resulting session will use the default endpoint interface (usually, public).
+    ///
+    /// The client should not follow redirects, see [RedirectPolicy](enum.RedirectPolicy.html).
     pub async fn new_with_client<Auth: AuthType + 'static>(
         client: Client,
         auth_type: Auth,
@@ -94,18 +148,16 @@ impl Session {
     /// The `cloud_name` argument is a name of the cloud entry to use.
     ///
     /// Supported features are:
-    /// 1. Password and HTTP basic authentication, as well as no authentication.
-    /// 2. Users, projects and domains by name.
+    /// 1. Password, token, application credential and HTTP basic authentication, as well as no
+    ///    authentication.
+    /// 2. Users, projects and domains by name or ID.
     /// 3. Region names (for password authentication).
     /// 4. Custom TLS CA certificates.
     /// 5. Profiles from `clouds-public.yaml`.
     /// 6. Credentials from `secure.yaml`.
-    ///
-    /// A non-exhaustive list of features that are not currently supported:
-    /// 1. Users, projects and domains by ID.
-    /// 2. Adapter options, such as interfaces, default API versions and endpoint overrides.
-    /// 3. Other authentication methods.
-    /// 4. Identity v2.
+    /// 7. Interfaces and endpoint overrides (`<service>_endpoint_override`).
+    /// 8. Default API versions (`<service>_api_version`) of the Compute and Bare Metal services.
+    /// 9. Identity v2 password authentication (`v2password`) with the `identity-v2` feature.
     #[inline]
     pub async fn from_config<S: AsRef<str>>(cloud_name: S) -> Result<Session, Error> {
         CloudConfig::from_config(cloud_name)?.create_session().await
@@ -113,18 +165,35 @@ impl Session {
 
     /// Create a `Session` from environment variables.
     ///
-    /// Supports the following authentication types: `password`, `v3token`, `http_basic` and `noop`.
+    /// Supports the following authentication types: `password`, `v3token`,
+    /// `v3applicationcredential`, `http_basic` and `noop`.
+    /// With the `identity-v2` feature, `v2password` is also supported and is used instead of
+    /// `password` if the Identity service does not support API v3.
     ///
     /// Understands the following variables:
     /// * `OS_CLOUD` (equivalent to calling [from_config](#method.from_config) with the given cloud).
-    /// * `OS_AUTH_TYPE` (defaults to `v3token` if `OS_TOKEN` is provided otherwise to `password`).
-    /// * `OS_AUTH_URL` for `password` and `v3token`, `OS_ENDPOINT` for `http_basic` and `noop`.
-    /// * `OS_USERNAME` and `OS_PASSWORD`.
-    /// * `OS_PROJECT_NAME` or `OS_PROJECT_ID`.
-    /// * `OS_USER_DOMAIN_NAME` or `OS_USER_DOMAIN_ID` (defaults to `Default`).
+    /// * `OS_AUTH_TYPE` (defaults to `v3token` if `OS_TOKEN` is provided,
+    ///   `v3applicationcredential` if `OS_APPLICATION_CREDENTIAL_SECRET` is provided, otherwise
+    ///   to `password`).
+    /// * `OS_AUTH_URL` for Identity authentication, `OS_ENDPOINT` for `http_basic` and `noop`.
+    /// * `OS_USERNAME` or `OS_USER_ID` and `OS_PASSWORD`.
+    /// * `OS_PROJECT_NAME` or `OS_PROJECT_ID` (the project domain is not used with the latter).
+    /// * `OS_DOMAIN_NAME` or `OS_DOMAIN_ID` for a domain-scoped token (ignored if a project is set).
+    /// * `OS_USER_DOMAIN_NAME` or `OS_USER_DOMAIN_ID`.
     /// * `OS_PROJECT_DOMAIN_NAME` or `OS_PROJECT_DOMAIN_ID`.
+    /// * `OS_DEFAULT_DOMAIN` - ID of the user and project domain if they are not set explicitly
+    ///   (otherwise the domain named `Default` is used).
     /// * `OS_TOKEN` (for `v3token`).
-    /// * `OS_REGION_NAME` and `OS_INTERFACE`.
+    /// * `OS_APPLICATION_CREDENTIAL_SECRET` and `OS_APPLICATION_CREDENTIAL_ID` or
+    ///   `OS_APPLICATION_CREDENTIAL_NAME` (for `v3applicationcredential`, the latter
+    ///   also requires `OS_USERNAME` or `OS_USER_ID`).
+    /// * `OS_REGION_NAME` and `OS_INTERFACE` (the latter may be a comma-separated list of
+    ///   interfaces in the order of preference, e.g. `internal,public`).
+    /// * `OS_<SERVICE>_ENDPOINT_OVERRIDE` to override the endpoint of a service, e.g.
+    ///   `OS_BAREMETAL_ENDPOINT_OVERRIDE` (also applies together with `OS_CLOUD`).
+    /// * `OS_COMPUTE_API_VERSION` and `OS_BAREMETAL_API_VERSION` to set the
+    ///   [default API version](#method.set_default_api_version) (major versions like `2` are
+    ///   ignored).
     #[inline]
     pub async fn from_env() -> Result<Session, Error> {
         CloudConfig::from_env()?.create_session().await
@@ -173,15 +242,109 @@ impl Session {
         Adapter::from_session(self, service)
     }
 
+    /// Set a new authentication for this `Session`.
+    ///
+    /// This call clears the cached service information for this `Session`.
+    /// It does not, however, affect clones of this `Session`.
+    #[inline]
+    pub fn set_auth_type<Auth: AuthType + 'static>(&mut self, auth_type: Auth) {
+        self.reset_cache();
+        self.client.set_auth_type(auth_type);
+    }
+
+    /// Convert this session into one using the given authentication.
+    #[inline]
+    pub fn with_auth_type<Auth: AuthType + 'static>(mut self, auth_method: Auth) -> Session {
+        self.set_auth_type(auth_method);
+        self
+    }
+
+    /// Create a copy of this `Session` with its own authentication state.
+    ///
+    /// Unlike clones, the resulting `Session` can be refreshed without affecting this one (and
+    /// vice versa). It starts with the current token (if any), endpoint filters and overrides,
+    /// but with empty cached service information.
+    ///
+    /// Fails with [InvalidInput](enum.ErrorKind.html#variant.InvalidInput) if the authentication
+    /// type does not support [detaching](trait.AuthType.html#method.detach).
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session = osauth::Session::from_env().await?;
+    /// let mut detached = session.detach()?;
+    /// // Does not affect the token of `session`.
+    /// detached.refresh().await?;
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub fn detach(&self) -> Result<Session, Error> {
+        Ok(Session {
+            client: self.client.detach()?,
+            endpoint_cache: Arc::new((*self.endpoint_cache).clone()),
+            coalescer: self
+                .coalescer
+                .as_ref()
+                .map(|_| Arc::new(Coalescer::default())),
+        })
+    }
+}
+
+impl<A: AuthType> Session<A> {
+    /// Create a new session with a concrete authentication type.
+    pub async fn new_typed(auth_type: A) -> Result<Session<A>, Error> {
+        Ok(Session::new_with_authenticated_client(
+            AuthenticatedClient::new_typed(redirect::default_client(), auth_type).await?,
+        ))
+    }
+
+    /// Create a new session with a concrete authentication type without authenticating.
+    ///
+    /// The authentication happens on the first request or on an explicit call to
+    /// [refresh](#method.refresh).
+    #[inline]
+    pub fn new_typed_lazy(auth_type: A) -> Session<A> {
+        Session::new_with_authenticated_client(AuthenticatedClient::new_typed_lazy(
+            redirect::default_client(),
+            auth_type,
+        ))
+    }
+
+    /// Convert into a session with a dynamic authentication type.
+    ///
+    /// The resulting session shares the authentication and the endpoint cache with this one.
+    #[inline]
+    pub fn into_dyn(self) -> Session
+    where
+        A: 'static,
+    {
+        Session {
+            client: self.client.into_dyn(),
+            endpoint_cache: self.endpoint_cache,
+            coalescer: self.coalescer,
+        }
+    }
+}
+
+impl<A: AuthType + ?Sized> Session<A> {
+    /// Create a new session with a given authenticated client.
+    pub fn new_with_authenticated_client(client: AuthenticatedClient<A>) -> Session<A> {
+        Session {
+            client,
+            endpoint_cache: Arc::new(EndpointCache::new()),
+            coalescer: None,
+        }
+    }
+
     /// Get a reference to the authentication type in use.
     #[inline]
-    pub fn auth_type(&self) -> &dyn AuthType {
+    pub fn auth_type(&self) -> &A {
         self.client.auth_type()
     }
 
     /// Get a reference to the authenticated client in use.
     #[inline]
-    pub fn client(&self) -> &AuthenticatedClient {
+    pub fn client(&self) -> &AuthenticatedClient<A> {
         &self.client
     }
 
@@ -213,12 +376,58 @@ impl Session {
         &mut Arc::make_mut(&mut self.endpoint_cache).clear().overrides
     }
 
+    /// `Host` header overrides in use.
+    #[inline]
+    pub fn endpoint_hosts(&self) -> &HashMap<String, String> {
+        &self.endpoint_cache.hosts
+    }
+
+    /// Modify `Host` header overrides.
+    ///
+    /// This call clears the cached service information for this `Session`.
+    /// It does not, however, affect clones of this `Session`.
+    pub fn endpoint_hosts_mut(&mut self) -> &mut HashMap<String, String> {
+        &mut Arc::make_mut(&mut self.endpoint_cache).clear().hosts
+    }
+
+    /// Whether endpoints are only taken from the endpoint overrides.
+    #[inline]
+    pub fn catalog_disabled(&self) -> bool {
+        self.endpoint_cache.catalog_disabled
+    }
+
+    /// Take endpoints only from the endpoint overrides, never from the service catalog.
+    ///
+    /// Useful for standalone services (e.g. Ironic or Swift) and for tokens without a catalog.
+    /// Requests to services without an endpoint override fail with `EndpointNotFound`.
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// use osauth::services::BAREMETAL;
+    ///
+    /// let session = osauth::Session::new(osauth::NoAuth::new_without_endpoint())
+    ///     .await?
+    ///     .with_endpoint_override(BAREMETAL, "http://127.0.0.1:6385".parse().unwrap())
+    ///     .with_catalog_disabled(true);
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    ///
+    /// This call clears the cached service information for this `Session`.
+    /// It does not, however, affect clones of this `Session`.
+    pub fn set_catalog_disabled(&mut self, value: bool) {
+        Arc::make_mut(&mut self.endpoint_cache)
+            .clear()
+            .catalog_disabled = value;
+    }
+
     /// Update the authentication and purges cached endpoint information.
     ///
     /// # Warning
     ///
     /// Authentication will also be updated for clones of this `Session`, since they share the same
-    /// authentication object.
+    /// authentication object. Use [detach](struct.Session.html#method.detach) to avoid that.
     #[inline]
     pub async fn refresh(&mut self) -> Result<(), Error> {
         self.reset_cache();
@@ -231,14 +440,94 @@ impl Session {
         let _ = Arc::make_mut(&mut self.endpoint_cache).clear();
     }
 
-    /// Set a new authentication for this `Session`.
+    /// Restore the configuration from a snapshot.
     ///
-    /// This call clears the cached service information for this `Session`.
-    /// It does not, however, affect clones of this `Session`.
+    /// Endpoint filters, endpoint overrides, default API versions and discovered service
+    /// information are replaced with the ones from the snapshot. The authentication (including the
+    /// [authentication parameters](struct.SessionSnapshot.html#structfield.auth)) is not changed.
+    ///
+    /// This call does not affect clones of this `Session`.
+    pub fn restore(&mut self, mut snapshot: SessionSnapshot) -> Result<(), Error> {
+        let api_versions = mem::take(&mut snapshot.api_versions);
+        let cache = snapshot.into_cache()?;
+        self.client.restore_api_versions(api_versions)?;
+        self.endpoint_cache = Arc::new(cache);
+        Ok(())
+    }
+
+    /// Take a serializable snapshot of the configuration.
+    ///
+    /// The snapshot can be persisted or sent to another component and applied to a session
+    /// with the same authentication using [restore](#method.restore):
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session = osauth::Session::from_env().await?;
+    /// let snapshot = serde_json::to_string(&session.snapshot().await)?;
+    ///
+    /// let mut other = osauth::Session::from_env().await?;
+    /// other.restore(serde_json::from_str(&snapshot)?)?;
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub async fn snapshot(&self) -> SessionSnapshot {
+        let mut snapshot = SessionSnapshot::from_cache(&self.endpoint_cache).await;
+        snapshot.api_versions = self.client.api_versions_snapshot();
+        snapshot.auth = self.client.auth_snapshot().cloned();
+        snapshot
+    }
+
+    /// Get a snapshot of runtime statistics (requests in flight, error rates, token age).
+    ///
+    /// See [SessionStats](struct.SessionStats.html) for details.
     #[inline]
-    pub fn set_auth_type<Auth: AuthType + 'static>(&mut self, auth_type: Auth) {
-        self.reset_cache();
-        self.client.set_auth_type(auth_type);
+    pub fn stats(&self) -> SessionStats {
+        self.client.stats()
+    }
+
+    /// Stop accepting new requests and wait for the running ones to finish.
+    ///
+    /// Requests that are still running after the timeout are cancelled. Use it for clean
+    /// termination of daemons:
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    ///
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session = osauth::Session::from_env().await?;
+    /// // ... spawn tasks using clones of the session, wait for a signal ...
+    /// if let Err(err) = session.shutdown(Duration::from_secs(30)).await {
+    ///     eprintln!("Unclean shutdown: {}", err);
+    /// }
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    ///
+    /// See [AuthenticatedClient::shutdown](client/struct.AuthenticatedClient.html#method.shutdown)
+    /// for details. The shutdown affects all clones of this session.
+    #[inline]
+    pub async fn shutdown(&self, timeout: Duration) -> Result<(), Error> {
+        self.client.shutdown(timeout).await
+    }
+
+    /// Whether to coalesce identical concurrent GET requests (disabled by default).
+    ///
+    /// When enabled, [fetch](struct.ServiceRequestBuilder.html#method.fetch) calls for GET
+    /// requests with the same URL and headers (including the API version) that overlap in time
+    /// share one HTTP request and its response. This helps when many tasks poll the same
+    /// resources. Results are not cached after the request finishes.
+    ///
+    /// The setting is shared with clones created after this call.
+    pub fn set_coalesce_requests(&mut self, value: bool) {
+        if value != self.coalescer.is_some() {
+            self.coalescer = if value {
+                Some(Arc::new(Coalescer::default()))
+            } else {
+                None
+            };
+        }
     }
 
     /// A convenience call to set an endpoint interface.
@@ -261,6 +550,36 @@ impl Session {
             .insert(service.catalog_type().to_string(), url);
     }
 
+    /// Send the given `Host` header to one service.
+    ///
+    /// Useful together with an endpoint override pointing at an IP-based load balancer or a
+    /// tunnel that routes requests by the `Host` header:
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// use osauth::services::COMPUTE;
+    ///
+    /// let session = osauth::Session::from_env()
+    ///     .await?
+    ///     .with_endpoint_override(COMPUTE, "http://10.0.0.5:8774/v2.1".parse().unwrap())
+    ///     .with_endpoint_host(COMPUTE, "compute.cloud.example.com");
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    ///
+    /// The TLS server name (SNI) and certificate verification still use the host from the
+    /// endpoint URL. For HTTPS, override the endpoint with the expected host name instead and
+    /// map the name to the address in the HTTP client (see `reqwest::ClientBuilder::resolve`).
+    ///
+    /// This call clears the cached service information for this `Session`.
+    /// It does not, however, affect clones of this `Session`.
+    pub fn set_endpoint_host<Svc: ServiceType, T: Into<String>>(&mut self, service: Svc, host: T) {
+        let _ = self
+            .endpoint_hosts_mut()
+            .insert(service.catalog_type().to_string(), host.into());
+    }
+
     /// A convenience call to set a region.
     ///
     /// This call clears the cached service information for this `Session`.
@@ -269,48 +588,512 @@ impl Session {
         self.endpoint_filters_mut().region = Some(region.into());
     }
 
-    /// Convert this session into one using the given authentication.
+    /// Set the maximum number of bytes read from an error response body (64 KiB by default).
     #[inline]
-    pub fn with_auth_type<Auth: AuthType + 'static>(mut self, auth_method: Auth) -> Session {
-        self.set_auth_type(auth_method);
+    pub fn set_max_error_body_size(&mut self, value: usize) {
+        self.client.set_max_error_body_size(value);
+    }
+
+    /// Set the maximum length of error messages built from non-JSON responses.
+    ///
+    /// HTML tags are removed from such messages and the result is truncated to the given number
+    /// of characters (1024 by default).
+    #[inline]
+    pub fn set_max_error_message_length(&mut self, value: usize) {
+        self.client.set_max_error_message_length(value);
+    }
+
+    /// Set the maximum size of a response body read by `fetch` and `get_json` calls.
+    ///
+    /// Larger responses result in an error with kind
+    /// [ResponseTooLarge](enum.ErrorKind.html#variant.ResponseTooLarge). Unlimited by default.
+    #[inline]
+    pub fn set_max_response_size(&mut self, value: impl Into<Option<usize>>) {
+        self.client.set_max_response_size(value);
+    }
+
+    /// Set a threshold for logging slow requests (disabled by default).
+    ///
+    /// Requests that take longer than the threshold to receive response headers are logged
+    /// with a warning that includes the service type. Use
+    /// [LatencyHistograms](struct.LatencyHistograms.html) to track latency per service.
+    #[inline]
+    pub fn set_slow_request_threshold(&mut self, value: impl Into<Option<Duration>>) {
+        self.client.set_slow_request_threshold(value);
+    }
+
+    /// Set the redirect policy.
+    ///
+    /// By default, up to 10 redirects are followed and credentials are removed when a redirect
+    /// leaves the original host. See [RedirectPolicy](enum.RedirectPolicy.html) for details.
+    #[inline]
+    pub fn set_redirect_policy(&mut self, value: RedirectPolicy) {
+        self.client.set_redirect_policy(value);
+    }
+
+    /// Set the retry policy.
+    ///
+    /// Retries are disabled by default. See [RetryPolicy](struct.RetryPolicy.html) for details.
+    #[inline]
+    pub fn set_retry_policy(&mut self, value: RetryPolicy) {
+        self.client.set_retry_policy(value);
+    }
+
+    /// Set the application name for the User-Agent header.
+    ///
+    /// Allows operators to identify which tool generated which API calls. The User-Agent has the
+    /// form `<app_name>/<app_version> <components> osauth/<version>` and is not sent unless an
+    /// application name or an additional component is set. It is not used for requests made by
+    /// authentication types (e.g. token requests).
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session = osauth::Session::from_env()
+    ///     .await?
+    ///     .with_app_name("my-tool")
+    ///     .with_app_version("1.0.0")
+    ///     .with_user_agent_component("my-plugin", "0.3.1");
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    #[inline]
+    pub fn set_app_name<S: AsRef<str>>(&mut self, value: S) {
+        self.client.set_app_name(value);
+    }
+
+    /// Set the application version for the User-Agent header.
+    #[inline]
+    pub fn set_app_version<S: AsRef<str>>(&mut self, value: S) {
+        self.client.set_app_version(value);
+    }
+
+    /// Add a component (e.g. a library or a plugin) to the User-Agent header.
+    #[inline]
+    pub fn add_user_agent_component<N: AsRef<str>, V: AsRef<str>>(&mut self, name: N, version: V) {
+        self.client.add_user_agent_component(name, version);
+    }
+
+    /// Set the default `Accept` header for requests to the given service type.
+    ///
+    /// Only used when the request has no `Accept` header and does not expect JSON (requests
+    /// using `fetch` and `get_json` accept `application/json` by default):
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// use reqwest::header::HeaderValue;
+    ///
+    /// let session = osauth::Session::from_env()
+    ///     .await?
+    ///     .with_default_accept("object-store", HeaderValue::from_static("text/plain"));
+    /// let listing = session
+    ///     .get(osauth::services::OBJECT_STORAGE, &["my-container"])
+    ///     .send()
+    ///     .await?
+    ///     .text()
+    ///     .await?;
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    #[inline]
+    pub fn set_default_accept<T: Into<String>>(&mut self, service_type: T, value: HeaderValue) {
+        self.client.set_default_accept(service_type, value);
+    }
+
+    /// Default API version for requests to the given service (if any).
+    #[inline]
+    pub fn default_api_version<Srv: ServiceType>(&self, service: Srv) -> Option<ApiVersion> {
+        self.client.default_api_version(service.catalog_type())
+    }
+
+    /// Set the default API version for requests to the given service.
+    ///
+    /// Used by requests that do not call
+    /// [api_version](struct.ServiceRequestBuilder.html#method.api_version) explicitly:
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// use osauth::services::BAREMETAL;
+    ///
+    /// let session = osauth::Session::from_env()
+    ///     .await?
+    ///     .with_default_api_version(BAREMETAL, (1, 69));
+    /// let nodes: serde_json::Value = session.get_json(BAREMETAL, &["nodes"]).await?;
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    #[inline]
+    pub fn set_default_api_version<Srv, V>(&mut self, service: Srv, version: V)
+    where
+        Srv: VersionedService,
+        V: Into<ApiVersion>,
+    {
+        self.client.set_default_api_version(service, version.into());
+    }
+
+    /// Set a custom transport for sending authenticated requests.
+    ///
+    /// See [Transport](client/trait.Transport.html) for details.
+    #[inline]
+    pub fn set_transport<T: Transport + 'static>(&mut self, transport: T) {
+        self.client.set_transport(transport);
+    }
+
+    /// Send authenticated requests through a `reqwest-middleware` stack.
+    ///
+    /// The middleware is used as a [transport](#method.set_transport), the requests to the
+    /// Identity service made by the authentication type do not go through it.
+    ///
+    /// Available with the `reqwest-middleware` feature.
+    #[cfg(feature = "reqwest-middleware")]
+    #[inline]
+    pub fn set_middleware(&mut self, client: reqwest_middleware::ClientWithMiddleware) {
+        self.set_transport(client);
+    }
+
+    /// Set a sink for the audit log of outgoing requests.
+    ///
+    /// See [AuditSink](trait.AuditSink.html) for details.
+    #[inline]
+    pub fn set_audit_sink<T: AuditSink + 'static>(&mut self, sink: T) {
+        self.client.set_audit_sink(sink);
+    }
+
+    /// Set a sink for request metrics.
+    ///
+    /// Authentication metrics are reported by the authentication type and require setting a sink
+    /// on it, e.g. [Password::set_metrics_sink](identity/struct.Password.html#method.set_metrics_sink).
+    /// See [MetricsSink](trait.MetricsSink.html) for details.
+    #[inline]
+    pub fn set_metrics_sink<T: MetricsSink + 'static>(&mut self, sink: T) {
+        self.client.set_metrics_sink(sink);
+    }
+
+    /// Set a handler for lifecycle events.
+    ///
+    /// Token events are emitted by the authentication type and require setting a handler
+    /// on it, e.g. [Password::set_event_handler](identity/struct.Password.html#method.set_event_handler).
+    /// See [Event](enum.Event.html) for the list of events.
+    #[inline]
+    pub fn set_event_handler<H: EventHandler + 'static>(&mut self, handler: H) {
+        self.client.set_event_handler(handler);
+    }
+
+    /// Convert this session into one that coalesces identical concurrent GET requests.
+    #[inline]
+    pub fn with_coalesce_requests(mut self, value: bool) -> Session<A> {
+        self.set_coalesce_requests(value);
         self
     }
 
     /// Convert this session into one using the given endpoint filters.
     #[inline]
-    pub fn with_endpoint_filters(mut self, endpoint_filters: EndpointFilters) -> Session {
+    pub fn with_endpoint_filters(mut self, endpoint_filters: EndpointFilters) -> Session<A> {
         *self.endpoint_filters_mut() = endpoint_filters;
         self
     }
 
     /// Convert this session into one using the given endpoint filters.
     #[inline]
-    pub fn with_endpoint_interface(mut self, endpoint_interface: InterfaceType) -> Session {
+    pub fn with_endpoint_interface(mut self, endpoint_interface: InterfaceType) -> Session<A> {
         self.set_endpoint_interface(endpoint_interface);
         self
     }
 
     /// Convert this session into one using the given endpoint override for the given service.
     #[inline]
-    pub fn with_endpoint_override<Srv: ServiceType>(mut self, service: Srv, url: Url) -> Session {
+    pub fn with_endpoint_override<Srv: ServiceType>(
+        mut self,
+        service: Srv,
+        url: Url,
+    ) -> Session<A> {
         self.set_endpoint_override(service, url);
         self
     }
 
+    /// Convert this session into one sending the given `Host` header to the given service.
+    #[inline]
+    pub fn with_endpoint_host<Svc: ServiceType, T: Into<String>>(
+        mut self,
+        service: Svc,
+        host: T,
+    ) -> Session<A> {
+        self.set_endpoint_host(service, host);
+        self
+    }
+
+    /// Convert this session into one taking endpoints only from the endpoint overrides.
+    #[inline]
+    pub fn with_catalog_disabled(mut self, value: bool) -> Session<A> {
+        self.set_catalog_disabled(value);
+        self
+    }
+
     /// Convert this session into one using the given endpoint overrides.
     #[inline]
-    pub fn with_endpoint_overrides(mut self, endpoint_overrides: HashMap<String, Url>) -> Session {
+    pub fn with_endpoint_overrides(
+        mut self,
+        endpoint_overrides: HashMap<String, Url>,
+    ) -> Session<A> {
         *self.endpoint_overrides_mut() = endpoint_overrides;
         self
     }
 
+    /// Convert this session into one using the given maximum size of error bodies.
+    #[inline]
+    pub fn with_max_error_body_size(mut self, value: usize) -> Session<A> {
+        self.set_max_error_body_size(value);
+        self
+    }
+
+    /// Convert this session into one using the given maximum length of error messages.
+    #[inline]
+    pub fn with_max_error_message_length(mut self, value: usize) -> Session<A> {
+        self.set_max_error_message_length(value);
+        self
+    }
+
+    /// Convert this session into one using the given maximum size of response bodies.
+    #[inline]
+    pub fn with_max_response_size(mut self, value: impl Into<Option<usize>>) -> Session<A> {
+        self.set_max_response_size(value);
+        self
+    }
+
+    /// Convert this session into one logging requests slower than the threshold.
+    #[inline]
+    pub fn with_slow_request_threshold(mut self, value: impl Into<Option<Duration>>) -> Session<A> {
+        self.set_slow_request_threshold(value);
+        self
+    }
+
+    /// Convert this session into one using the given redirect policy.
+    #[inline]
+    pub fn with_redirect_policy(mut self, value: RedirectPolicy) -> Session<A> {
+        self.set_redirect_policy(value);
+        self
+    }
+
+    /// Convert this session into one using the given retry policy.
+    #[inline]
+    pub fn with_retry_policy(mut self, value: RetryPolicy) -> Session<A> {
+        self.set_retry_policy(value);
+        self
+    }
+
+    /// Convert this session into one using the given application name in the User-Agent.
+    #[inline]
+    pub fn with_app_name<S: AsRef<str>>(mut self, value: S) -> Session<A> {
+        self.set_app_name(value);
+        self
+    }
+
+    /// Convert this session into one using the given application version in the User-Agent.
+    #[inline]
+    pub fn with_app_version<S: AsRef<str>>(mut self, value: S) -> Session<A> {
+        self.set_app_version(value);
+        self
+    }
+
+    /// Convert this session into one with an additional User-Agent component.
+    #[inline]
+    pub fn with_user_agent_component<N: AsRef<str>, V: AsRef<str>>(
+        mut self,
+        name: N,
+        version: V,
+    ) -> Session<A> {
+        self.add_user_agent_component(name, version);
+        self
+    }
+
+    /// Convert this session into one with the given default `Accept` header for a service type.
+    #[inline]
+    pub fn with_default_accept<T: Into<String>>(
+        mut self,
+        service_type: T,
+        value: HeaderValue,
+    ) -> Session<A> {
+        self.set_default_accept(service_type, value);
+        self
+    }
+
+    /// Convert this session into one with the given default API version for a service.
+    #[inline]
+    pub fn with_default_api_version<Srv, V>(mut self, service: Srv, version: V) -> Session<A>
+    where
+        Srv: VersionedService,
+        V: Into<ApiVersion>,
+    {
+        self.set_default_api_version(service, version);
+        self
+    }
+
+    /// Convert this session into one using the given transport.
+    #[inline]
+    pub fn with_transport<T: Transport + 'static>(mut self, transport: T) -> Session<A> {
+        self.set_transport(transport);
+        self
+    }
+
+    /// Convert this session into one sending authenticated requests through a
+    /// `reqwest-middleware` stack.
+    ///
+    /// Available with the `reqwest-middleware` feature.
+    #[cfg(feature = "reqwest-middleware")]
+    #[inline]
+    pub fn with_middleware(
+        mut self,
+        client: reqwest_middleware::ClientWithMiddleware,
+    ) -> Session<A> {
+        self.set_middleware(client);
+        self
+    }
+
+    /// Convert this session into one using the given audit sink.
+    #[inline]
+    pub fn with_audit_sink<T: AuditSink + 'static>(mut self, sink: T) -> Session<A> {
+        self.set_audit_sink(sink);
+        self
+    }
+
+    /// Convert this session into one using the given event handler.
+    #[inline]
+    pub fn with_event_handler<H: EventHandler + 'static>(mut self, handler: H) -> Session<A> {
+        self.set_event_handler(handler);
+        self
+    }
+
+    /// Convert this session into one using the given metrics sink.
+    #[inline]
+    pub fn with_metrics_sink<T: MetricsSink + 'static>(mut self, sink: T) -> Session<A> {
+        self.set_metrics_sink(sink);
+        self
+    }
+
     /// Convert this session into one using the given region.
     #[inline]
-    pub fn with_region<T: Into<String>>(mut self, region: T) -> Session {
+    pub fn with_region<T: Into<String>>(mut self, region: T) -> Session<A> {
         self.set_region(region);
         self
     }
 
+    /// List all records from the service catalog.
+    ///
+    /// Returns an empty list for authentication types without a service catalog.
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session = osauth::Session::from_env().await?;
+    /// for entry in session.catalog_entries().await? {
+    ///     for endpoint in entry.endpoints {
+    ///         println!(
+    ///             "{} ({}, {}): {}",
+    ///             entry.service_type, endpoint.interface, endpoint.region, endpoint.url
+    ///         );
+    ///     }
+    /// }
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    #[inline]
+    pub async fn catalog_entries(&self) -> Result<Vec<CatalogEntry>, Error> {
+        self.client.get_catalog().await
+    }
+
+    /// Check whether the authenticated user has the given role.
+    ///
+    /// Role names are compared case-insensitively. Always returns `false` for authentication
+    /// types without a notion of roles.
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session = osauth::Session::from_env().await?;
+    /// if !session.has_role("admin").await? {
+    ///     println!("Administrative commands are not available");
+    /// }
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub async fn has_role<S: AsRef<str>>(&self, role: S) -> Result<bool, Error> {
+        let role = role.as_ref();
+        Ok(self
+            .roles()
+            .await?
+            .iter()
+            .any(|item| item.eq_ignore_ascii_case(role)))
+    }
+
+    /// Names of the roles assigned to the authenticated user.
+    ///
+    /// Returns an empty list for authentication types without a notion of roles.
+    #[inline]
+    pub async fn roles(&self) -> Result<Vec<String>, Error> {
+        self.client.get_roles().await
+    }
+
+    /// Get the current authentication token, renewing it if needed.
+    ///
+    /// Useful for passing the token to external tools that cannot use a `Session`.
+    /// Returns `None` for authentication types without a token.
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session = osauth::Session::from_env().await?;
+    /// if let Some(token) = session.get_token().await? {
+    ///     let _ = std::process::Command::new("curl")
+    ///         .arg("-H")
+    ///         .arg(format!("X-Auth-Token: {}", token))
+    ///         .arg("https://cloud.local/compute/v2.1/servers")
+    ///         .status();
+    /// }
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    #[inline]
+    pub async fn get_token(&self) -> Result<Option<String>, Error> {
+        self.client.get_token().await
+    }
+
+    /// List all regions present in the service catalog.
+    ///
+    /// The result is sorted and contains no duplicates. It is empty for authentication types
+    /// without a service catalog.
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session = osauth::Session::from_env().await?;
+    /// for region in session.list_regions().await? {
+    ///     let regional = session.clone().with_region(region);
+    ///     // ... work with the regional session
+    /// }
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub async fn list_regions(&self) -> Result<Vec<String>, Error> {
+        Ok(catalog::regions(&self.catalog_entries().await?))
+    }
+
+    /// List regions in which the given service is present in the service catalog.
+    ///
+    /// The result is sorted and contains no duplicates. It is empty for authentication types
+    /// without a service catalog.
+    pub async fn list_service_regions<Srv: ServiceType>(
+        &self,
+        service: Srv,
+    ) -> Result<Vec<String>, Error> {
+        let catalog_type = service.catalog_type();
+        let entries = self.catalog_entries().await?;
+        Ok(catalog::regions(
+            entries.iter().filter(|x| x.service_type == catalog_type),
+        ))
+    }
+
     /// Get minimum/maximum API (micro)version information.
     ///
     /// Returns `None` if the range cannot be determined, which usually means
@@ -405,6 +1188,33 @@ impl Session {
             .await
     }
 
+    /// Check the availability of the service.
+    ///
+    /// Resolves the endpoint of the service and makes an authenticated GET request to it.
+    /// An error is only returned if the endpoint cannot be resolved, a failure to access
+    /// the service is reported in the resulting [`ServiceProbe`].
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session = osauth::Session::from_env().await?;
+    /// let probe = session.probe(osauth::services::COMPUTE).await?;
+    /// if probe.is_reachable() {
+    ///     println!("Compute responded in {:?}", probe.latency);
+    /// } else {
+    ///     println!("Compute is not available: {:?}", probe.error);
+    /// }
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub async fn probe<Srv>(&self, service: Srv) -> Result<ServiceProbe, Error>
+    where
+        Srv: ServiceType + Send,
+    {
+        let endpoint = self.get_endpoint(service, NO_PATH).await?;
+        Ok(probe::probe(&self.client, endpoint).await)
+    }
+
     /// Check if the service supports the API version.
     pub async fn supports_api_version<Srv>(
         &self,
@@ -456,7 +1266,7 @@ impl Session {
         service: Srv,
         method: Method,
         path: I,
-    ) -> ServiceRequestBuilder<Srv>
+    ) -> ServiceRequestBuilder<Srv, A>
     where
         Srv: ServiceType + Send + Clone,
         I: IntoIterator,
@@ -466,12 +1276,17 @@ impl Session {
         // we build a fake URL. The real URL is fetched in ServiceRequestBuilder::send_unchecked,
         // and the host, port and scheme are replaced. Anyone who invents a better procedure
         // gets a drink from me at the nearest occasion.
-        let url_with_path = url_utils::extend(FAKE_URL.clone(), path.into_iter());
+        let url_with_path = url_utils::extend(FAKE_URL.clone(), path);
 
         ServiceRequestBuilder {
-            inner: self.client.request(method, url_with_path),
+            inner: self
+                .client
+                .request(method, url_with_path)
+                .service_type(service.catalog_type()),
             endpoint_cache: self.endpoint_cache.clone(),
-            service: service.clone(),
+            coalescer: self.coalescer.clone(),
+            service,
+            region: None,
         }
     }
 
@@ -479,7 +1294,7 @@ impl Session {
     ///
     /// See [request](#method.request) for an explanation of the parameters.
     #[inline]
-    pub fn get<Srv, I>(&self, service: Srv, path: I) -> ServiceRequestBuilder<Srv>
+    pub fn get<Srv, I>(&self, service: Srv, path: I) -> ServiceRequestBuilder<Srv, A>
     where
         Srv: ServiceType + Send + Clone,
         I: IntoIterator,
@@ -534,7 +1349,7 @@ impl Session {
     ///
     /// See [request](#method.request) for an explanation of the parameters.
     #[inline]
-    pub fn post<Srv, I>(&self, service: Srv, path: I) -> ServiceRequestBuilder<Srv>
+    pub fn post<Srv, I>(&self, service: Srv, path: I) -> ServiceRequestBuilder<Srv, A>
     where
         Srv: ServiceType + Send + Clone,
         I: IntoIterator,
@@ -543,11 +1358,102 @@ impl Session {
         self.request(service, Method::POST, path)
     }
 
+    /// Start a PATCH request.
+    ///
+    /// See [request](#method.request) for an explanation of the parameters.
+    #[inline]
+    pub fn patch<Srv, I>(&self, service: Srv, path: I) -> ServiceRequestBuilder<Srv, A>
+    where
+        Srv: ServiceType + Send + Clone,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.request(service, Method::PATCH, path)
+    }
+
+    /// Start a PATCH request with a JSON Patch document.
+    ///
+    /// Sets the `application/json-patch+json` content type and serializes the operations.
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// use osauth::JsonPatchOp;
+    ///
+    /// let session = osauth::Session::from_env().await?;
+    /// let node: serde_json::Value = session
+    ///     .patch_json(
+    ///         osauth::services::BAREMETAL,
+    ///         &["nodes", "node-1"],
+    ///         &[
+    ///             JsonPatchOp::add("/extra/owner", "me"),
+    ///             JsonPatchOp::remove("/description"),
+    ///         ],
+    ///     )
+    ///     .api_version((1, 50))
+    ///     .fetch()
+    ///     .await?;
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub fn patch_json<Srv, I>(
+        &self,
+        service: Srv,
+        path: I,
+        patch: &[JsonPatchOp],
+    ) -> ServiceRequestBuilder<Srv, A>
+    where
+        Srv: ServiceType + Send + Clone,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.patch(service, path)
+            .header(CONTENT_TYPE, JSON_PATCH_CONTENT_TYPE)
+            .json(patch)
+    }
+
+    /// Start a PATCH request with a JSON Merge Patch (RFC 7396) document.
+    ///
+    /// Sets the `application/merge-patch+json` content type and serializes the document.
+    /// Fields set to `null` are removed, other fields are replaced or added.
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session = osauth::Session::from_env().await?;
+    /// let project: serde_json::Value = session
+    ///     .patch_merge_json(
+    ///         osauth::services::IDENTITY,
+    ///         &["v3", "projects", "<UUID>"],
+    ///         &serde_json::json!({"project": {"description": "Updated", "extra": null}}),
+    ///     )
+    ///     .fetch()
+    ///     .await?;
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub fn patch_merge_json<Srv, I, T>(
+        &self,
+        service: Srv,
+        path: I,
+        patch: &T,
+    ) -> ServiceRequestBuilder<Srv, A>
+    where
+        Srv: ServiceType + Send + Clone,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        T: Serialize + ?Sized,
+    {
+        self.patch(service, path)
+            .header(CONTENT_TYPE, MERGE_PATCH_CONTENT_TYPE)
+            .json(patch)
+    }
+
     /// Start a PUT request.
     ///
     /// See [request](#method.request) for an explanation of the parameters.
     #[inline]
-    pub fn put<Srv, I>(&self, service: Srv, path: I) -> ServiceRequestBuilder<Srv>
+    pub fn put<Srv, I>(&self, service: Srv, path: I) -> ServiceRequestBuilder<Srv, A>
     where
         Srv: ServiceType + Send + Clone,
         I: IntoIterator,
@@ -560,7 +1466,7 @@ impl Session {
     ///
     /// See [request](#method.request) for an explanation of the parameters.
     #[inline]
-    pub fn delete<Srv, I>(&self, service: Srv, path: I) -> ServiceRequestBuilder<Srv>
+    pub fn delete<Srv, I>(&self, service: Srv, path: I) -> ServiceRequestBuilder<Srv, A>
     where
         Srv: ServiceType + Send + Clone,
         I: IntoIterator,
@@ -569,6 +1475,61 @@ impl Session {
         self.request(service, Method::DELETE, path)
     }
 
+    /// Send requests with at most `max_in_flight` of them running at the same time.
+    ///
+    /// Results are returned in the order of the requests. This is useful for a lot of small
+    /// independent calls (e.g. updating tags or fetching metadata):
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// use futures::pin_mut;
+    /// use futures::stream::TryStreamExt;
+    ///
+    /// let session = osauth::Session::from_env().await?;
+    /// let servers = vec!["<UUID1>", "<UUID2>", "<UUID3>"];
+    /// let requests = servers
+    ///     .iter()
+    ///     .map(|id| session.put(osauth::services::COMPUTE, &["servers", id, "tags", "managed"]));
+    /// let results = session.send_all(requests, 8);
+    /// pin_mut!(results);
+    /// while let Some(response) = results.try_next().await? {
+    ///     println!("Tagged with status {}", response.status());
+    /// }
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    ///
+    /// A `max_in_flight` of zero is treated as one. Requests are started only when the stream
+    /// is polled.
+    #[cfg(feature = "stream")]
+    pub fn send_all<Srv, I>(
+        &self,
+        requests: I,
+        max_in_flight: usize,
+    ) -> impl Stream<Item = Result<Response, Error>>
+    where
+        Srv: ServiceType + Send,
+        I: IntoIterator<Item = ServiceRequestBuilder<Srv, A>>,
+    {
+        stream::iter(requests)
+            .map(ServiceRequestBuilder::send)
+            .buffered(max_in_flight.max(1))
+    }
+
+    /// Open an authenticated WebSocket connection to the given URL.
+    ///
+    /// Accepts `ws://` and `wss://` URLs as returned, for example, by the Compute remote
+    /// console API. Use [ServiceRequestBuilder::connect_websocket] to connect to
+    /// a service endpoint instead.
+    #[cfg(feature = "websocket")]
+    pub async fn connect_websocket(&self, url: Url) -> Result<WebSocket, Error> {
+        self.client
+            .request(Method::GET, websocket::http_url(url)?)
+            .connect_websocket()
+            .await
+    }
+
     /// Ensure service info and return the cache.
     async fn extract_service_info<Srv, F, T>(&self, service: Srv, filter: F) -> Result<T, Error>
     where
@@ -577,7 +1538,7 @@ impl Session {
         T: Send,
     {
         self.endpoint_cache
-            .extract_service_info(&self.client, service, filter)
+            .extract_service_info(&self.client, service, None, filter)
             .await
     }
 
@@ -594,28 +1555,43 @@ impl Session {
 /// A request builder for a service.
 #[derive(Debug)]
 #[must_use = "preparing a request is not enough to run it"]
-pub struct ServiceRequestBuilder<S: ServiceType> {
-    inner: RequestBuilder,
+pub struct ServiceRequestBuilder<S: ServiceType, A: ?Sized = dyn AuthType> {
+    inner: RequestBuilder<A>,
     endpoint_cache: Arc<EndpointCache>,
+    coalescer: Option<Arc<Coalescer>>,
     service: S,
+    region: Option<String>,
+}
+
+/// Add the `Host` header override for the service (if any).
+fn with_host_override<A: AuthType + ?Sized>(
+    request: RequestBuilder<A>,
+    cache: &EndpointCache,
+    service_type: &str,
+) -> RequestBuilder<A> {
+    match cache.hosts.get(service_type) {
+        Some(host) => request.header(HOST, host),
+        None => request,
+    }
 }
 
 lazy_static::lazy_static! {
     static ref FAKE_URL: Url = Url::parse("http://openstack").expect("fake URL must parse");
 }
 
-impl<S> ServiceRequestBuilder<S>
+impl<S, A> ServiceRequestBuilder<S, A>
 where
+    A: AuthType + ?Sized,
     S: ServiceType,
 {
     /// Get a reference to the client.
     #[inline]
-    pub fn client(&self) -> &AuthenticatedClient {
+    pub fn client(&self) -> &AuthenticatedClient<A> {
         self.inner.client()
     }
 
     /// Add a body to the request.
-    pub fn body<T: Into<Body>>(self, body: T) -> ServiceRequestBuilder<S> {
+    pub fn body<T: Into<Body>>(self, body: T) -> ServiceRequestBuilder<S, A> {
         ServiceRequestBuilder {
             inner: self.inner.body(body),
             ..self
@@ -623,7 +1599,7 @@ where
     }
 
     /// Add a header to the request.
-    pub fn header<K, V>(self, key: K, value: V) -> ServiceRequestBuilder<S>
+    pub fn header<K, V>(self, key: K, value: V) -> ServiceRequestBuilder<S, A>
     where
         HeaderName: TryFrom<K>,
         <HeaderName as TryFrom<K>>::Error: Into<HttpError>,
@@ -636,8 +1612,68 @@ where
         }
     }
 
+    /// Set the `Accept` header of the request.
+    ///
+    /// Overrides the default `application/json` for `fetch` and the
+    /// [per-service default](struct.Session.html#method.set_default_accept).
+    pub fn accept<V>(self, value: V) -> ServiceRequestBuilder<S, A>
+    where
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: Into<HttpError>,
+    {
+        ServiceRequestBuilder {
+            inner: self.inner.accept(value),
+            ..self
+        }
+    }
+
+    /// Accept `application/json` in response.
+    #[inline]
+    pub fn accept_json(self) -> ServiceRequestBuilder<S, A> {
+        self.accept(client::JSON)
+    }
+
+    /// Accept `text/plain` in response (e.g. for plain text listings in Object Storage).
+    #[inline]
+    pub fn accept_text(self) -> ServiceRequestBuilder<S, A> {
+        self.accept("text/plain")
+    }
+
+    /// Accept `application/octet-stream` in response (e.g. for downloads).
+    #[inline]
+    pub fn accept_binary(self) -> ServiceRequestBuilder<S, A> {
+        self.accept("application/octet-stream")
+    }
+
+    /// Make the request conditional on the version of the resource.
+    ///
+    /// Adds the `If-Match` or `If-Unmodified-Since` header. If the condition is not satisfied,
+    /// the request fails with
+    /// [PreconditionFailed](enum.ErrorKind.html#variant.PreconditionFailed).
+    /// See [Precondition](struct.Precondition.html) for an example.
+    pub fn with_precondition(self, precondition: &Precondition) -> ServiceRequestBuilder<S, A> {
+        let (name, value) = precondition.header();
+        self.header(name, value)
+    }
+
+    /// Request only a range of bytes (the `Range` header).
+    ///
+    /// Use [ContentRange::from_response](struct.ContentRange.html#method.from_response) to find
+    /// out which range has been returned. See [ByteRange](enum.ByteRange.html) for an example.
+    pub fn with_range(self, range: ByteRange) -> ServiceRequestBuilder<S, A> {
+        self.header(RANGE, range.header_value())
+    }
+
+    /// Use `application/json` as the `Accept` header unless it is set explicitly.
+    fn accept_json_by_default(self) -> ServiceRequestBuilder<S, A> {
+        ServiceRequestBuilder {
+            inner: self.inner.accept_json_by_default(),
+            ..self
+        }
+    }
+
     /// Add headers to a request.
-    pub fn headers(self, headers: HeaderMap) -> ServiceRequestBuilder<S> {
+    pub fn headers(self, headers: HeaderMap) -> ServiceRequestBuilder<S, A> {
         ServiceRequestBuilder {
             inner: self.inner.headers(headers),
             ..self
@@ -645,7 +1681,7 @@ where
     }
 
     /// Add a JSON body to the request.
-    pub fn json<T: Serialize + ?Sized>(self, json: &T) -> ServiceRequestBuilder<S> {
+    pub fn json<T: Serialize + ?Sized>(self, json: &T) -> ServiceRequestBuilder<S, A> {
         ServiceRequestBuilder {
             inner: self.inner.json(json),
             ..self
@@ -653,15 +1689,79 @@ where
     }
 
     /// Send a query with the request.
-    pub fn query<T: Serialize + ?Sized>(self, query: &T) -> ServiceRequestBuilder<S> {
+    pub fn query<T: Serialize + ?Sized>(self, query: &T) -> ServiceRequestBuilder<S, A> {
         ServiceRequestBuilder {
             inner: self.inner.query(query),
             ..self
         }
     }
 
+    /// Append a single query parameter to the request.
+    ///
+    /// Useful to build a query conditionally:
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session = osauth::Session::from_env().await?;
+    /// let tags = vec!["gold", "silver"];
+    /// let mut request = session.get(osauth::services::COMPUTE, &["servers"]);
+    /// for tag in tags {
+    ///     request = request.query_pair("tags-any", tag);
+    /// }
+    /// let servers: serde_json::Value = request.fetch().await?;
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub fn query_pair<K, V>(self, key: K, value: V) -> ServiceRequestBuilder<S, A>
+    where
+        K: AsRef<str>,
+        V: ToString,
+    {
+        ServiceRequestBuilder {
+            inner: self.inner.query_pair(key, value),
+            ..self
+        }
+    }
+
+    /// Append query parameters to the request.
+    ///
+    /// Existing query parameters are preserved, repeated keys are sent several times.
+    pub fn append_query<I, K, V>(self, pairs: I) -> ServiceRequestBuilder<S, A>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: ToString,
+    {
+        ServiceRequestBuilder {
+            inner: self.inner.append_query(pairs),
+            ..self
+        }
+    }
+
+    /// Tag the request with an operation name for the audit log.
+    ///
+    /// See [AuditSink](trait.AuditSink.html) for details.
+    pub fn operation<T: Into<String>>(self, operation: T) -> ServiceRequestBuilder<S, A> {
+        ServiceRequestBuilder {
+            inner: self.inner.operation(operation),
+            ..self
+        }
+    }
+
+    /// Send the request to the endpoint of the service in the given region.
+    ///
+    /// Overrides the region (and fallback regions) of the session for this request only.
+    /// Endpoint overrides take precedence over the region.
+    pub fn with_region<T: Into<String>>(self, region: T) -> ServiceRequestBuilder<S, A> {
+        ServiceRequestBuilder {
+            region: Some(region.into()),
+            ..self
+        }
+    }
+
     /// Override the timeout for the request.
-    pub fn timeout(self, timeout: Duration) -> ServiceRequestBuilder<S> {
+    pub fn timeout(self, timeout: Duration) -> ServiceRequestBuilder<S, A> {
         ServiceRequestBuilder {
             inner: self.inner.timeout(timeout),
             ..self
@@ -669,43 +1769,245 @@ where
     }
 
     /// Send the request and receive JSON in response.
-    pub async fn fetch<T>(self) -> Result<T, Error>
+    ///
+    /// Accepts `application/json` unless the `Accept` header is set explicitly.
+    ///
+    /// If [request coalescing](struct.Session.html#method.set_coalesce_requests) is enabled,
+    /// identical GET requests that are in flight share one response.
+    pub async fn fetch<T>(mut self) -> Result<T, Error>
+    where
+        T: DeserializeOwned + Send,
+        S: Send,
+    {
+        self = self.accept_json_by_default();
+        let key = self.coalescer.as_ref().and_then(|_| {
+            self.inner.build_request().and_then(|req| {
+                let prefix = match self.region {
+                    Some(ref region) => format!("{}@{}", self.service.catalog_type(), region),
+                    None => self.service.catalog_type().to_string(),
+                };
+                coalesce::request_key(&prefix, &req)
+            })
+        });
+        if let (Some(coalescer), Some(key)) = (self.coalescer.clone(), key) {
+            let body = coalescer
+                .fetch(key, || async move {
+                    let response = self.send().await?;
+                    Ok(Arc::from(client::read_response(response).await?))
+                })
+                .await?;
+            serde_json::from_slice(&body).map_err(Error::from)
+        } else {
+            client::read_json(self.send().await?).await
+        }
+    }
+
+    /// Send the request and receive JSON and the version of the resource in response.
+    ///
+    /// The version can be used for a conditional update with
+    /// [with_precondition](#method.with_precondition). It is `None` if the response has neither
+    /// a strong `ETag` nor a `Last-Modified` header. Requests are never coalesced.
+    pub async fn fetch_with_precondition<T>(self) -> Result<(T, Option<Precondition>), Error>
+    where
+        T: DeserializeOwned + Send,
+        S: Send,
+    {
+        let response = self.accept_json_by_default().send().await?;
+        let precondition = Precondition::from_response(&response);
+        Ok((client::read_json(response).await?, precondition))
+    }
+
+    /// Send the request and receive a fragment of the JSON response.
+    ///
+    /// The fragment is identified by a JSON pointer (RFC 6901), which avoids modelling the
+    /// whole response when only a few fields are needed:
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session = osauth::Session::from_env().await?;
+    /// let status: String = session
+    ///     .get(osauth::services::COMPUTE, &["servers", "8f9cd1b2-d2f4-4c76-9a2c-5f5e20a51e71"])
+    ///     .fetch_field("/server/status")
+    ///     .await?;
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    ///
+    /// Fails with [InvalidResponse](enum.ErrorKind.html#variant.InvalidResponse) if the field is
+    /// missing or cannot be deserialized.
+    pub async fn fetch_field<T>(self, pointer: &str) -> Result<T, Error>
     where
         T: DeserializeOwned + Send,
         S: Send,
     {
-        self.send().await?.json::<T>().await.map_err(Error::from)
+        client::check_json_pointer(pointer)?;
+        let response = self.accept_json_by_default().send().await?;
+        client::read_json_field(response, pointer).await
     }
 
     /// Send the request and check for errors.
+    ///
+    /// Cached endpoint information is dropped on errors that suggest it is outdated (connection
+    /// failures, HTTP 401 and HTTP 404 for the service root from something that is not an
+    /// OpenStack service), so that the catalog is consulted again on the next request.
     pub async fn send(self) -> Result<Response, Error>
     where
         S: Send,
     {
-        client::check(self.send_unchecked().await?).await
+        let service_type = self.service.catalog_type();
+        let endpoint_cache = Arc::clone(&self.endpoint_cache);
+        let client = self.inner.client().clone();
+        let region = self.region.clone();
+        let started = Utc::now();
+        let lifecycle = client.lifecycle();
+        lifecycle
+            .run(async {
+                match client::check(self.send_unregistered().await?).await {
+                    Ok(response) => Ok(response),
+                    Err(e) => {
+                        endpoint_cache
+                            .invalidate_on_error(
+                                &client,
+                                service_type,
+                                region.as_deref(),
+                                started,
+                                &e,
+                            )
+                            .await;
+                        let e = e.with_service_type(service_type);
+                        client.emit_error(&e);
+                        Err(e)
+                    }
+                }
+            })
+            .await
     }
 
-    /// Send the request without checking for HTTP and OpenStack errors.
-    pub async fn send_unchecked(self) -> Result<Response, Error>
+    /// Open a WebSocket connection to the service.
+    ///
+    /// The request must be a GET request. Use headers to request a sub-protocol:
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// use osauth::services::{GenericService, VersionSelector};
+    ///
+    /// const CONSOLE: GenericService = GenericService::new("console", VersionSelector::Any);
+    ///
+    /// let session = osauth::Session::from_env().await?;
+    /// let connection = session
+    ///     .get(CONSOLE, &["consoles", "<UUID>"])
+    ///     .header("Sec-WebSocket-Protocol", "binary")
+    ///     .connect_websocket()
+    ///     .await?;
+    /// println!("Connected using protocol {:?}", connection.protocol());
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    #[cfg(feature = "websocket")]
+    pub async fn connect_websocket(self) -> Result<WebSocket, Error>
+    where
+        S: Send,
+    {
+        let (headers, accept) = websocket::handshake();
+        let response = self.headers(headers).send_unchecked().await?;
+        websocket::upgrade(response, &accept).await
+    }
+
+    /// Resolve the endpoint and authenticate the request without sending it.
+    ///
+    /// The resulting request has the final URL and all headers, including the authentication
+    /// ones. This allows using the token and catalog handling of this crate with other HTTP
+    /// stacks:
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session = osauth::Session::from_env().await?;
+    /// let request = session
+    ///     .get(osauth::services::COMPUTE, &["servers"])
+    ///     .prepare()
+    ///     .await?;
+    /// let headers: Vec<String> = request
+    ///     .headers()
+    ///     .iter()
+    ///     .map(|(name, value)| format!("-H '{}: {}'", name, value.to_str().unwrap()))
+    ///     .collect();
+    /// println!("curl {} '{}'", headers.join(" "), request.url());
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub async fn prepare(self) -> Result<Request, Error>
     where
         S: Send,
     {
+        let service_type = self.service.catalog_type();
+        let region = self.region.as_deref();
         let url = self
             .endpoint_cache
-            .extract_service_info(self.inner.client(), self.service, |info| {
-                info.get_endpoint(NO_PATH)
+            .extract_service_info(self.inner.client(), self.service, region, |info| {
+                info.root_url.clone()
             })
             .await?;
-        self.inner.send_unchecked_to(&url).await
+        with_host_override(self.inner, &self.endpoint_cache, service_type)
+            .prepare_for(&url)
+            .await
+            .map_err(|e| e.with_service_type(service_type))
+    }
+
+    /// Send the request without checking for HTTP and OpenStack errors.
+    pub async fn send_unchecked(self) -> Result<Response, Error>
+    where
+        S: Send,
+    {
+        let lifecycle = self.inner.client().lifecycle();
+        lifecycle.run(self.send_unregistered()).await
+    }
+
+    /// Send the request without registering it with the shutdown lifecycle.
+    async fn send_unregistered(self) -> Result<Response, Error>
+    where
+        S: Send,
+    {
+        let service_type = self.service.catalog_type();
+        let future = async move {
+            let started = Utc::now();
+            let region = self.region.as_deref();
+            let url = self
+                .endpoint_cache
+                .extract_service_info(self.inner.client(), self.service, region, |info| {
+                    info.root_url.clone()
+                })
+                .await?;
+            let client = self.inner.client().clone();
+            let inner = with_host_override(self.inner, &self.endpoint_cache, service_type);
+            let result = match inner.send_unchecked_to(&url).await {
+                Ok(response) => Ok(response),
+                Err(e) => {
+                    self.endpoint_cache
+                        .invalidate_on_error(&client, service_type, region, started, &e)
+                        .await;
+                    Err(e.with_service_type(service_type))
+                }
+            };
+            #[cfg(feature = "tracing")]
+            instrument::record_result(&result);
+            result
+        };
+        #[cfg(feature = "tracing")]
+        let future = future.instrument(instrument::service(service_type));
+        future.await
     }
 }
 
-impl<S> ServiceRequestBuilder<S>
+impl<S, A> ServiceRequestBuilder<S, A>
 where
+    A: AuthType + ?Sized,
     S: VersionedService,
 {
     /// Add an API version to this request.
-    pub fn api_version<A: Into<ApiVersion>>(self, version: A) -> ServiceRequestBuilder<S> {
+    pub fn api_version<V: Into<ApiVersion>>(self, version: V) -> ServiceRequestBuilder<S, A> {
         let (name, value) = self.service.get_version_header(version.into());
         ServiceRequestBuilder {
             inner: self.inner.header(name, value),
@@ -714,13 +2016,14 @@ where
     }
 
     /// Set the API version on the request.
-    pub fn set_api_version<A: Into<ApiVersion>>(&mut self, version: A) {
+    pub fn set_api_version<V: Into<ApiVersion>>(&mut self, version: V) {
         take_mut::take(self, |rb| rb.api_version(version));
     }
 }
 
-impl<S> ServiceRequestBuilder<S>
+impl<S, A> ServiceRequestBuilder<S, A>
 where
+    A: AuthType + ?Sized,
     S: ServiceType + Clone,
 {
     /// Send the request and receive JSON in response with pagination.
@@ -780,19 +2083,73 @@ where
         paginated(self, limit, starting_with)
     }
 
+    /// Send the request and incrementally deserialize a JSON array in response.
+    ///
+    /// The array is looked up by `collection_name` in the top-level object (e.g. `"ports"`),
+    /// use `None` if the response itself is an array. Items are yielded as soon as they are
+    /// received, so that huge collections are never buffered in memory as a whole.
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// use futures::pin_mut;
+    /// use futures::stream::TryStreamExt;
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Debug, Deserialize)]
+    /// pub struct Port {
+    ///     pub id: String,
+    /// }
+    ///
+    /// let session = osauth::Session::from_env().await?;
+    ///
+    /// let ports = session
+    ///     .get(osauth::services::NETWORK, &["ports"])
+    ///     .fetch_stream::<Port>(Some("ports"))
+    ///     .await?;
+    ///
+    /// pin_mut!(ports);
+    /// while let Some(port) = ports.try_next().await? {
+    ///     println!("ID = {}", port.id);
+    /// }
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    ///
+    /// Errors in the response body (including a missing or truncated array) are returned from
+    /// the stream.
+    #[cfg(feature = "stream")]
+    pub async fn fetch_stream<T>(
+        self,
+        collection_name: Option<&str>,
+    ) -> Result<impl Stream<Item = Result<T, Error>>, Error>
+    where
+        S: Send,
+        T: DeserializeOwned,
+    {
+        let response = self.accept_json_by_default().send().await?;
+        Ok(json_items(response.bytes_stream(), collection_name))
+    }
+
     /// Attempt to clone this request builder.
-    pub fn try_clone(&self) -> Option<ServiceRequestBuilder<S>> {
+    pub fn try_clone(&self) -> Option<ServiceRequestBuilder<S, A>> {
         self.inner.try_clone().map(|inner| ServiceRequestBuilder {
             inner,
             endpoint_cache: self.endpoint_cache.clone(),
+            coalescer: self.coalescer.clone(),
             service: self.service.clone(),
+            region: self.region.clone(),
         })
     }
 }
 
 #[cfg(feature = "stream")]
 #[async_trait]
-impl<S: ServiceType + Clone + Send + Sync> FetchNext for ServiceRequestBuilder<S> {
+impl<S, A> FetchNext for ServiceRequestBuilder<S, A>
+where
+    S: ServiceType + Clone + Send + Sync,
+    A: AuthType + ?Sized,
+{
     async fn fetch_next<Q: Serialize + Send, T: DeserializeOwned + Send>(
         &self,
         query: Q,
@@ -805,22 +2162,28 @@ impl<S: ServiceType + Clone + Send + Sync> FetchNext for ServiceRequestBuilder<S
     }
 }
 
-impl<S> Into<RequestBuilder> for ServiceRequestBuilder<S>
+impl<S, A> From<ServiceRequestBuilder<S, A>> for RequestBuilder<A>
 where
     S: ServiceType,
+    A: AuthType + ?Sized,
 {
-    fn into(self: ServiceRequestBuilder<S>) -> RequestBuilder {
-        self.inner
+    fn from(value: ServiceRequestBuilder<S, A>) -> RequestBuilder<A> {
+        value.inner
     }
 }
 
 #[cfg(test)]
 pub(crate) mod test_session {
-    use reqwest::Url;
+    use http::header::{HeaderValue, ACCEPT, CONTENT_TYPE, HOST, IF_MATCH};
+    use reqwest::{Method, Url};
 
+    use super::super::identity::Password;
     use super::super::protocol::ServiceInfo;
-    use super::super::services::{GenericService, VersionSelector};
-    use super::super::{ApiVersion, NoAuth};
+    use super::super::services::{self, GenericService, VersionSelector};
+    use super::super::{
+        ApiVersion, BasicAuth, ErrorKind, InterfaceType, JsonPatchOp, NoAuth, Precondition,
+        SessionSnapshot,
+    };
     use super::Session;
 
     pub const URL: &str = "http://127.0.0.1:5000/";
@@ -853,6 +2216,14 @@ pub(crate) mod test_session {
         assert_eq!(&ep.to_string(), URL);
     }
 
+    #[tokio::test]
+    async fn test_get_token_no_auth() {
+        let s = new_simple_session(URL).await;
+        assert!(s.get_token().await.unwrap().is_none());
+        assert!(s.roles().await.unwrap().is_empty());
+        assert!(!s.has_role("admin").await.unwrap());
+    }
+
     #[tokio::test]
     async fn test_get_endpoint_slice() {
         let s = new_simple_session(URL).await;
@@ -904,6 +2275,251 @@ pub(crate) mod test_session {
         }
     }
 
+    #[tokio::test]
+    async fn test_prepare() {
+        let s = new_simple_session(URL).await;
+        let req = s
+            .get(FAKE, &["v2", "servers"])
+            .query(&[("limit", 1)])
+            .prepare()
+            .await
+            .unwrap();
+        assert_eq!(req.method(), Method::GET);
+        assert_eq!(
+            req.url().as_str(),
+            "http://127.0.0.1:5000/v2/servers?limit=1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prepare_authenticated() {
+        let auth = BasicAuth::new(URL, "user", "pass").unwrap();
+        let mut s = Session::new(auth).await.unwrap();
+        s.cache_fake_service(
+            "baremetal",
+            ServiceInfo {
+                root_url: Url::parse("http://127.0.0.1:6385/").unwrap(),
+                major_version: None,
+                minimum_version: None,
+                current_version: None,
+            },
+        );
+        let req = s
+            .get(services::BAREMETAL, &["v1", "nodes"])
+            .api_version((1, 42))
+            .prepare()
+            .await
+            .unwrap();
+        assert_eq!(req.url().as_str(), "http://127.0.0.1:6385/v1/nodes");
+        assert_eq!(req.headers()["authorization"], "Basic dXNlcjpwYXNz");
+        assert_eq!(req.headers()["x-openstack-ironic-api-version"], "1.42");
+    }
+
+    #[tokio::test]
+    async fn test_accept() {
+        let s = new_simple_session(URL).await;
+        let req = s.get(FAKE, &["v2"]).prepare().await.unwrap();
+        assert!(req.headers().get(ACCEPT).is_none());
+
+        let s = s.with_default_accept("fake", HeaderValue::from_static("text/plain"));
+        let req = s.get(FAKE, &["v2"]).prepare().await.unwrap();
+        assert_eq!(req.headers()[ACCEPT], "text/plain");
+        let req = s
+            .get(FAKE, &["v2"])
+            .accept_binary()
+            .prepare()
+            .await
+            .unwrap();
+        assert_eq!(req.headers()[ACCEPT], "application/octet-stream");
+        assert_eq!(req.headers().get_all(ACCEPT).iter().count(), 1);
+
+        let req = s
+            .get(FAKE, &["v2"])
+            .accept_json_by_default()
+            .prepare()
+            .await
+            .unwrap();
+        assert_eq!(req.headers()[ACCEPT], "application/json");
+        let req = s
+            .get(FAKE, &["v2"])
+            .accept_text()
+            .accept_json_by_default()
+            .prepare()
+            .await
+            .unwrap();
+        assert_eq!(req.headers()[ACCEPT], "text/plain");
+    }
+
+    #[tokio::test]
+    async fn test_default_api_version() {
+        let url = Url::parse(URL).unwrap();
+        let s = new_simple_session(URL).await;
+        let req = s
+            .get(services::BAREMETAL, &["nodes"])
+            .inner
+            .prepare_for(&url)
+            .await
+            .unwrap();
+        assert!(req
+            .headers()
+            .get("x-openstack-ironic-api-version")
+            .is_none());
+
+        let s = s.with_default_api_version(services::BAREMETAL, (1, 42));
+        assert_eq!(
+            s.default_api_version(services::BAREMETAL),
+            Some(ApiVersion(1, 42))
+        );
+        assert!(s.default_api_version(services::COMPUTE).is_none());
+        let req = s
+            .get(services::BAREMETAL, &["nodes"])
+            .inner
+            .prepare_for(&url)
+            .await
+            .unwrap();
+        assert_eq!(req.headers()["x-openstack-ironic-api-version"], "1.42");
+        let req = s
+            .get(services::BAREMETAL, &["nodes"])
+            .api_version((1, 50))
+            .inner
+            .prepare_for(&url)
+            .await
+            .unwrap();
+        let values: Vec<_> = req
+            .headers()
+            .get_all("x-openstack-ironic-api-version")
+            .iter()
+            .collect();
+        assert_eq!(values, vec!["1.50"]);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_restore() {
+        let mut snapshot = SessionSnapshot {
+            interfaces: vec!["internal".into(), "public".into()],
+            region: Some("RegionOne".into()),
+            ..SessionSnapshot::default()
+        };
+        let _ = snapshot
+            .endpoint_hosts
+            .insert("fake".into(), "fake.example.com".into());
+        let _ = snapshot
+            .services
+            .insert("fake".into(), fake_service_info().into());
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let mut s = Session::new(NoAuth::new(URL).unwrap()).await.unwrap();
+        s.restore(serde_json::from_str(&json).unwrap()).unwrap();
+        assert_eq!(s.endpoint_filters().region.as_deref(), Some("RegionOne"));
+        assert_eq!(
+            &*s.endpoint_filters().interfaces,
+            &[InterfaceType::Internal, InterfaceType::Public]
+        );
+        assert_eq!(
+            s.get_api_versions(FAKE).await.unwrap(),
+            Some((MIN_VERSION, MAX_VERSION))
+        );
+        assert_eq!(s.snapshot().await, snapshot);
+        let req = s.get(FAKE, &["v2"]).prepare().await.unwrap();
+        assert_eq!(req.headers()[HOST], "fake.example.com");
+
+        snapshot.interfaces = vec!["nonsense".into()];
+        assert!(s.restore(snapshot).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_api_versions() {
+        let s = Session::new(NoAuth::new(URL).unwrap())
+            .await
+            .unwrap()
+            .with_default_api_version(services::BAREMETAL, (1, 42));
+        let snapshot = s.snapshot().await;
+        let pin = &snapshot.api_versions["baremetal"];
+        assert_eq!(pin.version, ApiVersion(1, 42));
+        assert_eq!(pin.header, "x-openstack-ironic-api-version");
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let mut other = Session::new(NoAuth::new(URL).unwrap()).await.unwrap();
+        other.restore(serde_json::from_str(&json).unwrap()).unwrap();
+        assert_eq!(
+            other.default_api_version(services::BAREMETAL),
+            Some(ApiVersion(1, 42))
+        );
+
+        other.restore(SessionSnapshot::default()).unwrap();
+        assert!(other.default_api_version(services::BAREMETAL).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_catalog_disabled() {
+        let s = Session::new(NoAuth::new(URL).unwrap())
+            .await
+            .unwrap()
+            .with_catalog_disabled(true);
+        assert!(s.catalog_disabled());
+        let err = s.get_endpoint(FAKE, &["v2"]).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::EndpointNotFound);
+
+        let snapshot = s.snapshot().await;
+        assert!(snapshot.catalog_disabled);
+        let mut other = Session::new(NoAuth::new(URL).unwrap()).await.unwrap();
+        other.restore(snapshot).unwrap();
+        assert!(other.catalog_disabled());
+    }
+
+    #[tokio::test]
+    async fn test_patch_json() {
+        let s = new_simple_session(URL).await;
+        let req = s
+            .patch_json(FAKE, &["nodes", "1"], &[JsonPatchOp::remove("/extra")])
+            .inner
+            .build()
+            .unwrap();
+        assert_eq!(req.method(), Method::PATCH);
+        assert_eq!(
+            req.headers().get(CONTENT_TYPE).unwrap(),
+            "application/json-patch+json"
+        );
+        let body: serde_json::Value =
+            serde_json::from_slice(req.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!([{"op": "remove", "path": "/extra"}])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_with_precondition() {
+        let s = new_simple_session(URL).await;
+        let precondition = Precondition::etag("\"abcd\"").unwrap();
+        let req = s
+            .put(FAKE, &["nodes", "1"])
+            .with_precondition(&precondition)
+            .prepare()
+            .await
+            .unwrap();
+        assert_eq!(req.headers()[IF_MATCH], "\"abcd\"");
+    }
+
+    #[tokio::test]
+    async fn test_patch_merge_json() {
+        let s = new_simple_session(URL).await;
+        let patch = serde_json::json!({"name": "new", "extra": null});
+        let req = s
+            .patch_merge_json(FAKE, &["clusters", "1"], &patch)
+            .inner
+            .build()
+            .unwrap();
+        assert_eq!(req.method(), Method::PATCH);
+        assert_eq!(
+            req.headers().get(CONTENT_TYPE).unwrap(),
+            "application/merge-patch+json"
+        );
+        let body: serde_json::Value =
+            serde_json::from_slice(req.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert_eq!(body, patch);
+    }
+
     #[tokio::test]
     async fn test_pick_api_version_empty() {
         let service_info = fake_service_info();
@@ -945,6 +2561,33 @@ pub(crate) mod test_session {
         assert_eq!(res, Some(ApiVersion(2, 4)));
     }
 
+    #[tokio::test]
+    async fn test_new_typed() {
+        let mut s = Session::new_typed(NoAuth::new(URL).unwrap()).await.unwrap();
+        s.cache_fake_service("fake", fake_service_info());
+        let ep = s.get_endpoint(FAKE, &["v2", "servers"]).await.unwrap();
+        assert_eq!(&ep.to_string(), URL_WITH_SUFFIX);
+
+        let s = s.into_dyn();
+        let ep = s.get_endpoint(FAKE, &["v2", "servers"]).await.unwrap();
+        assert_eq!(&ep.to_string(), URL_WITH_SUFFIX);
+    }
+
+    #[tokio::test]
+    async fn test_new_lazy() {
+        // Nothing listens on port 1, so eager authentication would fail.
+        let auth = Password::new(
+            "http://127.0.0.1:1/identity",
+            "admin",
+            "pa$$w0rd",
+            "Default",
+        )
+        .unwrap();
+        let s = Session::new_lazy(auth);
+        let err = s.catalog_entries().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ProtocolError);
+    }
+
     #[tokio::test]
     async fn test_pick_api_version_impossible() {
         let service_info = fake_service_info();
@@ -976,7 +2619,9 @@ mod test_request_builder {
         let rb = ServiceRequestBuilder {
             inner: cli.request(Method::GET, Url::parse("http://127.0.0.1").unwrap()),
             endpoint_cache: Arc::new(EndpointCache::new()),
+            coalescer: None,
             service: services::BAREMETAL,
+            region: None,
         }
         .api_version((1, 42));
         let req = rb.inner.build().unwrap();
@@ -992,7 +2637,9 @@ mod test_request_builder {
         let mut rb = ServiceRequestBuilder {
             inner: cli.request(Method::GET, Url::parse("http://127.0.0.1").unwrap()),
             endpoint_cache: Arc::new(EndpointCache::new()),
+            coalescer: None,
             service: services::BAREMETAL,
+            region: None,
         };
         rb.set_api_version((1, 42));
         let req = rb.inner.build().unwrap();
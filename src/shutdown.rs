@@ -0,0 +1,237 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Graceful shutdown.
+
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use log::{debug, warn};
+use pin_project::pin_project;
+use tokio::sync::{watch, Notify};
+
+use super::{Error, ErrorKind};
+
+/// Shutdown state shared between clones of a client.
+#[derive(Debug)]
+pub(crate) struct Lifecycle {
+    closed: AtomicBool,
+    in_flight: AtomicUsize,
+    idle: Notify,
+    cancel: watch::Sender<bool>,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Lifecycle {
+        Lifecycle {
+            closed: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            idle: Notify::new(),
+            cancel: watch::channel(false).0,
+        }
+    }
+}
+
+/// Guard that keeps a request registered until dropped.
+#[derive(Debug)]
+pub(crate) struct Running<'l> {
+    lifecycle: &'l Lifecycle,
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        if self.lifecycle.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.lifecycle.idle.notify_waiters();
+        }
+    }
+}
+
+/// A future that fails when the shutdown timeout is reached.
+#[pin_project]
+pub(crate) struct Cancellable<F, C> {
+    #[pin]
+    future: F,
+    #[pin]
+    cancelled: C,
+}
+
+impl<F, C, T> Future for Cancellable<F, C>
+where
+    F: Future<Output = Result<T, Error>>,
+    C: Future<Output = ()>,
+{
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        if let Poll::Ready(result) = this.future.poll(cx) {
+            return Poll::Ready(result);
+        }
+        this.cancelled.poll(cx).map(|_| {
+            Err(Error::new(
+                ErrorKind::OperationFailed,
+                "Request cancelled because the session is shut down",
+            ))
+        })
+    }
+}
+
+impl Lifecycle {
+    /// Register a new request, failing if the shutdown has been started.
+    pub fn start(&self) -> Result<Running<'_>, Error> {
+        // Increment first, so that shutdown cannot miss a request that passes the check.
+        let _ = self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let running = Running { lifecycle: self };
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(Error::new(
+                ErrorKind::OperationFailed,
+                "Session is shut down and does not accept new requests",
+            ));
+        }
+        Ok(running)
+    }
+
+    /// Wrap a future so that it is cancelled when the shutdown timeout is reached.
+    pub fn cancellable<F>(&self, future: F) -> Cancellable<F, impl Future<Output = ()>> {
+        let mut cancel = self.cancel.subscribe();
+        Cancellable {
+            future,
+            cancelled: async move {
+                while !*cancel.borrow_and_update() {
+                    if cancel.changed().await.is_err() {
+                        future::pending::<()>().await;
+                    }
+                }
+            },
+        }
+    }
+
+    /// Run a request unless the shutdown has been started, cancelling it on the shutdown timeout.
+    pub async fn run<F, T>(&self, future: F) -> Result<T, Error>
+    where
+        F: Future<Output = Result<T, Error>>,
+    {
+        let _running = self.start()?;
+        self.cancellable(future).await
+    }
+
+    /// Whether the shutdown has been started.
+    #[inline]
+    pub fn is_shut_down(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Stop accepting requests and wait for the running ones, cancelling them after a timeout.
+    pub async fn shutdown(&self, timeout: Duration) -> Result<(), Error> {
+        self.closed.store(true, Ordering::SeqCst);
+        debug!(
+            "Shutting down, waiting for {} request(s)",
+            self.in_flight.load(Ordering::SeqCst)
+        );
+        if tokio::time::timeout(timeout, self.wait_idle())
+            .await
+            .is_ok()
+        {
+            return Ok(());
+        }
+
+        let remaining = self.in_flight.load(Ordering::SeqCst);
+        warn!(
+            "Cancelling {} request(s) that did not finish in {:?}",
+            remaining, timeout
+        );
+        let _ = self.cancel.send_replace(true);
+        Err(Error::new(
+            ErrorKind::OperationTimedOut,
+            format!(
+                "{} request(s) did not finish in {:?} and were cancelled",
+                remaining, timeout
+            ),
+        ))
+    }
+
+    async fn wait_idle(&self) {
+        loop {
+            // Created before the check, so that a notification in between is not lost.
+            let notified = self.idle.notified();
+            if self.in_flight.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::Lifecycle;
+    use crate::{Error, ErrorKind};
+
+    #[tokio::test]
+    async fn test_shutdown_idle() {
+        let lifecycle = Lifecycle::default();
+        drop(lifecycle.start().unwrap());
+        lifecycle.shutdown(Duration::from_secs(1)).await.unwrap();
+        assert!(lifecycle.is_shut_down());
+        let err = lifecycle.start().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::OperationFailed);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains() {
+        let lifecycle = Arc::new(Lifecycle::default());
+        let clone = Arc::clone(&lifecycle);
+        let task = tokio::spawn(async move {
+            let _running = clone.start().unwrap();
+            clone
+                .cancellable(async {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    Ok::<_, Error>(42)
+                })
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        lifecycle.shutdown(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(task.await.unwrap().unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_cancels() {
+        let lifecycle = Arc::new(Lifecycle::default());
+        let clone = Arc::clone(&lifecycle);
+        let task = tokio::spawn(async move {
+            let _running = clone.start().unwrap();
+            clone
+                .cancellable(async {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    Ok::<_, Error>(42)
+                })
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let err = lifecycle
+            .shutdown(Duration::from_millis(10))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::OperationTimedOut);
+        let err = task.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::OperationFailed);
+    }
+}
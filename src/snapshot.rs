@@ -0,0 +1,236 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Serializable session configuration.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use super::cache::EndpointCache;
+use super::protocol::ServiceInfo;
+use super::{ApiVersion, EndpointFilters, Error, InterfaceType, ValidInterfaces};
+
+/// Serializable configuration of a [Session](struct.Session.html).
+///
+/// Created by [Session::snapshot](struct.Session.html#method.snapshot) and applied with
+/// [Session::restore](struct.Session.html#method.restore). Contains endpoint filters and
+/// overrides, default API versions as well as the discovered endpoints and API versions of
+/// services, so that another process can skip the discovery.
+///
+/// Secrets are not a part of the snapshot, only the [parameters](struct.AuthSnapshot.html) of
+/// the authentication.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SessionSnapshot {
+    /// Acceptable endpoint interfaces in the priority order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub interfaces: Vec<String>,
+    /// Cloud region.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// Fallback regions.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback_regions: Vec<String>,
+    /// Service name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_name: Option<String>,
+    /// Endpoint overrides by service type.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub endpoint_overrides: HashMap<String, Url>,
+    /// `Host` header overrides by service type.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub endpoint_hosts: HashMap<String, String>,
+    /// Whether endpoints are only taken from the overrides.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub catalog_disabled: bool,
+    /// Discovered services by service type.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub services: HashMap<String, ServiceSnapshot>,
+    /// Default API versions by service type.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub api_versions: HashMap<String, ApiVersionSnapshot>,
+    /// Authentication parameters (if the session was created from a cloud configuration).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<AuthSnapshot>,
+}
+
+/// Default API version of a service.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ApiVersionSnapshot {
+    /// API version.
+    pub version: ApiVersion,
+    /// Name of the header used to request the API version.
+    pub header: String,
+}
+
+/// Authentication parameters of a session without the secrets.
+///
+/// The fields have the same names as in `clouds.yaml`, so that a session can be re-created by
+/// adding the password, token or application credential secret.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AuthSnapshot {
+    /// Authentication type, e.g. `password`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_type: Option<String>,
+    /// Identity service URL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_url: Option<String>,
+    /// Endpoint for authentication types without an Identity service.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    /// User ID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    /// User name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Domain ID of the user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_domain_id: Option<String>,
+    /// Domain name of the user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_domain_name: Option<String>,
+    /// Project ID (project scope).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project_id: Option<String>,
+    /// Project name (project scope).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project_name: Option<String>,
+    /// Domain ID of the project.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project_domain_id: Option<String>,
+    /// Domain name of the project.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project_domain_name: Option<String>,
+    /// Domain ID (domain scope).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain_id: Option<String>,
+    /// Domain name (domain scope).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain_name: Option<String>,
+    /// Domain ID used when no user or project domain is provided.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_domain: Option<String>,
+    /// Application credential ID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub application_credential_id: Option<String>,
+    /// Application credential name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub application_credential_name: Option<String>,
+}
+
+/// Discovered information about a service.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ServiceSnapshot {
+    /// Root endpoint.
+    pub root_url: Url,
+    /// Major API version.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub major_version: Option<ApiVersion>,
+    /// Minimum API version (if supported).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minimum_version: Option<ApiVersion>,
+    /// Current (maximum) API version (if supported).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_version: Option<ApiVersion>,
+}
+
+impl From<ServiceInfo> for ServiceSnapshot {
+    fn from(value: ServiceInfo) -> ServiceSnapshot {
+        ServiceSnapshot {
+            root_url: value.root_url,
+            major_version: value.major_version,
+            minimum_version: value.minimum_version,
+            current_version: value.current_version,
+        }
+    }
+}
+
+impl From<ServiceSnapshot> for ServiceInfo {
+    fn from(value: ServiceSnapshot) -> ServiceInfo {
+        ServiceInfo {
+            root_url: value.root_url,
+            major_version: value.major_version,
+            minimum_version: value.minimum_version,
+            current_version: value.current_version,
+        }
+    }
+}
+
+impl SessionSnapshot {
+    /// Take a snapshot of an endpoint cache.
+    pub(crate) async fn from_cache(cache: &EndpointCache) -> SessionSnapshot {
+        SessionSnapshot {
+            interfaces: cache
+                .filters
+                .interfaces
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            region: cache.filters.region.clone(),
+            fallback_regions: cache.filters.fallback_regions.clone(),
+            service_name: cache.filters.service_name.clone(),
+            endpoint_overrides: cache.overrides.clone(),
+            endpoint_hosts: cache.hosts.clone(),
+            catalog_disabled: cache.catalog_disabled,
+            services: cache
+                .service_info()
+                .await
+                .into_iter()
+                .map(|(key, value)| (key, value.into()))
+                .collect(),
+            api_versions: HashMap::new(),
+            auth: None,
+        }
+    }
+
+    /// Create an endpoint cache from the snapshot.
+    pub(crate) fn into_cache(self) -> Result<EndpointCache, Error> {
+        let mut filters = EndpointFilters::default();
+        if !self.interfaces.is_empty() {
+            filters.interfaces = self
+                .interfaces
+                .iter()
+                .map(|item| InterfaceType::from_str(item))
+                .collect::<Result<ValidInterfaces, _>>()?;
+        }
+        filters.region = self.region;
+        filters.fallback_regions = self.fallback_regions;
+        filters.service_name = self.service_name;
+        let info = self
+            .services
+            .into_iter()
+            .map(|(key, value)| (key, value.into()))
+            .collect();
+        let mut cache = EndpointCache::new_restored(
+            filters,
+            self.endpoint_overrides,
+            self.endpoint_hosts,
+            info,
+        );
+        cache.catalog_disabled = self.catalog_disabled;
+        Ok(cache)
+    }
+}
+
+#[inline]
+fn is_false(value: &bool) -> bool {
+    !*value
+}
@@ -0,0 +1,202 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Runtime statistics.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Number of recent requests used to calculate error rates.
+const RECENT_WINDOW: usize = 100;
+
+/// Snapshot of runtime statistics of a session.
+///
+/// Created by [Session::stats](struct.Session.html#method.stats), suitable for building health
+/// endpoints:
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// let session = osauth::Session::from_env().await?;
+/// // ... make some requests ...
+/// let stats = session.stats();
+/// for (service, service_stats) in &stats.services {
+///     if service_stats.recent_error_rate > 0.5 {
+///         println!("{} is unhealthy", service);
+///     }
+/// }
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+///
+/// Statistics are shared between clones of a session.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub struct SessionStats {
+    /// Number of requests currently being sent or waiting for a response.
+    pub requests_in_flight: usize,
+    /// Statistics by service type.
+    ///
+    /// Only requests made through a [Session](struct.Session.html) (which have a service type)
+    /// are tracked.
+    pub services: HashMap<String, ServiceStats>,
+    /// Time since the current token was issued (if known).
+    pub token_age: Option<Duration>,
+}
+
+/// Statistics of requests to a service.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub struct ServiceStats {
+    /// Total number of finished requests.
+    pub requests: u64,
+    /// Total number of failed requests.
+    ///
+    /// A request is failed if no response was received or the response has a server error
+    /// (5xx) status. Client errors are not counted since they are usually caused by the caller.
+    pub errors: u64,
+    /// Ratio of failed requests among the recent (up to 100) requests.
+    pub recent_error_rate: f64,
+}
+
+/// Outcomes of requests to a service.
+#[derive(Debug, Default)]
+struct ServiceRecord {
+    requests: u64,
+    errors: u64,
+    recent: VecDeque<bool>,
+}
+
+impl ServiceRecord {
+    fn record(&mut self, failed: bool) {
+        self.requests += 1;
+        if failed {
+            self.errors += 1;
+        }
+        if self.recent.len() == RECENT_WINDOW {
+            let _ = self.recent.pop_front();
+        }
+        self.recent.push_back(failed);
+    }
+
+    fn stats(&self) -> ServiceStats {
+        let recent_errors = self.recent.iter().filter(|failed| **failed).count();
+        ServiceStats {
+            requests: self.requests,
+            errors: self.errors,
+            recent_error_rate: if self.recent.is_empty() {
+                0.0
+            } else {
+                recent_errors as f64 / self.recent.len() as f64
+            },
+        }
+    }
+}
+
+/// Collector of runtime statistics.
+#[derive(Debug, Default)]
+pub(crate) struct StatsCollector {
+    in_flight: AtomicUsize,
+    services: Mutex<HashMap<String, ServiceRecord>>,
+}
+
+/// Guard that tracks an in-flight request until dropped.
+#[derive(Debug)]
+pub(crate) struct InFlight<'s> {
+    collector: &'s StatsCollector,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        let _ = self.collector.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl StatsCollector {
+    /// Start tracking a request.
+    pub fn start(&self) -> InFlight<'_> {
+        let _ = self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight { collector: self }
+    }
+
+    /// Record the outcome of a request.
+    pub fn finish(&self, service_type: Option<&str>, failed: bool) {
+        if let Some(service_type) = service_type {
+            self.services
+                .lock()
+                .expect("Statistics lock is poisoned")
+                .entry(service_type.to_string())
+                .or_default()
+                .record(failed);
+        }
+    }
+
+    /// Create a snapshot of the statistics.
+    pub fn snapshot(&self, token_age: Option<Duration>) -> SessionStats {
+        SessionStats {
+            requests_in_flight: self.in_flight.load(Ordering::Relaxed),
+            services: self
+                .services
+                .lock()
+                .expect("Statistics lock is poisoned")
+                .iter()
+                .map(|(key, value)| (key.clone(), value.stats()))
+                .collect(),
+            token_age,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::StatsCollector;
+
+    #[test]
+    fn test_in_flight() {
+        let collector = StatsCollector::default();
+        assert_eq!(collector.snapshot(None).requests_in_flight, 0);
+        let first = collector.start();
+        let second = collector.start();
+        assert_eq!(collector.snapshot(None).requests_in_flight, 2);
+        drop(first);
+        assert_eq!(collector.snapshot(None).requests_in_flight, 1);
+        drop(second);
+        assert_eq!(collector.snapshot(None).requests_in_flight, 0);
+    }
+
+    #[test]
+    fn test_error_rate() {
+        let collector = StatsCollector::default();
+        collector.finish(None, true);
+        assert!(collector.snapshot(None).services.is_empty());
+
+        for index in 0..200 {
+            // The first 100 requests fail, then every fourth one.
+            collector.finish(Some("compute"), index < 100 || index % 4 == 0);
+        }
+        collector.finish(Some("network"), false);
+
+        let stats = collector.snapshot(None);
+        let compute = &stats.services["compute"];
+        assert_eq!(compute.requests, 200);
+        assert_eq!(compute.errors, 125);
+        assert!((compute.recent_error_rate - 0.25).abs() < f64::EPSILON);
+        let network = &stats.services["network"];
+        assert_eq!(network.requests, 1);
+        assert_eq!(network.errors, 0);
+        assert_eq!(network.recent_error_rate, 0.0);
+    }
+}
@@ -15,15 +15,16 @@
 //! A stream of resources.
 
 use std::fmt::Debug;
+use std::mem;
 
 use async_stream::try_stream;
 use async_trait::async_trait;
 use futures::pin_mut;
-use futures::stream::{Stream, TryStreamExt};
+use futures::stream::{Stream, StreamExt, TryStreamExt};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
-use super::Error;
+use super::{Error, ErrorKind};
 
 /// A single resource.
 ///
@@ -31,7 +32,8 @@ use super::Error;
 ///
 /// * add a `#[resource_id]` attribute to the field that serves as a pagination marker
 /// * add a `#[collection_name = "resources"]` attribute to the structure with a name of
-///   the field that is returned in the collection (e.g. "servers" for Compute servers).
+///   the field that is returned in the collection (e.g. "servers" for Compute servers)
+///   or a `#[root_array]` attribute if the response is a bare JSON array.
 ///
 /// The trait and its dependencies can be implemented manually like this:
 ///
@@ -65,6 +67,35 @@ use super::Error;
 ///     }
 /// }
 /// ```
+///
+/// If the response is a bare JSON array (e.g. `[{"id": "1"}, {"id": "2"}]`), use the array
+/// itself as the root type:
+///
+/// ```rust
+/// use serde::Deserialize;
+/// use osauth::PaginatedResource;
+///
+/// #[derive(Debug, Deserialize, PaginatedResource)]
+/// #[root_array]
+/// pub struct Port {
+///     #[resource_id]
+///     pub id: String,
+/// }
+///
+/// #[derive(Debug, Deserialize)]
+/// pub struct Node {
+///     pub id: String,
+/// }
+///
+/// // Equivalent of #[root_array] with #[resource_id] on the `id` field.
+/// impl osauth::PaginatedResource for Node {
+///     type Id = String;
+///     type Root = Vec<Node>;
+///     fn resource_id(&self) -> Self::Id {
+///         self.id.clone()
+///     }
+/// }
+/// ```
 pub trait PaginatedResource {
     /// Type of an ID.
     type Id: Debug + Serialize + Send;
@@ -106,7 +137,7 @@ where
 
     try_stream! {
         loop {
-            let result: T::Root = builder.fetch_next(Query{ limit: limit, marker: marker.take() }).await?;
+            let result: T::Root = builder.fetch_next(Query{ limit, marker: marker.take() }).await?;
             let items = result.into();
             if let Some(new_m) = items.last() {
                 marker = Some(new_m.resource_id());
@@ -118,6 +149,189 @@ where
     }
 }
 
+/// Incremental splitter of a JSON array into raw items.
+///
+/// Only tracks the structure of the document (nesting and strings), the items themselves are
+/// validated when they are deserialized.
+#[derive(Debug)]
+struct ArrayScanner {
+    /// Name of the collection in the top-level object or `None` for a top-level array.
+    collection: Option<Vec<u8>>,
+    depth: usize,
+    in_string: bool,
+    escape: bool,
+    /// Whether the next string in the top-level object is a key.
+    expect_key: bool,
+    /// Last key seen in the top-level object.
+    key: Vec<u8>,
+    /// Depth of the array once it has been found.
+    array_depth: Option<usize>,
+    item: Vec<u8>,
+    finished: bool,
+}
+
+impl ArrayScanner {
+    fn new(collection: Option<&str>) -> ArrayScanner {
+        ArrayScanner {
+            collection: collection.map(|c| c.as_bytes().to_vec()),
+            depth: 0,
+            in_string: false,
+            escape: false,
+            expect_key: false,
+            key: Vec::new(),
+            array_depth: None,
+            item: Vec::new(),
+            finished: false,
+        }
+    }
+
+    /// Feed a chunk of data, returning raw items that have been completed.
+    fn feed(&mut self, chunk: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+        let mut items = Vec::new();
+        for &byte in chunk {
+            if self.finished {
+                break;
+            }
+
+            let in_array = self.array_depth == Some(self.depth);
+            if self.in_string {
+                if self.array_depth.is_some() {
+                    self.item.push(byte);
+                } else if self.depth == 1 && self.expect_key {
+                    self.key.push(byte);
+                }
+
+                if self.escape {
+                    self.escape = false;
+                } else if byte == b'\\' {
+                    self.escape = true;
+                } else if byte == b'"' {
+                    self.in_string = false;
+                    if self.array_depth.is_none() && self.depth == 1 && self.expect_key {
+                        let _ = self.key.pop();
+                        self.expect_key = false;
+                    }
+                }
+                continue;
+            }
+
+            match byte {
+                b'"' => {
+                    self.in_string = true;
+                    if self.depth == 1 && self.expect_key && self.array_depth.is_none() {
+                        self.key.clear();
+                    }
+                }
+                b'{' | b'[' => {
+                    if self.array_depth.is_none() && byte == b'[' && self.is_target() {
+                        self.depth += 1;
+                        self.array_depth = Some(self.depth);
+                        continue;
+                    }
+                    if self.depth == 0 && byte == b'{' && self.collection.is_some() {
+                        self.expect_key = true;
+                    }
+                    self.depth += 1;
+                }
+                b'}' | b']' => {
+                    if in_array {
+                        if byte == b']' {
+                            self.finish_item(&mut items);
+                            self.finished = true;
+                            continue;
+                        }
+                        return Err(invalid("Unexpected end of an object in an array"));
+                    }
+                    self.depth = self
+                        .depth
+                        .checked_sub(1)
+                        .ok_or_else(|| invalid("Unbalanced brackets"))?;
+                }
+                b',' if in_array => {
+                    self.finish_item(&mut items);
+                    continue;
+                }
+                b',' if self.depth == 1 && self.array_depth.is_none() => {
+                    self.expect_key = self.collection.is_some();
+                }
+                _ => {}
+            }
+
+            if self.array_depth.is_some() {
+                self.item.push(byte);
+            }
+        }
+        Ok(items)
+    }
+
+    /// Whether the next array is the one we are looking for.
+    fn is_target(&self) -> bool {
+        match self.collection {
+            Some(ref name) => self.depth == 1 && !self.expect_key && self.key == *name,
+            None => self.depth == 0,
+        }
+    }
+
+    fn finish_item(&mut self, items: &mut Vec<Vec<u8>>) {
+        let item = mem::take(&mut self.item);
+        if item.iter().any(|b| !b.is_ascii_whitespace()) {
+            items.push(item);
+        }
+    }
+
+    /// Check that the array has been fully read.
+    fn finish(&self) -> Result<(), Error> {
+        if self.finished {
+            Ok(())
+        } else if self.array_depth.is_some() {
+            Err(invalid("The response ended in the middle of an array"))
+        } else {
+            Err(invalid(match self.collection {
+                Some(ref name) => format!(
+                    "The response does not contain a {} array",
+                    String::from_utf8_lossy(name)
+                ),
+                None => "The response is not an array".into(),
+            }))
+        }
+    }
+}
+
+#[inline]
+fn invalid<S: Into<String>>(message: S) -> Error {
+    Error::new(ErrorKind::InvalidResponse, message)
+}
+
+/// Incrementally deserializes items of a JSON array from a stream of chunks.
+///
+/// If `collection` is `None`, the top-level value must be an array, otherwise the array is
+/// looked up by the given key in the top-level object.
+pub(crate) fn json_items<S, B, E, T>(
+    data: S,
+    collection: Option<&str>,
+) -> impl Stream<Item = Result<T, Error>>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: Into<Error>,
+    T: DeserializeOwned,
+{
+    let mut scanner = ArrayScanner::new(collection);
+    try_stream! {
+        pin_mut!(data);
+        while let Some(chunk) = data.next().await {
+            let chunk = chunk.map_err(Into::into)?;
+            for item in scanner.feed(chunk.as_ref())? {
+                yield serde_json::from_slice(&item)?;
+            }
+            if scanner.finished {
+                break;
+            }
+        }
+        scanner.finish()?;
+    }
+}
+
 /// Creates a paginated resource stream.
 ///
 /// # Panics
@@ -143,3 +357,151 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use async_trait::async_trait;
+    use futures::stream::{self, TryStreamExt};
+    use serde::de::DeserializeOwned;
+    use serde::{Deserialize, Serialize};
+    use serde_json::Value;
+
+    use super::{json_items, paginated, FetchNext, PaginatedResource};
+    use crate::{Error, ErrorKind};
+
+    #[derive(Debug, Deserialize, PartialEq, Eq)]
+    struct Item {
+        id: String,
+    }
+
+    async fn collect<T: DeserializeOwned>(
+        chunks: Vec<&'static str>,
+        collection: Option<&str>,
+    ) -> Result<Vec<T>, Error> {
+        let data = stream::iter(chunks.into_iter().map(Ok::<_, Error>));
+        json_items(data, collection).try_collect().await
+    }
+
+    #[tokio::test]
+    async fn test_json_items() {
+        let result: Vec<Item> = collect(
+            vec![
+                r#"{"ports_links": [{"href": "x"}], "name": "ports", "ports": [{"id": "#,
+                r#""1"}, {"id": "2", "tags": ["a]\"", "#,
+                r#""b"]}, {"id"#,
+                r#"": "3"} ], "other": []}"#,
+            ],
+            Some("ports"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            result,
+            vec![
+                Item { id: "1".into() },
+                Item { id: "2".into() },
+                Item { id: "3".into() }
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_json_items_root() {
+        let result: Vec<Value> = collect(vec!["[1, \"two\", ", "[3], {\"4\": 4}]"], None)
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            vec![
+                Value::from(1),
+                Value::from("two"),
+                serde_json::json!([3]),
+                serde_json::json!({"4": 4})
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_json_items_empty() {
+        let result: Vec<Item> = collect(vec![r#"{"ports": [ ]}"#], Some("ports"))
+            .await
+            .unwrap();
+        assert!(result.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_json_items_nested_key() {
+        let err = collect::<Item>(vec![r#"{"x": {"ports": [{"id": "1"}]}}"#], Some("ports"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidResponse);
+    }
+
+    #[tokio::test]
+    async fn test_json_items_truncated() {
+        let err = collect::<Item>(vec![r#"{"ports": [{"id": "1"}, {"id""#], Some("ports"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidResponse);
+    }
+
+    #[tokio::test]
+    async fn test_json_items_invalid_item() {
+        let err = collect::<Item>(vec![r#"{"ports": [{"name": "1"}]}"#], Some("ports"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidResponse);
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Eq)]
+    struct Node {
+        id: u32,
+    }
+
+    impl PaginatedResource for Node {
+        type Id = u32;
+        type Root = Vec<Node>;
+        fn resource_id(&self) -> Self::Id {
+            self.id
+        }
+    }
+
+    /// Returns bare arrays of nodes with IDs from 1 to 5.
+    struct FakeNodes;
+
+    #[async_trait]
+    impl FetchNext for FakeNodes {
+        async fn fetch_next<Q: Serialize + Send, T: DeserializeOwned + Send>(
+            &self,
+            query: Q,
+        ) -> Result<T, Error> {
+            let query = serde_json::to_value(query).unwrap();
+            let limit = query["limit"].as_u64().unwrap();
+            let marker = query["marker"].as_u64().unwrap_or(0);
+            let items: Vec<_> = (marker + 1..=5)
+                .take(limit as usize)
+                .map(|id| serde_json::json!({ "id": id }))
+                .collect();
+            Ok(serde_json::from_value(Value::Array(items)).unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_paginated_root_array() {
+        let nodes: Vec<Node> = paginated(FakeNodes, Some(2), None)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(
+            nodes.iter().map(|n| n.id).collect::<Vec<_>>(),
+            vec![1, 2, 3, 4, 5]
+        );
+        assert_eq!(nodes[0].resource_id(), 1);
+
+        let nodes: Vec<Node> = paginated(FakeNodes, Some(2), Some(3))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(nodes, vec![Node { id: 4 }, Node { id: 5 }]);
+    }
+}
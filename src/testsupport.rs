@@ -0,0 +1,951 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Test support: an in-process mock of the Identity service, failure injection and recording.
+//!
+//! Available with the `test-support` feature. [MockKeystone](struct.MockKeystone.html) allows
+//! integration testing of authentication flows without a real cloud:
+//!
+//! ```rust,no_run
+//! # async fn example() -> Result<(), osauth::Error> {
+//! use osauth::services::OBJECT_STORAGE;
+//! use osauth::testsupport::MockKeystone;
+//! use reqwest::{Method, StatusCode};
+//!
+//! let keystone = MockKeystone::start().await;
+//! keystone.add_user("admin", "pa$$w0rd");
+//! keystone.add_endpoint("object-store", "public", "RegionOne", "swift/v1");
+//! keystone.set_response(
+//!     Method::GET,
+//!     "/swift/v1/containers",
+//!     StatusCode::OK,
+//!     serde_json::json!([]),
+//! );
+//!
+//! let auth = osauth::identity::Password::new(keystone.url(), "admin", "pa$$w0rd", "Default")?;
+//! let session = osauth::Session::new(auth).await?;
+//! let containers: Vec<serde_json::Value> = session
+//!     .get_json(OBJECT_STORAGE, &["containers"])
+//!     .await?;
+//! assert_eq!(keystone.issued_tokens(), 1);
+//! # Ok(()) }
+//! # #[tokio::main]
+//! # async fn main() { example().await.unwrap(); }
+//! ```
+//!
+//! [FaultInjector](struct.FaultInjector.html) simulates failures to verify retry and
+//! re-authentication handling. [RecordingTransport](struct.RecordingTransport.html) and
+//! [ReplayTransport](struct.ReplayTransport.html) run the same code against recorded responses.
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hyper::server::conn::AddrIncoming;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use log::{debug, trace};
+use reqwest::{Client, Method, StatusCode, Url};
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+
+use crate::client::Transport;
+use crate::{Error, ErrorKind};
+
+pub use crate::cassette::{RecordingTransport, ReplayTransport};
+
+#[derive(Debug)]
+struct Endpoint {
+    service_type: String,
+    interface: String,
+    region: String,
+    path: String,
+}
+
+#[derive(Debug)]
+struct State {
+    url: Url,
+    users: HashMap<String, String>,
+    passcodes: HashMap<String, String>,
+    receipts: HashMap<String, (String, String)>,
+    endpoints: Vec<Endpoint>,
+    responses: HashMap<(Method, String), (StatusCode, Value)>,
+    tokens: HashMap<String, DateTime<Utc>>,
+    token_lifetime: Duration,
+    issued: usize,
+}
+
+/// An in-process mock of the Identity service.
+///
+/// Issues tokens for the configured users (or for any user if none are configured) with
+/// a configurable service catalog and token lifetime. Canned responses can be configured
+/// for other paths; they are only returned for requests with a valid token, otherwise
+/// HTTP 401 is returned.
+///
+/// The server is stopped when this object is dropped.
+#[derive(Debug)]
+pub struct MockKeystone {
+    state: Arc<Mutex<State>>,
+    address: SocketAddr,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockKeystone {
+    /// Start a server on a random local port.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the server cannot be started.
+    pub async fn start() -> MockKeystone {
+        let incoming = AddrIncoming::bind(&([127, 0, 0, 1], 0).into())
+            .expect("Cannot bind the mock Identity service");
+        let address = incoming.local_addr();
+        let url = Url::parse(&format!("http://{}/", address)).expect("Valid URL");
+        let state = Arc::new(Mutex::new(State {
+            url,
+            users: HashMap::new(),
+            passcodes: HashMap::new(),
+            receipts: HashMap::new(),
+            endpoints: Vec::new(),
+            responses: HashMap::new(),
+            tokens: HashMap::new(),
+            token_lifetime: Duration::from_secs(3600),
+            issued: 0,
+        }));
+
+        let server_state = Arc::clone(&state);
+        let make_service = make_service_fn(move |_| {
+            let state = Arc::clone(&server_state);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let state = Arc::clone(&state);
+                    async move { Ok::<_, Infallible>(handle(&state, request).await) }
+                }))
+            }
+        });
+        let (shutdown, receiver) = oneshot::channel::<()>();
+        let server = Server::builder(incoming)
+            .serve(make_service)
+            .with_graceful_shutdown(async {
+                let _ = receiver.await;
+            });
+        drop(tokio::spawn(server));
+        debug!("Started a mock Identity service on {}", address);
+
+        MockKeystone {
+            state,
+            address,
+            shutdown: Some(shutdown),
+        }
+    }
+
+    /// Base URL of the server, also used as the authentication URL.
+    pub fn url(&self) -> String {
+        format!("http://{}/", self.address)
+    }
+
+    /// Accept the given user name and password.
+    ///
+    /// If no users are added, any credentials are accepted.
+    pub fn add_user<U: Into<String>, P: Into<String>>(&self, user_name: U, password: P) {
+        let _ = self.state().users.insert(user_name.into(), password.into());
+    }
+
+    /// Require the given TOTP passcode from the user in addition to the password.
+    ///
+    /// Password authentication of the user then results in an authentication receipt.
+    pub fn set_passcode<U: Into<String>, P: Into<String>>(&self, user_name: U, passcode: P) {
+        let _ = self
+            .state()
+            .passcodes
+            .insert(user_name.into(), passcode.into());
+    }
+
+    /// Add an endpoint to the service catalog.
+    ///
+    /// The path is relative to the [URL](#method.url) of the server.
+    pub fn add_endpoint<S1, S2, S3, S4>(
+        &self,
+        service_type: S1,
+        interface: S2,
+        region: S3,
+        path: S4,
+    ) where
+        S1: Into<String>,
+        S2: Into<String>,
+        S3: Into<String>,
+        S4: Into<String>,
+    {
+        self.state().endpoints.push(Endpoint {
+            service_type: service_type.into(),
+            interface: interface.into(),
+            region: region.into(),
+            path: path.into(),
+        });
+    }
+
+    /// Return a canned JSON response for the given method and path.
+    pub fn set_response<P: Into<String>>(
+        &self,
+        method: Method,
+        path: P,
+        status: StatusCode,
+        body: Value,
+    ) {
+        let _ = self
+            .state()
+            .responses
+            .insert((method, path.into()), (status, body));
+    }
+
+    /// Lifetime of newly issued tokens (one hour by default).
+    pub fn set_token_lifetime(&self, value: Duration) {
+        self.state().token_lifetime = value;
+    }
+
+    /// Revoke all issued tokens.
+    ///
+    /// Subsequent requests with these tokens receive HTTP 401.
+    pub fn revoke_tokens(&self) {
+        self.state().tokens.clear();
+    }
+
+    /// Number of tokens issued so far.
+    pub fn issued_tokens(&self) -> usize {
+        self.state().issued
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("Mock state lock is poisoned")
+    }
+}
+
+impl Drop for MockKeystone {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+fn json_response(status: StatusCode, body: &Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .expect("Valid response")
+}
+
+fn unauthorized() -> Response<Body> {
+    json_response(
+        StatusCode::UNAUTHORIZED,
+        &json!({
+            "error": {
+                "code": 401,
+                "message": "The request you have made requires authentication.",
+                "title": "Unauthorized"
+            }
+        }),
+    )
+}
+
+async fn handle(state: &Mutex<State>, request: Request<Body>) -> Response<Body> {
+    let (parts, body) = request.into_parts();
+    trace!("Mock Identity service: {} {}", parts.method, parts.uri);
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(_) => return json_response(StatusCode::BAD_REQUEST, &Value::Null),
+    };
+
+    let mut state = state.lock().expect("Mock state lock is poisoned");
+    let path = parts.uri.path();
+    if parts.method == Method::POST && path.trim_end_matches('/') == "/v3/auth/tokens" {
+        return match serde_json::from_slice(&body) {
+            Ok(request) => {
+                let receipt = parts
+                    .headers
+                    .get("openstack-auth-receipt")
+                    .and_then(|value| value.to_str().ok());
+                issue_token(&mut state, &request, receipt)
+            }
+            Err(_) => json_response(StatusCode::BAD_REQUEST, &Value::Null),
+        };
+    }
+
+    let now = Utc::now();
+    let valid = parts
+        .headers
+        .get("x-auth-token")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|token| state.tokens.get(token))
+        .map(|expires_at| *expires_at > now)
+        .unwrap_or(false);
+    if !valid {
+        return unauthorized();
+    }
+
+    match state.responses.get(&(parts.method, path.to_string())) {
+        Some((status, body)) => json_response(*status, body),
+        None => json_response(
+            StatusCode::NOT_FOUND,
+            &json!({"error": {"code": 404, "message": "Not found", "title": "Not Found"}}),
+        ),
+    }
+}
+
+/// Extract an ID and a name from an ID-or-name object.
+fn id_and_name(value: &Value) -> Option<(String, String)> {
+    match (value["id"].as_str(), value["name"].as_str()) {
+        (Some(id), name) => Some((id.to_string(), name.unwrap_or(id).to_string())),
+        (None, Some(name)) => Some((format!("{}-id", name), name.to_string())),
+        (None, None) => None,
+    }
+}
+
+fn issue_receipt(state: &mut State, user: (String, String)) -> Response<Body> {
+    let receipt = format!("mock-receipt-{}", state.receipts.len() + 1);
+    let body = json!({
+        "receipt": {
+            "expires_at": (Utc::now() + chrono::Duration::minutes(5)).to_rfc3339(),
+            "methods": ["password"],
+            "user": {"id": user.0, "name": user.1},
+        },
+        "required_auth_methods": [["password", "totp"]],
+    });
+    let _ = state.receipts.insert(receipt.clone(), user);
+    debug!("Mock Identity service issued receipt {}", receipt);
+    let mut response = json_response(StatusCode::UNAUTHORIZED, &body);
+    let _ = response.headers_mut().insert(
+        "openstack-auth-receipt",
+        receipt.parse().expect("Valid header value"),
+    );
+    response
+}
+
+fn issue_token(state: &mut State, request: &Value, receipt: Option<&str>) -> Response<Body> {
+    let identity = &request["auth"]["identity"];
+    let user = match identity["methods"][0].as_str() {
+        Some("password") => {
+            let user = &identity["password"]["user"];
+            let (id, name) = match id_and_name(user) {
+                Some(found) => found,
+                None => return unauthorized(),
+            };
+            if !state.users.is_empty()
+                && state.users.get(&name).map(String::as_str) != user["password"].as_str()
+            {
+                return unauthorized();
+            }
+            if let Some(expected) = state.passcodes.get(&name) {
+                let passcode = &identity["totp"]["user"]["passcode"];
+                if passcode.is_null() {
+                    return issue_receipt(state, (id, name));
+                } else if passcode.as_str() != Some(expected.as_str()) {
+                    return unauthorized();
+                }
+            }
+            (id, name)
+        }
+        Some("totp") => {
+            let user = match receipt.and_then(|receipt| state.receipts.get(receipt)) {
+                Some(user) => user.clone(),
+                None => return unauthorized(),
+            };
+            let passcode = identity["totp"]["user"]["passcode"].as_str();
+            if identity["totp"]["user"]["id"].as_str() != Some(user.0.as_str())
+                || state.passcodes.get(&user.1).map(String::as_str) != passcode
+            {
+                return unauthorized();
+            }
+            user
+        }
+        Some("token") => {
+            let now = Utc::now();
+            let valid = identity["token"]["id"]
+                .as_str()
+                .and_then(|token| state.tokens.get(token))
+                .map(|expires_at| *expires_at > now)
+                .unwrap_or(false);
+            if !valid {
+                return unauthorized();
+            }
+            ("user-id".to_string(), "user".to_string())
+        }
+        _ => return unauthorized(),
+    };
+
+    let lifetime = chrono::Duration::from_std(state.token_lifetime)
+        .unwrap_or_else(|_| chrono::Duration::hours(1));
+    let expires_at = Utc::now() + lifetime;
+    state.issued += 1;
+    let token = format!("mock-token-{}", state.issued);
+    let _ = state.tokens.insert(token.clone(), expires_at);
+
+    let mut catalog: Vec<Value> = Vec::new();
+    for endpoint in &state.endpoints {
+        let url = state
+            .url
+            .join(&endpoint.path)
+            .expect("Valid endpoint path")
+            .to_string();
+        let record = json!({
+            "interface": endpoint.interface,
+            "region": endpoint.region,
+            "url": url,
+        });
+        match catalog
+            .iter_mut()
+            .find(|item| item["type"] == endpoint.service_type.as_str())
+        {
+            Some(item) => item["endpoints"]
+                .as_array_mut()
+                .expect("Endpoints are an array")
+                .push(record),
+            None => catalog.push(json!({
+                "type": endpoint.service_type,
+                "endpoints": [record],
+            })),
+        }
+    }
+
+    let mut body = json!({
+        "token": {
+            "expires_at": expires_at.to_rfc3339(),
+            "user": {"id": user.0, "name": user.1},
+            "catalog": catalog,
+        }
+    });
+    if let Some((id, name)) = id_and_name(&request["auth"]["scope"]["project"]) {
+        body["token"]["project"] = json!({"id": id, "name": name});
+    }
+
+    debug!("Mock Identity service issued token {}", token);
+    let mut response = json_response(StatusCode::CREATED, &body);
+    let _ = response.headers_mut().insert(
+        "x-subject-token",
+        token.parse().expect("Valid header value"),
+    );
+    response
+}
+
+/// A simulated failure.
+#[derive(Debug, Clone, Copy)]
+enum Fault {
+    Status(StatusCode),
+    Disconnect,
+    ExpiredToken,
+}
+
+#[derive(Debug, Default)]
+struct Faults {
+    delay: Option<Duration>,
+    queue: VecDeque<Fault>,
+}
+
+/// Transport that injects failures into authenticated requests.
+///
+/// Clones share the configuration, so a clone can be used to inject failures after the
+/// transport is installed:
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use osauth::testsupport::FaultInjector;
+/// use reqwest::StatusCode;
+///
+/// let faults = FaultInjector::new();
+/// let session = osauth::Session::from_env()
+///     .await?
+///     .with_transport(faults.clone());
+/// faults.fail_next(3, StatusCode::SERVICE_UNAVAILABLE);
+/// faults.disconnect_next(1);
+/// // The next three requests receive HTTP 503, the fourth fails with a connection error.
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+///
+/// Queued failures are applied to the following requests in order, requests without a failure
+/// are sent normally. Requests made by the authentication type itself are not affected.
+#[derive(Debug, Clone, Default)]
+pub struct FaultInjector {
+    inner: Option<Arc<dyn Transport>>,
+    faults: Arc<Mutex<Faults>>,
+}
+
+impl FaultInjector {
+    /// Inject failures into requests sent with the inner `reqwest` client.
+    pub fn new() -> FaultInjector {
+        FaultInjector::default()
+    }
+
+    /// Inject failures into requests sent with another transport.
+    pub fn wrap<T: Transport + 'static>(inner: T) -> FaultInjector {
+        FaultInjector {
+            inner: Some(Arc::new(inner)),
+            faults: Arc::default(),
+        }
+    }
+
+    /// Delay all requests by the given duration (or remove the delay).
+    ///
+    /// Requests with a timeout shorter than the delay fail with `OperationTimedOut`.
+    pub fn set_delay(&self, value: impl Into<Option<Duration>>) {
+        self.faults().delay = value.into();
+    }
+
+    /// Respond to the next `count` requests with the given status (e.g. a burst of HTTP 503).
+    pub fn fail_next(&self, count: usize, status: StatusCode) {
+        self.push(count, Fault::Status(status));
+    }
+
+    /// Fail the next `count` requests as if the connection was dropped.
+    pub fn disconnect_next(&self, count: usize) {
+        self.push(count, Fault::Disconnect);
+    }
+
+    /// Respond to the next `count` requests with HTTP 401 as if the token has expired.
+    pub fn expire_token_next(&self, count: usize) {
+        self.push(count, Fault::ExpiredToken);
+    }
+
+    /// Number of queued failures that have not been applied yet.
+    pub fn pending(&self) -> usize {
+        self.faults().queue.len()
+    }
+
+    /// Remove all queued failures and the delay.
+    pub fn clear(&self) {
+        let mut faults = self.faults();
+        faults.delay = None;
+        faults.queue.clear();
+    }
+
+    fn push(&self, count: usize, fault: Fault) {
+        let mut faults = self.faults();
+        for _ in 0..count {
+            faults.queue.push_back(fault);
+        }
+    }
+
+    fn faults(&self) -> MutexGuard<'_, Faults> {
+        self.faults
+            .lock()
+            .expect("Fault injection lock is poisoned")
+    }
+}
+
+fn fault_response(status: StatusCode, body: &Value) -> reqwest::Response {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(body.to_string())
+        .expect("Valid response")
+        .into()
+}
+
+#[async_trait]
+impl Transport for FaultInjector {
+    async fn execute(
+        &self,
+        client: &Client,
+        request: reqwest::Request,
+    ) -> Result<reqwest::Response, Error> {
+        let (delay, fault) = {
+            let mut faults = self.faults();
+            (faults.delay, faults.queue.pop_front())
+        };
+
+        if let Some(delay) = delay {
+            match request.timeout() {
+                Some(timeout) if *timeout < delay => {
+                    tokio::time::sleep(*timeout).await;
+                    return Err(Error::new(
+                        ErrorKind::OperationTimedOut,
+                        "Injected delay exceeded the request timeout",
+                    ));
+                }
+                _ => tokio::time::sleep(delay).await,
+            }
+        }
+
+        match fault {
+            Some(Fault::Status(status)) => {
+                debug!("Injecting HTTP {} for {}", status, request.url());
+                Ok(fault_response(
+                    status,
+                    &json!({
+                        "error": {
+                            "code": status.as_u16(),
+                            "message": "Injected failure",
+                            "title": status.canonical_reason().unwrap_or("Error"),
+                        }
+                    }),
+                ))
+            }
+            Some(Fault::Disconnect) => {
+                debug!("Injecting a connection failure for {}", request.url());
+                Err(Error::new(
+                    ErrorKind::ProtocolError,
+                    "Injected connection failure: connection reset",
+                ))
+            }
+            Some(Fault::ExpiredToken) => {
+                debug!("Injecting an expired token for {}", request.url());
+                Ok(fault_response(
+                    StatusCode::UNAUTHORIZED,
+                    &json!({
+                        "error": {
+                            "code": 401,
+                            "message": "The request you have made requires authentication.",
+                            "title": "Unauthorized",
+                        }
+                    }),
+                ))
+            }
+            None => match self.inner {
+                Some(ref inner) => inner.execute(client, request).await,
+                None => client.execute(request).await.map_err(Error::from),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use reqwest::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use super::{FaultInjector, MockKeystone};
+    use crate::identity::{AuthReceipt, Password, Totp};
+    use crate::services::OBJECT_STORAGE;
+    use crate::{ErrorKind, Event, ManualClock, Session};
+
+    #[tokio::test]
+    async fn test_mock_keystone() {
+        let keystone = MockKeystone::start().await;
+        keystone.add_user("admin", "secret");
+        keystone.add_endpoint("object-store", "public", "RegionOne", "swift/v1");
+        keystone.set_response(
+            Method::GET,
+            "/swift/v1/containers",
+            StatusCode::OK,
+            json!([{"name": "test"}]),
+        );
+
+        let auth = Password::new(keystone.url(), "admin", "secret", "Default").unwrap();
+        let session = Session::new(auth).await.unwrap();
+        let containers: Vec<Value> = session
+            .get_json(OBJECT_STORAGE, &["containers"])
+            .await
+            .unwrap();
+        assert_eq!(containers, vec![json!({"name": "test"})]);
+        assert_eq!(keystone.issued_tokens(), 1);
+        let token = session.get_token().await.unwrap().unwrap();
+        assert!(!token.is_empty());
+        assert_eq!(keystone.issued_tokens(), 1);
+
+        let err = session
+            .get_json::<_, _, Value>(OBJECT_STORAGE, &["missing"])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ResourceNotFound);
+    }
+
+    #[tokio::test]
+    async fn test_mock_keystone_receipt() {
+        let keystone = MockKeystone::start().await;
+        keystone.add_user("admin", "secret");
+        keystone.set_passcode("admin", "123456");
+        keystone.add_endpoint("object-store", "public", "RegionOne", "swift/v1");
+
+        let client = reqwest::Client::new();
+        let auth = Password::new(keystone.url(), "admin", "secret", "Default").unwrap();
+        let receipt = auth.request_receipt(&client).await.unwrap().unwrap();
+        assert_eq!(receipt.user_id(), "admin-id");
+        assert_eq!(keystone.issued_tokens(), 0);
+
+        // Simulate a separate invocation.
+        let saved = serde_json::to_string(&receipt).unwrap();
+        let receipt: AuthReceipt = serde_json::from_str(&saved).unwrap();
+        let err = Session::new(Totp::from_receipt(receipt.clone(), "000000").unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AuthenticationFailed);
+        let session = Session::new(Totp::from_receipt(receipt, "123456").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(keystone.issued_tokens(), 1);
+        assert!(session.get_token().await.unwrap().is_some());
+
+        let other = Password::new(keystone.url(), "other", "secret", "Default").unwrap();
+        let err = other.request_receipt(&client).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AuthenticationFailed);
+    }
+
+    #[tokio::test]
+    async fn test_mock_keystone_passcode_provider() {
+        let keystone = MockKeystone::start().await;
+        keystone.add_user("admin", "secret");
+        keystone.set_passcode("admin", "123456");
+        // Shorter than the minimum validity, so every request gets a new token.
+        keystone.set_token_lifetime(Duration::from_secs(60));
+
+        let calls = Arc::new(Mutex::new(0));
+        let calls2 = Arc::clone(&calls);
+        let auth = Password::new(keystone.url(), "admin", "secret", "Default")
+            .unwrap()
+            .with_passcode_provider(move || {
+                *calls2.lock().unwrap() += 1;
+                async { Ok("123456".to_string()) }
+            });
+        let session = Session::new(auth).await.unwrap();
+        let _ = session.get_token().await.unwrap();
+        assert_eq!(keystone.issued_tokens(), 2);
+        assert_eq!(*calls.lock().unwrap(), 2);
+
+        let auth = Password::new(keystone.url(), "admin", "secret", "Default")
+            .unwrap()
+            .with_passcode_provider(|| async { Ok("000000".to_string()) });
+        let err = Session::new(auth).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AuthenticationFailed);
+    }
+
+    #[tokio::test]
+    async fn test_mock_keystone_wrong_password() {
+        let keystone = MockKeystone::start().await;
+        keystone.add_user("admin", "secret");
+        let failures = Arc::new(Mutex::new(Vec::new()));
+        let failures2 = Arc::clone(&failures);
+        let auth = Password::new(keystone.url(), "admin", "wrong", "Default")
+            .unwrap()
+            .with_event_handler(move |event: &Event| {
+                if let Event::TokenRefreshFailed { error } = event {
+                    failures2.lock().unwrap().push(error.kind());
+                }
+            });
+        let err = Session::new(auth).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AuthenticationFailed);
+        assert_eq!(
+            *failures.lock().unwrap(),
+            vec![ErrorKind::AuthenticationFailed]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mock_keystone_expiration() {
+        let keystone = MockKeystone::start().await;
+        // Shorter than the minimum validity, so every request gets a new token.
+        keystone.set_token_lifetime(Duration::from_secs(60));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events2 = Arc::clone(&events);
+        let auth = Password::new(keystone.url(), "admin", "secret", "Default")
+            .unwrap()
+            .with_event_handler(move |event: &Event| {
+                let name = match event {
+                    Event::TokenExpiring { .. } => "expiring",
+                    Event::TokenRefreshed { .. } => "refreshed",
+                    _ => "other",
+                };
+                events2.lock().unwrap().push(name);
+            });
+        let session = Session::new(auth).await.unwrap();
+        let _ = session.catalog_entries().await.unwrap();
+        assert_eq!(keystone.issued_tokens(), 2);
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec!["refreshed", "expiring", "refreshed"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mock_keystone_forced_refresh() {
+        let keystone = MockKeystone::start().await;
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events2 = Arc::clone(&events);
+        let auth = Password::new(keystone.url(), "admin", "secret", "Default")
+            .unwrap()
+            .with_event_handler(move |event: &Event| {
+                if let Event::TokenExpiring { .. } = event {
+                    events2.lock().unwrap().push("expiring");
+                }
+            });
+        let mut session = Session::new(auth).await.unwrap();
+        session.refresh().await.unwrap();
+        assert_eq!(keystone.issued_tokens(), 2);
+        // The token was still valid, so it was not expiring.
+        assert!(events.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mock_keystone_request_region() {
+        let keystone = MockKeystone::start().await;
+        keystone.add_endpoint("object-store", "public", "RegionOne", "one/swift/v1");
+        keystone.add_endpoint("object-store", "public", "RegionTwo", "two/swift/v1");
+        for region in &["one", "two"] {
+            keystone.set_response(
+                Method::GET,
+                format!("/{}/swift/v1/info", region),
+                StatusCode::OK,
+                json!({ "region": region }),
+            );
+        }
+
+        let auth = Password::new(keystone.url(), "admin", "secret", "Default").unwrap();
+        let session = Session::new(auth).await.unwrap().with_region("RegionOne");
+        let info: Value = session.get_json(OBJECT_STORAGE, &["info"]).await.unwrap();
+        assert_eq!(info, json!({"region": "one"}));
+        let info: Value = session
+            .get(OBJECT_STORAGE, &["info"])
+            .with_region("RegionTwo")
+            .fetch()
+            .await
+            .unwrap();
+        assert_eq!(info, json!({"region": "two"}));
+        assert_eq!(
+            session.endpoint_filters().region.as_deref(),
+            Some("RegionOne")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mock_keystone_manual_clock() {
+        let keystone = MockKeystone::start().await;
+        let clock = ManualClock::default();
+        let auth = Password::new(keystone.url(), "admin", "secret", "Default")
+            .unwrap()
+            .with_clock(clock.clone());
+        let session = Session::new(auth).await.unwrap();
+        let _ = session.catalog_entries().await.unwrap();
+        assert_eq!(keystone.issued_tokens(), 1);
+        clock.advance(chrono::Duration::days(1));
+        let _ = session.catalog_entries().await.unwrap();
+        assert_eq!(keystone.issued_tokens(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_mock_keystone_detach() {
+        let keystone = MockKeystone::start().await;
+        let auth = Password::new(keystone.url(), "admin", "secret", "Default").unwrap();
+        let mut session = Session::new(auth).await.unwrap();
+        let mut detached = session.detach().unwrap();
+        // The current token is copied.
+        let _ = detached.catalog_entries().await.unwrap();
+        assert_eq!(keystone.issued_tokens(), 1);
+
+        detached.refresh().await.unwrap();
+        assert_eq!(keystone.issued_tokens(), 2);
+        let _ = session.catalog_entries().await.unwrap();
+        assert_eq!(keystone.issued_tokens(), 2);
+
+        session.refresh().await.unwrap();
+        assert_eq!(keystone.issued_tokens(), 3);
+        let _ = detached.catalog_entries().await.unwrap();
+        assert_eq!(keystone.issued_tokens(), 3);
+    }
+
+    async fn faulty_session() -> (MockKeystone, Session, FaultInjector) {
+        let keystone = MockKeystone::start().await;
+        keystone.add_endpoint("object-store", "public", "RegionOne", "swift/v1");
+        keystone.set_response(
+            Method::GET,
+            "/swift/v1/containers",
+            StatusCode::OK,
+            json!([]),
+        );
+        let auth = Password::new(keystone.url(), "admin", "secret", "Default").unwrap();
+        let faults = FaultInjector::new();
+        let session = Session::new(auth)
+            .await
+            .unwrap()
+            .with_transport(faults.clone());
+        (keystone, session, faults)
+    }
+
+    #[tokio::test]
+    async fn test_fault_injector_status_burst() {
+        let (_keystone, session, faults) = faulty_session().await;
+        faults.fail_next(2, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(faults.pending(), 2);
+        for _ in 0..2 {
+            let err = session
+                .get_json::<_, _, Value>(OBJECT_STORAGE, &["containers"])
+                .await
+                .unwrap_err();
+            assert_eq!(err.status(), Some(StatusCode::SERVICE_UNAVAILABLE));
+        }
+        assert_eq!(faults.pending(), 0);
+        let containers: Vec<Value> = session
+            .get_json(OBJECT_STORAGE, &["containers"])
+            .await
+            .unwrap();
+        assert!(containers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fault_injector_disconnect() {
+        let (_keystone, session, faults) = faulty_session().await;
+        faults.disconnect_next(1);
+        let err = session
+            .get_json::<_, _, Value>(OBJECT_STORAGE, &["containers"])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ProtocolError);
+    }
+
+    #[tokio::test]
+    async fn test_fault_injector_expired_token() {
+        let (keystone, session, faults) = faulty_session().await;
+        let issued = keystone.issued_tokens();
+        faults.expire_token_next(1);
+        let err = session
+            .get_json::<_, _, Value>(OBJECT_STORAGE, &["containers"])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unauthorized);
+        // HTTP 401 causes re-authentication.
+        assert_eq!(keystone.issued_tokens(), issued + 1);
+        let _: Vec<Value> = session
+            .get_json(OBJECT_STORAGE, &["containers"])
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fault_injector_delay() {
+        let (_keystone, session, faults) = faulty_session().await;
+        faults.set_delay(Duration::from_secs(10));
+        let err = session
+            .get(OBJECT_STORAGE, &["containers"])
+            .timeout(Duration::from_millis(10))
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::OperationTimedOut);
+
+        faults.set_delay(Duration::from_millis(10));
+        let _ = session
+            .get(OBJECT_STORAGE, &["containers"])
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Integration with `tower`.
+
+use std::convert::TryFrom;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use reqwest::{Body, Request, Response};
+use tower_service::Service;
+
+use super::client::AuthenticatedClient;
+use super::{AuthType, Error};
+
+/// Convert a `reqwest` response into an `http` one.
+fn into_http_response(response: Response) -> http::Response<Body> {
+    let mut result = http::Response::builder()
+        .status(response.status())
+        .version(response.version());
+    if let Some(headers) = result.headers_mut() {
+        *headers = response.headers().clone();
+    }
+    result
+        .body(Body::from(response))
+        .expect("Response parts are always valid")
+}
+
+/// Authenticated client as a `tower` service.
+///
+/// Requests must use absolute URLs. They are authenticated and sent as is, HTTP errors are
+/// returned as normal responses so that middleware (e.g. retries) can inspect them.
+///
+/// Available with the `tower` feature.
+impl<A, B> Service<http::Request<B>> for AuthenticatedClient<A>
+where
+    A: AuthType + ?Sized + 'static,
+    B: Into<Body>,
+{
+    type Response = http::Response<Body>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let client = self.clone();
+        let request = Request::try_from(request);
+        Box::pin(async move {
+            let response = client.send_request(request?).await?;
+            Ok(into_http_response(response))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::future::Future;
+
+    use reqwest::StatusCode;
+    use tower_service::Service;
+
+    use super::into_http_response;
+    use crate::client::AuthenticatedClient;
+    use crate::ErrorKind;
+
+    fn assert_service<S, R>(_service: &S)
+    where
+        S: Service<http::Request<R>, Error = crate::Error>,
+        S::Future: Future + Send + 'static,
+    {
+    }
+
+    #[tokio::test]
+    async fn test_service() {
+        let mut client = AuthenticatedClient::new_noauth("http://127.0.0.1:1").await;
+        assert_service::<_, String>(&client);
+
+        let request = http::Request::get("/relative").body("").unwrap();
+        let err = client.call(request).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        let request = http::Request::get("http://127.0.0.1:1/v2")
+            .body("")
+            .unwrap();
+        let err = client.call(request).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ProtocolError);
+    }
+
+    #[tokio::test]
+    async fn test_into_http_response() {
+        let response = http::Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("x-openstack-request-id", "req-1")
+            .body("not found")
+            .unwrap();
+        let result = into_http_response(response.into());
+        assert_eq!(result.status(), StatusCode::NOT_FOUND);
+        assert_eq!(result.headers()["x-openstack-request-id"], "req-1");
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! User-Agent header.
+
+use http::header::HeaderValue;
+
+/// Library component of the User-Agent.
+const LIBRARY: &str = concat!("osauth/", env!("CARGO_PKG_VERSION"));
+
+/// Components of the User-Agent header.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct UserAgent {
+    app_name: Option<String>,
+    app_version: Option<String>,
+    components: Vec<String>,
+    header: Option<HeaderValue>,
+}
+
+/// Make a valid product token (or version) by replacing unsupported characters.
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() && c != '/' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+impl UserAgent {
+    /// The header value (if anything is configured).
+    #[inline]
+    pub fn header(&self) -> Option<&HeaderValue> {
+        self.header.as_ref()
+    }
+
+    /// Set the application name.
+    pub fn set_app_name(&mut self, value: &str) {
+        self.app_name = Some(sanitize(value));
+        self.update();
+    }
+
+    /// Set the application version.
+    pub fn set_app_version(&mut self, value: &str) {
+        self.app_version = Some(sanitize(value));
+        self.update();
+    }
+
+    /// Add an additional component after the application.
+    pub fn add_component(&mut self, name: &str, version: &str) {
+        self.components
+            .push(format!("{}/{}", sanitize(name), sanitize(version)));
+        self.update();
+    }
+
+    fn update(&mut self) {
+        if self.app_name.is_none() && self.components.is_empty() {
+            return;
+        }
+
+        let mut result = Vec::with_capacity(self.components.len() + 2);
+        if let Some(ref name) = self.app_name {
+            result.push(match self.app_version {
+                Some(ref version) => format!("{}/{}", name, version),
+                None => name.clone(),
+            });
+        }
+        result.extend(self.components.iter().cloned());
+        result.push(LIBRARY.to_string());
+        // Only visible ASCII characters and spaces are left after sanitizing.
+        self.header = Some(HeaderValue::from_str(&result.join(" ")).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{UserAgent, LIBRARY};
+
+    #[test]
+    fn test_empty() {
+        let mut ua = UserAgent::default();
+        assert!(ua.header().is_none());
+        ua.set_app_version("1.0");
+        assert!(ua.header().is_none());
+    }
+
+    #[test]
+    fn test_full() {
+        let mut ua = UserAgent::default();
+        ua.set_app_name("my tool");
+        assert_eq!(ua.header().unwrap(), &format!("my_tool {}", LIBRARY));
+        ua.set_app_version("1.0");
+        ua.add_component("plugin", "2.0/beta");
+        assert_eq!(
+            ua.header().unwrap(),
+            &format!("my_tool/1.0 plugin/2.0_beta {}", LIBRARY)
+        );
+    }
+
+    #[test]
+    fn test_components_only() {
+        let mut ua = UserAgent::default();
+        ua.add_component("plugin", "2.0");
+        assert_eq!(ua.header().unwrap(), &format!("plugin/2.0 {}", LIBRARY));
+    }
+}
@@ -48,8 +48,67 @@ pub fn merge_mappings(src: serde_yaml::Mapping, dest: &mut serde_yaml::Mapping,
 
 #[cfg(test)]
 pub mod test {
+    use std::net::SocketAddr;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
     use super::merge_mappings;
 
+    /// A raw HTTP response without content.
+    pub(crate) const NO_CONTENT: &str = "HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n";
+
+    /// Answer consecutive connections on a local port with the given raw HTTP responses.
+    ///
+    /// The task returns the received requests (headers and bodies of fixed length).
+    pub(crate) async fn serve_raw_http(
+        responses: Vec<String>,
+    ) -> (SocketAddr, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 4096];
+                while !is_complete(&request) {
+                    let size = socket.read(&mut buffer).await.unwrap();
+                    assert!(
+                        size > 0,
+                        "Connection closed before the request was received"
+                    );
+                    request.extend_from_slice(&buffer[..size]);
+                }
+                socket.write_all(response.as_bytes()).await.unwrap();
+                requests.push(String::from_utf8_lossy(&request).into_owned());
+            }
+            requests
+        });
+        (addr, server)
+    }
+
+    /// Whether the headers and the body (according to `Content-Length`) have been received.
+    fn is_complete(request: &[u8]) -> bool {
+        let end = match request.windows(4).position(|item| item == b"\r\n\r\n") {
+            Some(end) => end + 4,
+            None => return false,
+        };
+        let length = String::from_utf8_lossy(&request[..end])
+            .lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                if name.eq_ignore_ascii_case("content-length") {
+                    value.trim().parse().ok()
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(0);
+        request.len() >= end + length
+    }
+
     pub(crate) fn to_yaml<S: AsRef<str>>(source: S) -> serde_yaml::Mapping {
         let value = serde_yaml::from_str(source.as_ref()).unwrap();
         match value {
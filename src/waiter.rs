@@ -0,0 +1,267 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Waiting for resources to reach a certain state.
+
+use std::future::Future;
+use std::time::Duration;
+
+use log::debug;
+use serde::de::DeserializeOwned;
+use tokio::time::{sleep, Instant};
+
+use super::services::ServiceType;
+use super::{AuthType, Error, ErrorKind, ServiceRequestBuilder};
+
+/// Waiter for a resource to reach a certain state.
+///
+/// The resource is fetched repeatedly until a predicate on it returns `true` or the timeout
+/// expires. The predicate can return an error to stop waiting, e.g. when the resource reaches
+/// an error state.
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use std::time::Duration;
+///
+/// #[derive(Debug, serde::Deserialize)]
+/// struct Server {
+///     status: String,
+/// }
+///
+/// #[derive(Debug, serde::Deserialize)]
+/// struct ServerRoot {
+///     server: Server,
+/// }
+///
+/// let session = osauth::Session::from_env().await?;
+/// let root: ServerRoot = osauth::Waiter::new(Duration::from_secs(600))
+///     .with_interval(Duration::from_secs(5))
+///     .wait(
+///         || session.get(osauth::services::COMPUTE, &["servers", "<UUID>"]),
+///         |root: &ServerRoot| match root.server.status.as_str() {
+///             "ACTIVE" => Ok(true),
+///             "ERROR" => Err(osauth::Error::new(
+///                 osauth::ErrorKind::OperationFailed,
+///                 "Server went into ERROR state",
+///             )),
+///             _ => Ok(false),
+///         },
+///     )
+///     .await?;
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Waiter {
+    timeout: Duration,
+    interval: Duration,
+    backoff: f64,
+    max_interval: Duration,
+}
+
+impl Waiter {
+    /// Create a waiter with the given timeout.
+    pub fn new(timeout: Duration) -> Waiter {
+        Waiter {
+            timeout,
+            interval: Duration::from_secs(1),
+            backoff: 1.5,
+            max_interval: Duration::from_secs(30),
+        }
+    }
+
+    /// Multiplier applied to the interval after each attempt (1.5 by default).
+    ///
+    /// Use 1.0 to poll with a constant interval.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is less than 1.0.
+    #[inline]
+    pub fn set_backoff(&mut self, value: f64) {
+        assert!(value >= 1.0, "Backoff must be at least 1.0");
+        self.backoff = value;
+    }
+
+    /// Interval before the second attempt (1 second by default).
+    #[inline]
+    pub fn set_interval(&mut self, value: Duration) {
+        self.interval = value;
+    }
+
+    /// Maximum interval between attempts (30 seconds by default).
+    #[inline]
+    pub fn set_max_interval(&mut self, value: Duration) {
+        self.max_interval = value;
+    }
+
+    /// Multiplier applied to the interval after each attempt (1.5 by default).
+    ///
+    /// Use 1.0 to poll with a constant interval.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is less than 1.0.
+    #[inline]
+    pub fn with_backoff(mut self, value: f64) -> Self {
+        self.set_backoff(value);
+        self
+    }
+
+    /// Interval before the second attempt (1 second by default).
+    #[inline]
+    pub fn with_interval(mut self, value: Duration) -> Self {
+        self.set_interval(value);
+        self
+    }
+
+    /// Maximum interval between attempts (30 seconds by default).
+    #[inline]
+    pub fn with_max_interval(mut self, value: Duration) -> Self {
+        self.set_max_interval(value);
+        self
+    }
+
+    /// Wait for the resource to satisfy the predicate.
+    ///
+    /// The `request` callback is called to build a new request on each attempt. Returns
+    /// the last fetched resource on success, `OperationTimedOut` on timeout.
+    pub async fn wait<S, A, F, T, P>(&self, mut request: F, predicate: P) -> Result<T, Error>
+    where
+        S: ServiceType + Send,
+        A: AuthType + ?Sized,
+        F: FnMut() -> ServiceRequestBuilder<S, A>,
+        T: DeserializeOwned + Send,
+        P: FnMut(&T) -> Result<bool, Error>,
+    {
+        self.wait_for(|| request().fetch(), predicate).await
+    }
+
+    async fn wait_for<F, R, T, P>(&self, mut fetch: F, mut predicate: P) -> Result<T, Error>
+    where
+        F: FnMut() -> R,
+        R: Future<Output = Result<T, Error>>,
+        P: FnMut(&T) -> Result<bool, Error>,
+    {
+        let deadline = Instant::now() + self.timeout;
+        let mut interval = self.interval;
+        let mut attempt = 1;
+        loop {
+            let resource = fetch().await?;
+            if predicate(&resource)? {
+                debug!("Waiting finished after {} attempt(s)", attempt);
+                return Ok(resource);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::new(
+                    ErrorKind::OperationTimedOut,
+                    format!(
+                        "Resource did not reach the expected state in {} seconds",
+                        self.timeout.as_secs()
+                    ),
+                ));
+            }
+
+            // Make sure the last attempt happens right at the deadline.
+            let delay = interval.min(deadline - now);
+            debug!(
+                "Resource is not ready after {} attempt(s), retrying in {:?}",
+                attempt, delay
+            );
+            sleep(delay).await;
+            interval = self.next_interval(interval);
+            attempt += 1;
+        }
+    }
+
+    fn next_interval(&self, current: Duration) -> Duration {
+        current.mul_f64(self.backoff).min(self.max_interval)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::Waiter;
+    use crate::{Error, ErrorKind};
+
+    #[test]
+    fn test_next_interval() {
+        let waiter = Waiter::new(Duration::from_secs(60))
+            .with_backoff(2.0)
+            .with_max_interval(Duration::from_secs(5));
+        assert_eq!(
+            waiter.next_interval(Duration::from_secs(1)),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            waiter.next_interval(Duration::from_secs(4)),
+            Duration::from_secs(5)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_success() {
+        let waiter = Waiter::new(Duration::from_secs(5)).with_interval(Duration::from_millis(1));
+        let mut counter = 0;
+        let result = waiter
+            .wait_for(
+                || {
+                    counter += 1;
+                    std::future::ready(Ok::<_, Error>(counter))
+                },
+                |value| Ok(*value >= 3),
+            )
+            .await
+            .unwrap();
+        assert_eq!(result, 3);
+    }
+
+    #[tokio::test]
+    async fn test_wait_predicate_error() {
+        let waiter = Waiter::new(Duration::from_secs(5)).with_interval(Duration::from_millis(1));
+        let err = waiter
+            .wait_for(
+                || std::future::ready(Ok::<_, Error>("error")),
+                |_| Err::<bool, _>(Error::new(ErrorKind::OperationFailed, "failed")),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::OperationFailed);
+    }
+
+    #[tokio::test]
+    async fn test_wait_timeout() {
+        let waiter = Waiter::new(Duration::from_millis(20))
+            .with_interval(Duration::from_millis(5))
+            .with_backoff(1.0);
+        let mut counter = 0;
+        let err = waiter
+            .wait_for(
+                || {
+                    counter += 1;
+                    std::future::ready(Ok::<_, Error>(counter))
+                },
+                |_| Ok(false),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::OperationTimedOut);
+        assert!(counter >= 2);
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright 2022 Dmitry Tantsur <dtantsur@protonmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Authenticated WebSocket connections.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use http::header::{
+    HeaderMap, HeaderValue, CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY,
+    SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION, UPGRADE,
+};
+use log::debug;
+use reqwest::{Response, StatusCode, Upgraded, Url};
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::client;
+use super::{Error, ErrorKind};
+
+/// GUID used to calculate `Sec-WebSocket-Accept` (RFC 6455).
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// An established WebSocket connection.
+///
+/// Only the opening handshake is handled by this crate, the connection is a raw byte stream
+/// that implements [AsyncRead] and [AsyncWrite]. Pass it to a WebSocket implementation
+/// for framing, for example `tokio_tungstenite::WebSocketStream::from_raw_socket`.
+#[derive(Debug)]
+pub struct WebSocket {
+    inner: Upgraded,
+    protocol: Option<String>,
+}
+
+impl WebSocket {
+    /// Sub-protocol selected by the server (if any).
+    #[inline]
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    /// Extract the underlying upgraded connection.
+    #[inline]
+    pub fn into_inner(self) -> Upgraded {
+        self.inner
+    }
+}
+
+impl AsyncRead for WebSocket {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for WebSocket {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Convert a `ws://` or `wss://` URL into an HTTP one.
+pub(crate) fn http_url(mut url: Url) -> Result<Url, Error> {
+    let scheme = match url.scheme() {
+        "ws" | "http" => "http",
+        "wss" | "https" => "https",
+        other => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unsupported scheme {} for a WebSocket URL {}", other, url),
+            ))
+        }
+    };
+    url.set_scheme(scheme)
+        .expect("Cannot change a special scheme into another special scheme");
+    Ok(url)
+}
+
+/// Generate the handshake headers, returning them with the expected accept value.
+pub(crate) fn handshake() -> (HeaderMap, String) {
+    // The key only has to be unpredictable for intermediaries, RandomState is good enough.
+    let mut nonce = [0u8; 16];
+    for chunk in nonce.chunks_mut(8) {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(chunk.as_ptr() as usize);
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    let key = BASE64.encode(nonce);
+
+    let mut headers = HeaderMap::new();
+    let _ = headers.insert(CONNECTION, HeaderValue::from_static("Upgrade"));
+    let _ = headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+    let _ = headers.insert(SEC_WEBSOCKET_VERSION, HeaderValue::from_static("13"));
+    let _ = headers.insert(
+        SEC_WEBSOCKET_KEY,
+        HeaderValue::from_str(&key).expect("Base64 is always a valid header"),
+    );
+    (headers, accept_value(&key))
+}
+
+fn accept_value(key: &str) -> String {
+    BASE64.encode(Sha1::digest(format!("{}{}", key, ACCEPT_GUID).as_bytes()))
+}
+
+/// Finish the handshake by validating the response and upgrading the connection.
+pub(crate) async fn upgrade(response: Response, accept: &str) -> Result<WebSocket, Error> {
+    if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+        let response = client::check(response).await?;
+        return Err(Error::new(
+            ErrorKind::InvalidResponse,
+            format!(
+                "Expected a WebSocket upgrade from {}, got HTTP {}",
+                response.url(),
+                response.status()
+            ),
+        ));
+    }
+
+    let headers = response.headers();
+    if headers.get(SEC_WEBSOCKET_ACCEPT).map(HeaderValue::as_bytes) != Some(accept.as_bytes()) {
+        return Err(Error::new(
+            ErrorKind::InvalidResponse,
+            "Missing or invalid Sec-WebSocket-Accept header in the WebSocket handshake",
+        ));
+    }
+
+    let protocol = headers
+        .get(SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok())
+        .map(From::from);
+    debug!(
+        "WebSocket connection to {} established (protocol {:?})",
+        response.url(),
+        protocol
+    );
+    let inner = response.upgrade().await?;
+    Ok(WebSocket { inner, protocol })
+}
+
+#[cfg(test)]
+mod test {
+    use http::header::SEC_WEBSOCKET_KEY;
+    use reqwest::Url;
+
+    use super::{accept_value, handshake, http_url};
+    use crate::ErrorKind;
+
+    #[test]
+    fn test_accept_value() {
+        // Example from RFC 6455.
+        assert_eq!(
+            accept_value("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_handshake() {
+        let (headers, accept) = handshake();
+        let key = headers.get(SEC_WEBSOCKET_KEY).unwrap().to_str().unwrap();
+        assert_eq!(key.len(), 24);
+        assert_eq!(accept, accept_value(key));
+        let (other, _) = handshake();
+        assert_ne!(other.get(SEC_WEBSOCKET_KEY).unwrap(), key);
+    }
+
+    #[test]
+    fn test_http_url() {
+        let url = http_url(Url::parse("wss://example.com/?token=abcd").unwrap()).unwrap();
+        assert_eq!(url.as_str(), "https://example.com/?token=abcd");
+        let url = http_url(Url::parse("ws://example.com:6083/").unwrap()).unwrap();
+        assert_eq!(url.as_str(), "http://example.com:6083/");
+        let err = http_url(Url::parse("ftp://example.com").unwrap()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}